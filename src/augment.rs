@@ -0,0 +1,330 @@
+//! Augmenting the `clap::Command` of the application with arguments
+//! that let end users control the reports of this crate, so that the
+//! application does not have to write the plumbing itself.
+//! Call `ReportContext::configure()` with the parsed `ArgMatches` to
+//! apply them.
+
+use std::ffi::OsString;
+
+use clap::{builder::EnumValueParser, parser::ValueSource, Arg, ArgAction, ArgMatches, Command};
+use miette::{LabeledSpan, MietteDiagnostic, Report, Severity, SourceSpan};
+
+use crate::{
+    code,
+    parse::{all_aliases, display_name, ArgAlias},
+    report::{DiagnosticFormat, ReportContext},
+    scope::locate_scoped,
+    source::{ArgvSource, SourceProvider},
+};
+
+/// `clap::Id` of the argument added by `augment_no_warnings()`.
+pub const NO_WARNINGS_ID: &str = "no_fancy_warnings";
+/// Env variable that sets the argument added by `augment_no_warnings()`.
+pub const NO_WARNINGS_ENV: &str = "NO_FANCY_WARNINGS";
+
+/// `clap::Id` of the argument added by `augment_explain()`.
+pub const EXPLAIN_ID: &str = "fancy_explain";
+
+/// `clap::Id` of the argument added by `augment_diagnostic_format()`.
+pub const DIAGNOSTIC_FORMAT_ID: &str = "fancy_diagnostic_format";
+
+/// Adds a hidden global `--no-fancy-warnings` flag to `command`, which
+/// can also be set by the `NO_FANCY_WARNINGS` env variable, for
+/// silencing the warnings queued in `ReportContext`.
+pub fn augment_no_warnings(command: Command) -> Command {
+    command.arg(
+        Arg::new(NO_WARNINGS_ID)
+            .long("no-fancy-warnings")
+            .env(NO_WARNINGS_ENV)
+            .action(ArgAction::SetTrue)
+            .global(true)
+            .hide(true),
+    )
+}
+
+/// Adds a global `--explain <ARG>` argument to `command`. See
+/// `exit_on_explain()` for handling it.
+pub fn augment_explain(command: Command) -> Command {
+    command.arg(
+        Arg::new(EXPLAIN_ID)
+            .long("explain")
+            .value_name("ARG")
+            .help("Explain where the value of an argument came from and exit")
+            .global(true),
+    )
+}
+
+/// Adds a hidden global `--diagnostic-format <human|json|sarif|github>`
+/// argument to `command` for switching `ReportContext::format`.
+pub fn augment_diagnostic_format(command: Command) -> Command {
+    command.arg(
+        Arg::new(DIAGNOSTIC_FORMAT_ID)
+            .long("diagnostic-format")
+            .value_name("FORMAT")
+            .value_parser(EnumValueParser::<DiagnosticFormat>::new())
+            .help("Format of the diagnostics about the arguments")
+            .global(true)
+            .hide(true),
+    )
+}
+
+/// Prints `explain()` of the argument passed to the `--explain` added
+/// by `augment_explain()` for `std::env::args_os()` and exits.
+/// Does nothing if `--explain` is absent.
+pub fn exit_on_explain(command: &Command, matches: &ArgMatches) {
+    let Ok(Some(arg)) = matches.try_get_one::<String>(EXPLAIN_ID) else {
+        return;
+    };
+    eprintln!("{:?}", explain(command, std::env::args_os(), matches, arg));
+    std::process::exit(0);
+}
+
+/// Returns an advice that explains the value of `arg` in `matches`,
+/// including where it came from and which alias was typed. `arg` can
+/// be the id, a long or a short of the argument, with or without the
+/// leading hyphens. Env variable names and values, and default values,
+/// that are hidden from the help are redacted as well.
+pub fn explain<R>(command: &Command, args: R, matches: &ArgMatches, arg: &str) -> Report
+where
+    R: IntoIterator<Item: Into<OsString>>,
+{
+    let mut current_command = command;
+    let mut current_matches = matches;
+    let mut path = vec![];
+    let found = loop {
+        if let Some(found) = find_arg(current_command, arg) {
+            break found;
+        }
+        let sub = current_matches.subcommand().and_then(|(name, sub_matches)| {
+            Some((current_command.find_subcommand(name)?, sub_matches))
+        });
+        let Some((sub_command, sub_matches)) = sub else {
+            return Report::from(MietteDiagnostic::new(format!("unknown argument `{arg}`")));
+        };
+        path.push(sub_command.get_name().to_owned());
+        current_command = sub_command;
+        current_matches = sub_matches;
+    };
+    let id = found.get_id().as_str();
+
+    let mut diagnostic = MietteDiagnostic::new(format!("explanation of `{}`", display_name(found)))
+        .with_severity(Severity::Advice)
+        .with_code(code::EXPLANATION);
+    let mut notes = vec![];
+    let source = current_matches.value_source(id);
+    match source {
+        Some(ValueSource::CommandLine) => notes.push("the value came from the command line".to_owned()),
+        Some(ValueSource::EnvVariable) if found.is_hide_env_set() => notes.push("the value came from an env variable".to_owned()),
+        Some(ValueSource::EnvVariable) => notes.push(format!(
+            "the value came from the env variable `{}`",
+            found.get_env().unwrap_or_default().to_string_lossy(),
+        )),
+        Some(ValueSource::DefaultValue) => notes.push("the value is the default value".to_owned()),
+        _ => notes.push("the argument is not set".to_owned()),
+    }
+    // Env variables and defaults that are hidden from the help may
+    // hold secrets, so are their values.
+    let hidden = match source {
+        Some(ValueSource::EnvVariable) => found.is_hide_env_set() || found.is_hide_env_values_set(),
+        Some(ValueSource::DefaultValue) => found.is_hide_default_value_set(),
+        _ => false,
+    };
+    let values = current_matches
+        .get_raw(id)
+        .map(|values| values.map(|value| format!("`{}`", value.to_string_lossy())).collect::<Vec<_>>())
+        .unwrap_or_default();
+    if hidden && !values.is_empty() {
+        notes.push("value: (hidden)".to_owned());
+    } else if !values.is_empty() {
+        notes.push(format!("value: {}", values.join(", ")));
+    }
+    diagnostic.help = Some(notes.join("; "));
+
+    if current_matches.value_source(id) != Some(ValueSource::CommandLine) {
+        return Report::from(diagnostic);
+    }
+    let args = args.into_iter().map(|arg| arg.into().to_string_lossy().to_string()).collect::<Vec<_>>();
+    let full_command = args.join(" ");
+    // Global arguments belong to the subcommand they were typed after.
+    let locations = locate_scoped(command, &args)
+        .into_iter()
+        .filter(|located| located.id == id && (located.scope == path || found.is_global_set() && located.scope.starts_with(&path)))
+        .map(|located| located.location)
+        .enumerate()
+        .collect::<Vec<_>>();
+    let Some((last, _)) = locations.last() else {
+        return Report::from(diagnostic);
+    };
+    // Repeating an argument that sets its value is only accepted if the
+    // last occurrence overrides the others.
+    let overrides = matches!(found.get_action(), ArgAction::Set | ArgAction::SetTrue | ArgAction::SetFalse);
+    let mut labels = vec![];
+    for (index, location) in &locations {
+        let text = match index == last || !overrides {
+            true => {
+                let declaration = location.declaration();
+                let name = location.name();
+                format!(
+                    "typed as `{}{}`",
+                    full_command.get(declaration.offset..declaration.offset + declaration.length).unwrap_or_default(),
+                    full_command.get(name.offset..name.offset + name.length).unwrap_or_default(),
+                )
+            }
+            false => "overridden".to_owned(),
+        };
+        let whole = location.whole();
+        let span = SourceSpan::new(whole.offset.into(), whole.length);
+        labels.push(match index == last {
+            true => LabeledSpan::new_primary_with_span(Some(text), span),
+            false => LabeledSpan::new_with_span(Some(text), span),
+        });
+    }
+    diagnostic = diagnostic.with_labels(labels);
+    if overrides && *last > 0 {
+        let help = diagnostic.help.take().unwrap_or_default();
+        diagnostic = diagnostic.with_help(format!("{help}; the last of {} occurrences overrides the others", locations.len()));
+    }
+
+    ArgvSource::new(full_command).attach(Report::from(diagnostic))
+}
+
+/// Finds `arg` by its id, or by a long or short alias. `--name` only
+/// matches longs and `-x` only shorts, while a bare name matches both.
+fn find_arg<'a>(command: &'a Command, arg: &str) -> Option<&'a Arg> {
+    let (name, longs, shorts) = match (arg.strip_prefix("--"), arg.strip_prefix('-')) {
+        (Some(long), _) => (long, true, false),
+        (None, Some(short)) => (short, false, true),
+        (None, None) => (arg, true, true),
+    };
+    let mut chars = name.chars();
+    let short = chars.next().filter(|_| chars.next().is_none());
+    command.get_arguments().find(|candidate| {
+        candidate.get_id() == arg
+            || all_aliases(candidate).any(|alias| match alias {
+                ArgAlias::Long(long) => longs && long == name,
+                ArgAlias::Short(alias) => shorts && short == Some(alias),
+            })
+    })
+}
+
+impl ReportContext {
+    /// Applies the arguments added by the `augment_*()` functions in
+    /// `matches`. Arguments that were not added are ignored.
+    pub fn configure(&mut self, matches: &ArgMatches) {
+        if let Ok(Some(true)) = matches.try_get_one::<bool>(NO_WARNINGS_ID) {
+            self.silence_warnings = true;
+        }
+        if let Ok(Some(format)) = matches.try_get_one::<DiagnosticFormat>(DIAGNOSTIC_FORMAT_ID) {
+            self.format = *format;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_augment_no_warnings() {
+        let command = augment_no_warnings(Command::new("app").subcommand(Command::new("sub")));
+        let arg = command.get_arguments().find(|arg| arg.get_id() == NO_WARNINGS_ID).unwrap();
+        assert!(arg.is_hide_set());
+        assert_eq!(arg.get_env(), Some(NO_WARNINGS_ENV.as_ref()));
+
+        let mut context = ReportContext::default();
+        context.configure(&command.clone().get_matches_from(["app", "sub"]));
+        assert!(!context.silence_warnings);
+        context.configure(&command.get_matches_from(["app", "sub", "--no-fancy-warnings"]));
+        assert!(context.silence_warnings);
+    }
+
+    #[test]
+    fn test_augment_diagnostic_format() {
+        let command = augment_diagnostic_format(Command::new("app"));
+        let mut context = ReportContext::default();
+        context.configure(&command.clone().get_matches_from(["app"]));
+        assert_eq!(context.format, DiagnosticFormat::Human);
+        context.configure(&command.clone().get_matches_from(["app", "--diagnostic-format=sarif"]));
+        assert_eq!(context.format, DiagnosticFormat::Sarif);
+        assert!(command.try_get_matches_from(["app", "--diagnostic-format=xml"]).is_err());
+    }
+
+    #[test]
+    fn test_explain() {
+        let command = augment_explain(
+            Command::new("app")
+                .arg(Arg::new("port").long("port").visible_alias("p0rt"))
+                .arg(Arg::new("host").long("host").env("FANCY_CLAP_TEST_HOST").default_value("localhost")),
+        );
+        let args = ["app", "--p0rt=2", "--explain", "port"];
+        let matches = command.clone().get_matches_from(args);
+        assert_eq!(matches.get_one::<String>(EXPLAIN_ID).map(String::as_str), Some("port"));
+
+        let report = explain(&command, args, &matches, "--port");
+        assert_eq!(report.to_string(), "explanation of `--port`");
+        let help = report.help().unwrap().to_string();
+        assert!(help.contains("command line"));
+        assert!(help.contains("value: `2`"));
+        let labels = report.labels().unwrap().collect::<Vec<_>>();
+        assert_eq!(labels[0].label(), Some("typed as `--p0rt`"));
+        assert_eq!((labels[0].offset(), labels[0].len()), (4, 8));
+
+        // The last occurrence overrides the others.
+        let command = command.args_override_self(true);
+        let args = ["app", "--port=1", "--p0rt", "2"];
+        let matches = command.clone().get_matches_from(args);
+        let report = explain(&command, args, &matches, "port");
+        assert!(report.help().unwrap().to_string().ends_with("; the last of 2 occurrences overrides the others"));
+        let labels = report.labels().unwrap().map(|label| (label.offset(), label.label().unwrap().to_owned(), label.primary())).collect::<Vec<_>>();
+        assert_eq!(labels, [(4, "overridden".to_owned(), false), (13, "typed as `--p0rt`".to_owned(), true)]);
+
+        let report = explain(&command, args, &matches, "host");
+        assert!(report.help().unwrap().to_string().contains("default value"));
+        assert!(report.labels().is_none());
+        assert_eq!(explain(&command, args, &matches, "nope").to_string(), "unknown argument `nope`");
+
+        // Hyphens tell longs and shorts apart.
+        let command = Command::new("app").arg(Arg::new("port").long("port").short('p')).arg(Arg::new("p").long("p"));
+        let matches = command.clone().get_matches_from(["app"]);
+        let name = |arg| explain(&command, ["app"], &matches, arg).to_string();
+        assert_eq!(name("-p"), "explanation of `--port`");
+        assert_eq!(name("--p"), "explanation of `--p`");
+        assert_eq!(name("-port"), "unknown argument `-port`");
+        assert_eq!(name("--port"), "explanation of `--port`");
+
+        // Arguments of subcommands are labelled where they were typed
+        // under the subcommand.
+        let command = Command::new("app")
+            .arg(Arg::new("verbose").short('v').action(ArgAction::SetTrue).global(true))
+            .arg(Arg::new("port").long("port"))
+            .subcommand(Command::new("serve").arg(Arg::new("port").long("port")).arg(Arg::new("tls").long("tls")));
+        let args = ["app", "--port=1", "serve", "-v", "--tls=x", "--port=2"];
+        let matches = command.clone().get_matches_from(args);
+        let label = |arg| explain(&command, args, &matches, arg).labels().map(|labels| labels.map(|label| (label.offset(), label.len())).collect::<Vec<_>>());
+        assert_eq!(label("tls"), Some(vec![(22, 7)]));
+        assert_eq!(label("port"), Some(vec![(4, 8)]));
+        assert_eq!(label("verbose"), Some(vec![(19, 2)]));
+    }
+
+    #[test]
+    fn test_explain_hidden_provenance() {
+        // clap reads the env variables when they are declared.
+        std::env::set_var("FANCY_CLAP_TEST_SECRET", "hunter2");
+        let command = Command::new("app")
+            .arg(Arg::new("token").long("token").env("FANCY_CLAP_TEST_SECRET").hide_env(true))
+            .arg(Arg::new("key").long("key").env("FANCY_CLAP_TEST_SECRET").hide_env_values(true))
+            .arg(Arg::new("salt").long("salt").default_value("pepper").hide_default_value(true));
+        let matches = command.clone().get_matches_from(["app"]);
+        let help = |arg| explain(&command, ["app"], &matches, arg).help().unwrap().to_string();
+        assert_eq!(help("token"), "the value came from an env variable; value: (hidden)");
+        assert_eq!(help("key"), "the value came from the env variable `FANCY_CLAP_TEST_SECRET`; value: (hidden)");
+        assert_eq!(help("salt"), "the value is the default value; value: (hidden)");
+    }
+
+    #[test]
+    fn test_configure_without_augment() {
+        let mut context = ReportContext::default();
+        context.configure(&Command::new("app").get_matches_from(["app"]));
+        assert!(!context.silence_warnings);
+    }
+}
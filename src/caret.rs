@@ -0,0 +1,276 @@
+//! Rendering labels under the Argv string as plain text, without a
+//! `miette` report handler, for binaries that only want carets. See
+//! `render_carets()`.
+
+use std::{ffi::OsString, io::IsTerminal};
+
+use anstyle::{AnsiColor, Style};
+use clap::ColorChoice;
+
+use crate::{
+    lossy::LossyArgv,
+    parse::{ArgLocation, ArgPart},
+    theme::Markers,
+    width::columns,
+};
+
+/// How severe the labels of `render_carets_styled()` are, which picks
+/// their style in `CaretStyles`.
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub enum CaretLevel {
+    Error,
+    Warning,
+    Advice,
+}
+
+/// Styles of `render_carets_styled()`, as `anstyle` styles, which are
+/// also what `clap::builder::styling` re-exports.
+#[derive(Clone, Debug)]
+pub struct CaretStyles {
+    /// The underlines and the label texts of an error.
+    pub error: Style,
+    /// The underlines and the label texts of a warning.
+    pub warning: Style,
+    /// The underlines and the label texts of an advice.
+    pub advice: Style,
+    /// The text of the Argv under the primary label.
+    pub value: Style,
+}
+
+impl CaretStyles {
+    /// No styles at all, e.g. for terminals without color support.
+    pub fn plain() -> Self {
+        Self {
+            error: Style::new(),
+            warning: Style::new(),
+            advice: Style::new(),
+            value: Style::new(),
+        }
+    }
+
+    /// Returns the default styles, or `Self::plain()` if `choice` turns
+    /// colors off. `ColorChoice::Auto` turns them off if the `NO_COLOR`
+    /// env variable is set to anything but an empty string, or if the
+    /// standard error is not a terminal.
+    pub fn for_color_choice(choice: ColorChoice) -> Self {
+        let colored = match choice {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty()) && std::io::stderr().is_terminal(),
+        };
+        match colored {
+            true => Self::default(),
+            false => Self::plain(),
+        }
+    }
+
+    /// Same as `Self::for_color_choice()` with the `ColorChoice` of
+    /// `command`, so the output follows the color policy of the
+    /// application, e.g. set by `Command::color()`.
+    pub fn for_command(command: &clap::Command) -> Self {
+        Self::for_color_choice(command.get_color())
+    }
+
+    fn level(&self, level: CaretLevel) -> Style {
+        match level {
+            CaretLevel::Error => self.error,
+            CaretLevel::Warning => self.warning,
+            CaretLevel::Advice => self.advice,
+        }
+    }
+}
+
+impl Default for CaretStyles {
+    fn default() -> Self {
+        Self {
+            error: AnsiColor::Red.on_default().bold(),
+            warning: AnsiColor::Yellow.on_default().bold(),
+            advice: AnsiColor::Cyan.on_default().bold(),
+            value: Style::new().bold().underline(),
+        }
+    }
+}
+
+/// Returns `text` as an OSC-8 hyperlink to `url`, which terminals that
+/// support it render as a clickable link.
+#[cfg(feature = "miette")]
+pub(crate) fn hyperlink(text: &str, url: &str) -> String {
+    format!("\u{1b}]8;;{url}\u{1b}\\{text}\u{1b}]8;;\u{1b}\\")
+}
+
+/// Whether the standard error is a terminal that is known to support
+/// OSC-8 hyperlinks. `FORCE_HYPERLINK` overrides the detection.
+#[cfg(feature = "miette")]
+pub(crate) fn supports_hyperlinks() -> bool {
+    if let Some(force) = std::env::var_os("FORCE_HYPERLINK") {
+        return force != "0";
+    }
+    if !std::io::stderr().is_terminal() {
+        return false;
+    }
+    let var = |name| std::env::var(name).unwrap_or_default();
+    ["WT_SESSION", "DOMTERM", "KONSOLE_VERSION"].iter().any(|name| std::env::var_os(name).is_some())
+        || matches!(var("TERM_PROGRAM").as_str(), "iTerm.app" | "WezTerm" | "vscode" | "ghostty" | "Hyper")
+        || matches!(var("TERM").as_str(), "xterm-kitty" | "alacritty" | "xterm-ghostty")
+        || var("VTE_VERSION").parse().is_ok_and(|version: u32| version >= 5000)
+}
+
+/// Returns `args` joined by spaces, a line that underlines the labels
+/// and a line for the text of every label beneath, connected to the
+/// start of its underline. A label covers the value of the argument, or
+/// the whole argument if it has no value, like
+/// `ArgLocator::get_label_span()`. The first label is primary and the
+/// others are secondary. Every line ends with a newline.
+///
+/// # Examples
+/// ```
+/// use clap::{Arg, Command};
+/// use fancy_clap::{render_carets, ArgLocator, Markers};
+///
+/// let command = Command::new("app").arg(Arg::new("name").long("name")).arg(Arg::new("port").long("port"));
+/// let locator = ArgLocator::from_command(&command);
+/// let args = ["app", "--name=x", "--port", "80"];
+/// let port = locator.get_location(args, "port").unwrap();
+/// let name = locator.get_location(args, "name").unwrap();
+/// let rendered = render_carets(args, &[(&port, "privileged port"), (&name, "set here")], &Markers::ascii());
+/// assert_eq!(rendered, [
+///     "app --name=x --port 80\n",
+///     "           -        ^^\n",
+///     "           |        `-- privileged port\n",
+///     "           `-- set here\n",
+/// ].concat());
+/// ```
+pub fn render_carets<R>(args: R, labels: &[(&ArgLocation, &str)], markers: &Markers) -> String
+where
+    R: IntoIterator<Item: Into<OsString>>,
+{
+    render(args, labels, markers, Style::new(), Style::new())
+}
+
+/// Same as `render_carets()` but the underlines and the label texts are
+/// styled by the style of `level` in `styles`, and the text under the
+/// primary label by `CaretStyles::value`.
+pub fn render_carets_styled<R>(args: R, labels: &[(&ArgLocation, &str)], markers: &Markers, styles: &CaretStyles, level: CaretLevel) -> String
+where
+    R: IntoIterator<Item: Into<OsString>>,
+{
+    render(args, labels, markers, styles.level(level), styles.value)
+}
+
+fn render<R>(args: R, labels: &[(&ArgLocation, &str)], markers: &Markers, style: Style, value_style: Style) -> String
+where
+    R: IntoIterator<Item: Into<OsString>>,
+{
+    let paint = |style: Style, text: &str| format!("{}{text}{}", style.render(), style.render_reset());
+    // The locations are in the raw Argv, so they are moved past every
+    // replacement character like the line is.
+    let lossy = LossyArgv::new(args);
+    let argv = &lossy.lossy;
+    let parts = labels
+        .iter()
+        .map(|(location, text)| (lossy.remap_part(&location.content().cloned().unwrap_or_else(|| location.whole())), *text))
+        .collect::<Vec<_>>();
+    let (primary, secondary) = parts.split_at(parts.len().min(1));
+    let part = |(part, _): &(ArgPart, &str)| part.clone();
+    let underline = markers.underline(argv, &primary.iter().map(part).collect::<Vec<_>>(), &secondary.iter().map(part).collect::<Vec<_>>());
+
+    let value = primary.first().map(|(part, _)| part.offset..part.offset + part.length);
+    let highlighted = match value.filter(|value| argv.get(value.clone()).is_some()) {
+        Some(value) => format!("{}{}{}", &argv[..value.start], paint(value_style, &argv[value.clone()]), &argv[value.end..]),
+        None => argv.to_owned(),
+    };
+    let mut rendered = format!("{highlighted}\n{}\n", paint(style, &underline));
+    let mut starts = parts
+        .iter()
+        .map(|(part, text)| (argv.get(..part.offset).map_or(0, columns), *text))
+        .collect::<Vec<_>>();
+    starts.sort_by_key(|(column, _)| *column);
+    // The label of the rightmost underline is the top row, so that the
+    // connectors of the others pass by on its left. Labels that start at
+    // the same column share their corner instead.
+    for (index, (column, text)) in starts.iter().enumerate().rev() {
+        let mut row = vec![' '; *column];
+        for (left, _) in starts[..index].iter().filter(|(left, _)| left < column) {
+            row[*left] = markers.connector;
+        }
+        row.extend([markers.corner, markers.horizontal, markers.horizontal, ' ']);
+        rendered += &paint(style, &format!("{}{text}", row.into_iter().collect::<String>()));
+        rendered.push('\n');
+    }
+
+    rendered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ArgLocator;
+    use clap::{Arg, ArgAction, Command};
+
+    #[test]
+    fn test_render_carets() {
+        let command = Command::new("app")
+            .arg(Arg::new("verbose").short('v').action(ArgAction::SetTrue))
+            .arg(Arg::new("name").long("name"));
+        let locator = ArgLocator::from_command(&command);
+        let args = ["app", "-v", "--name=\u{540d}\u{524d}"];
+        let name = locator.get_location(args, "name").unwrap();
+        let verbose = locator.get_location(args, "verbose").unwrap();
+        let rendered = render_carets(args, &[(&name, "bad name")], &Markers::ascii());
+        #[cfg(feature = "unicode-width")]
+        assert_eq!(rendered, "app -v --name=\u{540d}\u{524d}\n              ^^^^\n              `-- bad name\n");
+        #[cfg(not(feature = "unicode-width"))]
+        assert_eq!(rendered, "app -v --name=\u{540d}\u{524d}\n              ^^\n              `-- bad name\n");
+
+        let rendered = render_carets(args, &[(&verbose, "here")], &Markers::unicode());
+        assert_eq!(rendered, "app -v --name=\u{540d}\u{524d}\n    ━━\n    ╰── here\n");
+        assert_eq!(render_carets(["app"], &[], &Markers::ascii()), "app\n\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_render_carets_lossy() {
+        use std::os::unix::ffi::OsStringExt;
+
+        let command = Command::new("app").arg(Arg::new("name").long("name")).arg(Arg::new("port").long("port"));
+        let locator = ArgLocator::from_command(&command);
+        let args = [OsString::from("app"), OsString::from_vec(b"--name=\xff\xfe".to_vec()), OsString::from("--port=80")];
+        let port = locator.get_location(args.clone(), "port").unwrap();
+        let rendered = render_carets(args, &[(&port, "here")], &Markers::ascii());
+        assert_eq!(rendered, "app --name=\u{fffd}\u{fffd} --port=80\n                     ^^\n                     `-- here\n");
+    }
+
+    #[test]
+    fn test_render_carets_same_column() {
+        let command = Command::new("app").arg(Arg::new("name").long("name")).arg(Arg::new("port").long("port"));
+        let locator = ArgLocator::from_command(&command);
+        let args = ["app", "--name=x", "--port", "80"];
+        let port = locator.get_location(args, "port").unwrap();
+        let name = locator.get_location(args, "name").unwrap();
+        let rendered = render_carets(args, &[(&port, "a"), (&port, "b"), (&name, "c")], &Markers::ascii());
+        assert_eq!(rendered, [
+            "app --name=x --port 80\n",
+            "           -        ^^\n",
+            "           |        `-- b\n",
+            "           |        `-- a\n",
+            "           `-- c\n",
+        ].concat());
+    }
+
+    #[test]
+    fn test_render_carets_styled() {
+        let command = Command::new("app").arg(Arg::new("port").long("port"));
+        let locator = ArgLocator::from_command(&command);
+        let args = ["app", "--port", "80"];
+        let port = locator.get_location(args, "port").unwrap();
+        let styles = CaretStyles::default();
+        let rendered = render_carets_styled(args, &[(&port, "here")], &Markers::ascii(), &styles, CaretLevel::Warning);
+        let wrap = |style: Style, text: &str| format!("{}{text}{}", style.render(), style.render_reset());
+        assert_eq!(rendered, format!("app --port {}\n{}\n{}\n", wrap(styles.value, "80"), wrap(styles.warning, "           ^^"), wrap(styles.warning, "           `-- here")));
+
+        let plain = CaretStyles::for_color_choice(ColorChoice::Never);
+        assert_eq!(render_carets_styled(args, &[(&port, "here")], &Markers::ascii(), &plain, CaretLevel::Error), render_carets(args, &[(&port, "here")], &Markers::ascii()));
+        assert_eq!(CaretStyles::for_color_choice(ColorChoice::Always).error, styles.error);
+        assert_eq!(CaretStyles::for_command(&command.color(ColorChoice::Never)).error, Style::new());
+    }
+}
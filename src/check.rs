@@ -0,0 +1,117 @@
+//! Validating the produced `ArgLocation`s against the Argv string they
+//! came from, so off-by-one regressions in the offset accounting panic
+//! in tests rather than silently producing misplaced labels. Enabled by
+//! the `span-checks` feature.
+
+use std::ffi::OsString;
+
+use crate::parse::{ArgLocation, ArgPart};
+
+/// Panics with a descriptive message if `location` does not slice to
+/// the expected text of `args` joined by spaces.
+pub(crate) fn check_location(args: &[OsString], location: &ArgLocation) {
+    let source = args.iter().map(|arg| arg.as_encoded_bytes()).collect::<Vec<_>>().join(&b' ');
+    // Offsets of the first byte and the byte after the last byte of
+    // every argument.
+    let mut tokens = vec![];
+    let mut offset = 0;
+    for arg in args {
+        tokens.push((offset, offset + arg.len()));
+        offset += arg.len() + 1;
+    }
+    let fail = |reason: &str| -> ! {
+        panic!(
+            "Invalid location of argument ({reason}):\n  source:   {:?}\n  location: {location:?}",
+            String::from_utf8_lossy(&source),
+        )
+    };
+    let slice = |part: &ArgPart| {
+        source
+            .get(part.offset..part.offset + part.length)
+            .unwrap_or_else(|| fail("part is out of bounds"))
+    };
+    let token_of = |part: &ArgPart| {
+        tokens
+            .iter()
+            .position(|&(start, end)| start <= part.offset && part.offset + part.length <= end)
+            .unwrap_or_else(|| fail("part spans multiple arguments"))
+    };
+
+    let declaration = location.declaration();
+    let name = location.name();
+    let token = token_of(declaration);
+    if tokens[token].0 != declaration.offset {
+        fail("declaration does not start an argument");
+    }
+    match slice(declaration) {
+        b"--" if name.offset == declaration.offset + 2 => (),
+        b"-" if name.offset > declaration.offset => (),
+        _ => fail("declaration is not followed by the name"),
+    }
+    if token_of(name) != token || slice(name).is_empty() || slice(name).contains(&b'=') {
+        fail("name is not in the argument of the declaration");
+    }
+
+    match location {
+        ArgLocation::Discrete { .. } => (),
+        ArgLocation::Stuck { content, .. } => {
+            if content.offset != name.offset + name.length || content.offset + content.length != tokens[token].1 {
+                fail("stuck content does not fill the rest of the argument");
+            }
+        }
+        ArgLocation::Complete { delimiter, content, .. } => {
+            if delimiter.offset != name.offset + name.length || content.offset != delimiter.offset + delimiter.length {
+                fail("delimiter is not in between the name and the content");
+            }
+            let content_token = match slice(delimiter) {
+                b"=" => token,
+                b" " => token + 1,
+                _ => fail("delimiter is neither `=` nor a space"),
+            };
+            if tokens.get(content_token).is_none_or(|&(start, end)| content.offset < start || content.offset + content.length != end) {
+                fail("content does not fill the rest of its argument");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args() -> Vec<OsString> {
+        ["program_name", "-abc", "--long=value", "--space", "value"].map(OsString::from).to_vec()
+    }
+
+    #[test]
+    fn test_check_location_valid() {
+        check_location(&args(), &ArgLocation::Stuck {
+            declaration: ArgPart { offset: 13, length: 1 },
+            name: ArgPart { offset: 15, length: 1 },
+            content: ArgPart { offset: 16, length: 1 },
+        });
+        check_location(&args(), &ArgLocation::Complete {
+            declaration: ArgPart { offset: 18, length: 2 },
+            name: ArgPart { offset: 20, length: 4 },
+            delimiter: ArgPart { offset: 24, length: 1 },
+            content: ArgPart { offset: 25, length: 5 },
+        });
+        check_location(&args(), &ArgLocation::Complete {
+            declaration: ArgPart { offset: 31, length: 2 },
+            name: ArgPart { offset: 33, length: 5 },
+            delimiter: ArgPart { offset: 38, length: 1 },
+            content: ArgPart { offset: 39, length: 5 },
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "content does not fill the rest of its argument")]
+    fn test_check_location_off_by_one() {
+        check_location(&args(), &ArgLocation::Complete {
+            declaration: ArgPart { offset: 18, length: 2 },
+            name: ArgPart { offset: 20, length: 4 },
+            delimiter: ArgPart { offset: 24, length: 1 },
+            content: ArgPart { offset: 25, length: 4 },
+        });
+    }
+}
@@ -0,0 +1,177 @@
+//! Stable codes of the diagnostics of this crate, attached with
+//! `Diagnostic::code()`, and a registry that applications extend with
+//! their own codes, e.g. to answer `--explain CODE` or to filter the
+//! machine-readable output by code.
+
+use std::collections::BTreeMap;
+
+#[cfg(feature = "miette")]
+use miette::{MietteDiagnostic, Report, Severity};
+
+/// See `suggest_subcommand()`.
+pub const UNRECOGNIZED_SUBCOMMAND: &str = "fancy_clap::unrecognized_subcommand";
+/// See `missing_required_hint()`.
+pub const MISSING_REQUIRED: &str = "fancy_clap::missing_required";
+/// See `stuck_value_advisories()`.
+pub const STUCK_VALUE: &str = "fancy_clap::stuck_value";
+/// See `Deprecations::check()`.
+pub const DEPRECATED: &str = "fancy_clap::deprecated";
+/// See `error_report()`.
+pub const INVALID_VALUE: &str = "fancy_clap::invalid_value";
+/// See `env_override_advisories()`.
+pub const ENV_OVERRIDE: &str = "fancy_clap::env_override";
+/// See `negation_reports()`.
+pub const NEGATION_CONFLICT: &str = "fancy_clap::negation_conflict";
+/// See `explain()`.
+pub const EXPLANATION: &str = "fancy_clap::explanation";
+/// See `ArgHighlighter::highlight_value_file()`.
+pub const VALUE_FILE: &str = "fancy_clap::value_file";
+/// See `UnknownToken`.
+pub const UNKNOWN_TOKEN: &str = "fancy_clap::unknown_token";
+/// See `InvalidUnicode`.
+pub const INVALID_UNICODE: &str = "fancy_clap::invalid_unicode";
+/// See `Desync`.
+pub const DESYNC: &str = "fancy_clap::desync";
+/// See `error_report()`.
+pub const ARGUMENT_CONFLICT: &str = "fancy_clap::argument_conflict";
+
+/// A code and what it means, shown by `CodeRegistry::explain()`.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct CodeEntry {
+    pub code: String,
+    /// One line, e.g. "an argument is missing".
+    pub summary: String,
+    /// Why the diagnostic is reported and how to fix it.
+    pub explanation: String,
+}
+
+/// Codes that are known by `Self::explain()`, keyed by the code.
+/// `Self::default()` has the codes of this crate.
+#[derive(Clone, Debug)]
+pub struct CodeRegistry {
+    pub entries: BTreeMap<String, CodeEntry>,
+}
+
+impl Default for CodeRegistry {
+    fn default() -> Self {
+        let mut registry = Self { entries: BTreeMap::new() };
+        registry.register(
+            UNRECOGNIZED_SUBCOMMAND,
+            "a subcommand is not recognized",
+            "The argument is in the place of a subcommand but matches none of them. Check the spelling against the suggested subcommands.",
+        );
+        registry.register(
+            MISSING_REQUIRED,
+            "required arguments are missing",
+            "The command cannot run without the arguments that the label asks to append.",
+        );
+        registry.register(
+            STUCK_VALUE,
+            "a cluster of shorts was taken as a value",
+            "A short that takes a value took the rest of its cluster, e.g. `-npspn` gives `spn` to `-p`. Separate the value with a space, or put the short last.",
+        );
+        registry.register(
+            DEPRECATED,
+            "a deprecated argument or alias is used",
+            "The argument or alias still works but will be removed. Switch to the replacement in the help before the removal version.",
+        );
+        registry.register(
+            INVALID_VALUE,
+            "a value is invalid",
+            "The value of the argument was rejected by its value parser. The label tells why, and the help lists the possible values if there are few.",
+        );
+        registry.register(
+            ENV_OVERRIDE,
+            "an argument overrides its env variable",
+            "The argument was given on the command line, so the different value of its env variable is ignored. Unset the env variable or drop the argument.",
+        );
+        registry.register(
+            NEGATION_CONFLICT,
+            "both forms of a `--no-` flag are given",
+            "A flag and its `--no-` form were both given. Only the last one takes effect if one overrides the other, otherwise both do. Drop the one that is not meant.",
+        );
+        registry.register(EXPLANATION, "where an argument came from", "Explains the value of an argument and which source it came from.");
+        registry.register(
+            VALUE_FILE,
+            "a value was read from a file",
+            "The value of the argument was `@file` or `-`, so the problem is in the content of that file or stdin.",
+        );
+        registry.register(UNKNOWN_TOKEN, "a long or short is not known", "The command has no argument with this long or short.");
+        registry.register(
+            INVALID_UNICODE,
+            "an argument is not valid unicode",
+            "The argument cannot be shown as it is. The label lists its raw bytes.",
+        );
+        registry.register(
+            DESYNC,
+            "the Argv does not match the parsed arguments",
+            "The Argv passed to fancy_clap is not what the `ArgMatches` was parsed from, so the spans would point at the wrong characters.",
+        );
+        registry.register(
+            ARGUMENT_CONFLICT,
+            "arguments that conflict are given together",
+            "The arguments under the labels cannot be used together. Drop all but one of them.",
+        );
+
+        registry
+    }
+}
+
+impl CodeRegistry {
+    /// Registers `code` of an application, or replaces the entry of an
+    /// already registered one, and returns the entry.
+    pub fn register(&mut self, code: impl Into<String>, summary: impl Into<String>, explanation: impl Into<String>) -> &mut CodeEntry {
+        let code = code.into();
+        let entry = CodeEntry {
+            code: code.clone(),
+            summary: summary.into(),
+            explanation: explanation.into(),
+        };
+        self.entries.insert(code.clone(), entry);
+        self.entries.get_mut(&code).expect("An entry was just inserted")
+    }
+
+    pub fn get(&self, code: &str) -> Option<&CodeEntry> {
+        self.entries.get(code)
+    }
+
+    /// Returns an advice explaining `code`, for an `--explain CODE`
+    /// argument, or an error if `code` is not registered.
+    #[cfg(feature = "miette")]
+    pub fn explain(&self, code: &str) -> Report {
+        let Some(entry) = self.get(code) else {
+            return Report::from(MietteDiagnostic::new(format!("unknown diagnostic code `{code}`")));
+        };
+
+        Report::from(
+            MietteDiagnostic::new(format!("{}: {}", entry.code, entry.summary))
+                .with_severity(Severity::Advice)
+                .with_help(&entry.explanation),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_code_registry() {
+        let mut registry = CodeRegistry::default();
+        assert_eq!(registry.get(DESYNC).unwrap().summary, "the Argv does not match the parsed arguments");
+        registry.register("myapp::port", "the port is privileged", "Use a port of 1024 or above.");
+        assert_eq!(registry.get("myapp::port").unwrap().summary, "the port is privileged");
+    }
+
+    #[test]
+    #[cfg(feature = "miette")]
+    fn test_explain() {
+        let mut registry = CodeRegistry::default();
+        registry.register("myapp::port", "the port is privileged", "Use a port of 1024 or above.");
+        let report = registry.explain("myapp::port");
+        assert_eq!(report.to_string(), "myapp::port: the port is privileged");
+        assert_eq!(report.help().unwrap().to_string(), "Use a port of 1024 or above.");
+        assert_eq!(report.severity(), Some(Severity::Advice));
+        assert_eq!(registry.explain("myapp::nope").to_string(), "unknown diagnostic code `myapp::nope`");
+    }
+}
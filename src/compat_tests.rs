@@ -0,0 +1,228 @@
+//! A test suite for downstream crates to run against their own
+//! `clap::Command`, which types every long, short and alias of every
+//! argument in the subcommand tree in each spelling that clap accepts
+//! and checks the locations against what clap parsed. Catches clap
+//! settings that the locator does not support before they ship as
+//! misplaced labels. Enabled by the `compat-tests` feature, see
+//! `compat_tests!`.
+
+use std::fmt;
+
+use clap::{builder::PossibleValue, ArgMatches, Command};
+
+use crate::{
+    parse::{all_aliases, classify_arg, ArgAlias, ArgClass},
+    scope::locate_in_scope,
+    sync::check_sync,
+};
+
+/// A spelling of an argument that the locator got wrong. See
+/// `check_command()`.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct CompatFailure {
+    /// Names of the subcommands that own the argument.
+    pub path: Vec<String>,
+    /// `clap::Id` of the argument.
+    pub arg: String,
+    /// The Argv that was located in, including the program name.
+    pub args: Vec<String>,
+    pub reason: String,
+}
+
+impl fmt::Display for CompatFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "`{}` in `{}`: {}", self.arg, self.args.join(" "), self.reason)
+    }
+}
+
+/// Types every long, short and alias of every argument of `command` and
+/// its subcommands, alone, in each spelling: `--name`, or `--name=V`,
+/// `--name V`, `-nV`, `-n=V` and `-n V` if it takes a value. Returns the
+/// spellings whose location does not match what clap parsed. Spellings
+/// that clap rejects, e.g. for a required group or a value that no
+/// candidate satisfies, are not the locator's concern and are skipped.
+pub fn check_command(command: &Command) -> Vec<CompatFailure> {
+    let mut command = relax(command.clone());
+    command.build();
+
+    let mut failures = vec![];
+    let mut stack = vec![(vec![], &command)];
+    while let Some((path, current)) = stack.pop() {
+        for subcommand in current.get_subcommands() {
+            let mut path: Vec<String> = path.clone();
+            path.push(subcommand.get_name().to_owned());
+            stack.push((path, subcommand));
+        }
+        for arg in current.get_arguments() {
+            let class = classify_arg(arg);
+            if class == ArgClass::Positional {
+                continue;
+            }
+            for alias in all_aliases(arg) {
+                for spelling in spellings(&alias, class, &value_candidates(arg)) {
+                    let args = std::iter::once(command.get_name().to_owned()).chain(path.iter().cloned()).chain(spelling).collect::<Vec<_>>();
+                    if let Some(reason) = check_spelling(&command, &path, arg.get_id().as_str(), &alias, &args) {
+                        failures.push(CompatFailure {
+                            path: path.clone(),
+                            arg: arg.get_id().to_string(),
+                            args,
+                            reason,
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    failures
+}
+
+/// Panics with every failure of `check_command()`.
+#[track_caller]
+pub fn assert_compatible(command: &Command) {
+    let failures = check_command(command);
+    if !failures.is_empty() {
+        let failures = failures.iter().map(|failure| format!("  {failure}")).collect::<Vec<_>>();
+        panic!("The locator does not support {} spellings of `{}`:\n{}", failures.len(), command.get_name(), failures.join("\n"));
+    }
+}
+
+/// Defines a `#[test]` that runs `assert_compatible()` for each command.
+///
+/// # Examples
+/// ```
+/// fn command() -> clap::Command {
+///     clap::Command::new("app").arg(clap::Arg::new("port").long("port").short('p'))
+/// }
+///
+/// fancy_clap::compat_tests! {
+///     app_is_compatible: command(),
+/// }
+/// ```
+#[macro_export]
+macro_rules! compat_tests {
+    ($($name:ident: $command:expr),+ $(,)?) => {
+        $(
+            #[test]
+            fn $name() {
+                $crate::compat_tests::assert_compatible(&$command);
+            }
+        )+
+    };
+}
+
+/// Returns `command` without the requirements that would make clap
+/// reject an Argv of a single argument.
+fn relax(command: Command) -> Command {
+    command
+        .subcommand_required(false)
+        .arg_required_else_help(false)
+        .mut_args(|arg| arg.required(false))
+        .mut_subcommands(relax)
+}
+
+/// Values to try for `arg`. Those that clap rejects are skipped by
+/// `check_spelling()`.
+fn value_candidates(arg: &clap::Arg) -> Vec<String> {
+    let mut candidates = arg.get_possible_values().iter().map(PossibleValue::get_name).map(str::to_owned).take(1).collect::<Vec<_>>();
+    candidates.extend(arg.get_default_values().iter().map(|value| value.to_string_lossy().to_string()));
+    candidates.extend(["1", "value"].map(str::to_owned));
+
+    candidates
+}
+
+fn spellings(alias: &ArgAlias, class: ArgClass, values: &[String]) -> Vec<Vec<String>> {
+    let (flag, short) = match alias {
+        ArgAlias::Long(long) => (format!("--{long}"), false),
+        ArgAlias::Short(short) => (format!("-{short}"), true),
+    };
+    let require_equals = match class {
+        ArgClass::Flag => return vec![vec![flag]],
+        ArgClass::Option { require_equals, .. } => require_equals,
+        ArgClass::Positional => return vec![],
+    };
+
+    let mut spellings = vec![];
+    for value in values {
+        spellings.push(vec![format!("{flag}={value}")]);
+        if short && !require_equals {
+            spellings.push(vec![format!("{flag}{value}")]);
+        }
+        if !require_equals {
+            spellings.push(vec![flag.clone(), value.clone()]);
+        }
+    }
+
+    spellings
+}
+
+/// Returns why `arg` is not located correctly in `args`, or `None` if it
+/// is or clap rejects `args`.
+fn check_spelling(command: &Command, path: &[String], arg: &str, alias: &ArgAlias, args: &[String]) -> Option<String> {
+    let matches = command.clone().try_get_matches_from(args).ok()?;
+    let mut matches = &matches;
+    let mut scoped = command;
+    for name in path {
+        matches = subcommand_matches(matches, name)?;
+        scoped = scoped.find_subcommand(name)?;
+    }
+
+    let argv = args.join(" ");
+    let Some(location) = locate_in_scope(command, args, path, arg) else {
+        return Some("not located".to_owned());
+    };
+    if location.typed_alias(&argv).as_ref() != Some(alias) {
+        return Some(format!("located as `{}`", argv.get(location.whole().offset..).unwrap_or_default()));
+    }
+    if let Err(desync) = check_sync(scoped, &args[path.len()..], matches) {
+        return Some(desync.to_string());
+    }
+
+    None
+}
+
+fn subcommand_matches<'a>(matches: &'a ArgMatches, name: &str) -> Option<&'a ArgMatches> {
+    matches.subcommand().filter(|(matched, _)| *matched == name).map(|(_, matches)| matches)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::{Arg, ArgAction};
+
+    fn command() -> Command {
+        Command::new("app")
+            .subcommand_required(true)
+            .arg(Arg::new("verbose").short('v').long("verbose").action(ArgAction::Count).global(true))
+            .arg(Arg::new("port").short('p').long("port").visible_alias("p0rt").value_parser(clap::value_parser!(u16)).required(true))
+            .arg(Arg::new("color").long("color").value_parser(["auto", "never"]).require_equals(true))
+            .subcommand(Command::new("build").arg(Arg::new("jobs").short('j').short_alias('J').action(ArgAction::Append)))
+    }
+
+    compat_tests! {
+        test_compat_tests_macro: command(),
+    }
+
+    #[test]
+    fn test_check_command() {
+        assert_eq!(check_command(&command()), []);
+
+        assert_eq!(spellings(&ArgAlias::Long("color".to_owned()), classify_arg(&Arg::new("color").long("color").require_equals(true)), &["a".to_owned()]), [["--color=a"]]);
+        assert_eq!(spellings(&ArgAlias::Short('j'), ArgClass::Option { min_values: 1, max_values: 1, require_equals: false }, &["a".to_owned()]), [
+            vec!["-j=a"],
+            vec!["-ja"],
+            vec!["-j", "a"],
+        ]);
+    }
+
+    #[test]
+    fn test_check_command_failure() {
+        // Argv that do not type the alias being checked.
+        let mut command = relax(command());
+        command.build();
+        let failure = check_spelling(&command, &[], "port", &ArgAlias::Long("p0rt".to_owned()), &["app".to_owned(), "--port=1".to_owned()]).unwrap();
+        assert_eq!(failure, "located as `--port=1`");
+        let failure = check_spelling(&command, &[], "jobs", &ArgAlias::Short('j'), &["app".to_owned(), "--port=1".to_owned()]).unwrap();
+        assert_eq!(failure, "not located");
+    }
+}
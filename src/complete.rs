@@ -0,0 +1,124 @@
+//! Shell completions generated by `clap_complete` that warn users
+//! about deprecated arguments before they even run the command.
+//! With the `dynamic-complete` feature, the partially typed command
+//! line is also checked while completing it. See `complete_with_checks()`.
+
+use std::io::Write;
+#[cfg(feature = "dynamic-complete")]
+use std::{ffi::OsString, ops::ControlFlow, path::Path};
+
+use clap::Command;
+use clap_complete::Generator;
+
+use crate::deprecation::Deprecations;
+
+/// Same as `clap_complete::generate()` but with the notes of
+/// `deprecations` prepended to the descriptions of the arguments.
+/// See `Deprecations::annotate_help()`.
+pub fn generate_completions<G: Generator>(generator: G, command: Command, deprecations: &Deprecations, bin_name: impl Into<String>, buf: &mut dyn Write) {
+    let mut command = deprecations.annotate_help(command);
+    clap_complete::generate(generator, &mut command, bin_name, buf);
+}
+
+/// Same as `clap_complete::engine::complete()` but the arguments typed
+/// before `arg_index` are located first. The candidates of arguments
+/// that conflict with the typed ones are annotated with the conflict,
+/// and if the typed arguments already conflict with each other, every
+/// candidate is annotated with the problem since the command cannot
+/// succeed before fixing it. Only the arguments of `command` itself
+/// are checked, not those of the subcommands.
+#[cfg(feature = "dynamic-complete")]
+pub fn complete_with_checks(
+    command: &mut Command,
+    args: Vec<OsString>,
+    arg_index: usize,
+    current_dir: Option<&Path>,
+) -> std::io::Result<Vec<clap_complete::engine::CompletionCandidate>> {
+    use crate::parse::{display_name, ArgLocator};
+
+    let typed_args = args[..arg_index.min(args.len())].to_vec();
+    let candidates = clap_complete::engine::complete(command, args, arg_index, current_dir)?;
+    let mut typed = vec![];
+    ArgLocator::from_command(command).visit_locations(typed_args, |id, _| {
+        if let Some(arg) = command.get_arguments().find(|arg| arg.get_id() == id) {
+            typed.push(arg);
+        }
+        ControlFlow::<()>::Continue(())
+    });
+    let conflicts = |a: &clap::Arg, b: &clap::Arg| {
+        a.get_id() != b.get_id()
+            && (command.get_arg_conflicts_with(a).contains(&b) || command.get_arg_conflicts_with(b).contains(&a))
+    };
+
+    let mut problems = vec![];
+    for (index, a) in typed.iter().enumerate() {
+        for b in &typed[index + 1..] {
+            if conflicts(a, b) {
+                problems.push(format!("`{}` cannot be used with `{}`", display_name(a), display_name(b)));
+            }
+        }
+    }
+
+    Ok(candidates
+        .into_iter()
+        .map(|candidate| {
+            let arg = candidate
+                .get_id()
+                .and_then(|id| id.strip_prefix("arg::"))
+                .and_then(|id| command.get_arguments().find(|arg| arg.get_id() == id));
+            let mut notes = problems.clone();
+            if let Some(arg) = arg {
+                notes.extend(typed.iter().filter(|typed| conflicts(arg, typed)).map(|typed| format!("conflicts with `{}`", display_name(typed))));
+            }
+            if notes.is_empty() {
+                return candidate;
+            }
+            let help = candidate.get_help().map(ToString::to_string).unwrap_or_default();
+            let help = format!("[{}] {help}", notes.join("; ")).trim_end().to_owned();
+            candidate.help(Some(help.into()))
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Arg;
+
+    #[test]
+    fn test_generate_completions() {
+        let mut deprecations = Deprecations::default();
+        deprecations.deprecate_arg("colour").replacement("--color");
+        let command = Command::new("app").arg(Arg::new("colour").long("colour").help("Colorize output"));
+
+        let mut buf = vec![];
+        generate_completions(clap_complete::Shell::Fish, command, &deprecations, "app", &mut buf);
+        let script = String::from_utf8(buf).unwrap();
+        assert!(script.contains("[deprecated, use `--color` instead] Colorize output"));
+    }
+
+    #[test]
+    #[cfg(feature = "dynamic-complete")]
+    fn test_complete_with_checks() {
+        use clap::ArgAction;
+
+        let mut command = Command::new("app")
+            .arg(Arg::new("quiet").long("quiet").action(ArgAction::SetTrue).conflicts_with("verbose"))
+            .arg(Arg::new("verbose").long("verbose").action(ArgAction::SetTrue).help("Print more"))
+            .arg(Arg::new("json").long("json").action(ArgAction::SetTrue).conflicts_with("color"))
+            .arg(Arg::new("color").long("color").action(ArgAction::SetTrue));
+        let help = |candidates: &[clap_complete::engine::CompletionCandidate], value: &str| {
+            let candidate = candidates.iter().find(|candidate| candidate.get_value() == value).unwrap();
+            candidate.get_help().map(ToString::to_string)
+        };
+
+        let args = ["app", "--quiet", "--"].map(OsString::from).to_vec();
+        let candidates = complete_with_checks(&mut command, args, 2, None).unwrap();
+        assert_eq!(help(&candidates, "--verbose").as_deref(), Some("[conflicts with `--quiet`] Print more"));
+        assert_eq!(help(&candidates, "--color"), None);
+
+        let args = ["app", "--json", "--color", "--"].map(OsString::from).to_vec();
+        let candidates = complete_with_checks(&mut command, args, 3, None).unwrap();
+        assert_eq!(help(&candidates, "--verbose").as_deref(), Some("[`--json` cannot be used with `--color`] Print more"));
+    }
+}
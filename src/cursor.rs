@@ -0,0 +1,177 @@
+//! What the cursor of a line editor is in, for REPLs that complete the
+//! arguments of a clap command as they are typed. See
+//! `completion_context()`.
+
+use std::ffi::OsString;
+
+use clap::Command;
+
+use crate::{parse::ArgPart, scope::scan_scoped};
+
+/// What the cursor is in according to `completion_context()`.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub enum CursorTarget {
+    /// The hyphens or the name of a long or short. `id` is `None` if it
+    /// is not known, e.g. while it is half typed.
+    Name { id: Option<String> },
+    /// The value of the argument `id`, including a value that was not
+    /// typed yet after an option that takes one.
+    Value { id: String },
+    /// A token that is not a long or short, i.e. a positional argument
+    /// or the name of a subcommand, or the program name.
+    Positional,
+}
+
+/// The argument that the cursor is in and what was typed of it so far.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct CompletionContext {
+    /// Names of the subcommands typed before the cursor, empty for the
+    /// top-level command. See `subcommand_scope()`.
+    pub scope: Vec<String>,
+    /// Index of the token of the cursor in the Argv, which is the
+    /// length of the Argv if the cursor starts a new token.
+    pub token_index: usize,
+    pub target: CursorTarget,
+    /// The text from the start of the target to the cursor, with the
+    /// hyphens for a `CursorTarget::Name`.
+    pub partial: String,
+}
+
+/// Returns what the cursor at the byte `cursor_offset` of `args` joined
+/// by spaces is in. The cursor at the end of a token is in that token,
+/// and the cursor after the space that ends the Argv starts a new one,
+/// e.g. the value of `--port` in `app --port |`.
+///
+/// # Examples
+/// ```
+/// use clap::{Arg, Command};
+/// use fancy_clap::{completion_context, CursorTarget};
+///
+/// let command = Command::new("app").arg(Arg::new("port").long("port"));
+/// let context = completion_context(&command, ["app", "--po"], 8);
+/// assert_eq!(context.target, CursorTarget::Name { id: None });
+/// assert_eq!(context.partial, "--po");
+///
+/// let context = completion_context(&command, ["app", "--port=80"], 12);
+/// assert_eq!(context.target, CursorTarget::Value { id: "port".to_owned() });
+/// assert_eq!(context.partial, "8");
+/// ```
+pub fn completion_context<R>(command: &Command, args: R, cursor_offset: usize) -> CompletionContext
+where
+    R: IntoIterator<Item: Into<OsString>>,
+{
+    let mut args = args.into_iter().map(|arg| arg.into().to_string_lossy().to_string()).collect::<Vec<_>>();
+    let argv_length = args.iter().map(|arg| arg.len() + 1).sum::<usize>().saturating_sub(1);
+    // The cursor after the last space starts a new token, which is
+    // located as an empty one.
+    if args.is_empty() || cursor_offset > argv_length {
+        args.push(String::new());
+    }
+    let argv = args.join(" ");
+    let cursor_offset = cursor_offset.min(argv.len());
+
+    let mut offsets = vec![];
+    let mut offset = 0;
+    for arg in &args {
+        offsets.push(offset);
+        offset += arg.len() + 1;
+    }
+    let token_index = (0..args.len()).find(|&index| cursor_offset <= offsets[index] + args[index].len()).unwrap_or(args.len() - 1);
+    let partial = |from: usize| argv.get(from..cursor_offset).unwrap_or_default().to_owned();
+    let covers = |part: &ArgPart| part.offset <= cursor_offset && cursor_offset <= part.offset + part.length;
+
+    let (located, subcommands) = scan_scoped(command, &args);
+    let mut scope = vec![];
+    let mut current = command;
+    for &index in subcommands.iter().filter(|&&index| index < token_index) {
+        let Some(subcommand) = current.find_subcommand(&args[index]) else {
+            break;
+        };
+        scope.push(subcommand.get_name().to_owned());
+        current = subcommand;
+    }
+
+    let context = |target, partial| CompletionContext {
+        scope: scope.clone(),
+        token_index,
+        target,
+        partial,
+    };
+    let named = located.iter().filter(|located| {
+        let (declaration, name) = (located.location.declaration(), located.location.name());
+        covers(&ArgPart {
+            offset: declaration.offset,
+            length: name.offset + name.length - declaration.offset,
+        })
+    });
+    // In a cluster of shorts, the cursor is in the short before it, or
+    // the first one if it is right after the hyphen.
+    let named = named
+        .clone()
+        .find(|located| located.location.name().offset < cursor_offset && covers(located.location.name()))
+        .or_else(|| named.min_by_key(|located| located.location.name().offset));
+    if let Some(located) = named {
+        return context(CursorTarget::Name { id: Some(located.id.clone()) }, partial(located.location.declaration().offset));
+    }
+    let valued = located.iter().find(|located| located.location.content().is_some_and(covers));
+    if let Some(located) = valued {
+        let content = located.location.content().expect("Found by content");
+        return context(CursorTarget::Value { id: located.id.clone() }, partial(content.offset));
+    }
+
+    // Everything after a `--` that is not a value is positional.
+    let escaped = (1..token_index).any(|index| {
+        args[index] == "--"
+            && !located.iter().any(|located| {
+                let whole = located.location.whole();
+                (whole.offset..whole.offset + whole.length).contains(&offsets[index])
+            })
+    });
+    let token = &args[token_index];
+    let target = match token_index > 0 && !escaped && token.starts_with('-') && token != "-" {
+        true => CursorTarget::Name { id: None },
+        false => CursorTarget::Positional,
+    };
+
+    context(target, partial(offsets[token_index]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::{Arg, ArgAction};
+
+    #[test]
+    fn test_completion_context() {
+        let command = Command::new("app")
+            .arg(Arg::new("verbose").short('v').action(ArgAction::SetTrue))
+            .arg(Arg::new("output").short('o'))
+            .arg(Arg::new("port").long("port"))
+            .subcommand(Command::new("remote").arg(Arg::new("url").long("url")).arg(Arg::new("name")));
+        let at = |args: &[&str], offset| {
+            let context = completion_context(&command, args, offset);
+            (context.scope.join(" "), context.token_index, context.target, context.partial)
+        };
+        let name = |id: &str| CursorTarget::Name { id: Some(id.to_owned()) };
+        let value = |id: &str| CursorTarget::Value { id: id.to_owned() };
+
+        // app --port 80
+        assert_eq!(at(&["app", "--port", "80"], 7), (String::new(), 1, name("port"), "--p".to_owned()));
+        assert_eq!(at(&["app", "--port", "80"], 10), (String::new(), 1, name("port"), "--port".to_owned()));
+        assert_eq!(at(&["app", "--port", "80"], 13), (String::new(), 2, value("port"), "80".to_owned()));
+        // The value of `--port` is not typed yet.
+        assert_eq!(at(&["app", "--port"], 11), (String::new(), 2, value("port"), String::new()));
+        // app -vofile
+        assert_eq!(at(&["app", "-vofile"], 5), (String::new(), 1, name("verbose"), "-".to_owned()));
+        assert_eq!(at(&["app", "-vofile"], 6), (String::new(), 1, name("verbose"), "-v".to_owned()));
+        assert_eq!(at(&["app", "-vofile"], 7), (String::new(), 1, name("output"), "-vo".to_owned()));
+        assert_eq!(at(&["app", "-vofile"], 8), (String::new(), 1, value("output"), "f".to_owned()));
+        // app remote --u
+        assert_eq!(at(&["app", "remote", "--u"], 14), ("remote".to_owned(), 2, CursorTarget::Name { id: None }, "--u".to_owned()));
+        assert_eq!(at(&["app", "remote", "--url", "x", "or"], 21), ("remote".to_owned(), 4, CursorTarget::Positional, "or".to_owned()));
+        // The subcommand itself is in the scope of its parent.
+        assert_eq!(at(&["app", "rem"], 7), (String::new(), 1, CursorTarget::Positional, "rem".to_owned()));
+        assert_eq!(at(&["app", "--", "--p"], 10), (String::new(), 2, CursorTarget::Positional, "--p".to_owned()));
+        assert_eq!(at(&["app"], 2), (String::new(), 0, CursorTarget::Positional, "ap".to_owned()));
+    }
+}
@@ -0,0 +1,262 @@
+//! Registry of deprecated arguments and aliases, so that everything
+//! generated from the `clap::Command` (help, shell completions and
+//! diagnostics) can tell users about them consistently.
+
+#[cfg(feature = "miette")]
+use std::ffi::OsString;
+
+use clap::Command;
+#[cfg(feature = "miette")]
+use miette::{LabeledSpan, MietteDiagnostic, Report, Severity, SourceSpan};
+
+use crate::parse::ArgAlias;
+#[cfg(feature = "miette")]
+use crate::{
+    code,
+    report::{ReportContext, ScopedReport},
+    scope::locate_scoped,
+    suggest::Suggested,
+};
+
+#[derive(Default, Clone, Debug)]
+pub struct Deprecations {
+    pub entries: Vec<Deprecation>,
+}
+
+#[derive(Default, Clone, Debug)]
+pub struct Deprecation {
+    /// `clap::Id` of the deprecated argument, or the argument that owns
+    /// the deprecated alias.
+    pub arg: String,
+    /// The deprecated alias, or `None` if the whole argument is deprecated.
+    pub alias: Option<ArgAlias>,
+    /// What users should type instead, e.g. `--output`.
+    pub replacement: Option<String>,
+    /// The version of the application that removes the argument or alias.
+    pub removal_version: Option<String>,
+}
+
+impl Deprecations {
+    /// Registers `arg` as deprecated and returns the entry for
+    /// filling in the replacement and the removal version.
+    pub fn deprecate_arg(&mut self, arg: impl Into<String>) -> &mut Deprecation {
+        self.push(Deprecation {
+            arg: arg.into(),
+            ..Default::default()
+        })
+    }
+
+    /// Registers `alias` of `arg` as deprecated and returns the entry
+    /// for filling in the replacement and the removal version.
+    pub fn deprecate_alias(&mut self, arg: impl Into<String>, alias: ArgAlias) -> &mut Deprecation {
+        self.push(Deprecation {
+            arg: arg.into(),
+            alias: Some(alias),
+            ..Default::default()
+        })
+    }
+
+    fn push(&mut self, deprecation: Deprecation) -> &mut Deprecation {
+        self.entries.push(deprecation);
+        self.entries.last_mut().expect("An entry was just pushed")
+    }
+
+    /// Returns the deprecations registered for `arg`.
+    pub fn of_arg<'a>(&'a self, arg: &'a str) -> impl Iterator<Item = &'a Deprecation> + 'a {
+        self.entries.iter().filter(move |deprecation| deprecation.arg == arg)
+    }
+
+    /// Returns `command` with the notes of the deprecations prepended
+    /// to the help of the arguments in the whole subcommand tree, which
+    /// is what shell completions and man pages show as descriptions.
+    pub fn annotate_help(&self, command: Command) -> Command {
+        command
+            .mut_args(|arg| {
+                let notes = self.of_arg(arg.get_id().as_str()).map(Deprecation::note).collect::<Vec<_>>();
+                if notes.is_empty() {
+                    return arg;
+                }
+                let help = arg.get_help().map(ToString::to_string).unwrap_or_default();
+                arg.help(format!("[{}] {help}", notes.join("; ")).trim_end().to_owned())
+            })
+            .mut_subcommands(|subcommand| self.annotate_help(subcommand))
+    }
+}
+
+#[cfg(feature = "miette")]
+impl Deprecations {
+    /// Returns a warning for every deprecated argument or alias typed in
+    /// `args`, labelled where it was typed. Deprecated longs, and shorts
+    /// that are not in a cluster, with a replacement carry it as a
+    /// `Suggestion`.
+    pub fn check<R>(&self, command: &Command, args: R) -> Vec<Report>
+    where
+        R: IntoIterator<Item: Into<OsString>>,
+    {
+        self.scan(command, args).into_iter().map(|warning| warning.report).collect()
+    }
+
+    /// Queues the warnings of `Self::check()` in `context`, about the
+    /// deprecated arguments in the subcommands they were typed in, so
+    /// that a single call after parsing reports every deprecated usage.
+    /// Returns the number of warnings queued.
+    pub fn warn_into<R>(&self, context: &mut ReportContext, command: &Command, args: R) -> usize
+    where
+        R: IntoIterator<Item: Into<OsString>>,
+    {
+        let warnings = self.scan(command, args);
+        let count = warnings.len();
+        context.warns.extend(warnings);
+
+        count
+    }
+
+    fn scan<R>(&self, command: &Command, args: R) -> Vec<ScopedReport>
+    where
+        R: IntoIterator<Item: Into<OsString>>,
+    {
+        let args = args.into_iter().map(|arg| arg.into().to_string_lossy().to_string()).collect::<Vec<_>>();
+        let full_command = args.join(" ");
+
+        let mut warnings = vec![];
+        for located in locate_scoped(command, &args) {
+            let location = &located.location;
+            let Some(typed) = location.typed_alias(&full_command) else {
+                continue;
+            };
+            let Some(deprecation) = self.of_arg(&located.id).find(|deprecation| deprecation.alias.as_ref().is_none_or(|alias| *alias == typed)) else {
+                continue;
+            };
+            let name = location.name();
+            let mut diagnostic = MietteDiagnostic::new(format!("`{typed}` is deprecated"))
+                .with_severity(Severity::Warning)
+                .with_code(code::DEPRECATED)
+                .with_label(LabeledSpan::new_primary_with_span(Some("deprecated".to_owned()), SourceSpan::new(name.offset.into(), name.length)));
+            let mut help = vec![];
+            if let Some(replacement) = &deprecation.replacement {
+                help.push(format!("use `{replacement}` instead"));
+            }
+            if let Some(version) = &deprecation.removal_version {
+                help.push(format!("it will be removed in {version}"));
+            }
+            if !help.is_empty() {
+                diagnostic = diagnostic.with_help(help.join(", "));
+            }
+            let mut suggested = Suggested::new(diagnostic).with_source_code(full_command.clone());
+            let declaration = location.declaration();
+            // A short in a cluster cannot be replaced on its own.
+            let alone = match typed {
+                ArgAlias::Long(_) => true,
+                ArgAlias::Short(_) => declaration.offset + declaration.length == name.offset && full_command[name.offset + name.length..].chars().next().is_none_or(|c| c == ' '),
+            };
+            if let (true, Some(replacement)) = (alone, &deprecation.replacement) {
+                let span = SourceSpan::new(declaration.offset.into(), name.offset + name.length - declaration.offset);
+                suggested = suggested.with_suggestion(span, replacement);
+            }
+            warnings.push(ScopedReport {
+                scope: located.scope.clone(),
+                arg: Some(located.id.clone()),
+                report: Report::from(suggested),
+            });
+        }
+
+        warnings
+    }
+}
+
+impl Deprecation {
+    pub fn replacement(&mut self, replacement: impl Into<String>) -> &mut Self {
+        self.replacement = Some(replacement.into());
+        self
+    }
+
+    pub fn removal_version(&mut self, version: impl Into<String>) -> &mut Self {
+        self.removal_version = Some(version.into());
+        self
+    }
+
+    /// Returns a short note like "`--col` is deprecated, use `--color`
+    /// instead, removal in 2.0".
+    pub fn note(&self) -> String {
+        let mut note = match &self.alias {
+            Some(ArgAlias::Long(long)) => format!("`--{long}` is deprecated"),
+            Some(ArgAlias::Short(short)) => format!("`-{short}` is deprecated"),
+            None => "deprecated".to_owned(),
+        };
+        if let Some(replacement) = &self.replacement {
+            note += &format!(", use `{replacement}` instead");
+        }
+        if let Some(version) = &self.removal_version {
+            note += &format!(", removal in {version}");
+        }
+
+        note
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Arg;
+
+    #[test]
+    fn test_annotate_help() {
+        let mut deprecations = Deprecations::default();
+        deprecations.deprecate_arg("colour").replacement("--color").removal_version("2.0");
+        deprecations.deprecate_alias("output", ArgAlias::Short('O'));
+
+        let command = deprecations.annotate_help(
+            Command::new("app")
+                .arg(Arg::new("colour").long("colour").help("Colorize output"))
+                .subcommand(Command::new("build").arg(Arg::new("output").long("output").short_alias('O'))),
+        );
+        let help = |command: &Command, id: &str| {
+            command.get_arguments().find(|arg| arg.get_id() == id).unwrap().get_help().unwrap().to_string()
+        };
+        assert_eq!(help(&command, "colour"), "[deprecated, use `--color` instead, removal in 2.0] Colorize output");
+        assert_eq!(help(command.find_subcommand("build").unwrap(), "output"), "[`-O` is deprecated]");
+    }
+
+    #[test]
+    #[cfg(feature = "miette")]
+    fn test_check() {
+        let mut deprecations = Deprecations::default();
+        deprecations.deprecate_alias("color", ArgAlias::Long("colour".to_owned())).replacement("--color").removal_version("2.0");
+        deprecations.deprecate_alias("output", ArgAlias::Short('O'));
+        let command = Command::new("app")
+            .arg(Arg::new("color").long("color").alias("colour"))
+            .subcommand(Command::new("build").arg(Arg::new("output").long("output").short_alias('O')));
+
+        let warnings = deprecations.check(&command, ["app", "--colour=auto", "build", "-O", "a"]);
+        assert_eq!(warnings.len(), 2);
+        assert_eq!(warnings[0].to_string(), "`--colour` is deprecated");
+        assert_eq!(warnings[0].help().unwrap().to_string(), "use `--color` instead, it will be removed in 2.0");
+        let suggestion = &warnings[0].downcast_ref::<Suggested>().unwrap().suggestions[0];
+        assert_eq!((suggestion.span.offset(), suggestion.span.len()), (4, 8));
+        assert_eq!(warnings[1].to_string(), "`-O` is deprecated");
+        assert!(warnings[1].downcast_ref::<Suggested>().unwrap().suggestions.is_empty());
+
+        assert!(deprecations.check(&command, ["app", "--color=auto", "build", "--output", "a"]).is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "miette")]
+    fn test_warn_into() {
+        let mut deprecations = Deprecations::default();
+        deprecations.deprecate_alias("output", ArgAlias::Short('O')).replacement("--output");
+        let command = Command::new("app").subcommand(
+            Command::new("build")
+                .arg(Arg::new("verbose").short('v').action(clap::ArgAction::SetTrue))
+                .arg(Arg::new("output").long("output").short_alias('O')),
+        );
+
+        let mut context = ReportContext::default();
+        assert_eq!(deprecations.warn_into(&mut context, &command, ["app", "build", "-O", "a", "-vOb"]), 2);
+        assert_eq!(context.warns[0].scope, ["build"]);
+        assert_eq!(context.warns[0].arg.as_deref(), Some("output"));
+        let suggestions = |index: usize| context.warns[index].report.downcast_ref::<Suggested>().unwrap().suggestions.clone();
+        assert_eq!(suggestions(0).iter().map(|suggestion| (suggestion.span.offset(), suggestion.span.len())).collect::<Vec<_>>(), [(10, 2)]);
+        // `-O` in the cluster `-vOb` is not replaced.
+        assert!(suggestions(1).is_empty());
+    }
+}
@@ -0,0 +1,102 @@
+//! Flushing the reports queued in a `ReportContext` when the application
+//! panics or returns early after validating the arguments, so problems
+//! that were already detected are not silently lost. See
+//! `ReportContext::flush_on_exit()`.
+
+use std::ops::{Deref, DerefMut};
+
+use crate::report::{ReportContext, ReportCounts};
+
+/// Owns a `ReportContext` and prints its queued reports to the standard
+/// error when dropped, including while unwinding from a panic, unless
+/// they were already printed by `Self::finish()` or taken back by
+/// `Self::into_inner()`. Dereferences to the context for queuing.
+///
+/// `std::process::exit()` skips destructors, use `Self::exit()` instead.
+/// Nothing is printed if the application is built with `panic = "abort"`.
+pub struct FlushOnExit {
+    context: Option<ReportContext>,
+}
+
+impl ReportContext {
+    /// Returns a guard that prints the queued reports if the application
+    /// panics or drops it without calling `FlushOnExit::finish()`.
+    pub fn flush_on_exit(self) -> FlushOnExit {
+        FlushOnExit { context: Some(self) }
+    }
+}
+
+impl FlushOnExit {
+    /// Same as `ReportContext::finish()`.
+    pub fn finish(mut self) -> ReportCounts {
+        self.take().finish()
+    }
+
+    /// Prints the queued reports and exits with `code`.
+    pub fn exit(self, code: i32) -> ! {
+        self.finish();
+        std::process::exit(code)
+    }
+
+    /// Returns the context without printing its queued reports.
+    pub fn into_inner(mut self) -> ReportContext {
+        self.take()
+    }
+
+    fn take(&mut self) -> ReportContext {
+        self.context.take().expect("The context is only taken by consuming methods")
+    }
+}
+
+impl Deref for FlushOnExit {
+    type Target = ReportContext;
+
+    fn deref(&self) -> &Self::Target {
+        self.context.as_ref().expect("The context is only taken by consuming methods")
+    }
+}
+
+impl DerefMut for FlushOnExit {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.context.as_mut().expect("The context is only taken by consuming methods")
+    }
+}
+
+impl Drop for FlushOnExit {
+    fn drop(&mut self) {
+        let Some(context) = self.context.take() else {
+            return;
+        };
+        if context.counts() == ReportCounts::default() {
+            return;
+        }
+        if std::thread::panicking() {
+            eprintln!("diagnostics queued before the panic:");
+        }
+        context.finish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use miette::MietteDiagnostic;
+
+    #[test]
+    fn test_flush_on_exit() {
+        let mut guard = ReportContext::default().flush_on_exit();
+        guard.warn(MietteDiagnostic::new("deprecated"));
+        guard.error(MietteDiagnostic::new("invalid"));
+        assert_eq!(guard.counts(), ReportCounts { errors: 1, warnings: 1 });
+        let context = guard.into_inner();
+        assert_eq!(context.warns.len(), 1);
+
+        // Dropping while unwinding flushes rather than aborts.
+        let result = std::panic::catch_unwind(|| {
+            let mut guard = ReportContext::default().flush_on_exit();
+            guard.warn(MietteDiagnostic::new("deprecated"));
+            panic!("after the validation");
+        });
+        assert!(result.is_err());
+    }
+}
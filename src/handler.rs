@@ -0,0 +1,246 @@
+//! A `miette` report handler for reports about the Argv string, which
+//! is a single line, unlike the source files that the default layout
+//! of `miette` is made for. Enabled by the `fancy` feature, see
+//! `install_handler()`.
+
+use std::fmt;
+
+use miette::{Diagnostic, InstallError, MietteHandler, MietteHandlerOpts, ReportHandler};
+
+/// The graphical handler of `miette` without the line number gutter
+/// and the frame around sources that are a single line, like:
+/// ```text
+///   × invalid port
+///     app --port=x
+///         ───┬──
+///            ╰── here
+///   help: use a number
+/// ```
+/// Named sources keep their name above the line. Sources of several
+/// lines, e.g. value files, are rendered by `miette` as they are.
+pub struct ArgvHandler {
+    inner: MietteHandler,
+}
+
+impl ArgvHandler {
+    /// Detects the width of the terminal and whether it supports colors
+    /// and unicode like the default handler of `miette`.
+    pub fn new() -> Self {
+        Self::with_opts(MietteHandlerOpts::new())
+    }
+
+    /// Same as `Self::new()` but with the options of `opts`, e.g. a
+    /// theme from `Markers::graphical_theme()`.
+    pub fn with_opts(opts: MietteHandlerOpts) -> Self {
+        Self { inner: opts.build() }
+    }
+}
+
+impl Default for ArgvHandler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ReportHandler for ArgvHandler {
+    fn debug(&self, diagnostic: &dyn Diagnostic, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let rendered = Inner { handler: &self.inner, diagnostic }.to_string();
+
+        f.write_str(&compact(&rendered))
+    }
+}
+
+/// Installs `ArgvHandler` as the handler of every `miette::Report`.
+/// Fails if a handler was already installed.
+pub fn install_handler() -> Result<(), InstallError> {
+    miette::set_hook(Box::new(|_| Box::new(ArgvHandler::new())))
+}
+
+/// Renders `diagnostic` with `handler`.
+struct Inner<'a> {
+    handler: &'a MietteHandler,
+    diagnostic: &'a dyn Diagnostic,
+}
+
+impl fmt::Display for Inner<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.handler.debug(self.diagnostic, f)
+    }
+}
+
+/// Returns `rendered` with every snippet that `snippet()` recognises
+/// compacted, and the rest as it is.
+fn compact(rendered: &str) -> String {
+    let lines = rendered.split('\n').collect::<Vec<_>>();
+    let mut compacted = vec![];
+    let mut index = 0;
+    while index < lines.len() {
+        match snippet(&lines[index..]) {
+            Some((length, snippet)) => {
+                compacted.extend(snippet);
+                index += length;
+            }
+            None => {
+                compacted.push(lines[index].to_owned());
+                index += 1;
+            }
+        }
+    }
+
+    compacted.join("\n")
+}
+
+/// If `lines` start with the snippet of a source that is a single line,
+/// returns the number of lines of the snippet and the lines that
+/// replace them. A snippet of a named source looks like:
+/// ```text
+///    ╭─[name:1:5]
+///  1 │ app --port=x
+///    ·     ───┬──
+///    ·        ╰── here
+///    ╰────
+/// ```
+fn snippet(lines: &[&str]) -> Option<(usize, Vec<String>)> {
+    let [header, numbered, rest @ ..] = lines else {
+        return None;
+    };
+    let header = strip_ansi(header);
+    let indent = header.len() - header.trim_start_matches(' ').len();
+    // Named sources have the position after two drawing characters,
+    // unnamed ones have only the drawing characters.
+    let position = header[indent..].chars().skip(2).collect::<String>();
+    let name = match position.strip_prefix('[') {
+        Some(position) => {
+            let mut parts = position.strip_suffix(']')?.rsplitn(3, ':');
+            let (column, line, name) = (parts.next()?, parts.next()?, parts.next());
+            if line != "1" || column.parse::<usize>().is_err() {
+                return None;
+            }
+            name
+        }
+        None if !header.trim().is_empty() && !header.contains(char::is_alphanumeric) => None,
+        None => return None,
+    };
+
+    // The gutter is two columns wider than the indent, e.g. ` 1 │ `.
+    let gutter = indent + 2;
+    let numbered_gutter = strip_ansi(numbered).chars().take(gutter).collect::<String>();
+    let numbered_parts = numbered_gutter.split_whitespace().collect::<Vec<_>>();
+    if !numbered_gutter.ends_with(' ') || numbered_parts.len() != 2 || numbered_parts[0] != "1" || numbered_parts[1].chars().count() != 1 {
+        return None;
+    }
+    let breaks = rest.iter().take_while(|line| is_break(&strip_ansi(line), indent)).count();
+    let footer = strip_ansi(rest.get(breaks)?);
+    if footer.len() - footer.trim_start_matches(' ').len() != indent || footer.trim().is_empty() || footer.contains(char::is_alphanumeric) {
+        return None;
+    }
+
+    let pad = " ".repeat(indent + 1);
+    let mut compacted = name.map(|name| format!("{pad}[{name}]")).into_iter().collect::<Vec<_>>();
+    for line in std::iter::once(numbered).chain(&rest[..breaks]) {
+        compacted.push(format!("{pad}{}", skip_columns(line, gutter)));
+    }
+
+    Some((breaks + 3, compacted))
+}
+
+/// Returns whether `line` is a line of the labels under the source, like
+/// `   ·     ───┬──`, whose gutter has a drawing character at `indent`.
+fn is_break(line: &str, indent: usize) -> bool {
+    let chars = line.chars().collect::<Vec<_>>();
+    chars.len() > indent + 1 && chars[..indent].iter().all(|&c| c == ' ') && !chars[indent].is_whitespace() && chars[indent + 1] == ' '
+}
+
+/// Returns `line` without its first `columns` characters, not counting
+/// ANSI escape sequences, which are dropped along.
+fn skip_columns(line: &str, columns: usize) -> &str {
+    let mut skipped = 0;
+    let mut chars = line.char_indices().peekable();
+    while skipped < columns {
+        match chars.next() {
+            Some((_, '\u{1b}')) => {
+                for (_, c) in chars.by_ref() {
+                    if c.is_ascii_alphabetic() {
+                        break;
+                    }
+                }
+            }
+            Some(_) => skipped += 1,
+            None => return "",
+        }
+    }
+
+    chars.peek().map_or("", |&(index, _)| &line[index..])
+}
+
+/// Returns `line` without its ANSI escape sequences.
+fn strip_ansi(line: &str) -> String {
+    let mut stripped = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c != '\u{1b}' {
+            stripped.push(c);
+            continue;
+        }
+        for c in chars.by_ref() {
+            if c.is_ascii_alphabetic() {
+                break;
+            }
+        }
+    }
+
+    stripped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use miette::{LabeledSpan, MietteDiagnostic, NamedSource, Report, SourceSpan};
+
+    fn render(handler: &ArgvHandler, report: &Report) -> String {
+        struct Rendered<'a>(&'a ArgvHandler, &'a Report);
+        impl fmt::Display for Rendered<'_> {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                self.0.debug(self.1.as_ref(), f)
+            }
+        }
+
+        Rendered(handler, report).to_string()
+    }
+
+    fn diagnostic() -> MietteDiagnostic {
+        MietteDiagnostic::new("invalid port").with_help("use a number").with_labels([
+            LabeledSpan::new_primary_with_span(Some("here".to_owned()), SourceSpan::new(4.into(), 8)),
+            LabeledSpan::new_with_span(Some("program".to_owned()), SourceSpan::new(0.into(), 3)),
+        ])
+    }
+
+    #[test]
+    fn test_argv_handler() {
+        let opts = || MietteHandlerOpts::new().force_graphical(true).unicode(true).width(80);
+        let handler = ArgvHandler::with_opts(opts().color(false));
+        let report = Report::from(diagnostic()).with_source_code("app --port=x");
+        assert_eq!(render(&handler, &report), [
+            "  × invalid port",
+            "    app --port=x",
+            "    ─┬─ ────┬───",
+            "     │      ╰── here",
+            "     ╰── program",
+            "  help: use a number",
+            "",
+        ].join("\n"));
+
+        let report = Report::from(diagnostic()).with_source_code(NamedSource::new("deploy.sh", "app --port=x".to_owned()));
+        let rendered = render(&handler, &report);
+        assert!(rendered.starts_with("  × invalid port\n    [deploy.sh]\n    app --port=x\n"), "{rendered}");
+
+        // The escape sequences of colors are skipped with the gutter.
+        let rendered = render(&ArgvHandler::with_opts(opts().color(true)), &report);
+        let rendered = strip_ansi(&rendered);
+        assert!(rendered.starts_with("  × invalid port\n    [deploy.sh]\n    app --port=x\n"), "{rendered}");
+
+        // Sources of several lines keep the layout of `miette`.
+        let report = Report::from(diagnostic()).with_source_code("app --port=x\nsecond");
+        assert!(render(&handler, &report).contains(" 1 │ app --port=x\n"));
+    }
+}
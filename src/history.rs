@@ -0,0 +1,189 @@
+//! Auditing how a program is actually called by scanning a shell
+//! history file, or any file of command lines, for its invocations.
+//! Reports can point at the exact line and column in the file, which
+//! also works for an invocation in a shell script. See `invocation_at()`.
+
+use std::ops::Range;
+
+use miette::{LabeledSpan, MietteDiagnostic, Report, SourceSpan};
+
+use crate::{
+    parse::ArgLocator,
+    shell::{split_command_line_with_env, ShellDialect, ShellToken},
+    source::{ScriptSource, SourceProvider},
+};
+
+/// An invocation of the program found by `scan_history()`.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct HistoryEntry {
+    /// Line number in the history, starting from `1`.
+    pub line: usize,
+    /// Range of the command line in the history, excluding the
+    /// timestamps of zsh and fish.
+    pub span: Range<usize>,
+    /// Arguments of the invocation starting from the program name.
+    /// Their ranges are offsets in the whole history.
+    pub tokens: Vec<ShellToken>,
+}
+
+/// Returns the lines of `history` that invoke `program`, split by the
+/// rules of `dialect`. `program` is compared against the file name of
+/// the first argument, so `./target/debug/app` and `app.exe` match `app`.
+///
+/// The extended history format of zsh (`: 1700000000:0;app ...`) and
+/// the history file of fish (`- cmd: app ...`) are recognized. Leading
+/// `NAME=value` env assignments are skipped. Placeholders are kept as
+/// they were typed since the env variables of the auditing process are
+/// not those of the invocation.
+pub fn scan_history<'a>(history: &'a str, program: &'a str, dialect: ShellDialect) -> impl Iterator<Item = HistoryEntry> + 'a {
+    let mut line_start = 0;
+    history.split_inclusive('\n').enumerate().filter_map(move |(index, line)| {
+        let start = line_start;
+        line_start += line.len();
+        let (command_start, command) = strip_history_prefix(line.trim_end_matches(['\r', '\n']));
+
+        let entry = HistoryEntry::new(index + 1, start + command_start, command, dialect);
+        entry.tokens.first().is_some_and(|first| is_program(&first.value, program)).then_some(entry)
+    })
+}
+
+/// Returns the invocation at `range` of `script`, e.g. a line of a
+/// shell script that passes a bad flag, so that reports made by
+/// `HistoryEntry::highlight()` with the whole script point at the
+/// right line. `range` may span lines joined by line continuations.
+/// Placeholders and env assignments are handled like `scan_history()`.
+/// Returns `None` if `range` is out of bounds or not on character
+/// boundaries.
+pub fn invocation_at(script: &str, range: Range<usize>, dialect: ShellDialect) -> Option<HistoryEntry> {
+    let line = script.get(..range.start)?.matches('\n').count() + 1;
+
+    Some(HistoryEntry::new(line, range.start, script.get(range)?, dialect))
+}
+
+impl HistoryEntry {
+    fn new(line: usize, start: usize, command: &str, dialect: ShellDialect) -> Self {
+        let mut tokens = split_command_line_with_env(command, dialect, |_| None);
+        let assignments = tokens.iter().take_while(|token| is_env_assignment(&token.value)).count();
+        tokens.drain(..assignments);
+        for token in &mut tokens {
+            token.shift(start);
+        }
+
+        Self {
+            line,
+            span: start..start + command.len(),
+            tokens,
+        }
+    }
+
+    /// Returns the Argv of the invocation.
+    pub fn args(&self) -> impl Iterator<Item = &str> {
+        self.tokens.iter().map(|token| token.value.as_str())
+    }
+
+    /// Labels where `arg` appears in this invocation and attaches the
+    /// whole `history` named `name` as the source code of `diagnostic`.
+    /// The whole command line is labelled if `arg` cannot be found.
+    pub fn highlight<T, V>(&self, locator: &ArgLocator<T, V>, mut diagnostic: MietteDiagnostic, arg: &str, label: &str, name: &str, history: &str) -> Report
+    where
+        T: Default,
+        V: AsRef<clap::Arg>,
+    {
+        let span = match locator.get_location_in_tokens(&self.tokens, arg) {
+            Some(location) => {
+                let whole = location.whole();
+                SourceSpan::new(whole.offset.into(), whole.length)
+            }
+            None => SourceSpan::new(self.span.start.into(), self.span.len()),
+        };
+        diagnostic.labels.get_or_insert_with(Vec::new).push(LabeledSpan::new_primary_with_span(Some(label.to_owned()), span));
+
+        let source = ScriptSource {
+            name: name.to_owned(),
+            content: history.to_owned(),
+            tokens: self.tokens.clone(),
+        };
+
+        source.attach(Report::from(diagnostic))
+    }
+}
+
+/// Returns the offset and the command line after the timestamps of the
+/// zsh extended history or the fish history.
+fn strip_history_prefix(line: &str) -> (usize, &str) {
+    if let Some(command) = line.strip_prefix("- cmd: ") {
+        return (line.len() - command.len(), command);
+    }
+    let zsh = line
+        .strip_prefix(": ")
+        .and_then(|rest| rest.split_once(';'))
+        .filter(|(timestamps, _)| timestamps.chars().all(|c| c.is_ascii_digit() || c == ':'));
+    match zsh {
+        Some((_, command)) => (line.len() - command.len(), command),
+        None => (0, line),
+    }
+}
+
+fn is_env_assignment(token: &str) -> bool {
+    token.split_once('=').is_some_and(|(name, _)| {
+        !name.is_empty() && !name.starts_with(|c: char| c.is_ascii_digit()) && name.chars().all(|c| c.is_alphanumeric() || c == '_')
+    })
+}
+
+fn is_program(first: &str, program: &str) -> bool {
+    let file_name = first.rsplit(['/', '\\']).next().unwrap_or(first);
+
+    file_name == program || file_name.strip_suffix(".exe") == Some(program)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HISTORY: &str = "ls -la\n: 1700000000:0;./target/debug/app --port 80\nRUST_LOG=1 app --name 'a b'\r\n- cmd: app.exe -p1\napplication --port 1\n";
+
+    #[test]
+    fn test_scan_history() {
+        let entries = scan_history(HISTORY, "app", ShellDialect::Zsh).collect::<Vec<_>>();
+        assert_eq!(entries.iter().map(|entry| entry.line).collect::<Vec<_>>(), [2, 3, 4]);
+        assert_eq!(entries[0].args().collect::<Vec<_>>(), ["./target/debug/app", "--port", "80"]);
+        assert_eq!(&HISTORY[entries[0].span.clone()], "./target/debug/app --port 80");
+        assert_eq!(entries[1].args().collect::<Vec<_>>(), ["app", "--name", "a b"]);
+        assert_eq!(&HISTORY[entries[1].tokens[2].span.clone()], "'a b'");
+        assert_eq!(&HISTORY[entries[2].span.clone()], "app.exe -p1");
+    }
+
+    #[test]
+    fn test_invocation_at() {
+        let script = "#!/bin/zsh\nset -e\nDEBUG=1 app --port 80 \\\n  --name x\n";
+        let start = script.find("DEBUG").unwrap();
+        let entry = invocation_at(script, start..script.len() - 1, ShellDialect::Zsh).unwrap();
+        assert_eq!(entry.line, 3);
+        assert_eq!(entry.args().collect::<Vec<_>>(), ["app", "--port", "80", "--name", "x"]);
+        assert_eq!(&script[entry.tokens[4].span.clone()], "x");
+
+        let locator = ArgLocator::from_command(&clap::Command::new("app").arg(clap::Arg::new("name").long("name")));
+        let report = entry.highlight(&locator, MietteDiagnostic::new("bad name"), "name", "here", "deploy.sh", script);
+        let label = report.labels().unwrap().next().unwrap();
+        assert_eq!(&script[label.offset()..label.offset() + label.len()], "--name x");
+
+        assert_eq!(invocation_at(script, start..script.len() + 1, ShellDialect::Zsh), None);
+        assert_eq!(invocation_at("app --name \u{e9}", 0..12, ShellDialect::Zsh), None);
+    }
+
+    #[test]
+    fn test_highlight() {
+        let command = clap::Command::new("app")
+            .arg(clap::Arg::new("port").short('p').long("port"))
+            .arg(clap::Arg::new("name").long("name"));
+        let locator = ArgLocator::from_command(&command);
+        let entries = scan_history(HISTORY, "app", ShellDialect::Zsh).collect::<Vec<_>>();
+
+        let report = entries[0].highlight(&locator, MietteDiagnostic::new("privileged port"), "port", "here", ".zsh_history", HISTORY);
+        let label = report.labels().unwrap().next().unwrap();
+        assert_eq!(&HISTORY[label.offset()..label.offset() + label.len()], "--port 80");
+        let report = entries[2].highlight(&locator, MietteDiagnostic::new("no name"), "name", "here", ".zsh_history", HISTORY);
+        let label = report.labels().unwrap().next().unwrap();
+        assert_eq!(&HISTORY[label.offset()..label.offset() + label.len()], "app.exe -p1");
+    }
+}
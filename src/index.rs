@@ -0,0 +1,141 @@
+//! Locations of every argument of an Argv collected in one pass, for
+//! programs that label many arguments after parsing. See
+//! `ArgLocator::index()`.
+
+use std::{collections::BTreeMap, ffi::OsString, ops::ControlFlow};
+
+use crate::parse::{ArgLocation, ArgLocator, ArgPart, ArgPartKind};
+
+/// The locations of every occurrence of every known argument of an
+/// Argv, keyed by the `clap::Id`. Looking up an argument does not parse
+/// the Argv again, unlike `ArgLocator::get_location()`.
+#[derive(Default, Clone, Debug)]
+pub struct ArgvIndex {
+    /// Occurrences of every argument in the order they appear.
+    pub locations: BTreeMap<String, Vec<ArgLocation>>,
+}
+
+impl<T: Default, V: AsRef<clap::Arg>> ArgLocator<T, V> {
+    /// Parses `args` once and returns the locations of every argument
+    /// in them. Positional arguments are not located, see
+    /// `Self::locate_positional()`.
+    ///
+    /// # Examples
+    /// ```
+    /// use clap::{Arg, ArgAction, Command};
+    /// use fancy_clap::ArgLocator;
+    ///
+    /// let command = Command::new("app")
+    ///     .arg(Arg::new("verbose").short('v').action(ArgAction::SetTrue))
+    ///     .arg(Arg::new("port").long("port"));
+    /// let index = ArgLocator::from_command(&command).index(["app", "-v", "--port=1"]);
+    /// assert_eq!(index.get("port").unwrap().content().unwrap().offset, 14);
+    /// assert_eq!(index.get_all("verbose").len(), 1);
+    /// ```
+    pub fn index<R>(&self, args: R) -> ArgvIndex
+    where
+        R: IntoIterator<Item: Into<OsString>>,
+    {
+        let args = args.into_iter().map(Into::into).collect::<Vec<OsString>>();
+        let mut index = ArgvIndex::default();
+        self.visit_locations(&args, |id, location| {
+            index.locations.entry(id.to_string()).or_default().push(location);
+            ControlFlow::<()>::Continue(())
+        });
+
+        index
+    }
+}
+
+impl ArgvIndex {
+    /// Same as `ArgLocator::get_location()`.
+    pub fn get(&self, arg: &str) -> Option<&ArgLocation> {
+        self.get_all(arg).first()
+    }
+
+    /// Same as `ArgLocator::get_location_nth()`.
+    pub fn get_nth(&self, arg: &str, n: usize) -> Option<&ArgLocation> {
+        self.get_all(arg).get(n)
+    }
+
+    /// Same as `ArgLocator::get_locations_all()` without the indices,
+    /// which are the positions in the slice.
+    pub fn get_all(&self, arg: &str) -> &[ArgLocation] {
+        self.locations.get(arg).map_or(&[], Vec::as_slice)
+    }
+
+    /// Returns the `clap::Id` of the argument that covers the byte at
+    /// `offset` in the Argv string, and the part of it there, e.g. for
+    /// jumping from a click on the Argv to the argument. The hyphen of a
+    /// cluster of shorts is given to the first short of the cluster.
+    ///
+    /// # Examples
+    /// ```
+    /// use clap::{Arg, Command};
+    /// use fancy_clap::{ArgLocator, ArgPartKind};
+    ///
+    /// let command = Command::new("app").arg(Arg::new("port").long("port"));
+    /// let index = ArgLocator::from_command(&command).index(["app", "--port", "8080"]);
+    /// let (id, kind, part) = index.arg_at(12).unwrap();
+    /// assert_eq!((id, kind, part.offset), ("port", ArgPartKind::Content, 11));
+    /// assert!(index.arg_at(1).is_none());
+    /// ```
+    pub fn arg_at(&self, offset: usize) -> Option<(&str, ArgPartKind, &ArgPart)> {
+        self.locations
+            .iter()
+            .flat_map(|(id, locations)| locations.iter().map(move |location| (id, location)))
+            .filter_map(|(id, location)| {
+                let (kind, part) = location.part_at(offset)?;
+                Some((location.name().offset, (id.as_str(), kind, part)))
+            })
+            .min_by_key(|(name_offset, _)| *name_offset)
+            .map(|(_, found)| found)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::{Arg, ArgAction, Command};
+
+    #[test]
+    fn test_index() {
+        let command = Command::new("app")
+            .arg(Arg::new("verbose").short('v').action(ArgAction::Count))
+            .arg(Arg::new("include").short('I').long("include").action(ArgAction::Append))
+            .arg(Arg::new("port").long("port"))
+            .arg(Arg::new("file"));
+        let locator = ArgLocator::from_command(&command);
+        let args = ["app", "-vIa", "file", "--include", "b", "-vv", "--", "--port"];
+        let index = locator.index(args);
+        for id in ["verbose", "include", "port", "file"] {
+            let all = locator.get_locations_all(args, id).into_iter().map(|(_, location)| location).collect::<Vec<_>>();
+            assert_eq!(index.get_all(id), all);
+            assert_eq!(index.get(id), locator.get_location(args, id).as_ref());
+        }
+        assert_eq!(index.get_all("verbose").len(), 3);
+        assert_eq!(index.get_nth("include", 1), locator.get_location_nth(args, "include", 1).as_ref());
+        assert_eq!(index.get_nth("include", 2), None);
+        assert!(index.get_all("port").is_empty());
+    }
+
+    #[test]
+    fn test_arg_at() {
+        let command = Command::new("app")
+            .arg(Arg::new("verbose").short('v').action(ArgAction::SetTrue))
+            .arg(Arg::new("output").short('o'))
+            .arg(Arg::new("port").long("port"));
+        // app -vofile --port=1
+        let index = ArgLocator::from_command(&command).index(["app", "-vofile", "--port=1"]);
+        let at = |offset| index.arg_at(offset).map(|(id, kind, part)| (id.to_owned(), kind, part.clone()));
+        assert_eq!(at(4), Some(("verbose".to_owned(), ArgPartKind::Declaration, ArgPart { offset: 4, length: 1 })));
+        assert_eq!(at(5), Some(("verbose".to_owned(), ArgPartKind::Name, ArgPart { offset: 5, length: 1 })));
+        assert_eq!(at(6), Some(("output".to_owned(), ArgPartKind::Name, ArgPart { offset: 6, length: 1 })));
+        assert_eq!(at(10), Some(("output".to_owned(), ArgPartKind::Content, ArgPart { offset: 7, length: 4 })));
+        assert_eq!(at(11), None);
+        assert_eq!(at(14).map(|(id, kind, _)| (id, kind)), Some(("port".to_owned(), ArgPartKind::Name)));
+        assert_eq!(at(18).map(|(_, kind, _)| kind), Some(ArgPartKind::Delimiter));
+        assert_eq!(at(19).map(|(_, kind, _)| kind), Some(ArgPartKind::Content));
+        assert_eq!(at(20), None);
+    }
+}
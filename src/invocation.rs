@@ -0,0 +1,153 @@
+//! Echoing the command line with syntax coloring, e.g. at the top of
+//! verbose logs, so operators can see exactly how the tool was invoked.
+
+use std::ffi::OsString;
+
+use anstyle::{AnsiColor, Style};
+use clap::Command;
+
+use crate::{parse::ArgPart, scope::scan_scoped};
+
+/// Styles of the parts of the command line in `render_invocation()`.
+#[derive(Clone, Debug)]
+pub struct InvocationStyles {
+    pub program: Style,
+    /// Hyphens and names of longs and shorts.
+    pub flag: Style,
+    /// Values of longs and shorts, and their equal signs.
+    pub value: Style,
+    pub subcommand: Style,
+    /// The spans passed to `render_invocation()` as errors.
+    pub error: Style,
+}
+
+impl InvocationStyles {
+    /// No styles at all, e.g. for terminals without color support.
+    pub fn plain() -> Self {
+        Self {
+            program: Style::new(),
+            flag: Style::new(),
+            value: Style::new(),
+            subcommand: Style::new(),
+            error: Style::new(),
+        }
+    }
+}
+
+impl Default for InvocationStyles {
+    fn default() -> Self {
+        Self {
+            program: Style::new().bold(),
+            flag: AnsiColor::Cyan.on_default(),
+            value: AnsiColor::Green.on_default(),
+            subcommand: AnsiColor::Magenta.on_default().bold(),
+            error: AnsiColor::Red.on_default().bold().underline(),
+        }
+    }
+}
+
+/// Returns `args` joined by spaces with the program, the arguments of
+/// `command` and its subcommands styled by `styles`. `errors` are spans
+/// in the joined string that are styled as errors over anything else.
+pub fn render_invocation<R>(command: &Command, args: R, errors: &[ArgPart], styles: &InvocationStyles) -> String
+where
+    R: IntoIterator<Item: Into<OsString>>,
+{
+    let args = args.into_iter().map(|arg| arg.into().to_string_lossy().to_string()).collect::<Vec<_>>();
+    let full_command = args.join(" ");
+    let (located, subcommands) = scan_scoped(command, &args);
+
+    // Style of every byte of the joined string.
+    let mut byte_styles = vec![None; full_command.len()];
+    let mut paint = |part: &ArgPart, style: &Style| {
+        let end = (part.offset + part.length).min(byte_styles.len());
+        for byte_style in byte_styles.get_mut(part.offset.min(end)..end).into_iter().flatten() {
+            *byte_style = Some(*style);
+        }
+    };
+    let mut offset = 0;
+    for (index, arg) in args.iter().enumerate() {
+        let part = ArgPart { offset, length: arg.len() };
+        if index == 0 {
+            paint(&part, &styles.program);
+        } else if subcommands.contains(&index) {
+            paint(&part, &styles.subcommand);
+        }
+        offset += arg.len() + 1;
+    }
+    for located in &located {
+        let location = &located.location;
+        paint(location.declaration(), &styles.flag);
+        paint(location.name(), &styles.flag);
+        if let crate::ArgLocation::Complete { delimiter, .. } = location {
+            // A space in between the name and the value is not styled.
+            if full_command.get(delimiter.offset..delimiter.offset + delimiter.length) == Some("=") {
+                paint(delimiter, &styles.value);
+            }
+        }
+        if let Some(content) = location.content() {
+            paint(content, &styles.value);
+        }
+    }
+    for error in errors {
+        paint(error, &styles.error);
+    }
+
+    let mut rendered = String::with_capacity(full_command.len());
+    let mut current = None;
+    for (index, c) in full_command.char_indices() {
+        let style = byte_styles[index];
+        if style != current {
+            if let Some(current) = current {
+                rendered += &current.render_reset().to_string();
+            }
+            if let Some(style) = style {
+                rendered += &style.render().to_string();
+            }
+            current = style;
+        }
+        rendered.push(c);
+    }
+    if let Some(current) = current {
+        rendered += &current.render_reset().to_string();
+    }
+
+    rendered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::{Arg, ArgAction};
+
+    #[test]
+    fn test_render_invocation() {
+        let command = Command::new("app")
+            .arg(Arg::new("verbose").short('v').action(ArgAction::SetTrue))
+            .subcommand(Command::new("push").arg(Arg::new("remote").long("remote")));
+        let args = ["app", "-v", "push", "--remote=origin", "main"];
+        assert_eq!(render_invocation(&command, args, &[], &InvocationStyles::plain()), "app -v push --remote=origin main");
+
+        let styles = InvocationStyles {
+            program: Style::new().bold(),
+            flag: Style::new().italic(),
+            value: Style::new().underline(),
+            subcommand: Style::new().dimmed(),
+            error: Style::new().strikethrough(),
+        };
+        let rendered = render_invocation(&command, args, &[ArgPart { offset: 28, length: 4 }], &styles);
+        let wrap = |style: Style, text: &str| format!("{}{text}{}", style.render(), style.render_reset());
+        assert_eq!(rendered, [
+            wrap(styles.program, "app"),
+            " ".to_owned(),
+            wrap(styles.flag, "-v"),
+            " ".to_owned(),
+            wrap(styles.subcommand, "push"),
+            " ".to_owned(),
+            wrap(styles.flag, "--remote"),
+            wrap(styles.value, "=origin"),
+            " ".to_owned(),
+            wrap(styles.error, "main"),
+        ].concat());
+    }
+}
@@ -0,0 +1,119 @@
+//! A token stream over the Argv with the offsets of every token in the
+//! Argv string, i.e. the arguments joined by spaces, for building
+//! custom analyses on top of `clap_lex` without redoing the offset
+//! bookkeeping. Tokens are classified by their syntax only, so whether
+//! an argument takes the next token as its value is up to the caller.
+
+use std::{ffi::OsString, ops::Range};
+
+/// An argument in the Argv. See `tokenize()`.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct Token {
+    /// Index of the argument in the Argv, where the program name is at `0`.
+    pub index: usize,
+    /// Range of the whole argument in the Argv string.
+    pub span: Range<usize>,
+    pub kind: TokenKind,
+}
+
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub enum TokenKind {
+    /// `--name` or `--name=value`. The ranges exclude the hyphens and
+    /// the equal sign.
+    Long {
+        name: Range<usize>,
+        value: Option<Range<usize>>,
+    },
+    /// `-abc`, which is one or more shorts, possibly followed by the
+    /// value of one of them. The range excludes the hyphen.
+    Shorts { shorts: Range<usize> },
+    /// `--`, after which every argument is a value.
+    Escape,
+    /// Anything else, including `-`, negative numbers and the program name.
+    Value,
+}
+
+/// Splits `args` into tokens with their ranges in the Argv string.
+/// Lengths are counted in bytes of the platform encoding, same as
+/// `ArgLocator::get_location()`.
+///
+/// # Examples
+/// ```
+/// use fancy_clap::lex::{tokenize, TokenKind};
+///
+/// let tokens = tokenize(["program_name", "--port=80", "-v"]);
+/// assert_eq!(tokens[1].kind, TokenKind::Long { name: 15..19, value: Some(20..22) });
+/// assert_eq!(tokens[2].kind, TokenKind::Shorts { shorts: 24..25 });
+/// ```
+pub fn tokenize<R>(args: R) -> Vec<Token>
+where
+    R: IntoIterator<Item: Into<OsString>>,
+{
+    let args = args.into_iter().map(Into::into).collect::<Vec<OsString>>();
+    let raw = clap_lex::RawArgs::new(&args);
+    let mut cursor = raw.cursor();
+    let mut tokens = vec![];
+    let mut offset = 0;
+    let mut escaped = false;
+
+    while let Some(parsed_arg) = raw.next(&mut cursor) {
+        let start = offset;
+        let length = parsed_arg.to_value_os().len();
+        offset += length + 1;
+
+        let kind = if escaped {
+            TokenKind::Value
+        } else if parsed_arg.is_escape() {
+            escaped = true;
+            TokenKind::Escape
+        } else if let Some((name, value)) = parsed_arg.to_long() {
+            let name_length = match name {
+                Ok(name) => name.len(),
+                Err(name) => name.len(),
+            };
+            let name = start + 2..start + 2 + name_length;
+            TokenKind::Long {
+                value: value.map(|value| name.end + 1..name.end + 1 + value.len()),
+                name,
+            }
+        } else if parsed_arg.is_negative_number() {
+            TokenKind::Value
+        } else if parsed_arg.to_short().is_some() {
+            TokenKind::Shorts { shorts: start + 1..start + length }
+        } else {
+            TokenKind::Value
+        };
+        tokens.push(Token {
+            index: tokens.len(),
+            span: start..start + length,
+            kind,
+        });
+    }
+
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokenize() {
+        let args = ["program_name", "--port", "80", "-vp1", "-", "-5", "--", "--not-long"];
+        let kinds = tokenize(args).into_iter().map(|token| token.kind).collect::<Vec<_>>();
+        assert_eq!(kinds, [
+            TokenKind::Value,
+            TokenKind::Long { name: 15..19, value: None },
+            TokenKind::Value,
+            TokenKind::Shorts { shorts: 24..27 },
+            TokenKind::Value,
+            TokenKind::Value,
+            TokenKind::Escape,
+            TokenKind::Value,
+        ]);
+        let argv = args.join(" ");
+        for token in tokenize(args) {
+            assert_eq!(&argv[token.span], args[token.index]);
+        }
+    }
+}
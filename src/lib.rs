@@ -1,4 +1,96 @@
+#[cfg(feature = "miette")]
+mod augment;
+mod caret;
+#[cfg(any(test, feature = "span-checks"))]
+mod check;
+pub mod code;
+#[cfg(feature = "compat-tests")]
+pub mod compat_tests;
+#[cfg(feature = "complete")]
+mod complete;
+mod cursor;
+mod deprecation;
+#[cfg(feature = "miette")]
+mod flush;
+#[cfg(feature = "fancy")]
+mod handler;
+#[cfg(feature = "miette")]
+mod history;
+mod index;
+mod invocation;
+pub mod lex;
+mod locations;
+mod lossy;
+#[cfg(feature = "mangen")]
+mod mangen;
+mod metadata;
+mod negation;
 mod parse;
+#[cfg(feature = "miette")]
+mod parser;
+mod plan;
+mod preprocess;
+mod provenance;
+#[cfg(feature = "miette")]
+mod render;
+#[cfg(feature = "miette")]
+mod report;
+mod scope;
+mod shell;
+#[cfg(feature = "miette")]
+mod source;
+#[cfg(feature = "miette")]
+mod suggest;
+mod sync;
+pub mod testing;
+mod theme;
+mod width;
+mod wrap;
+
+#[cfg(feature = "miette")]
+pub use augment::{augment_diagnostic_format, augment_explain, augment_no_warnings, exit_on_explain, explain, DIAGNOSTIC_FORMAT_ID, EXPLAIN_ID, NO_WARNINGS_ENV, NO_WARNINGS_ID};
+pub use caret::{render_carets, render_carets_styled, CaretLevel, CaretStyles};
+#[cfg(feature = "complete")]
+pub use complete::generate_completions;
+#[cfg(feature = "dynamic-complete")]
+pub use complete::complete_with_checks;
+pub use cursor::{completion_context, CompletionContext, CursorTarget};
+pub use deprecation::{Deprecation, Deprecations};
+#[cfg(feature = "miette")]
+pub use flush::FlushOnExit;
+#[cfg(feature = "fancy")]
+pub use handler::{install_handler, ArgvHandler};
+#[cfg(feature = "miette")]
+pub use history::{invocation_at, scan_history, HistoryEntry};
+pub use index::ArgvIndex;
+pub use invocation::{render_invocation, InvocationStyles};
+pub use locations::Locations;
+pub use lossy::LossyArgv;
+#[cfg(feature = "mangen")]
+pub use mangen::man_page;
+pub use metadata::{ArgMetadata, ArgMetadataRegistry, Stability};
+pub use negation::{negation_conflicts, negation_pairs, NegationConflict, NegationPair};
+#[cfg(feature = "miette")]
+pub use negation::negation_reports;
+pub use parse::{classify_arg, ArgAlias, ArgClass, ArgLocation, ArgLocator, ArgPart, ArgPartKind, ClapAliasesMapper, GetArgByAlias, InvalidUnicode, LocateScratch, LocateStrategy, OffsetUnit, TokenLocation, UnknownToken, UnknownTokenPolicy};
+#[cfg(feature = "miette")]
+pub use parser::{error_report, FancyCommand, FancyParser};
+pub use plan::{LocationPlan, SearchStrategy};
+pub use preprocess::ArgvMap;
+pub use provenance::{provenance, provenances, Provenance};
+#[cfg(feature = "miette")]
+pub use report::{ArgHighlighter, DiagnosticFormat, FallbackFormat, FallbackNote, ReportContext, ReportCounts, ScopedReport, ValueFile, Verbosity};
+pub use scope::{locate_in_scope, locate_scoped, subcommand_scope, ScopedLocation};
+pub use shell::{remap_location, split_command_line, split_command_line_with_env, split_windows_command_line, to_source_string, ShellDialect, ShellToken};
+#[cfg(windows)]
+pub use shell::raw_command_line;
+#[cfg(feature = "miette")]
+pub use source::{ArgvSource, EnvSource, FileSource, ScriptSource, SourceProvider, SourceRegistry};
+#[cfg(feature = "miette")]
+pub use suggest::{env_override_advisories, missing_required_hint, stuck_value_advisories, suggest_subcommand, Suggested, Suggestion};
+pub use sync::{check_sync, Desync};
+pub use theme::Markers;
+pub use wrap::{LinePosition, WrappedArgv};
 
 /// Credit: SOF3
 #[macro_export]
@@ -12,55 +104,3 @@ macro_rules! field {
         stringify!($field)
     }};
 }
-
-#[derive(Default)]
-pub struct ReportContext {
-    pub arg_highlighter: ArgHighlighter,
-    pub warns: Option<Report>,
-    pub errs: Option<Report>,
-}
-
-#[derive(Default)]
-pub struct ArgHighlighter {
-    arg_aliases: Option<BTreeMap<ArgAlias, Rc<clap::Arg>>>,
-    pub include_arg_name: bool,
-    pub force_lossy_os_string: bool,
-}
-
-#[derive(Ord, PartialOrd, Eq, PartialEq, Debug)]
-enum ArgAlias {
-    Long(String),
-    Short(char),
-}
-
-    pub fn highlight<T: CommandFactory>(&mut self, mut diagnostic: MietteDiagnostic, arg: &str, label: &str, fallback_label: &str) -> Report {
-        let matches = T::command().get_matches();
-
-        match matches.value_source(arg) {
-            Some(ValueSource::EnvVariable) => {
-                diagnostic.help = diagnostic.help
-                    .map_or(Some(String::new()), |help| Some(help + "; "))
-                    .map(|help| help + "This arg came from env");
-            }
-            Some(ValueSource::CommandLine) => {
-                let args = std::env::args_os().map(|arg| arg.to_string_lossy().to_string()).collect::<Vec<_>>();
-                let full_command = args.join(" ");
-                println!("{:#?}", T::command().get_matches().ids());
-                let offset = self.get_arg_location::<T, _>(args, matches, arg);
-                let label = LabeledSpan::new_primary_with_span(Some(label.to_owned()), SourceSpan::new(SourceOffset::from_location(&full_command, 0, offset.start), 0));
-
-                if let Some(labels) = &mut diagnostic.labels {
-                    labels.push(label);
-                } else {
-                    diagnostic.labels = Some(vec![label]);
-                }
-
-                return Report::from(diagnostic).with_source_code(full_command);
-            },
-
-
-            _ => (),
-        };
-        diagnostic.message += fallback_label;
-        return Report::from(diagnostic);
-    }
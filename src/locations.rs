@@ -0,0 +1,138 @@
+//! A lazy iterator over the locations of the arguments of an Argv. See
+//! `ArgLocator::iter_locations()`.
+
+use std::{collections::VecDeque, ffi::OsString, ops::ControlFlow};
+
+use crate::parse::{ArgLocation, ArgLocator, UnitConverter, UnknownTokenPolicy, Walker};
+
+/// Iterator returned by `ArgLocator::iter_locations()`. Tokens of the
+/// Argv are parsed as the iterator advances, so dropping it stops the
+/// parsing.
+pub struct Locations<'a, T: Default, V: AsRef<clap::Arg>> {
+    locator: &'a ArgLocator<T, V>,
+    walker: Walker,
+    /// Length of the wrapper tokens that the walker does not see.
+    shift: usize,
+    /// Locations found in the last token but not returned yet, e.g. the
+    /// rest of a cluster of shorts.
+    pending: VecDeque<(V, ArgLocation)>,
+    /// Converts the locations to `ArgLocator::offset_unit`.
+    converter: UnitConverter,
+    #[cfg(any(test, feature = "span-checks"))]
+    args: Vec<OsString>,
+}
+
+impl<T: Default, V: AsRef<clap::Arg>> ArgLocator<T, V> {
+    /// Returns an iterator over every argument in `args` that the
+    /// locator knows and its location, in the order they appear. Unlike
+    /// `Self::visit_locations()`, the caller drives the parsing, so
+    /// taking the first match or streaming the locations into a
+    /// renderer needs neither a `Vec` nor a `ControlFlow`. Positional
+    /// arguments are not located, see `Self::locate_positional()`.
+    ///
+    /// # Examples
+    /// ```
+    /// use clap::{Arg, ArgAction, Command};
+    /// use fancy_clap::ArgLocator;
+    ///
+    /// let command = Command::new("app")
+    ///     .arg(Arg::new("verbose").short('v').action(ArgAction::SetTrue))
+    ///     .arg(Arg::new("port").long("port"));
+    /// let locator = ArgLocator::from_command(&command);
+    /// let (port, location) = locator
+    ///     .iter_locations(["app", "-v", "--port=1"])
+    ///     .find(|(found, _)| found.get_id() == "port")
+    ///     .unwrap();
+    /// assert_eq!(port.get_long(), Some("port"));
+    /// assert_eq!(location.content().unwrap().offset, 14);
+    /// ```
+    pub fn iter_locations<R>(&self, args: R) -> Locations<'_, T, V>
+    where
+        R: IntoIterator<Item: Into<OsString>>,
+    {
+        let args = args.into_iter().map(Into::into).collect::<Vec<OsString>>();
+        let (unwrapped, shift) = self.unwrap_args(&args);
+        Locations {
+            locator: self,
+            walker: Walker::new(unwrapped),
+            shift,
+            pending: VecDeque::new(),
+            converter: self.unit_converter(&args),
+            #[cfg(any(test, feature = "span-checks"))]
+            args,
+        }
+    }
+}
+
+impl<T: Default, V: AsRef<clap::Arg>> Iterator for Locations<'_, T, V> {
+    type Item = (V, ArgLocation);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.pending.is_empty() {
+            let locator = self.locator;
+            let shift = self.shift as isize;
+            let pending = &mut self.pending;
+            let step = self.walker.step(
+                &|alias| locator.lookup(alias),
+                &mut |found, location: ArgLocation| {
+                    pending.push_back((found, location.shift(shift).expect("Shifted forward")));
+                    ControlFlow::<()>::Continue(())
+                },
+                &mut |_, _| match locator.unknown_tokens {
+                    UnknownTokenPolicy::Error => ControlFlow::Break(()),
+                    UnknownTokenPolicy::Skip | UnknownTokenPolicy::CollectAndContinue => ControlFlow::Continue(()),
+                },
+                &mut |_, _| (),
+            );
+            if step.is_break() {
+                break;
+            }
+        }
+
+        let (found, location) = self.pending.pop_front()?;
+        #[cfg(any(test, feature = "span-checks"))]
+        crate::check::check_location(&self.args, &location);
+        Some((found, self.converter.location(&location)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::{Arg, ArgAction, Command};
+
+    #[test]
+    fn test_iter_locations() {
+        let command = Command::new("app")
+            .arg(Arg::new("verbose").short('v').action(ArgAction::Count))
+            .arg(Arg::new("include").short('I').long("include").action(ArgAction::Append))
+            .arg(Arg::new("port").long("port"))
+            .arg(Arg::new("file"));
+        let locator = ArgLocator::from_command(&command);
+        let args = ["app", "-vIa", "file", "--include", "b", "-vv", "--", "--port"];
+        let mut visited = vec![];
+        locator.visit_locations(args, |id, location| {
+            visited.push((id.to_string(), location));
+            ControlFlow::<()>::Continue(())
+        });
+        let iterated = locator
+            .iter_locations(args)
+            .map(|(found, location)| (found.get_id().to_string(), location))
+            .collect::<Vec<_>>();
+        assert_eq!(iterated, visited);
+        assert_eq!(iterated.len(), 5);
+
+        // Locations after an unknown token are not returned if the
+        // policy is to fail there.
+        let mut locator = ArgLocator::from_command(&command);
+        locator.unknown_tokens = UnknownTokenPolicy::Error;
+        let ids = locator.iter_locations(["app", "-v", "--unknown", "--port", "1"]).map(|(found, _)| found.get_id().to_string()).collect::<Vec<_>>();
+        assert_eq!(ids, ["verbose"]);
+
+        // Wrapper tokens are skipped and shift the locations.
+        let mut locator = ArgLocator::from_command(&command);
+        locator.wrapper_tokens = 1;
+        let (_, location) = locator.iter_locations(["sudo", "app", "--port", "1"]).next().unwrap();
+        assert_eq!(location.content().unwrap().offset, 16);
+    }
+}
@@ -0,0 +1,156 @@
+//! Labelling an Argv that is not valid unicode. Locating the arguments
+//! in the raw bytes and rendering them with replacement characters
+//! moves every span after an invalid sequence, since a sequence of one
+//! byte is rendered as the three bytes of `U+FFFD`.
+
+use std::ffi::OsString;
+
+use crate::parse::{ArgLocation, ArgLocator, ArgPart, UnitConverter};
+
+/// The Argv rendered with `U+FFFD` for every invalid sequence, like
+/// `String::from_utf8_lossy`, and a table that translates offsets in
+/// the raw Argv, i.e. the `OsStr::as_encoded_bytes` of the arguments
+/// joined by spaces, to offsets in the rendered one. Unlike
+/// `ArgLocator::force_lossy_os_string`, the arguments are parsed as
+/// they are, so an invalid sequence never changes how they are parsed.
+#[derive(Clone, Debug)]
+pub struct LossyArgv {
+    args: Vec<OsString>,
+    /// The rendered Argv, which the translated locations are in.
+    pub lossy: String,
+    /// Every invalid sequence as its part in the raw Argv and the part
+    /// of its replacement character in the rendered one, in order.
+    replaced: Vec<(ArgPart, ArgPart)>,
+}
+
+impl LossyArgv {
+    pub fn new<R>(args: R) -> Self
+    where
+        R: IntoIterator<Item: Into<OsString>>,
+    {
+        let args = args.into_iter().map(Into::into).collect::<Vec<OsString>>();
+        let mut lossy = String::new();
+        let mut replaced = vec![];
+        let mut raw_offset = 0;
+        for (index, arg) in args.iter().enumerate() {
+            if index > 0 {
+                lossy.push(' ');
+                raw_offset += 1;
+            }
+            for chunk in arg.as_encoded_bytes().utf8_chunks() {
+                lossy.push_str(chunk.valid());
+                raw_offset += chunk.valid().len();
+                if chunk.invalid().is_empty() {
+                    continue;
+                }
+                let raw = ArgPart {
+                    offset: raw_offset,
+                    length: chunk.invalid().len(),
+                };
+                let rendered = ArgPart {
+                    offset: lossy.len(),
+                    length: char::REPLACEMENT_CHARACTER.len_utf8(),
+                };
+                lossy.push(char::REPLACEMENT_CHARACTER);
+                raw_offset += raw.length;
+                replaced.push((raw, rendered));
+            }
+        }
+
+        Self { args, lossy, replaced }
+    }
+
+    /// Whether the Argv is valid unicode, in which case the offsets are
+    /// the same in both.
+    pub fn is_lossless(&self) -> bool {
+        self.replaced.is_empty()
+    }
+
+    /// Locates `arg` in the raw Argv and returns the location in the
+    /// rendered one, in the `ArgLocator::offset_unit` of `locator`. See
+    /// `Self::remap_location()`.
+    pub fn get_location<T, V, A>(&self, locator: &ArgLocator<T, V>, arg: &A) -> Option<ArgLocation>
+    where
+        T: Default,
+        V: AsRef<clap::Arg>,
+        A: PartialEq<clap::Id> + ?Sized,
+    {
+        let location = locator.locate_bytes(&self.args, arg)?;
+
+        Some(UnitConverter::of_text(locator.offset_unit, &self.lossy).location(&self.remap_location(&location)))
+    }
+
+    /// Converts `location` in the raw Argv to the rendered one.
+    pub fn remap_location(&self, location: &ArgLocation) -> ArgLocation {
+        location.map_parts(|part| self.remap_part(part))
+    }
+
+    /// Same as `Self::remap_location()` but for a single part. A part
+    /// that starts or ends within an invalid sequence covers all of its
+    /// replacement character.
+    pub fn remap_part(&self, part: &ArgPart) -> ArgPart {
+        let start = self.remap_offset(part.offset, false);
+        let end = self.remap_offset(part.offset + part.length, true);
+
+        ArgPart {
+            offset: start,
+            length: end.saturating_sub(start),
+        }
+    }
+
+    /// Converts an offset in the raw Argv. An offset within an invalid
+    /// sequence moves to the end of its replacement if `end` is set,
+    /// and to the start of it otherwise.
+    fn remap_offset(&self, offset: usize, end: bool) -> usize {
+        let mut delta = 0_isize;
+        for (raw, rendered) in &self.replaced {
+            if offset <= raw.offset {
+                break;
+            }
+            if offset < raw.offset + raw.length {
+                return rendered.offset + if end { rendered.length } else { 0 };
+            }
+            delta += rendered.length as isize - raw.length as isize;
+        }
+
+        offset.saturating_add_signed(delta)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_lossy_argv() {
+        use std::os::unix::ffi::OsStringExt;
+
+        use clap::{Arg, Command};
+
+        let command = Command::new("app").arg(Arg::new("name").long("name")).arg(Arg::new("port").long("port"));
+        let locator = ArgLocator::from_command(&command);
+        let args = [
+            OsString::from("app"),
+            OsString::from_vec(b"--name=a\xff\xfeb".to_vec()),
+            OsString::from("--port"),
+            OsString::from_vec(b"\xc3".to_vec()),
+        ];
+        let argv = LossyArgv::new(args.clone());
+        assert!(!argv.is_lossless());
+        assert_eq!(argv.lossy, "app --name=a\u{fffd}\u{fffd}b --port \u{fffd}");
+        assert_eq!(argv.lossy, args.iter().map(|arg| arg.to_string_lossy()).collect::<Vec<_>>().join(" "));
+
+        let text = |part: &ArgPart| &argv.lossy[part.offset..part.offset + part.length];
+        let name = argv.get_location(&locator, "name").unwrap();
+        assert_eq!(text(name.content().unwrap()), "a\u{fffd}\u{fffd}b");
+        // Parts after the invalid sequences do not drift.
+        let port = argv.get_location(&locator, "port").unwrap();
+        assert_eq!(text(port.name()), "port");
+        assert_eq!(text(port.content().unwrap()), "\u{fffd}");
+        // A part within an invalid sequence covers its replacement.
+        assert_eq!(text(&argv.remap_part(&ArgPart { offset: 13, length: 1 })), "\u{fffd}");
+
+        assert!(LossyArgv::new(["app", "--port=1"]).is_lossless());
+    }
+}
@@ -0,0 +1,77 @@
+//! Man pages generated by `clap_mangen` that carry the same deprecation
+//! and provenance notes as the diagnostics at runtime.
+
+use clap::{Arg, Command};
+
+use crate::deprecation::Deprecations;
+
+/// Returns a `clap_mangen::Man` of `command` with the notes of
+/// `deprecations` prepended and the provenance notes appended to the
+/// help of every argument. See `Deprecations::annotate_help()`.
+pub fn man_page(command: Command, deprecations: &Deprecations) -> clap_mangen::Man {
+    clap_mangen::Man::new(annotate_provenance(deprecations.annotate_help(command)))
+}
+
+fn annotate_provenance(command: Command) -> Command {
+    command
+        .mut_args(|arg| {
+            let Some(note) = provenance_note(&arg) else {
+                return arg;
+            };
+            let help = arg.get_help().map(ToString::to_string).unwrap_or_default();
+            arg.help(format!("{help} {note}").trim_start().to_owned())
+        })
+        .mut_subcommands(annotate_provenance)
+}
+
+/// Returns a note like "The value is taken from the command line, then
+/// the `PORT` env variable, then the default `80`." or `None` if the
+/// argument can only come from the command line. Respects
+/// `Arg::hide_env()` and `Arg::hide_default_value()`.
+fn provenance_note(arg: &Arg) -> Option<String> {
+    let mut sources = vec![];
+    if let Some(env) = arg.get_env().filter(|_| !arg.is_hide_env_set()) {
+        sources.push(format!("the `{}` env variable", env.to_string_lossy()));
+    }
+    let defaults = arg.get_default_values();
+    if !defaults.is_empty() && !arg.is_hide_default_value_set() {
+        let defaults = defaults.iter().map(|value| format!("`{}`", value.to_string_lossy())).collect::<Vec<_>>();
+        sources.push(format!("the default {}", defaults.join(" ")));
+    }
+    if sources.is_empty() {
+        return None;
+    }
+
+    Some(format!("The value is taken from the command line, then {}.", sources.join(", then ")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_provenance_note() {
+        let arg = Arg::new("port").long("port").env("PORT").default_value("80");
+        assert_eq!(
+            provenance_note(&arg).unwrap(),
+            "The value is taken from the command line, then the `PORT` env variable, then the default `80`.",
+        );
+        assert_eq!(provenance_note(&arg.clone().hide_env(true).hide_default_value(true)), None);
+        assert_eq!(provenance_note(&Arg::new("host").long("host")), None);
+    }
+
+    #[test]
+    fn test_man_page() {
+        let mut deprecations = Deprecations::default();
+        deprecations.deprecate_arg("colour").replacement("--color");
+        let command = Command::new("app")
+            .arg(Arg::new("colour").long("colour").help("Colorize output"))
+            .arg(Arg::new("port").long("port").env("PORT"));
+
+        let mut buf = vec![];
+        man_page(command, &deprecations).render(&mut buf).unwrap();
+        let page = String::from_utf8(buf).unwrap();
+        assert!(page.contains("deprecated, use"));
+        assert!(page.contains("PORT"));
+    }
+}
@@ -0,0 +1,129 @@
+//! Extra information that applications associate with their arguments,
+//! pulled into the reports about them. See `ArgMetadataRegistry`.
+
+use std::{collections::BTreeMap, fmt};
+
+#[cfg(feature = "miette")]
+use miette::MietteDiagnostic;
+
+/// How stable an argument is, noted in the reports about it unless it
+/// is `Stability::Stable`.
+#[derive(Ord, PartialOrd, Eq, PartialEq, Clone, Copy, Debug)]
+pub enum Stability {
+    Stable,
+    Unstable,
+    Experimental,
+    Deprecated,
+}
+
+impl fmt::Display for Stability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Stable => "stable",
+            Self::Unstable => "unstable",
+            Self::Experimental => "experimental",
+            Self::Deprecated => "deprecated",
+        })
+    }
+}
+
+/// What an application knows about one of its arguments beyond clap.
+#[derive(Eq, PartialEq, Default, Clone, Debug)]
+pub struct ArgMetadata {
+    /// The docs page of the argument.
+    pub doc_url: Option<String>,
+    pub stability: Option<Stability>,
+    /// The team or person that owns the argument, for operators to know
+    /// who to ask.
+    pub owner: Option<String>,
+    /// Prefixed to the codes of the reports about the argument that are
+    /// not namespaced yet, e.g. `myapp::net` for `port` makes the code
+    /// `myapp::net::port`.
+    pub code_namespace: Option<String>,
+}
+
+/// The metadata of the arguments of an application, keyed by the
+/// argument id. `ArgHighlighter` enriches the reports it builds with it.
+#[derive(Default)]
+pub struct ArgMetadataRegistry {
+    pub args: BTreeMap<String, ArgMetadata>,
+}
+
+impl ArgMetadataRegistry {
+    /// Registers `metadata` for `arg`, replacing the one registered
+    /// before if any.
+    pub fn register(&mut self, arg: impl Into<String>, metadata: ArgMetadata) {
+        self.args.insert(arg.into(), metadata);
+    }
+
+    pub fn get(&self, arg: &str) -> Option<&ArgMetadata> {
+        self.args.get(arg)
+    }
+
+    /// Returns `diagnostic` about `arg` with its code namespaced and
+    /// notes on the stability and the owner appended to the help. The
+    /// docs page becomes the `Diagnostic::url()` if `hyperlinks` is set,
+    /// e.g. for a terminal that supports OSC-8 hyperlinks, and is
+    /// appended to the help otherwise. The fields that were already set
+    /// are kept.
+    #[cfg(feature = "miette")]
+    pub fn enrich(&self, arg: &str, mut diagnostic: MietteDiagnostic, hyperlinks: bool) -> MietteDiagnostic {
+        let Some(metadata) = self.get(arg) else {
+            return diagnostic;
+        };
+        if let (Some(namespace), Some(code)) = (&metadata.code_namespace, &diagnostic.code) {
+            if !code.contains("::") {
+                diagnostic.code = Some(format!("{namespace}::{code}"));
+            }
+        }
+        let mut notes = vec![];
+        if let Some(stability) = metadata.stability.filter(|stability| *stability != Stability::Stable) {
+            notes.push(format!("`{arg}` is {stability}"));
+        }
+        if let Some(owner) = &metadata.owner {
+            notes.push(format!("owned by {owner}"));
+        }
+        match &metadata.doc_url {
+            Some(url) if hyperlinks => {
+                diagnostic.url.get_or_insert_with(|| url.clone());
+            }
+            Some(url) => notes.push(format!("docs: {url}")),
+            None => (),
+        }
+        for note in notes {
+            diagnostic.help = Some(match diagnostic.help {
+                Some(help) => format!("{help}\n{note}"),
+                None => note,
+            });
+        }
+
+        diagnostic
+    }
+}
+
+#[cfg(all(test, feature = "miette"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_enrich() {
+        let mut registry = ArgMetadataRegistry::default();
+        registry.register("port", ArgMetadata {
+            doc_url: Some("https://example.com/port".to_owned()),
+            stability: Some(Stability::Experimental),
+            owner: Some("net team".to_owned()),
+            code_namespace: Some("myapp::net".to_owned()),
+        });
+        let diagnostic = MietteDiagnostic::new("privileged port").with_code("port");
+
+        let enriched = registry.enrich("port", diagnostic.clone(), false);
+        assert_eq!(enriched.code.as_deref(), Some("myapp::net::port"));
+        assert_eq!(enriched.help.as_deref(), Some("`port` is experimental\nowned by net team\ndocs: https://example.com/port"));
+        assert_eq!(enriched.url, None);
+
+        let enriched = registry.enrich("port", diagnostic.clone().with_code("other::port"), true);
+        assert_eq!(enriched.code.as_deref(), Some("other::port"));
+        assert_eq!(enriched.url.as_deref(), Some("https://example.com/port"));
+        assert_eq!(registry.enrich("name", diagnostic.clone(), false), diagnostic);
+    }
+}
@@ -0,0 +1,247 @@
+//! Paired flags like `--color` and `--no-color`, which users tend to
+//! combine through shell aliases and wrapper scripts, often without
+//! noticing that one of them is silently dropped.
+
+use std::ffi::OsString;
+
+use clap::{parser::ValueSource, ArgMatches, Command};
+#[cfg(feature = "miette")]
+use miette::{LabeledSpan, MietteDiagnostic, Report, Severity, SourceSpan};
+
+use crate::{parse::ArgLocation, provenance::Provenance, scope::locate_scoped};
+#[cfg(feature = "miette")]
+use crate::{
+    code,
+    source::{ArgvSource, SourceProvider},
+};
+
+/// An argument with a long `--x` and another argument with the long
+/// `--no-x` of the same command.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct NegationPair {
+    /// `clap::Id` of the argument with the long `--x`.
+    pub positive: String,
+    /// `clap::Id` of the argument with the long `--no-x`.
+    pub negative: String,
+    /// The long of the positive argument, `x`.
+    pub long: String,
+}
+
+/// Both forms of a `NegationPair` typed in the same Argv. See
+/// `negation_conflicts()`.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct NegationConflict {
+    /// Names of the subcommands that own the pair, empty for the
+    /// top-level command.
+    pub scope: Vec<String>,
+    pub pair: NegationPair,
+    /// Locations of the positive form, in the order they appear.
+    pub positive: Vec<ArgLocation>,
+    /// Locations of the negative form, in the order they appear.
+    pub negative: Vec<ArgLocation>,
+    /// `clap::Id` of the argument that clap kept, which is the one typed
+    /// last if either overrides the other with `Arg::overrides_with()`,
+    /// and its provenance. `None` if both took effect.
+    pub winner: Option<(String, Provenance)>,
+}
+
+/// Returns the pairs of arguments of `command`, not its subcommands,
+/// whose longs are `--x` and `--no-x`.
+pub fn negation_pairs(command: &Command) -> Vec<NegationPair> {
+    command
+        .get_arguments()
+        .filter_map(|negative| {
+            let long = negative.get_long()?.strip_prefix("no-")?;
+            let positive = command.get_arguments().find(|positive| positive.get_long() == Some(long))?;
+            Some(NegationPair {
+                positive: positive.get_id().to_string(),
+                negative: negative.get_id().to_string(),
+                long: long.to_owned(),
+            })
+        })
+        .collect()
+}
+
+/// Returns every `NegationPair` in the subcommand tree of `command` of
+/// which both forms were typed in `args` at the same level. `matches`
+/// should be parsed from `args` and tells which form won.
+pub fn negation_conflicts<R>(command: &Command, args: R, matches: &ArgMatches) -> Vec<NegationConflict>
+where
+    R: IntoIterator<Item: Into<OsString>>,
+{
+    let args = args.into_iter().map(Into::into).collect::<Vec<OsString>>();
+    let mut command = command.clone();
+    command.build();
+    let located = locate_scoped(&command, &args);
+
+    let mut scopes: Vec<&Vec<String>> = vec![];
+    for scope in located.iter().map(|located| &located.scope) {
+        if !scopes.contains(&scope) {
+            scopes.push(scope);
+        }
+    }
+    let mut conflicts = vec![];
+    for scope in scopes {
+        let scoped_command = scope.iter().try_fold(&command, |current, name| current.find_subcommand(name));
+        let scoped_matches = scope.iter().try_fold(matches, |current, name| current.subcommand_matches(name));
+        let (Some(scoped_command), Some(scoped_matches)) = (scoped_command, scoped_matches) else {
+            continue;
+        };
+        for pair in negation_pairs(scoped_command) {
+            let locations = |id: &str| {
+                located
+                    .iter()
+                    .filter(|located| located.scope == *scope && located.id == id)
+                    .map(|located| located.location.clone())
+                    .collect::<Vec<_>>()
+            };
+            let (positive, negative) = (locations(&pair.positive), locations(&pair.negative));
+            if positive.is_empty() || negative.is_empty() {
+                continue;
+            }
+            let from_command_line = |id: &str| scoped_matches.value_source(id) == Some(ValueSource::CommandLine);
+            let winner = match (from_command_line(&pair.positive), from_command_line(&pair.negative)) {
+                (true, false) => Some((pair.positive.clone(), Provenance::CommandLine(positive.last().cloned()))),
+                (false, true) => Some((pair.negative.clone(), Provenance::CommandLine(negative.last().cloned()))),
+                _ => None,
+            };
+            conflicts.push(NegationConflict {
+                scope: scope.clone(),
+                pair,
+                positive,
+                negative,
+                winner,
+            });
+        }
+    }
+
+    conflicts
+}
+
+/// Returns a report for every conflict of `negation_conflicts()`,
+/// labelling each occurrence of both forms. It is an advice if one
+/// form overrides the other, and a warning if both took effect.
+#[cfg(feature = "miette")]
+pub fn negation_reports<R>(command: &Command, args: R, matches: &ArgMatches) -> Vec<Report>
+where
+    R: IntoIterator<Item: Into<OsString>>,
+{
+    let args = args.into_iter().map(Into::into).collect::<Vec<OsString>>();
+    let full_command = args.iter().map(|arg| arg.to_string_lossy()).collect::<Vec<_>>().join(" ");
+    negation_conflicts(command, &args, matches)
+        .into_iter()
+        .map(|conflict| ArgvSource::new(full_command.clone()).attach(Report::from(conflict.diagnostic())))
+        .collect()
+}
+
+#[cfg(feature = "miette")]
+impl NegationConflict {
+    /// Returns the diagnostic of `negation_reports()`, with spans into
+    /// the Argv joined by spaces.
+    pub fn diagnostic(&self) -> MietteDiagnostic {
+        let long = &self.pair.long;
+        let (positive, negative) = (format!("--{long}"), format!("--no-{long}"));
+        let winner = self.winner.as_ref().map(|(id, provenance)| {
+            let name = if *id == self.pair.positive { &positive } else { &negative };
+            let location = match provenance {
+                Provenance::CommandLine(location) => location.clone(),
+                _ => None,
+            };
+            (name, location)
+        });
+
+        let mut labels = vec![];
+        for (name, locations) in [(&positive, &self.positive), (&negative, &self.negative)] {
+            for location in locations {
+                let whole = location.whole();
+                let span = SourceSpan::new(whole.offset.into(), whole.length);
+                let label = match &winner {
+                    Some((_, Some(won))) if won == location => LabeledSpan::new_primary_with_span(Some("wins".to_owned()), span),
+                    Some((won, _)) => LabeledSpan::new_with_span(Some(format!("overridden by `{won}`")), span),
+                    None => LabeledSpan::new_with_span(Some(format!("`{name}` given here")), span),
+                };
+                labels.push(label);
+            }
+        }
+        labels.sort_by_key(|label| label.offset());
+
+        let diagnostic = MietteDiagnostic::new(format!("`{positive}` and `{negative}` are both given"))
+            .with_code(code::NEGATION_CONFLICT)
+            .with_labels(labels);
+        match winner {
+            Some((won, _)) => diagnostic
+                .with_severity(Severity::Advice)
+                .with_help(format!("`{won}` was given last and takes effect, drop the other to make it clear")),
+            None => diagnostic
+                .with_severity(Severity::Warning)
+                .with_help(format!("both take effect, make `{negative}` override `{positive}` with `Arg::overrides_with()`")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::{Arg, ArgAction};
+
+    fn command(overrides: bool) -> Command {
+        let mut no_color = Arg::new("no_color").long("no-color").action(ArgAction::SetTrue);
+        if overrides {
+            no_color = no_color.overrides_with("color");
+        }
+        Command::new("app")
+            .arg(Arg::new("color").long("color").short('c').action(ArgAction::SetTrue))
+            .arg(no_color)
+            .arg(Arg::new("no_op").long("no-op").action(ArgAction::SetTrue))
+            .subcommand(
+                Command::new("build")
+                    .arg(Arg::new("cache").long("cache").action(ArgAction::SetTrue).overrides_with("no_cache"))
+                    .arg(Arg::new("no_cache").long("no-cache").action(ArgAction::SetTrue)),
+            )
+    }
+
+    #[test]
+    fn test_negation_pairs() {
+        assert_eq!(negation_pairs(&command(true)), [NegationPair {
+            positive: "color".to_owned(),
+            negative: "no_color".to_owned(),
+            long: "color".to_owned(),
+        }]);
+    }
+
+    #[test]
+    fn test_negation_conflicts() {
+        let args = ["app", "--color", "--no-color", "build", "--no-cache", "--cache"];
+        let matches = command(true).get_matches_from(args);
+        let conflicts = negation_conflicts(&command(true), args, &matches);
+        assert_eq!(conflicts.len(), 2);
+        assert_eq!(conflicts[0].scope, Vec::<String>::new());
+        assert_eq!(conflicts[0].winner.as_ref().unwrap().0, "no_color");
+        assert_eq!(conflicts[1].scope, ["build"]);
+        assert_eq!(conflicts[1].winner.as_ref().unwrap().0, "cache");
+
+        let args = ["app", "--color", "build", "--no-cache"];
+        assert!(negation_conflicts(&command(true), args, &command(true).get_matches_from(args)).is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "miette")]
+    fn test_negation_reports() {
+        let args = ["app", "--color", "--no-color", "build", "--no-cache", "--cache"];
+        let matches = command(true).get_matches_from(args);
+        let reports = negation_reports(&command(true), args, &matches);
+        assert_eq!(reports[0].to_string(), "`--color` and `--no-color` are both given");
+        assert_eq!(reports[0].severity(), Some(Severity::Advice));
+        let labels = reports[0].labels().unwrap().map(|label| (label.offset(), label.label().unwrap().to_owned())).collect::<Vec<_>>();
+        assert_eq!(labels, [(4, "overridden by `--no-color`".to_owned()), (12, "wins".to_owned())]);
+
+        // Without `overrides_with()`, both took effect.
+        let args = ["app", "-c", "--no-color", "--no-op"];
+        let matches = command(false).get_matches_from(args);
+        let reports = negation_reports(&command(false), args, &matches);
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].severity(), Some(Severity::Warning));
+        let labels = reports[0].labels().unwrap().map(|label| label.label().unwrap().to_owned()).collect::<Vec<_>>();
+        assert_eq!(labels, ["`--color` given here", "`--no-color` given here"]);
+    }
+}
@@ -4,11 +4,12 @@
 //! please refer to
 //! https://docs.rs/clap/latest/clap/struct.ArgMatches.html#examples-15
 //!
-//! Subcommands not supported yet! TODO
+//! Subcommands are not descended into here, see `locate_scoped()` and
+//! `locate_in_scope()`.
 
-use std::{cell::{OnceCell, RefCell}, convert::AsRef, ffi::{OsStr, OsString}, rc::Rc};
+use std::{cell::OnceCell, convert::AsRef, ffi::OsString, ops::{ControlFlow, Range}, rc::Rc};
 
-use clap::{builder::ValueParser, CommandFactory};
+use clap::CommandFactory;
 
 /// Parses the Argv string and finds a how a specific argument
 /// appears in the Argv string.
@@ -21,10 +22,77 @@ pub struct ArgLocator<T: Default, V: AsRef<clap::Arg>> {
     /// or cache. Wrapping the `clap::Arg` with a reference counted
     /// smart pointer (`Rc`) is recommended due to multiple aliases
     /// may lead to the same argument. See `arg_aliases`.
-    pub get_arg_by_alias: Box<dyn Fn(&Self, &T, &ArgAlias) -> Option<V>>,
+    pub get_arg_by_alias: GetArgByAlias<T, V>,
+    /// Also covers the hyphens and name of the argument with the part
+    /// returned by `Self::get_label_span()` rather than just its value.
+    pub include_arg_name: bool,
+    /// Locates arguments with `Self::get_label_span()` even if the Argv
+    /// string contains invalid unicode, which is then replaced by `U+FFFD`.
+    /// See `LossyArgv` for locating in the raw Argv instead.
+    pub force_lossy_os_string: bool,
+    /// How `Self::get_location_in_matches()` finds the argument.
+    pub strategy: LocateStrategy,
+    /// What to do with the longs and shorts that are not known.
+    pub unknown_tokens: UnknownTokenPolicy,
+    /// Number of tokens before the program name in the Argv, e.g. `sudo`
+    /// or a shim that re-executes the program. They are skipped but still
+    /// counted in the offsets. See `ArgLocation::shift()` for dropping
+    /// them from the offsets instead.
+    pub wrapper_tokens: usize,
+    /// Unit of the offsets and lengths of the returned parts.
+    pub offset_unit: OffsetUnit,
     arg_aliases: T,
 }
 
+/// See `ArgLocator::strategy`.
+#[derive(Eq, PartialEq, Default, Clone, Copy, Debug)]
+pub enum LocateStrategy {
+    /// Finds the occurrences by lexing the Argv like clap does, same as
+    /// `ArgLocator::get_location()`.
+    #[default]
+    Lexed,
+    /// Takes the occurrence from `ArgMatches::indices_of()` and only
+    /// lexes the Argv to convert the index to offsets, which is more
+    /// robust for clap settings that the lexer does not model, such as
+    /// multiple values. clap only keeps the index of the last
+    /// occurrence of a flag, so that is located rather than the first.
+    IndexDriven,
+}
+
+/// A part of the Argv string that clap gives an index. See
+/// `ArgLocator::clap_indices()`.
+#[derive(Clone, Debug)]
+enum IndexedPart {
+    Flag { declaration: ArgPart, name: ArgPart },
+    /// `positional` if the value does not belong to a long or short.
+    /// `value` is the part of `content` between the value delimiters,
+    /// see `Arg::value_delimiter()`, or all of it.
+    Value {
+        delimiter: Option<ArgPart>,
+        content: ArgPart,
+        value: ArgPart,
+        positional: bool,
+    },
+    /// A default missing value of a long or short typed without a value,
+    /// which clap gives an index although it is not in the Argv.
+    Missing,
+}
+
+/// A long or short typed without a value, which takes its values from
+/// the tokens after it. See `ArgLocator::clap_indices()`.
+struct PendingValues {
+    id: clap::Id,
+    /// Index of the occurrence among those of the argument.
+    occurrence: usize,
+    delimiter: Option<char>,
+    shape: ArgShape,
+    /// How many tokens it took as values so far.
+    taken: usize,
+}
+
+/// See `ArgLocator::get_arg_by_alias`.
+pub type GetArgByAlias<T, V> = Box<dyn Fn(&ArgLocator<T, V>, &T, &ArgAlias) -> Option<V>>;
+
 #[derive(Ord, PartialOrd, Eq, PartialEq, Hash, Clone, Debug)]
 // Differentiates if an alias is long or short since long aliases
 // can also be one character, i.e. the length of a short.
@@ -40,6 +108,16 @@ pub enum ArgAlias {
     Short(char),
 }
 
+impl std::fmt::Display for ArgAlias {
+    /// Formats as typed in the Argv string, i.e. with the hyphens.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Long(long) => write!(f, "--{long}"),
+            Self::Short(short) => write!(f, "-{short}"),
+        }
+    }
+}
+
 #[derive(Ord, PartialOrd, Eq, PartialEq, Default, Clone, Debug)]
 /// # Example (`ArgLocation::Complete`)
 /// ```md
@@ -60,6 +138,150 @@ pub struct ArgPart {
     pub length: usize,
 }
 
+/// A part of an argument in Argv kept as a vector rather than joined
+/// into a string. See `ArgPart::to_token_location()`.
+#[derive(Eq, PartialEq, Hash, Clone, Debug)]
+pub struct TokenLocation {
+    /// Index of the argument in Argv.
+    pub token_index: usize,
+    /// Bytes of the argument that the part covers, which slice the
+    /// `OsString` with `OsStr::as_encoded_bytes`.
+    pub intra_token_range: Range<usize>,
+}
+
+impl ArgPart {
+    /// Returns a part for every element of the text of this part in
+    /// `argv`, the Argv string, separated by `delimiter`, e.g. for a
+    /// value like `1,2,x,4` of an argument with `Arg::value_delimiter()`.
+    /// Empty elements get empty parts. Returns the part itself if it is
+    /// out of `argv`.
+    ///
+    /// # Examples
+    /// ```
+    /// use fancy_clap::ArgPart;
+    ///
+    /// let content = ArgPart { offset: 10, length: 7 };
+    /// let elements = content.split("app --ids=1,2,x,4", ',');
+    /// assert_eq!(elements[2], ArgPart { offset: 14, length: 1 });
+    /// ```
+    pub fn split(&self, argv: &str, delimiter: char) -> Vec<ArgPart> {
+        match argv.get(self.offset..self.offset + self.length) {
+            Some(text) => split_parts(self.offset, text.as_bytes(), delimiter),
+            None => vec![self.clone()],
+        }
+    }
+
+    /// Returns the part moved by `delta` bytes, e.g. by the length of
+    /// the tokens prepended to or removed from the Argv. Returns `None`
+    /// if it would move before the start of the Argv.
+    pub fn shift(&self, delta: isize) -> Option<Self> {
+        Some(Self {
+            offset: self.offset.checked_add_signed(delta)?,
+            length: self.length,
+        })
+    }
+
+    /// Returns the location of the part in `args`, the Argv that it was
+    /// located in. Returns `None` if the part is not within a single
+    /// argument, e.g. the space that delimits a long and its value.
+    pub fn to_token_location<S: AsRef<std::ffi::OsStr>>(&self, args: &[S]) -> Option<TokenLocation> {
+        let mut start = 0;
+        for (token_index, arg) in args.iter().enumerate() {
+            let end = start + arg.as_ref().len();
+            if start <= self.offset && self.offset + self.length <= end {
+                let intra = self.offset - start;
+                return Some(TokenLocation {
+                    token_index,
+                    intra_token_range: intra..intra + self.length,
+                });
+            }
+            start = end + DELIMITER_LENGTH;
+        }
+
+        None
+    }
+}
+
+/// See `ArgLocator::offset_unit`.
+#[derive(Eq, PartialEq, Default, Clone, Copy, Debug)]
+pub enum OffsetUnit {
+    /// Bytes of the Argv string, which slice it as a `str`.
+    #[default]
+    Bytes,
+    /// `char`s of the Argv string, i.e. unicode scalar values.
+    Chars,
+    /// UTF-16 code units, e.g. for the positions of the Language Server
+    /// Protocol.
+    Utf16,
+}
+
+impl OffsetUnit {
+    /// Converts `part`, in bytes of `argv`, to this unit. A sequence of
+    /// invalid unicode counts as the one `U+FFFD` that replaces it.
+    ///
+    /// # Examples
+    /// ```
+    /// use fancy_clap::{ArgPart, OffsetUnit};
+    ///
+    /// // `😀` after the 2 bytes of `é`.
+    /// let part = ArgPart { offset: 13, length: 4 };
+    /// assert_eq!(OffsetUnit::Chars.convert("app --name=é😀x", &part), ArgPart { offset: 12, length: 1 });
+    /// assert_eq!(OffsetUnit::Utf16.convert("app --name=é😀x", &part), ArgPart { offset: 12, length: 2 });
+    /// ```
+    pub fn convert(self, argv: impl AsRef<[u8]>, part: &ArgPart) -> ArgPart {
+        let argv = argv.as_ref();
+        let start = self.count(&argv[..part.offset.min(argv.len())]);
+        let end = self.count(&argv[..(part.offset + part.length).min(argv.len())]);
+
+        ArgPart { offset: start, length: end - start }
+    }
+
+    fn count(self, bytes: &[u8]) -> usize {
+        let units: fn(char) -> usize = match self {
+            Self::Bytes => return bytes.len(),
+            Self::Chars => |_| 1,
+            Self::Utf16 => char::len_utf16,
+        };
+        bytes
+            .utf8_chunks()
+            .map(|chunk| chunk.valid().chars().map(units).sum::<usize>() + usize::from(!chunk.invalid().is_empty()))
+            .sum()
+    }
+}
+
+/// Converts the parts in bytes of an Argv to an `OffsetUnit`. See
+/// `ArgLocator::unit_converter()`.
+pub(crate) struct UnitConverter {
+    unit: OffsetUnit,
+    /// The Argv joined by spaces, empty for `OffsetUnit::Bytes`.
+    argv: Vec<u8>,
+}
+
+impl UnitConverter {
+    /// Converts `part` of the text `argv`, e.g. a command line.
+    pub(crate) fn of_text(unit: OffsetUnit, argv: &str) -> Self {
+        let argv = match unit {
+            OffsetUnit::Bytes => vec![],
+            _ => argv.as_bytes().to_vec(),
+        };
+        Self { unit, argv }
+    }
+
+    pub(crate) fn part(&self, part: &ArgPart) -> ArgPart {
+        match self.unit {
+            OffsetUnit::Bytes => part.clone(),
+            unit => unit.convert(&self.argv, part),
+        }
+    }
+
+    pub(crate) fn location(&self, location: &ArgLocation) -> ArgLocation {
+        match self.unit {
+            OffsetUnit::Bytes => location.clone(),
+            _ => location.map_parts(|part| self.part(part)),
+        }
+    }
+}
+
 #[derive(Ord, PartialOrd, Eq, PartialEq, Clone, Debug)]
 /// Represents how an argument appears as a part in the Argv string.
 /// Every argument has the `declaration` and `name` fields.
@@ -98,9 +320,20 @@ pub enum ArgLocation {
     },
 }
 
+/// Which part of an `ArgLocation` an `ArgPart` is. See
+/// `ArgLocation::part_at()`.
+#[derive(Eq, PartialEq, Hash, Clone, Copy, Debug)]
+pub enum ArgPartKind {
+    Declaration,
+    Name,
+    Delimiter,
+    Content,
+}
+
 const DELIMITER_LENGTH: usize = 1;
 const SHORT_LENGTH: usize = 1;
 const LONG_DECLARATION_LENGTH: usize = 2;
+const TERMINATOR_LENGTH: usize = 2;
 const SHORT_DECLARATION_LENGTH: usize = 1; // There are stuff going on in
                                            // https://github.com/clap-rs/clap/issues/5377 but since
                                            // it is left opened, let's not touch the codes first.
@@ -118,53 +351,275 @@ impl ArgLocation {
 
         Self::Complete { declaration, name, delimiter, content }
     }
+
+    /// Returns the leading hyphens part, which every variant has.
+    pub fn declaration(&self) -> &ArgPart {
+        match self {
+            Self::Discrete { declaration, .. }
+            | Self::Stuck { declaration, .. }
+            | Self::Complete { declaration, .. } => declaration,
+        }
+    }
+
+    /// Returns the name part, which every variant has.
+    pub fn name(&self) -> &ArgPart {
+        match self {
+            Self::Discrete { name, .. }
+            | Self::Stuck { name, .. }
+            | Self::Complete { name, .. } => name,
+        }
+    }
+
+    /// Returns the value part or `None` if the argument is `Discrete`.
+    pub fn content(&self) -> Option<&ArgPart> {
+        match self {
+            Self::Discrete { .. } => None,
+            Self::Stuck { content, .. } | Self::Complete { content, .. } => Some(content),
+        }
+    }
+
+    /// Returns the part that covers the byte at `offset` in the Argv
+    /// string and which part it is, or `None` if no part does, e.g. for
+    /// the other shorts between the hyphen and the name.
+    pub fn part_at(&self, offset: usize) -> Option<(ArgPartKind, &ArgPart)> {
+        let (declaration, name) = (self.declaration(), self.name());
+        let parts = match self {
+            Self::Discrete { .. } => [Some((ArgPartKind::Declaration, declaration)), Some((ArgPartKind::Name, name)), None, None],
+            Self::Stuck { content, .. } => [
+                Some((ArgPartKind::Declaration, declaration)),
+                Some((ArgPartKind::Name, name)),
+                None,
+                Some((ArgPartKind::Content, content)),
+            ],
+            Self::Complete { delimiter, content, .. } => [
+                Some((ArgPartKind::Declaration, declaration)),
+                Some((ArgPartKind::Name, name)),
+                Some((ArgPartKind::Delimiter, delimiter)),
+                Some((ArgPartKind::Content, content)),
+            ],
+        };
+
+        parts.into_iter().flatten().find(|(_, part)| part.offset <= offset && offset < part.offset + part.length)
+    }
+
+    /// Returns a part that covers everything from the declaration to
+    /// the end of the content (or the name if there is no content).
+    /// For shorts, flags in between the hyphen and the name are covered.
+    pub fn whole(&self) -> ArgPart {
+        let declaration = self.declaration();
+        let last = self.content().unwrap_or(self.name());
+
+        ArgPart {
+            offset: declaration.offset,
+            length: last.offset + last.length - declaration.offset,
+        }
+    }
+
+    /// Returns the long or short that was typed in `argv`, the Argv
+    /// string that the location is in, which may be an alias of the
+    /// argument. Returns `None` if the location is out of `argv`.
+    pub fn typed_alias(&self, argv: &str) -> Option<ArgAlias> {
+        let name = self.name();
+        let typed = argv.get(name.offset..name.offset + name.length)?;
+        match self.declaration().length {
+            LONG_DECLARATION_LENGTH => Some(ArgAlias::Long(typed.to_owned())),
+            _ => typed.chars().next().map(ArgAlias::Short),
+        }
+    }
+
+    /// Returns the location with every part moved by `delta` bytes, see
+    /// `ArgPart::shift()`. For example, a location in `sudo app --port=1`
+    /// is shifted by `-5` for displaying `app --port=1`.
+    pub fn shift(&self, delta: isize) -> Option<Self> {
+        let mut out_of_argv = false;
+        let shifted = self.map_parts(|part| {
+            part.shift(delta).unwrap_or_else(|| {
+                out_of_argv = true;
+                part.clone()
+            })
+        });
+
+        (!out_of_argv).then_some(shifted)
+    }
+
+    /// Returns the same variant with every part replaced by `f` of it,
+    /// for converting the offsets to another source.
+    pub fn map_parts(&self, mut f: impl FnMut(&ArgPart) -> ArgPart) -> Self {
+        match self {
+            Self::Discrete { declaration, name } => Self::Discrete {
+                declaration: f(declaration),
+                name: f(name),
+            },
+            Self::Stuck { declaration, name, content } => Self::Stuck {
+                declaration: f(declaration),
+                name: f(name),
+                content: f(content),
+            },
+            Self::Complete { declaration, name, delimiter, content } => Self::Complete {
+                declaration: f(declaration),
+                name: f(name),
+                delimiter: f(delimiter),
+                content: f(content),
+            },
+        }
+    }
 }
 
-type BinarySearchableArgAliasesInCommands = OnceCell<Vec<(ArgAlias, Rc<clap::Arg>)>>;
+/// Aliases of the arguments of a command, sorted for binary search.
+type AliasTable = Vec<(ArgAlias, Rc<clap::Arg>)>;
+type BinarySearchableArgAliasesInCommands = OnceCell<AliasTable>;
 
 impl ArgLocator<BinarySearchableArgAliasesInCommands, Rc<clap::Arg>> {
     /// Returns `Self` with a lazily initialised aliases mapping to
     /// arguments mapping which is created from `CommandFactory`. (Or
     /// any types that derive `clap::Parser`.)
     pub fn from_command_factory<C: CommandFactory>() -> Self {
-        Self {
-            arg_aliases: OnceCell::new(),
-            get_arg_by_alias: Box::new(|_, arg_aliases, alias| {
-                let cache = arg_aliases.get_or_init(|| {
-                    let mut aliases = vec![];
-                    for arg in C::command().get_arguments() {
-                        let rc = Rc::new(arg.to_owned());
-                        if let Some(all_aliases) = arg.get_all_aliases() {
-                            for alias in all_aliases.into_iter().chain(arg.get_long()) {
-                                aliases.push((ArgAlias::Long(alias.to_string()), Rc::clone(&rc)));
-                            }
-                        }
-                        if let Some(short_aliases) = arg.get_all_short_aliases() {
-                            for alias in short_aliases.into_iter().chain(arg.get_short()) {
-                                aliases.push((ArgAlias::Short(alias), Rc::clone(&rc)));
-                            }
-                        }
-                    }
-                    aliases.sort_unstable_by(|(a, _), (b, _)| a.cmp(&b)); // For binary search.
+        Self::from_command_fn(C::command as fn() -> clap::Command)
+    }
 
-                    aliases
-                });
-                let index = cache
-                    .binary_search_by(|(k, _)| k.cmp(alias))
-                    .expect("All aliases of arg should be already known");
+    /// Same as `Self::from_command_factory()` but for a `clap::Command`
+    /// that is built at runtime, e.g. with the builder API.
+    pub fn from_command(command: &clap::Command) -> Self {
+        let command = command.clone();
+        Self::from_command_fn(move || command.clone())
+    }
 
-                Some(Rc::clone(&cache[index].1))
-            }),
+    /// Same as `Self::locate_batch()` but locates the records in
+    /// parallel with rayon. Every thread builds its own locator from
+    /// `command` since locators are not thread safe.
+    #[cfg(feature = "rayon")]
+    pub fn locate_batch_par<A>(command: &clap::Command, records: &[Vec<OsString>], targets: &[&A]) -> Vec<Vec<Option<ArgLocation>>>
+    where
+        A: PartialEq<clap::Id> + Sync + ?Sized,
+    {
+        use rayon::prelude::*;
+
+        records
+            .par_iter()
+            .map_init(|| Self::from_command(command), |locator, record| locator.locate_record(record, targets))
+            .collect()
+    }
+
+    fn from_command_fn(command_fn: impl Fn() -> clap::Command + 'static) -> Self {
+        Self::from_mapper(Box::new(move |_, arg_aliases: &BinarySearchableArgAliasesInCommands, alias| {
+            let cache = arg_aliases.get_or_init(|| {
+                let mut command = command_fn();
+                command.build();
+
+                alias_table(&command)
+            });
+
+            find_in_table(cache, alias).map(Rc::clone)
+        }))
+    }
+}
+
+/// Alias tables of every command in a subcommand tree, built in one
+/// pass by `Self::build_all()` and looked up by the path of subcommand
+/// names, so that a long-running process locating arguments of many
+/// different subcommands does not rebuild them for every locator.
+#[derive(Clone, Debug, Default)]
+pub struct ClapAliasesMapper {
+    /// Sorted by the path for binary search.
+    tables: Vec<(Vec<String>, AliasTable)>,
+}
+
+impl ClapAliasesMapper {
+    /// Builds the alias tables of `command` and all its subcommands.
+    /// Global arguments are in the tables of the subcommands too.
+    pub fn build_all(command: &clap::Command) -> Self {
+        let mut command = command.clone();
+        command.build();
+        let mut tables = vec![];
+        let mut stack = vec![(vec![], &command)];
+        while let Some((path, command)) = stack.pop() {
+            for subcommand in command.get_subcommands() {
+                let mut path: Vec<String> = path.clone();
+                path.push(subcommand.get_name().to_owned());
+                stack.push((path, subcommand));
+            }
+            tables.push((path, alias_table(command)));
         }
+        tables.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+
+        Self { tables }
+    }
+
+    /// Returns the argument of the subcommand at `path` that `alias`
+    /// refers to. An empty `path` is the top-level command.
+    pub fn get<S: AsRef<str>>(&self, path: &[S], alias: &ArgAlias) -> Option<&Rc<clap::Arg>> {
+        let index = self.tables.binary_search_by(|(k, _)| k.iter().map(String::as_str).cmp(path.iter().map(AsRef::as_ref))).ok()?;
+
+        find_in_table(&self.tables[index].1, alias)
+    }
+
+    /// Returns a locator for the arguments of the subcommand at `path`
+    /// that shares the tables instead of building its own.
+    pub fn locator<S: AsRef<str>>(self: &Rc<Self>, path: &[S]) -> ArgLocator<(), Rc<clap::Arg>> {
+        let mapper = Rc::clone(self);
+        let path = path.iter().map(|name| name.as_ref().to_owned()).collect::<Vec<_>>();
+
+        ArgLocator::from_mapper(Box::new(move |_, _, alias| mapper.get(&path, alias).map(Rc::clone)))
     }
 }
 
+/// Returns every alias of the arguments of `command`, which should be
+/// built, sorted for `find_in_table()`.
+fn alias_table(command: &clap::Command) -> AliasTable {
+    let mut aliases = vec![];
+    for arg in command.get_arguments() {
+        let rc = Rc::new(arg.to_owned());
+        aliases.extend(all_aliases(arg).map(|alias| (alias, Rc::clone(&rc))));
+    }
+    aliases.sort_unstable_by(|(a, _), (b, _)| a.cmp(b)); // For binary search.
+
+    aliases
+}
+
+fn find_in_table<'a>(table: &'a [(ArgAlias, Rc<clap::Arg>)], alias: &ArgAlias) -> Option<&'a Rc<clap::Arg>> {
+    // Unknown aliases are skipped since the Argv string is
+    // user input and may not be accepted by clap at all.
+    let index = table.binary_search_by(|(k, _)| k.cmp(alias)).ok()?;
+
+    Some(&table[index].1)
+}
+
 impl<T: Default, V: AsRef<clap::Arg>> ArgLocator<T, V> {
+    /// Returns `Self` that finds arguments with `get_arg_by_alias`, for
+    /// tools that keep the arguments somewhere other than a
+    /// `clap::Command`. See `Self::get_arg_by_alias`.
+    ///
+    /// # Examples
+    /// ```
+    /// use std::rc::Rc;
+    /// use fancy_clap::{ArgAlias, ArgLocator};
+    ///
+    /// let port = Rc::new(clap::Arg::new("port").long("port"));
+    /// let locator = ArgLocator::<(), Rc<clap::Arg>>::from_mapper(Box::new(move |_, _, alias| {
+    ///     (*alias == ArgAlias::Long("port".to_owned())).then(|| Rc::clone(&port))
+    /// }));
+    /// assert!(locator.get_location(["program_name", "--port=1"], "port").is_some());
+    /// ```
+    pub fn from_mapper(get_arg_by_alias: GetArgByAlias<T, V>) -> Self {
+        Self {
+            get_arg_by_alias,
+            include_arg_name: false,
+            force_lossy_os_string: false,
+            strategy: LocateStrategy::default(),
+            unknown_tokens: UnknownTokenPolicy::default(),
+            wrapper_tokens: 0,
+            offset_unit: OffsetUnit::default(),
+            arg_aliases: T::default(),
+        }
+    }
+
     /// Returns the location of parts of argument in the given `args`.
     /// Returns `None` if the argument never appears in `args`.
     ///
     /// # Examples
     /// ```
+    /// use fancy_clap::{ArgLocation, ArgLocator, ArgPart};
+    ///
     /// #[derive(clap::Parser)]
     /// struct Args {
     ///     #[clap(short, long)]
@@ -182,201 +637,1750 @@ impl<T: Default, V: AsRef<clap::Arg>> ArgLocator<T, V> {
     ///     "--complete=1", // 12 chars.
     /// ];
     ///
-    /// let locator = ArgLocator::new_from_command_factory::<Args>();
-    /// assert_eq!(locator.get_location(env_args.clone(), "discrete", Some(ArgLocation::Discrete {
+    /// let locator = ArgLocator::from_command_factory::<Args>();
+    /// assert_eq!(locator.get_location(env_args.clone(), "discrete"), Some(ArgLocation::Discrete {
     ///     declaration: ArgPart { offset: 13, length: 1 },
     ///     name:        ArgPart { offset: 14, length: 1 },
-    /// })));
-    /// assert_eq!(locator.get_location(env_args.clone(), "stuck",    Some(ArgLocation::Stuck    {
+    /// }));
+    /// assert_eq!(locator.get_location(env_args.clone(), "stuck"),    Some(ArgLocation::Stuck    {
     ///     declaration: ArgPart { offset: 13, length: 1 },
     ///     name:        ArgPart { offset: 15, length: 1 },
     ///     content:     ArgPart { offset: 16, length: 5 },
-    /// })));
-    /// assert_eq!(locator.get_location(env_args.clone(), "complete", Some(ArgLocation::Complete {
+    /// }));
+    /// assert_eq!(locator.get_location(env_args.clone(), "complete"), Some(ArgLocation::Complete {
     ///     declaration: ArgPart { offset: 22, length: 2 },
     ///     name:        ArgPart { offset: 24, length: 8 },
     ///     delimiter:   ArgPart { offset: 32, length: 1 },
     ///     content:     ArgPart { offset: 33, length: 1 },
-    /// })));
-    /// assert_eq!(locator.get_location(env_args.clone(), "optional", None));
+    /// }));
+    /// assert_eq!(locator.get_location(env_args.clone(), "optional"), None);
     /// ```
     pub fn get_location<R, A>(&self, args: R, arg: &A) -> Option<ArgLocation>
     where
         R: IntoIterator<Item: Into<OsString>>,
         A: PartialEq<clap::Id> + ?Sized,
     {
-        let raw = clap_lex::RawArgs::new(args);
-        let cursor = RefCell::new(raw.cursor());
-        let mut name = ArgPart::default();
-        let peek_or_discrete = |declaration: ArgPart, name: ArgPart| {
-            if let Some(peek) = raw.peek(&cursor.borrow()) {
-                // Marks all following arguments that do not start with `-` or `--` as part
-                // of the content.
-                if peek.to_long().is_none() && peek.to_short().is_none() {
-                    return ArgLocation::new_complete(declaration, name, peek.to_value_os().len());
-                }
-            }
-            ArgLocation::Discrete { declaration, name }
-        };
+        let args = args.into_iter().map(Into::into).collect::<Vec<OsString>>();
+        let location = self.locate_bytes(&args, arg)?;
 
-        'cursor: while let Some(parsed_arg) = raw.next(&mut cursor.borrow_mut()) {
-            if let Some((Ok(long), accompany)) = parsed_arg.to_long() {
-                let Some(found_generic) = (self.get_arg_by_alias)(
-                    self,
-                    &self.arg_aliases,
-                    &ArgAlias::Long(long.to_string()).into(),
-                ) else { continue 'cursor; };
-                let found = found_generic.as_ref();
-                if arg != found.get_id() {
-                    name.offset += LONG_DECLARATION_LENGTH + long.len() + DELIMITER_LENGTH;
-                    continue 'cursor;
-                } else {
-                    name.length = long.len();
-                }
-                let declaration = ArgPart {
-                    offset: name.offset - LONG_DECLARATION_LENGTH,
-                    length: LONG_DECLARATION_LENGTH,
-                };
-                let name = name; // Safety: make immutable.
+        Some(self.unit_converter(&args).location(&location))
+    }
 
-                if let Some(value) = accompany {
-                    return Some(ArgLocation::new_complete(declaration, name, value.len()));
+    /// Same as `Self::get_location()` but returns the unknown token that
+    /// stopped the search with `UnknownTokenPolicy::Error` as the error,
+    /// instead of `None`. Its part is in bytes like that of
+    /// `Self::unknown_tokens()`. Unknown tokens are skipped with the
+    /// other policies.
+    ///
+    /// # Examples
+    /// ```
+    /// use clap::{Arg, Command};
+    /// use fancy_clap::{ArgLocator, UnknownTokenPolicy};
+    ///
+    /// let command = Command::new("app").arg(Arg::new("port").long("port"));
+    /// let mut locator = ArgLocator::from_command(&command);
+    /// locator.unknown_tokens = UnknownTokenPolicy::Error;
+    /// let error = locator.try_get_location(["app", "--nope", "--port=80"], "port").unwrap_err();
+    /// assert_eq!(error.to_string(), "unknown argument `--nope`");
+    /// assert!(locator.try_get_location(["app", "--port=80", "--nope"], "port").unwrap().is_some());
+    /// ```
+    pub fn try_get_location<R, A>(&self, args: R, arg: &A) -> Result<Option<ArgLocation>, UnknownToken>
+    where
+        R: IntoIterator<Item: Into<OsString>>,
+        A: PartialEq<clap::Id> + ?Sized,
+    {
+        let args = args.into_iter().map(Into::into).collect::<Vec<OsString>>();
+        let (unwrapped, shift) = self.unwrap_args(&args);
+        let mut unknown = None;
+        let location = walk(
+            unwrapped,
+            |alias| self.lookup(alias),
+            |found, location| match arg == found.as_ref().get_id() {
+                true => ControlFlow::Break(location.shift(shift as isize).expect("Shifted forward")),
+                false => ControlFlow::Continue(()),
+            },
+            |alias, part| match self.unknown_tokens {
+                UnknownTokenPolicy::Error => {
+                    unknown = Some((alias, part.shift(shift as isize).expect("Shifted forward")));
+                    ControlFlow::Break(())
                 }
+                UnknownTokenPolicy::Skip | UnknownTokenPolicy::CollectAndContinue => ControlFlow::Continue(()),
+            },
+        );
+        if let Some((alias, part)) = unknown {
+            let argv = args.iter().map(|arg| arg.to_string_lossy()).collect::<Vec<_>>().join(" ");
+            return Err(UnknownToken { alias, part, argv });
+        }
+        #[cfg(any(test, feature = "span-checks"))]
+        if let Some(location) = &location {
+            crate::check::check_location(&args, location);
+        }
 
-                return Some(peek_or_discrete(declaration, name));
-            } else if let Some(mut shorts) = parsed_arg.to_short() {
-                let flag_possible_values = ValueParser::bool()
-                    .possible_values()
-                    .and_then(|values| Some(values.collect::<Vec<_>>()))
-                    .unwrap_or(vec![]);
+        Ok(location.map(|location| self.unit_converter(&args).location(&location)))
+    }
 
-                let declaration = ArgPart {
-                    offset: name.offset,
-                    length: SHORT_DECLARATION_LENGTH,
-                };
-                let name = &mut name; // Safety: prevent name from moving in this scope.
-                name.offset += SHORT_DECLARATION_LENGTH;
-                'shorts: while let Some(short_os) = shorts.next_flag() {
-                    if let Ok(short) = short_os {
-                        let Some(found_generic) = (self.get_arg_by_alias)(
-                            self,
-                            &self.arg_aliases,
-                            &ArgAlias::Short(short),
-                        ) else { continue 'shorts; };
-                        let found = found_generic.as_ref();
-                        if found.get_possible_values() == flag_possible_values {
-                            if arg != found.get_id() {
-                                name.offset += SHORT_LENGTH;
-                                continue 'shorts;
-                            }
+    /// Same as `Self::get_location()` but in bytes regardless of
+    /// `Self::offset_unit`, for locations that are moved to another
+    /// source before they are converted.
+    pub(crate) fn locate_bytes<A>(&self, args: &[OsString], arg: &A) -> Option<ArgLocation>
+    where
+        A: PartialEq<clap::Id> + ?Sized,
+    {
+        let location = self.locate(args, arg);
+        #[cfg(any(test, feature = "span-checks"))]
+        if let Some(location) = &location {
+            crate::check::check_location(args, location);
+        }
 
-                            return Some(ArgLocation::Discrete {
-                                declaration,
-                                name: ArgPart { length: 1, ..*name },
-                            });
-                        } else {
-                            let remain = shorts.next_value_os();
-                            if arg != found.get_id() {
-                                name.offset += SHORT_LENGTH
-                                    + remain.unwrap_or(OsStr::new("")).len()
-                                    + DELIMITER_LENGTH;
-                                continue 'cursor;
-                            }
+        location
+    }
 
-                            if let Some(stuck) = remain {
-                                let accompany_bytes = stuck.as_encoded_bytes();
-                                if let &[b'=', ..] = accompany_bytes {
-                                    return Some(ArgLocation::new_complete(declaration, ArgPart {
-                                        length: SHORT_LENGTH,
-                                        ..*name
-                                    }, stuck.len() - 1));
-                                }
-
-                                return Some(ArgLocation::Stuck { declaration, name: ArgPart {
-                                    length: SHORT_LENGTH,
-                                    ..*name
-                                }, content: ArgPart {
-                                    offset: name.offset + 1,
-                                    length: stuck.len(),
-                                }});
-                            }
+    /// Returns the location of every occurrence of `arg` in `args`, in
+    /// the order they appear, tagged with the index of the occurrence
+    /// starting from `0`, e.g. for an `ArgAction::Append` argument whose
+    /// third value failed validation. Every repetition of a short in a
+    /// cluster is an occurrence with its own name part, so the excess
+    /// `v`s of an `ArgAction::Count` typed as `-vvvv` can be labelled.
+    /// See `Self::get_location()` for the first one only.
+    ///
+    /// # Examples
+    /// ```
+    /// use clap::{Arg, ArgAction, Command};
+    /// use fancy_clap::ArgLocator;
+    ///
+    /// let command = Command::new("app").arg(Arg::new("include").short('I').action(ArgAction::Append));
+    /// let locations = ArgLocator::from_command(&command).get_locations_all(["app", "-Ia", "-I", "b"], "include");
+    /// assert_eq!(locations.len(), 2);
+    /// assert_eq!(locations[1].0, 1);
+    /// assert_eq!(locations[1].1.content().unwrap().offset, 11);
+    /// ```
+    pub fn get_locations_all<R, A>(&self, args: R, arg: &A) -> Vec<(usize, ArgLocation)>
+    where
+        R: IntoIterator<Item: Into<OsString>>,
+        A: PartialEq<clap::Id> + ?Sized,
+    {
+        let args = args.into_iter().map(Into::into).collect::<Vec<OsString>>();
+        let mut locations = vec![];
+        let converter = self.unit_converter(&args);
+        self.walk(&args, |found, location| {
+            if *arg == *found.get_id() {
+                #[cfg(any(test, feature = "span-checks"))]
+                crate::check::check_location(&args, &location);
+                locations.push((locations.len(), converter.location(&location)));
+            }
+            ControlFlow::<()>::Continue(())
+        });
 
-                            return Some(peek_or_discrete(declaration, ArgPart {
-                                length: SHORT_LENGTH,
-                                ..*name
-                            }));
-                        }
-                    }
+        locations
+    }
+
+    /// Returns the location of the occurrence of `arg` at index `n`,
+    /// starting from `0`, in `args`. Stops parsing there, unlike
+    /// indexing the result of `Self::get_locations_all()`.
+    pub fn get_location_nth<R, A>(&self, args: R, arg: &A, n: usize) -> Option<ArgLocation>
+    where
+        R: IntoIterator<Item: Into<OsString>>,
+        A: PartialEq<clap::Id> + ?Sized,
+    {
+        let args = args.into_iter().map(Into::into).collect::<Vec<OsString>>();
+        let mut remaining = n;
+        let location = self.walk(&args, |found, location| {
+            if *arg != *found.get_id() {
+                return ControlFlow::Continue(());
+            }
+            match remaining.checked_sub(1) {
+                Some(left) => {
+                    remaining = left;
+                    ControlFlow::Continue(())
                 }
-            } else {
-                name.offset += parsed_arg.to_value_os().len() + DELIMITER_LENGTH;
-                continue 'cursor;
+                None => ControlFlow::Break(location),
             }
-
-            panic!("All branches should return or continue");
+        });
+        #[cfg(any(test, feature = "span-checks"))]
+        if let Some(location) = &location {
+            crate::check::check_location(&args, location);
         }
 
-        None
+        Some(self.unit_converter(&args).location(&location?))
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// Returns the first location of each of `targets` in `args`, in the
+    /// order of `targets`, parsing `args` once and stopping as soon as
+    /// all of them are found. A fixed set of arguments can be
+    /// destructured from the array without allocating, unlike
+    /// `Self::locate_batch()`.
+    ///
+    /// # Examples
+    /// ```
+    /// use clap::{Arg, ArgAction, Command};
+    /// use fancy_clap::ArgLocator;
+    ///
+    /// let command = Command::new("app")
+    ///     .arg(Arg::new("verbose").short('v').action(ArgAction::SetTrue))
+    ///     .arg(Arg::new("port").long("port"));
+    /// let locator = ArgLocator::from_command(&command);
+    /// let [port, verbose] = locator.get_locations(["app", "--port=1"], &["port", "verbose"]);
+    /// assert_eq!(port.unwrap().content().unwrap().offset, 11);
+    /// assert!(verbose.is_none());
+    /// ```
+    pub fn get_locations<R, A, const C: usize>(&self, args: R, targets: &[A; C]) -> [Option<ArgLocation>; C]
+    where
+        R: IntoIterator<Item: Into<OsString>>,
+        A: PartialEq<clap::Id>,
+    {
+        let args = args.into_iter().map(Into::into).collect::<Vec<OsString>>();
+        let mut locations = [const { None }; C];
+        let mut remaining = C;
+        self.walk(&args, |found, location| {
+            for (target, slot) in targets.iter().zip(locations.iter_mut()) {
+                if slot.is_none() && *target == *found.get_id() {
+                    #[cfg(any(test, feature = "span-checks"))]
+                    crate::check::check_location(&args, &location);
+                    *slot = Some(location.clone());
+                    remaining -= 1;
+                }
+            }
+            match remaining {
+                0 => ControlFlow::Break(()),
+                _ => ControlFlow::Continue(()),
+            }
+        });
+        let converter = self.unit_converter(&args);
 
-    #[test]
-    fn test_get_location_short_equals() {
-        #[derive(clap::Parser)]
-        struct Args {
-            #[clap(short)]
-            should_stick: String,
+        locations.map(|location| location.map(|location| converter.location(&location)))
+    }
+
+    /// Same as `Self::get_location()` but copies `args` into the buffers
+    /// of `scratch`, which are reused by the next call rather than
+    /// allocated again, for programs that locate an argument for each
+    /// of many diagnostics.
+    pub fn get_location_in<R, A>(&self, scratch: &mut LocateScratch, args: R, arg: &A) -> Option<ArgLocation>
+    where
+        R: IntoIterator<Item: AsRef<std::ffi::OsStr>>,
+        A: PartialEq<clap::Id> + ?Sized,
+    {
+        let args = scratch.fill(args);
+        let location = self.locate(args, arg);
+        #[cfg(any(test, feature = "span-checks"))]
+        if let Some(location) = &location {
+            crate::check::check_location(args, location);
         }
 
-        let locator = ArgLocator::from_command_factory::<Args>();
-        let env_args = ["program_name", "-s=Value"];
-        let location = locator.get_location(env_args, "should_stick");
-        assert_eq!(location, Some(ArgLocation::Complete {
-            declaration: ArgPart { offset: 13, length: 1 },
-            name: ArgPart { offset: 14, length: 1 },
-            delimiter: ArgPart { offset: 15, length: 1 },
-            content: ArgPart { offset: 16, length: 5 },
-        }));
+        Some(self.unit_converter(args).location(&location?))
     }
 
-    #[test]
-    fn test_get_location_hyphen_values() {
-        #[derive(clap::Parser)]
-        struct Args {
-            #[clap(long, allow_hyphen_values=true)]
-            complete: String,
-            #[clap(short, allow_hyphen_values=true)]
-            stuck: f32,
+    /// Same as `Self::locate_batch()` for a single record, but with the
+    /// buffers of `scratch` and the results written to `locations`,
+    /// whose allocation is reused as well.
+    pub fn locate_into<R, A>(&self, scratch: &mut LocateScratch, args: R, targets: &[&A], locations: &mut Vec<Option<ArgLocation>>)
+    where
+        R: IntoIterator<Item: AsRef<std::ffi::OsStr>>,
+        A: PartialEq<clap::Id> + ?Sized,
+    {
+        let args = scratch.fill(args);
+        self.locate_targets(args, targets, locations);
+    }
+
+    /// Same as `Self::get_location()`, but for `LocateStrategy::IndexDriven`
+    /// the argument is located by its index in `matches`, which should
+    /// be parsed from `args`. Returns `None` if the argument did not come
+    /// from the command line or is positional, see
+    /// `Self::locate_positional()`.
+    pub fn get_location_in_matches<R>(&self, args: R, matches: &clap::ArgMatches, arg: &str) -> Option<ArgLocation>
+    where
+        R: IntoIterator<Item: Into<OsString>>,
+    {
+        if self.strategy == LocateStrategy::Lexed {
+            return self.get_location(args, arg);
+        }
+        if !matches.try_contains_id(arg).ok()? || matches.value_source(arg) != Some(clap::parser::ValueSource::CommandLine) {
+            return None;
         }
+        let index = matches.indices_of(arg)?.min()?;
+        let args = args.into_iter().map(Into::into).collect::<Vec<OsString>>();
+        let parts = self.clap_indices(&args, matches);
+        let location = match (index.checked_sub(1).and_then(|flag| parts.get(flag)), parts.get(index)?) {
+            (_, IndexedPart::Flag { declaration, name }) | (Some(IndexedPart::Flag { declaration, name }), IndexedPart::Missing) => ArgLocation::Discrete {
+                declaration: declaration.clone(),
+                name: name.clone(),
+            },
+            (Some(IndexedPart::Flag { declaration, name }), IndexedPart::Value { delimiter, content, positional: false, .. }) => {
+                match delimiter {
+                    Some(delimiter) => ArgLocation::Complete {
+                        declaration: declaration.clone(),
+                        name: name.clone(),
+                        delimiter: delimiter.clone(),
+                        content: content.clone(),
+                    },
+                    None if content.offset == name.offset + name.length => ArgLocation::Stuck {
+                        declaration: declaration.clone(),
+                        name: name.clone(),
+                        content: content.clone(),
+                    },
+                    None => ArgLocation::new_complete(declaration.clone(), name.clone(), content.length),
+                }
+            }
+            _ => return None,
+        };
+        #[cfg(any(test, feature = "span-checks"))]
+        crate::check::check_location(&args, &location);
 
-        let locator = ArgLocator::from_command_factory::<Args>();
-        let env_args = ["program_name", "--complete", "-.1", "-s-2."];
-        assert_eq!(locator.get_location(env_args.clone(), "complete"), Some(ArgLocation::Complete {
-            declaration: ArgPart { offset: 13, length: 2 },
-            name: ArgPart { offset: 15, length: 8 },
-            delimiter: ArgPart { offset: 23, length: 1 },
-            content: ArgPart { offset: 24, length: 3 },
-        }));
-        assert_eq!(locator.get_location(env_args.clone(), "stuck"), Some(ArgLocation::Stuck {
-            declaration: ArgPart { offset: 28, length: 1 },
-            name: ArgPart { offset: 29, length: 1 },
-            content: ArgPart { offset: 30, length: 3 },
-        }));
+        Some(self.unit_converter(&args).location(&location))
     }
 
-    fn test_get_location_stuck_new_bool_and_hyphen_string() {
-        #[derive(Clone)]
-        struct NewFlag(bool);
-        #[derive(clap::Parser)]
+    /// Returns the parts of the values of the positional argument `arg`
+    /// in `args`, in the order they appear, e.g. every `FILE` operand of
+    /// a multi-value positional. Positionals cannot be told apart by
+    /// lexing, so they are located by their indices in `matches`, which
+    /// should be parsed from `args`. Returns an empty `Vec` if the
+    /// argument did not come from the command line or is not positional.
+    ///
+    /// # Examples
+    /// ```
+    /// use clap::{Arg, ArgAction, Command};
+    /// use fancy_clap::{ArgLocator, ArgPart};
+    ///
+    /// let command = Command::new("app")
+    ///     .arg(Arg::new("verbose").short('v').action(ArgAction::SetTrue))
+    ///     .arg(Arg::new("files").num_args(1..));
+    /// let args = ["app", "a", "-v", "bc"];
+    /// let matches = command.clone().get_matches_from(args);
+    /// let values = ArgLocator::from_command(&command).locate_positional(args, &matches, "files");
+    /// assert_eq!(values, [ArgPart { offset: 4, length: 1 }, ArgPart { offset: 9, length: 2 }]);
+    /// ```
+    pub fn locate_positional<R>(&self, args: R, matches: &clap::ArgMatches, arg: &str) -> Vec<ArgPart>
+    where
+        R: IntoIterator<Item: Into<OsString>>,
+    {
+        let mut values = self.indexed_values(args, matches, arg, |part| match part {
+            IndexedPart::Value { content, positional: true, .. } => Some(content),
+            _ => None,
+        });
+        // Values split by a value delimiter share the part.
+        values.dedup();
+
+        values
+    }
+
+    /// Returns the part of every value of `arg` in `args`, in the order
+    /// they appear, e.g. each path of `--files a b c` or `--files=a,b,c`
+    /// with a value delimiter, so that a label can point at the one that
+    /// is invalid rather than at all of them. Covers every occurrence of
+    /// a long, short or positional argument. Like
+    /// `Self::locate_positional()`, `matches` should be parsed from
+    /// `args`. Default missing values are not in the Argv and skipped.
+    /// Returns an empty `Vec` if the argument did not come from the
+    /// command line.
+    ///
+    /// # Examples
+    /// ```
+    /// use clap::{Arg, ArgAction, Command};
+    /// use fancy_clap::{ArgLocator, ArgPart};
+    ///
+    /// let command = Command::new("app").arg(Arg::new("files").long("files").num_args(1..).value_delimiter(',').action(ArgAction::Append));
+    /// let args = ["app", "--files", "a", "bc", "--files=d,ef"];
+    /// let matches = command.clone().get_matches_from(args);
+    /// let values = ArgLocator::from_command(&command).locate_values(args, &matches, "files");
+    /// assert_eq!(values[1], ArgPart { offset: 14, length: 2 });
+    /// assert_eq!(values[3], ArgPart { offset: 27, length: 2 });
+    /// ```
+    pub fn locate_values<R>(&self, args: R, matches: &clap::ArgMatches, arg: &str) -> Vec<ArgPart>
+    where
+        R: IntoIterator<Item: Into<OsString>>,
+    {
+        self.indexed_values(args, matches, arg, |part| match part {
+            IndexedPart::Value { value, .. } => Some(value),
+            _ => None,
+        })
+    }
+
+    /// Returns the part that `pick` takes from every value of `arg` in
+    /// `args` that clap gives an index, skipping default missing values.
+    /// Returns an empty `Vec` if the argument did not come from the
+    /// command line, or if `pick` returns `None` for any of the values.
+    fn indexed_values<R>(&self, args: R, matches: &clap::ArgMatches, arg: &str, pick: impl Fn(&IndexedPart) -> Option<&ArgPart>) -> Vec<ArgPart>
+    where
+        R: IntoIterator<Item: Into<OsString>>,
+    {
+        if !matches.try_contains_id(arg).unwrap_or_default() || matches.value_source(arg) != Some(clap::parser::ValueSource::CommandLine) {
+            return vec![];
+        }
+        let Some(indices) = matches.indices_of(arg) else {
+            return vec![];
+        };
+        let args = args.into_iter().map(Into::into).collect::<Vec<OsString>>();
+        let parts = self.clap_indices(&args, matches);
+        let mut values = vec![];
+        for index in indices {
+            match parts.get(index) {
+                Some(IndexedPart::Missing) => {}
+                part => match part.and_then(&pick) {
+                    Some(value) => values.push(value.clone()),
+                    None => return vec![],
+                },
+            }
+        }
+        let converter = self.unit_converter(&args);
+
+        values.iter().map(|value| converter.part(value)).collect()
+    }
+
+    /// Returns the part of every element of the value of the first
+    /// occurrence of `arg` in `args`, split by its value delimiter, see
+    /// `Arg::value_delimiter()` and `ArgPart::split()`, so that a label
+    /// can point at the invalid `x` of `--ids=1,2,x,4`. The whole value
+    /// is the only element if the argument has no value delimiter.
+    /// Returns `None` if the argument cannot be found or has no value.
+    ///
+    /// # Examples
+    /// ```
+    /// use clap::{Arg, Command};
+    /// use fancy_clap::{ArgLocator, ArgPart};
+    ///
+    /// let command = Command::new("app").arg(Arg::new("ids").long("ids").value_delimiter(','));
+    /// let elements = ArgLocator::from_command(&command).get_value_elements(["app", "--ids=1,2,x,4"], "ids").unwrap();
+    /// assert_eq!(elements.len(), 4);
+    /// assert_eq!(elements[2], ArgPart { offset: 14, length: 1 });
+    /// ```
+    pub fn get_value_elements<R, A>(&self, args: R, arg: &A) -> Option<Vec<ArgPart>>
+    where
+        R: IntoIterator<Item: Into<OsString>>,
+        A: PartialEq<clap::Id> + ?Sized,
+    {
+        let args = args.into_iter().map(Into::into).collect::<Vec<OsString>>();
+        let (content, delimiter) = self.walk(&args, |found, location| match *arg == *found.get_id() {
+            true => ControlFlow::Break((location.content().cloned(), found.get_value_delimiter())),
+            false => ControlFlow::Continue(()),
+        })?;
+        let content = content?;
+        // Split on the raw bytes, which the content part is in, since the
+        // lossy Argv is longer if there is invalid unicode before it.
+        let argv = args.iter().map(|arg| arg.as_encoded_bytes()).collect::<Vec<_>>().join(&b' ');
+
+        let elements = match (delimiter, argv.get(content.offset..content.offset + content.length)) {
+            (Some(delimiter), Some(value)) => split_parts(content.offset, value, delimiter),
+            _ => vec![content],
+        };
+        let converter = self.unit_converter(&args);
+
+        Some(elements.iter().map(|element| converter.part(element)).collect())
+    }
+
+    /// Returns the parts of `args` at the indices that clap gives them,
+    /// i.e. one for every long, short and value, where a value split by
+    /// a value delimiter takes one index for each of its values.
+    /// `matches` tells how many default missing values a long or short
+    /// typed without a value got, which the `clap::Arg` does not.
+    fn clap_indices(&self, args: &[OsString], matches: &clap::ArgMatches) -> Vec<IndexedPart> {
+        let (args, mut offset) = self.unwrap_args(args);
+        let raw = clap_lex::RawArgs::new(args);
+        let mut cursor = raw.cursor();
+        let mut parts = vec![];
+        let mut escaped = false;
+        let mut pending: Option<PendingValues> = None;
+        // Occurrences of every argument so far, for `missing_values`.
+        let mut occurrences: Vec<(clap::Id, usize)> = vec![];
+        let mut occur = |arg: &clap::Arg| match occurrences.iter_mut().find(|(id, _)| id == arg.get_id()) {
+            Some((_, count)) => {
+                *count += 1;
+                *count - 1
+            }
+            None => {
+                occurrences.push((arg.get_id().clone(), 1));
+                0
+            }
+        };
+        let missing_values = |arg: &clap::Id, occurrence: usize| {
+            let count = matches
+                .try_get_raw_occurrences(arg.as_str())
+                .ok()
+                .flatten()
+                .and_then(|mut occurrences| occurrences.nth(occurrence))
+                .map_or(0, Iterator::count);
+            std::iter::repeat_n(IndexedPart::Missing, count)
+        };
+        // Optional values that were not typed get the default missing
+        // values.
+        let untyped = |pending: Option<PendingValues>| {
+            pending.filter(|pending| pending.taken == 0).into_iter().flat_map(|pending| missing_values(&pending.id, pending.occurrence))
+        };
+        let pending_values = |arg: &clap::Arg, occurrence: usize| PendingValues {
+            id: arg.get_id().clone(),
+            occurrence,
+            delimiter: arg.get_value_delimiter(),
+            shape: ArgShape::of(arg),
+            taken: 0,
+        };
+        // One part for every value of `content`, split by `delimiter`.
+        let split = |content: &ArgPart, value: &[u8], delimiter: Option<char>| match delimiter {
+            Some(delimiter) => split_parts(content.offset, value, delimiter),
+            None => vec![content.clone()],
+        };
+
+        while let Some(parsed_arg) = raw.next(&mut cursor) {
+            let token_offset = offset;
+            let token_length = parsed_arg.to_value_os().len();
+            offset += token_length + DELIMITER_LENGTH;
+            let hyphen_value = pending.as_ref().is_some_and(|pending| pending.shape.takes_hyphen_value(&parsed_arg));
+            if parsed_arg.is_escape() && !escaped && !parts.is_empty() && !hyphen_value {
+                parts.extend(untyped(pending.take()));
+                escaped = true;
+                continue;
+            }
+
+            // The first argument is the binary name.
+            let lexed = !parts.is_empty() && !escaped && !hyphen_value;
+            let mut flags = vec![];
+            // The values of the flag before, which end at a new flag.
+            let mut ended = None;
+            if let Some((Ok(long), accompany)) = parsed_arg.to_long().filter(|_| lexed) {
+                if let Some(found) = (self.get_arg_by_alias)(self, &self.arg_aliases, &ArgAlias::Long(long.to_string())) {
+                    let occurrence = occur(found.as_ref());
+                    let name = ArgPart {
+                        offset: token_offset + LONG_DECLARATION_LENGTH,
+                        length: long.len(),
+                    };
+                    flags.push(IndexedPart::Flag {
+                        declaration: ArgPart {
+                            offset: token_offset,
+                            length: LONG_DECLARATION_LENGTH,
+                        },
+                        name: name.clone(),
+                    });
+                    ended = pending.take();
+                    match accompany {
+                        Some(value) => {
+                            let delimiter = ArgPart {
+                                offset: name.offset + name.length,
+                                length: DELIMITER_LENGTH,
+                            };
+                            let content = ArgPart {
+                                offset: delimiter.offset + delimiter.length,
+                                length: value.len(),
+                            };
+                            let values = split(&content, value.as_encoded_bytes(), found.as_ref().get_value_delimiter());
+                            flags.extend(values.into_iter().map(|value| IndexedPart::Value {
+                                delimiter: Some(delimiter.clone()),
+                                content: content.clone(),
+                                value,
+                                positional: false,
+                            }));
+                        }
+                        None if ArgShape::of(found.as_ref()).takes_next_value() => pending = Some(pending_values(found.as_ref(), occurrence)),
+                        None => flags.extend(missing_values(found.as_ref().get_id(), occurrence)),
+                    }
+                }
+            } else if let Some(mut shorts) = parsed_arg.to_short().filter(|_| lexed) {
+                let declaration = ArgPart {
+                    offset: token_offset,
+                    length: SHORT_DECLARATION_LENGTH,
+                };
+                let mut name = ArgPart {
+                    offset: token_offset + SHORT_DECLARATION_LENGTH,
+                    length: SHORT_LENGTH,
+                };
+                ended = pending.take();
+                while let Some(short) = shorts.next_flag() {
+                    // Clusters with unknown shorts are taken as values,
+                    // e.g. negative numbers.
+                    let Some(found) = short.ok().and_then(|short| {
+                        name.length = short.len_utf8();
+                        (self.get_arg_by_alias)(self, &self.arg_aliases, &ArgAlias::Short(short))
+                    }) else {
+                        flags.clear();
+                        break;
+                    };
+                    flags.push(IndexedPart::Flag {
+                        declaration: declaration.clone(),
+                        name: name.clone(),
+                    });
+                    let occurrence = occur(found.as_ref());
+                    name.offset += name.length;
+                    if ArgShape::of(found.as_ref()).discrete {
+                        continue;
+                    }
+                    let Some(stuck) = shorts.next_value_os() else {
+                        match ArgShape::of(found.as_ref()).takes_next_value() {
+                            true => pending = Some(pending_values(found.as_ref(), occurrence)),
+                            false => flags.extend(missing_values(found.as_ref().get_id(), occurrence)),
+                        }
+                        break;
+                    };
+                    let value = stuck.as_encoded_bytes();
+                    let (delimiter, value) = match value.strip_prefix(b"=") {
+                        Some(value) => {
+                            let delimiter = ArgPart {
+                                offset: name.offset,
+                                length: DELIMITER_LENGTH,
+                            };
+                            (Some(delimiter), value)
+                        }
+                        None => (None, value),
+                    };
+                    let content = ArgPart {
+                        offset: name.offset + delimiter.as_ref().map_or(0, |delimiter| delimiter.length),
+                        length: stuck.len() - delimiter.as_ref().map_or(0, |delimiter| delimiter.length),
+                    };
+                    let values = split(&content, value, found.as_ref().get_value_delimiter());
+                    flags.extend(values.into_iter().map(|value| IndexedPart::Value {
+                        delimiter: delimiter.clone(),
+                        content: content.clone(),
+                        value,
+                        positional: false,
+                    }));
+                    break;
+                }
+            }
+            if !flags.is_empty() {
+                parts.extend(untyped(ended));
+                parts.extend(flags);
+                continue;
+            }
+            // Clusters with unknown shorts are values of the flag before.
+            pending = pending.or(ended);
+
+            // The terminator ends the values without taking an index.
+            if pending.as_ref().is_some_and(|pending| pending.shape.is_value_terminator(&parsed_arg)) {
+                parts.extend(untyped(pending.take()));
+                continue;
+            }
+            let content = ArgPart {
+                offset: token_offset,
+                length: token_length,
+            };
+            let positional = pending.is_none();
+            let values = split(&content, parsed_arg.to_value_os().as_encoded_bytes(), pending.as_ref().and_then(|pending| pending.delimiter));
+            parts.extend(values.into_iter().map(|value| IndexedPart::Value {
+                delimiter: None,
+                content: content.clone(),
+                value,
+                positional,
+            }));
+            // Arguments with multiple values keep taking the tokens.
+            if let Some(values) = &mut pending {
+                values.taken += 1;
+                if values.taken >= values.shape.max_values {
+                    pending = None;
+                }
+            }
+        }
+        parts.extend(untyped(pending));
+
+        parts
+    }
+
+    /// Returns the Argv string, i.e. `args` joined by spaces, and the
+    /// part of it that a label for `arg` should cover: the value of the
+    /// argument, or the whole argument if it has no value or
+    /// `Self::include_arg_name` is set. Returns `None` if the argument
+    /// cannot be found, or if `args` contains invalid unicode and
+    /// `Self::force_lossy_os_string` is not set.
+    pub fn get_label_span<R, A>(&self, args: R, arg: &A) -> Option<(String, ArgPart)>
+    where
+        R: IntoIterator<Item: Into<OsString>>,
+        A: PartialEq<clap::Id> + ?Sized,
+    {
+        self.label_span(args, arg).map(|(full_command, _, part)| (full_command, part))
+    }
+
+    /// Same as `Self::get_label_span()` but rejects invalid unicode in
+    /// `args` regardless of `Self::force_lossy_os_string`, with an error
+    /// that identifies the first offending argument, for applications
+    /// that report such input instead of replacing it.
+    pub fn try_get_label_span<R, A>(&self, args: R, arg: &A) -> Result<Option<(String, ArgPart)>, InvalidUnicode>
+    where
+        R: IntoIterator<Item: Into<OsString>>,
+        A: PartialEq<clap::Id> + ?Sized,
+    {
+        let args = args.into_iter().map(Into::into).collect::<Vec<OsString>>();
+        if let Some(error) = InvalidUnicode::find(&args) {
+            return Err(error);
+        }
+
+        Ok(self.get_label_span(args, arg))
+    }
+
+    /// Same as `Self::get_label_span()` but also returns the location.
+    pub(crate) fn label_span<R, A>(&self, args: R, arg: &A) -> Option<(String, ArgLocation, ArgPart)>
+    where
+        R: IntoIterator<Item: Into<OsString>>,
+        A: PartialEq<clap::Id> + ?Sized,
+    {
+        let args = args.into_iter().map(Into::into).collect::<Vec<OsString>>();
+        if !self.force_lossy_os_string && args.iter().any(|arg| arg.to_str().is_none()) {
+            return None;
+        }
+        let args = args.iter().map(|arg| arg.to_string_lossy().to_string()).collect::<Vec<_>>();
+        let location = self.get_location(&args, arg)?;
+        let part = match location.content() {
+            Some(content) if !self.include_arg_name => content.clone(),
+            _ => location.whole(),
+        };
+
+        Some((args.join(" "), location, part))
+    }
+
+    /// Calls `visit` with the id and the location of every known
+    /// argument in `args` in the order they appear, until `visit`
+    /// returns `ControlFlow::Break`, whose value is returned.
+    ///
+    /// Unlike calling `Self::get_location()` for every argument, `args`
+    /// is parsed once and no results are collected.
+    ///
+    /// # Examples
+    /// ```
+    /// use std::ops::ControlFlow;
+    /// use fancy_clap::ArgLocator;
+    ///
+    /// #[derive(clap::Parser)]
+    /// struct Args {
+    ///     #[clap(short)]
+    ///     verbose: bool,
+    ///     #[clap(long)]
+    ///     port: u16,
+    /// }
+    ///
+    /// let locator = ArgLocator::from_command_factory::<Args>();
+    /// let mut ids = vec![];
+    /// locator.visit_locations(["program_name", "-v", "--port", "1"], |id, _| {
+    ///     ids.push(id.to_string());
+    ///     ControlFlow::<()>::Continue(())
+    /// });
+    /// assert_eq!(ids, ["verbose", "port"]);
+    /// ```
+    pub fn visit_locations<R, B>(&self, args: R, mut visit: impl FnMut(&clap::Id, ArgLocation) -> ControlFlow<B>) -> Option<B>
+    where
+        R: IntoIterator<Item: Into<OsString>>,
+    {
+        let args = args.into_iter().map(Into::into).collect::<Vec<OsString>>();
+        let converter = self.unit_converter(&args);
+        self.walk(&args, |found, location| {
+            #[cfg(any(test, feature = "span-checks"))]
+            crate::check::check_location(&args, &location);
+            visit(found.get_id(), converter.location(&location))
+        })
+    }
+
+    /// Returns the part of the `--` in `args` after which every argument
+    /// is positional, even if it looks like a flag. A `--` taken as the
+    /// value of an argument that allows hyphen values is not one.
+    ///
+    /// # Examples
+    /// ```
+    /// use fancy_clap::{ArgLocator, ArgPart};
+    ///
+    /// #[derive(clap::Parser)]
+    /// struct Args {
+    ///     #[clap(short)]
+    ///     verbose: bool,
+    ///     files: Vec<String>,
+    /// }
+    ///
+    /// let locator = ArgLocator::from_command_factory::<Args>();
+    /// let args = ["program_name", "-v", "--", "-v"];
+    /// assert_eq!(locator.get_terminator(args), Some(ArgPart { offset: 16, length: 2 }));
+    /// assert_eq!(locator.get_location(args, "verbose").unwrap().name().offset, 14);
+    /// ```
+    pub fn get_terminator<R>(&self, args: R) -> Option<ArgPart>
+    where
+        R: IntoIterator<Item: Into<OsString>>,
+    {
+        let args = args.into_iter().map(Into::into).collect::<Vec<OsString>>();
+        let converter = self.unit_converter(&args);
+        let (args, shift) = self.unwrap_args(&args);
+        let mut found = None;
+        walk_to_terminator(
+            args,
+            |alias| self.lookup(alias),
+            |_, _| ControlFlow::<()>::Continue(()),
+            |_, _| ControlFlow::Continue(()),
+            |arg, part| {
+                if arg.is_none() {
+                    found = Some(part.shift(shift as isize).expect("Shifted forward"));
+                }
+            },
+        );
+
+        found.map(|part| converter.part(&part))
+    }
+
+    /// Returns the part of the value terminator, see
+    /// `Arg::value_terminator()`, that ends the values of the first
+    /// occurrence of `arg` in `args`. Returns `None` if the values end
+    /// otherwise, e.g. at the next long or short.
+    ///
+    /// # Examples
+    /// ```
+    /// use clap::{Arg, Command};
+    /// use fancy_clap::{ArgLocator, ArgPart};
+    ///
+    /// let command = Command::new("app")
+    ///     .arg(Arg::new("exec").long("exec").num_args(1..).allow_hyphen_values(true).value_terminator(";"))
+    ///     .arg(Arg::new("name").long("name"));
+    /// let args = ["app", "--exec", "rm", "--name", ";", "--name=a"];
+    /// let locator = ArgLocator::from_command(&command);
+    /// assert_eq!(locator.get_value_terminator(args, "exec"), Some(ArgPart { offset: 21, length: 1 }));
+    /// assert_eq!(locator.get_location(args, "name").unwrap().name().offset, 25);
+    /// ```
+    pub fn get_value_terminator<R, A>(&self, args: R, arg: &A) -> Option<ArgPart>
+    where
+        R: IntoIterator<Item: Into<OsString>>,
+        A: PartialEq<clap::Id> + ?Sized,
+    {
+        let args = args.into_iter().map(Into::into).collect::<Vec<OsString>>();
+        let converter = self.unit_converter(&args);
+        let (args, shift) = self.unwrap_args(&args);
+        let mut found = None;
+        walk_to_terminator(
+            args,
+            |alias| self.lookup(alias),
+            |_, _| ControlFlow::<()>::Continue(()),
+            |_, _| ControlFlow::Continue(()),
+            |terminated: Option<&V>, part| {
+                if found.is_none() && terminated.is_some_and(|terminated| *arg == *terminated.as_ref().get_id()) {
+                    found = Some(part.shift(shift as isize).expect("Shifted forward"));
+                }
+            },
+        );
+
+        found.map(|part| converter.part(&part))
+    }
+
+    /// Returns the locations of `targets` in every record of Argv, in
+    /// the order of `records` and `targets`. Each record is parsed once
+    /// for all of the targets and the aliases mapping is shared by all
+    /// of the records, which makes this cheaper than calling
+    /// `Self::get_location()` for every pair when analysing recorded
+    /// command lines.
+    pub fn locate_batch<I, A>(&self, records: I, targets: &[&A]) -> Vec<Vec<Option<ArgLocation>>>
+    where
+        I: IntoIterator<Item = Vec<OsString>>,
+        A: PartialEq<clap::Id> + ?Sized,
+    {
+        records.into_iter().map(|record| self.locate_record(&record, targets)).collect()
+    }
+
+    fn locate_record<A>(&self, record: &[OsString], targets: &[&A]) -> Vec<Option<ArgLocation>>
+    where
+        A: PartialEq<clap::Id> + ?Sized,
+    {
+        let mut locations = vec![];
+        self.locate_targets(record, targets, &mut locations);
+
+        locations
+    }
+
+    fn locate_targets<A>(&self, args: &[OsString], targets: &[&A], locations: &mut Vec<Option<ArgLocation>>)
+    where
+        A: PartialEq<clap::Id> + ?Sized,
+    {
+        locations.clear();
+        locations.resize(targets.len(), None);
+        let mut remaining = targets.len();
+        self.walk(args, |found, location| {
+            #[cfg(any(test, feature = "span-checks"))]
+            crate::check::check_location(args, &location);
+            for (target, slot) in targets.iter().zip(locations.iter_mut()) {
+                if slot.is_none() && *target == found.get_id() {
+                    *slot = Some(location.clone());
+                    remaining -= 1;
+                }
+            }
+            match remaining {
+                0 => ControlFlow::Break(()),
+                _ => ControlFlow::Continue(()),
+            }
+        });
+        if self.offset_unit != OffsetUnit::Bytes {
+            let converter = self.unit_converter(args);
+            for location in locations.iter_mut().flatten() {
+                *location = converter.location(location);
+            }
+        }
+    }
+
+    fn locate<A>(&self, args: &[OsString], arg: &A) -> Option<ArgLocation>
+    where
+        A: PartialEq<clap::Id> + ?Sized,
+    {
+        self.walk(args, |found, location| match arg == found.get_id() {
+            true => ControlFlow::Break(location),
+            false => ControlFlow::Continue(()),
+        })
+    }
+
+    fn walk<B>(&self, args: &[OsString], mut visit: impl FnMut(&clap::Arg, ArgLocation) -> ControlFlow<B>) -> Option<B> {
+        let (args, shift) = self.unwrap_args(args);
+        walk(
+            args,
+            |alias| self.lookup(alias),
+            |found, location| visit(found.as_ref(), location.shift(shift as isize).expect("Shifted forward")),
+            |_, _| match self.unknown_tokens {
+                UnknownTokenPolicy::Error => ControlFlow::Break(()),
+                UnknownTokenPolicy::Skip | UnknownTokenPolicy::CollectAndContinue => ControlFlow::Continue(()),
+            },
+        )
+    }
+
+    /// Returns a converter of the parts in bytes of `args` joined by
+    /// spaces to `Self::offset_unit`.
+    pub(crate) fn unit_converter(&self, args: &[OsString]) -> UnitConverter {
+        let argv = match self.offset_unit {
+            OffsetUnit::Bytes => vec![],
+            _ => args.iter().map(|arg| arg.as_encoded_bytes()).collect::<Vec<_>>().join(&b' '),
+        };
+        UnitConverter { unit: self.offset_unit, argv }
+    }
+
+    /// Returns `args` without the tokens of `Self::wrapper_tokens`, and
+    /// the length that they take in the Argv string.
+    pub(crate) fn unwrap_args<'a>(&self, args: &'a [OsString]) -> (&'a [OsString], usize) {
+        let skipped = self.wrapper_tokens.min(args.len());
+        let length = args[..skipped].iter().map(|arg| arg.len() + DELIMITER_LENGTH).sum();
+
+        (&args[skipped..], length)
+    }
+
+    pub(crate) fn lookup(&self, alias: &ArgAlias) -> Option<(V, ArgShape)> {
+        let found = (self.get_arg_by_alias)(self, &self.arg_aliases, alias)?;
+        let shape = ArgShape::of(found.as_ref());
+        Some((found, shape))
+    }
+
+    /// Returns the longs and shorts in `args` that are not known by the
+    /// locator according to `Self::unknown_tokens`: none for `Skip`, all
+    /// of them for `CollectAndContinue`, and the first one as the error
+    /// for `Error`.
+    pub fn unknown_tokens<R>(&self, args: R) -> Result<Vec<UnknownToken>, UnknownToken>
+    where
+        R: IntoIterator<Item: Into<OsString>>,
+    {
+        if self.unknown_tokens == UnknownTokenPolicy::Skip {
+            return Ok(vec![]);
+        }
+        let args = args.into_iter().map(Into::into).collect::<Vec<OsString>>();
+        let argv = args.iter().map(|arg| arg.to_string_lossy()).collect::<Vec<_>>().join(" ");
+        let (args, shift) = self.unwrap_args(&args);
+        let mut unknown = vec![];
+        walk(
+            args,
+            |alias| self.lookup(alias),
+            |_, _| ControlFlow::<()>::Continue(()),
+            |alias, part| {
+                unknown.push(UnknownToken {
+                    alias,
+                    part: part.shift(shift as isize).expect("Shifted forward"),
+                    argv: argv.clone(),
+                });
+                match self.unknown_tokens {
+                    UnknownTokenPolicy::Error => ControlFlow::Break(()),
+                    UnknownTokenPolicy::Skip | UnknownTokenPolicy::CollectAndContinue => ControlFlow::Continue(()),
+                }
+            },
+        );
+
+        match self.unknown_tokens {
+            UnknownTokenPolicy::Error => unknown.pop().map_or(Ok(vec![]), Err),
+            UnknownTokenPolicy::Skip | UnknownTokenPolicy::CollectAndContinue => Ok(unknown),
+        }
+    }
+}
+
+/// What `ArgLocator` does with the longs and shorts it does not know,
+/// which clap would usually reject. See `ArgLocator::unknown_tokens()`.
+#[derive(Eq, PartialEq, Default, Clone, Copy, Debug)]
+pub enum UnknownTokenPolicy {
+    /// Skips them silently.
+    #[default]
+    Skip,
+    /// Skips them while locating, but `ArgLocator::unknown_tokens()`
+    /// returns all of them.
+    CollectAndContinue,
+    /// Stops at the first one, so arguments after it are not located
+    /// and `ArgLocator::unknown_tokens()` returns it as an error. The
+    /// other methods of `ArgLocator` return `None` or nothing for those
+    /// arguments without telling why, except
+    /// `ArgLocator::try_get_location()`, which returns the token.
+    Error,
+}
+
+/// A long or short that `ArgLocator` does not know. As a
+/// `miette::Diagnostic`, it labels the token in the lossy Argv string.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UnknownToken {
+    pub alias: ArgAlias,
+    /// Part of the long with its hyphens, or of the short without.
+    pub part: ArgPart,
+    /// The lossy Argv string, i.e. `args` joined by spaces.
+    pub argv: String,
+}
+
+impl std::fmt::Display for UnknownToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "unknown argument `{}`", self.alias)
+    }
+}
+
+impl std::error::Error for UnknownToken {}
+
+#[cfg(feature = "miette")]
+impl miette::Diagnostic for UnknownToken {
+    fn code<'a>(&'a self) -> Option<Box<dyn std::fmt::Display + 'a>> {
+        Some(Box::new(crate::code::UNKNOWN_TOKEN))
+    }
+
+    fn source_code(&self) -> Option<&dyn miette::SourceCode> {
+        Some(&self.argv)
+    }
+
+    fn labels(&self) -> Option<Box<dyn Iterator<Item = miette::LabeledSpan> + '_>> {
+        let label = miette::LabeledSpan::new_primary_with_span(
+            Some("not an argument of this command".to_owned()),
+            miette::SourceSpan::new(self.part.offset.into(), self.part.length),
+        );
+        Some(Box::new(std::iter::once(label)))
+    }
+}
+
+/// Buffers that `ArgLocator::get_location_in()` and
+/// `ArgLocator::locate_into()` reuse across calls.
+#[derive(Default, Clone, Debug)]
+pub struct LocateScratch {
+    args: Vec<OsString>,
+    /// Number of the arguments in `args` that are in use.
+    len: usize,
+}
+
+impl LocateScratch {
+    /// Returns `Self` with room for `args` arguments of `arg_length`
+    /// bytes each before any reallocation.
+    pub fn with_capacity(args: usize, arg_length: usize) -> Self {
+        Self {
+            args: (0..args).map(|_| OsString::with_capacity(arg_length)).collect(),
+            len: 0,
+        }
+    }
+
+    /// Copies `args` into the buffers, reusing the `OsString`s that are
+    /// already allocated.
+    fn fill<R>(&mut self, args: R) -> &[OsString]
+    where
+        R: IntoIterator<Item: AsRef<std::ffi::OsStr>>,
+    {
+        self.len = 0;
+        for arg in args {
+            if self.len == self.args.len() {
+                self.args.push(OsString::new());
+            }
+            let buffer = &mut self.args[self.len];
+            buffer.clear();
+            buffer.push(arg);
+            self.len += 1;
+        }
+
+        &self.args[..self.len]
+    }
+}
+
+/// Error of `ArgLocator::try_get_label_span()` for an argument that is
+/// not valid unicode. As a `miette::Diagnostic`, it labels the argument
+/// in the Argv string rendered with `OsStr::to_string_lossy`.
+#[derive(Clone, Debug)]
+pub struct InvalidUnicode {
+    /// Argv index of the argument.
+    pub index: usize,
+    /// Part of the argument in the lossy Argv string.
+    pub span: ArgPart,
+    /// Raw bytes of the argument, see `OsStr::as_encoded_bytes`.
+    pub bytes: Vec<u8>,
+    /// The lossy Argv string, i.e. `args` joined by spaces.
+    pub lossy: String,
+}
+
+impl InvalidUnicode {
+    fn find(args: &[OsString]) -> Option<Self> {
+        let index = args.iter().position(|arg| arg.to_str().is_none())?;
+        let lossy = args.iter().map(|arg| arg.to_string_lossy()).collect::<Vec<_>>();
+        let offset = lossy[..index].iter().map(|arg| arg.len() + DELIMITER_LENGTH).sum();
+        Some(Self {
+            index,
+            span: ArgPart {
+                offset,
+                length: lossy[index].len(),
+            },
+            bytes: args[index].as_encoded_bytes().to_vec(),
+            lossy: lossy.join(" "),
+        })
+    }
+}
+
+impl std::fmt::Display for InvalidUnicode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "argument {} is not valid unicode", self.index)
+    }
+}
+
+impl std::error::Error for InvalidUnicode {}
+
+#[cfg(feature = "miette")]
+impl miette::Diagnostic for InvalidUnicode {
+    fn code<'a>(&'a self) -> Option<Box<dyn std::fmt::Display + 'a>> {
+        Some(Box::new(crate::code::INVALID_UNICODE))
+    }
+
+    fn source_code(&self) -> Option<&dyn miette::SourceCode> {
+        Some(&self.lossy)
+    }
+
+    fn labels(&self) -> Option<Box<dyn Iterator<Item = miette::LabeledSpan> + '_>> {
+        let label = miette::LabeledSpan::new_primary_with_span(
+            Some(format!("raw bytes: b\"{}\"", self.bytes.escape_ascii())),
+            miette::SourceSpan::new(self.span.offset.into(), self.span.length),
+        );
+        Some(Box::new(std::iter::once(label)))
+    }
+}
+
+/// How an argument consumes the values after it. See `walk()`.
+#[derive(Clone, Debug)]
+pub(crate) struct ArgShape {
+    pub(crate) discrete: bool,
+    /// How many values are taken from the tokens after the argument.
+    pub(crate) max_values: usize,
+    /// Ends the values of the argument, see `Arg::value_terminator()`.
+    pub(crate) value_terminator: Option<Rc<str>>,
+    pub(crate) allow_hyphen_values: bool,
+    /// Values like `-2` are taken even if `2` is a short.
+    pub(crate) allow_negative_numbers: bool,
+    /// The value can only follow an equal sign, not a space.
+    pub(crate) require_equals: bool,
+}
+
+impl ArgShape {
+    /// Whether the argument takes the next token as its value when it is
+    /// typed without one.
+    pub(crate) fn takes_next_value(&self) -> bool {
+        !self.discrete && !self.require_equals
+    }
+
+    /// Whether `parsed_arg`, which starts with a hyphen, is taken as the
+    /// value rather than a new argument.
+    pub(crate) fn takes_hyphen_value(&self, parsed_arg: &clap_lex::ParsedArg) -> bool {
+        self.allow_hyphen_values || self.allow_negative_numbers && parsed_arg.is_negative_number()
+    }
+
+    /// Whether `parsed_arg` is the value terminator of the argument.
+    pub(crate) fn is_value_terminator(&self, parsed_arg: &clap_lex::ParsedArg) -> bool {
+        self.value_terminator.as_deref().is_some_and(|terminator| parsed_arg.to_value_os() == terminator)
+    }
+
+    pub(crate) fn of(arg: &clap::Arg) -> Self {
+        let class = classify_arg(arg);
+        Self {
+            discrete: class == ArgClass::Flag,
+            max_values: match class {
+                ArgClass::Option { max_values, .. } => max_values,
+                ArgClass::Flag | ArgClass::Positional => 0,
+            },
+            value_terminator: arg.get_value_terminator().map(|terminator| Rc::from(terminator.as_str())),
+            allow_hyphen_values: arg.is_allow_hyphen_values_set(),
+            allow_negative_numbers: arg.is_allow_negative_numbers_set(),
+            require_equals: matches!(class, ArgClass::Option { require_equals: true, .. }),
+        }
+    }
+}
+
+/// How an argument takes values in the Argv. See `classify_arg()`.
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+#[non_exhaustive]
+pub enum ArgClass {
+    /// Never takes a value, e.g. `ArgAction::SetTrue`, `SetFalse`,
+    /// `Count`, `Help` and `Version`, regardless of its value parser.
+    Flag,
+    /// A long or short that takes `min_values..=max_values` values,
+    /// which only follow an equal sign if `require_equals` is set.
+    Option {
+        min_values: usize,
+        max_values: usize,
+        require_equals: bool,
+    },
+    /// Has neither a long nor a short.
+    Positional,
+}
+
+/// Classifies `arg` the same way the locator does. `Arg::num_args()`
+/// overrides what `Arg::get_action()` implies, so an `ArgAction::Set`
+/// with `num_args(0)` is a flag. Arguments of a built `clap::Command`
+/// are classified more accurately, as clap infers the unset settings
+/// while building.
+pub fn classify_arg(arg: &clap::Arg) -> ArgClass {
+    if arg.is_positional() {
+        return ArgClass::Positional;
+    }
+    let range = arg.get_num_args().unwrap_or(match arg.get_action().takes_values() {
+        true => clap::builder::ValueRange::SINGLE,
+        false => clap::builder::ValueRange::EMPTY,
+    });
+    if !range.takes_values() {
+        return ArgClass::Flag;
+    }
+
+    ArgClass::Option {
+        min_values: range.min_values(),
+        max_values: range.max_values(),
+        require_equals: arg.is_require_equals_set(),
+    }
+}
+
+/// Calls `visit` with the location of every argument in `args` that
+/// `lookup` knows, until `visit` returns `ControlFlow::Break`.
+pub(crate) fn walk<K, B>(
+    args: &[OsString],
+    lookup: impl Fn(&ArgAlias) -> Option<(K, ArgShape)>,
+    visit: impl FnMut(K, ArgLocation) -> ControlFlow<B>,
+    unknown: impl FnMut(ArgAlias, ArgPart) -> ControlFlow<()>,
+) -> Option<B> {
+    walk_to_terminator(args, lookup, visit, unknown, |_, _| ())
+}
+
+/// Same as `walk()` but also calls `terminator` with the part of the
+/// `--` that ends the walk, unless it is the value of an argument, and
+/// with the argument and the part of every value terminator that ends
+/// the values of an argument.
+pub(crate) fn walk_to_terminator<K, B>(
+    args: &[OsString],
+    lookup: impl Fn(&ArgAlias) -> Option<(K, ArgShape)>,
+    mut visit: impl FnMut(K, ArgLocation) -> ControlFlow<B>,
+    mut unknown: impl FnMut(ArgAlias, ArgPart) -> ControlFlow<()>,
+    mut terminator: impl FnMut(Option<&K>, ArgPart),
+) -> Option<B> {
+    let mut walker = Walker::new(args);
+    loop {
+        if let ControlFlow::Break(output) = walker.step(&lookup, &mut visit, &mut unknown, &mut terminator) {
+            return output;
+        }
+    }
+}
+
+/// The state of `walk_to_terminator()` between the tokens of an Argv,
+/// so that a walk can be resumed, e.g. by `Locations`.
+pub(crate) struct Walker {
+    raw: clap_lex::RawArgs,
+    cursor: clap_lex::ArgCursor,
+    /// Offset of the token next to the cursor in the Argv string.
+    offset: usize,
+    /// Whether the walk met a `--` or an unknown token that ends it.
+    ended: bool,
+}
+
+impl Walker {
+    pub(crate) fn new(args: &[OsString]) -> Self {
+        let raw = clap_lex::RawArgs::new(args);
+        let cursor = raw.cursor();
+        Self { raw, cursor, offset: 0, ended: false }
+    }
+
+    /// Walks the token next to the cursor, and the values after it that
+    /// belong to it. Breaks with `None` once the walk ended, or with the
+    /// output of `visit` if it breaks.
+    pub(crate) fn step<K, B>(
+        &mut self,
+        lookup: &impl Fn(&ArgAlias) -> Option<(K, ArgShape)>,
+        visit: &mut impl FnMut(K, ArgLocation) -> ControlFlow<B>,
+        unknown: &mut impl FnMut(ArgAlias, ArgPart) -> ControlFlow<()>,
+        terminator: &mut impl FnMut(Option<&K>, ArgPart),
+    ) -> ControlFlow<Option<B>> {
+        let Self { raw, cursor, offset, ended } = self;
+        if *ended {
+            return ControlFlow::Break(None);
+        }
+        let raw = &*raw;
+        // Returns the length of the argument next to the cursor if `found`
+        // takes it as its value.
+        let peek_value = |cursor: &clap_lex::ArgCursor, shape: &ArgShape| {
+            let peek = raw.peek(cursor)?;
+            if !shape.takes_next_value() || shape.is_value_terminator(&peek) {
+                return None;
+            }
+            // `--` is a value only for the arguments that allow hyphen
+            // values, clap ends the options there otherwise.
+            if peek.is_escape() && !shape.allow_hyphen_values {
+                return None;
+            }
+            // Arguments that start with `-` or `--` are not values unless
+            // hyphen values, or negative numbers for those like `-2`, are
+            // allowed.
+            if shape.takes_hyphen_value(&peek) || (peek.to_long().is_none() && peek.to_short().is_none()) {
+                return Some(peek.to_value_os().len());
+            }
+            None
+        };
+        // Takes the rest of the values of an argument that took `taken`
+        // values from the tokens after it, and returns the part of the value
+        // terminator that ends them, if any.
+        let rest_values = |cursor: &mut clap_lex::ArgCursor, offset: &mut usize, shape: &ArgShape, mut taken: usize| loop {
+            if taken >= shape.max_values {
+                return None;
+            }
+            let peek = raw.peek(cursor)?;
+            if shape.is_value_terminator(&peek) {
+                let part = ArgPart {
+                    offset: *offset,
+                    length: peek.to_value_os().len(),
+                };
+                raw.next(cursor);
+                *offset += part.length + DELIMITER_LENGTH;
+                return Some(part);
+            }
+            let value_length = peek_value(cursor, shape)?;
+            raw.next(cursor);
+            *offset += value_length + DELIMITER_LENGTH;
+            taken += 1;
+        };
+
+        let Some(parsed_arg) = raw.next(cursor) else {
+            *ended = true;
+            return ControlFlow::Break(None);
+        };
+        let token_offset = *offset;
+        *offset += parsed_arg.to_value_os().len() + DELIMITER_LENGTH;
+        // Everything after `--` is positional.
+        if parsed_arg.is_escape() {
+            terminator(None, ArgPart {
+                offset: token_offset,
+                length: TERMINATOR_LENGTH,
+            });
+            *ended = true;
+            return ControlFlow::Break(None);
+        }
+
+        if let Some((Ok(long), accompany)) = parsed_arg.to_long() {
+            let Some((found, shape)) = lookup(&ArgAlias::Long(long.to_string())) else {
+                let part = ArgPart {
+                    offset: token_offset,
+                    length: LONG_DECLARATION_LENGTH + long.len(),
+                };
+                if unknown(ArgAlias::Long(long.to_string()), part).is_break() {
+                    *ended = true;
+                    return ControlFlow::Break(None);
+                }
+                return ControlFlow::Continue(());
+            };
+            let declaration = ArgPart {
+                offset: token_offset,
+                length: LONG_DECLARATION_LENGTH,
+            };
+            let name = ArgPart {
+                offset: token_offset + LONG_DECLARATION_LENGTH,
+                length: long.len(),
+            };
+
+            let location = match accompany {
+                Some(value) => ArgLocation::new_complete(declaration, name, value.len()),
+                None => match peek_value(cursor, &shape) {
+                    Some(value_length) => {
+                        raw.next(cursor);
+                        *offset += value_length + DELIMITER_LENGTH;
+                        ArgLocation::new_complete(declaration, name, value_length)
+                    }
+                    None => ArgLocation::Discrete { declaration, name },
+                },
+            };
+            if accompany.is_none() && !shape.discrete {
+                let taken = usize::from(matches!(location, ArgLocation::Complete { .. }));
+                if let Some(part) = rest_values(cursor, offset, &shape, taken) {
+                    terminator(Some(&found), part);
+                }
+            }
+            if let ControlFlow::Break(output) = visit(found, location) {
+                return ControlFlow::Break(Some(output));
+            }
+        } else if let Some(mut shorts) = parsed_arg.to_short() {
+            let declaration = ArgPart {
+                offset: token_offset,
+                length: SHORT_DECLARATION_LENGTH,
+            };
+            let mut name = ArgPart {
+                offset: token_offset + SHORT_DECLARATION_LENGTH,
+                length: SHORT_LENGTH,
+            };
+            while let Some(Ok(short)) = shorts.next_flag() {
+                name.length = short.len_utf8();
+                let Some((found, shape)) = lookup(&ArgAlias::Short(short)) else {
+                    // Negative numbers are values rather than shorts.
+                    if !parsed_arg.is_negative_number() && unknown(ArgAlias::Short(short), name.clone()).is_break() {
+                        *ended = true;
+                        return ControlFlow::Break(None);
+                    }
+                    name.offset += name.length;
+                    continue;
+                };
+                if shape.discrete {
+                    let location = ArgLocation::Discrete { declaration: declaration.clone(), name: name.clone() };
+                    if let ControlFlow::Break(output) = visit(found, location) {
+                        return ControlFlow::Break(Some(output));
+                    }
+                    name.offset += name.length;
+                    continue;
+                }
+
+                // The rest of the shorts are the value of this one.
+                let location = match shorts.next_value_os() {
+                    Some(stuck) if stuck.as_encoded_bytes().starts_with(b"=") => {
+                        ArgLocation::new_complete(declaration, name, stuck.len() - 1)
+                    }
+                    Some(stuck) => {
+                        let content = ArgPart {
+                            offset: name.offset + name.length,
+                            length: stuck.len(),
+                        };
+                        ArgLocation::Stuck { declaration, name, content }
+                    }
+                    None => {
+                        let location = match peek_value(cursor, &shape) {
+                            Some(value_length) => {
+                                raw.next(cursor);
+                                *offset += value_length + DELIMITER_LENGTH;
+                                ArgLocation::new_complete(declaration, name, value_length)
+                            }
+                            None => ArgLocation::Discrete { declaration, name },
+                        };
+                        let taken = usize::from(matches!(location, ArgLocation::Complete { .. }));
+                        if let Some(part) = rest_values(cursor, offset, &shape, taken) {
+                            terminator(Some(&found), part);
+                        }
+                        location
+                    }
+                };
+                if let ControlFlow::Break(output) = visit(found, location) {
+                    return ControlFlow::Break(Some(output));
+                }
+                break;
+            }
+        }
+
+        ControlFlow::Continue(())
+    }
+}
+
+/// Returns the parts of the elements of `text`, which starts at
+/// `offset`, separated by `delimiter`. `text` is raw bytes, e.g. of an
+/// `OsStr` that is not valid unicode, so the parts are in raw bytes too.
+fn split_parts(offset: usize, text: &[u8], delimiter: char) -> Vec<ArgPart> {
+    let mut buffer = [0; 4];
+    let delimiter = delimiter.encode_utf8(&mut buffer).as_bytes();
+    let mut parts = vec![];
+    let mut start = 0;
+    let mut index = 0;
+    while index < text.len() {
+        if !text[index..].starts_with(delimiter) {
+            index += 1;
+            continue;
+        }
+        parts.push(ArgPart { offset: offset + start, length: index - start });
+        index += delimiter.len();
+        start = index;
+    }
+    parts.push(ArgPart {
+        offset: offset + start,
+        length: text.len() - start,
+    });
+
+    parts
+}
+
+/// Returns the longs, shorts and their aliases of `arg`.
+pub(crate) fn all_aliases(arg: &clap::Arg) -> impl Iterator<Item = ArgAlias> + '_ {
+    let longs = arg.get_all_aliases().unwrap_or_default().into_iter().chain(arg.get_long());
+    let shorts = arg.get_all_short_aliases().unwrap_or_default().into_iter().chain(arg.get_short());
+
+    longs.map(|long| ArgAlias::Long(long.to_owned())).chain(shorts.map(ArgAlias::Short))
+}
+
+/// Returns how `arg` is written in the messages about it: its long, its
+/// short, or its id if it is a positional.
+#[cfg(any(feature = "miette", feature = "dynamic-complete"))]
+pub(crate) fn display_name(arg: &clap::Arg) -> String {
+    if let Some(long) = arg.get_long() {
+        return format!("--{long}");
+    }
+    if let Some(short) = arg.get_short() {
+        return format!("-{short}");
+    }
+    arg.get_id().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::OsStr;
+
+    #[test]
+    fn test_clap_aliases_mapper() {
+        use clap::{Arg, Command};
+
+        let command = Command::new("app")
+            .arg(Arg::new("config").long("config").global(true))
+            .subcommand(Command::new("remote").subcommand(Command::new("push").arg(Arg::new("force").long("force").short_alias('F'))));
+        let mapper = Rc::new(ClapAliasesMapper::build_all(&command));
+        let id = |path: &[&str], alias| mapper.get(path, &alias).map(|arg| arg.get_id().to_string());
+        assert_eq!(id(&["remote", "push"], ArgAlias::Short('F')).as_deref(), Some("force"));
+        assert_eq!(id(&["remote", "push"], ArgAlias::Long("config".to_owned())).as_deref(), Some("config"));
+        assert_eq!(id(&[], ArgAlias::Long("force".to_owned())), None);
+        assert_eq!(id(&["push"], ArgAlias::Long("config".to_owned())), None);
+
+        let locator = mapper.locator(&["remote", "push"]);
+        let location = locator.get_location(["push", "-F", "--config=a"], "config").unwrap();
+        assert_eq!(location.declaration().offset, 8);
+    }
+
+    #[test]
+    fn test_from_command_factory_aliases() {
+        #[derive(clap::Parser)]
+        struct Args {
+            #[clap(short, long, visible_alias = "col", alias = "c", short_alias = 'C')]
+            color: Option<String>,
+        }
+
+        let locator = ArgLocator::from_command_factory::<Args>();
+        let declaration = |args: &[&str]| locator.get_location(args, "color").map(|location| location.declaration().clone());
+        for (args, length) in [(["program_name", "--color=a"], 2), (["program_name", "--col=a"], 2), (["program_name", "--c=a"], 2), (["program_name", "-ca"], 1), (["program_name", "-Ca"], 1)] {
+            assert_eq!(declaration(&args), Some(ArgPart { offset: 13, length }), "{args:?}");
+        }
+        // Long aliases of one character are not shorts.
+        assert_eq!(declaration(&["program_name", "--C=a"]), None);
+    }
+
+    #[test]
+    fn test_get_location_short_equals() {
+        #[derive(clap::Parser)]
+        struct Args {
+            #[clap(short)]
+            should_stick: String,
+        }
+
+        let locator = ArgLocator::from_command_factory::<Args>();
+        let env_args = ["program_name", "-s=Value"];
+        let location = locator.get_location(env_args, "should_stick");
+        assert_eq!(location, Some(ArgLocation::Complete {
+            declaration: ArgPart { offset: 13, length: 1 },
+            name: ArgPart { offset: 14, length: 1 },
+            delimiter: ArgPart { offset: 15, length: 1 },
+            content: ArgPart { offset: 16, length: 5 },
+        }));
+    }
+
+    #[test]
+    fn test_get_location_hyphen_values() {
+        #[derive(clap::Parser)]
+        struct Args {
+            #[clap(long, allow_hyphen_values=true)]
+            complete: String,
+            #[clap(short, allow_hyphen_values=true)]
+            stuck: f32,
+        }
+
+        let locator = ArgLocator::from_command_factory::<Args>();
+        let env_args = ["program_name", "--complete", "-.1", "-s-2."];
+        assert_eq!(locator.get_location(env_args, "complete"), Some(ArgLocation::Complete {
+            declaration: ArgPart { offset: 13, length: 2 },
+            name: ArgPart { offset: 15, length: 8 },
+            delimiter: ArgPart { offset: 23, length: 1 },
+            content: ArgPart { offset: 24, length: 3 },
+        }));
+        assert_eq!(locator.get_location(env_args, "stuck"), Some(ArgLocation::Stuck {
+            declaration: ArgPart { offset: 28, length: 1 },
+            name: ArgPart { offset: 29, length: 1 },
+            content: ArgPart { offset: 30, length: 3 },
+        }));
+    }
+
+    #[test]
+    fn test_wrapper_tokens() {
+        use clap::{Arg, ArgAction, Command};
+
+        let command = Command::new("app")
+            .arg(Arg::new("port").long("port"))
+            .arg(Arg::new("verbose").short('v').action(ArgAction::SetTrue))
+            .arg(Arg::new("file"));
+        let mut locator = ArgLocator::from_command(&command);
+        locator.wrapper_tokens = 2;
+        locator.unknown_tokens = UnknownTokenPolicy::CollectAndContinue;
+        // The wrapper tokens look like arguments of the program.
+        let args = ["sudo", "-v", "app", "--port=1", "-x", "--", "f"];
+
+        let location = locator.get_location(args, "port").unwrap();
+        assert_eq!(location.declaration(), &ArgPart { offset: 12, length: 2 });
+        assert_eq!(locator.get_location(args, "verbose"), None);
+        assert_eq!(locator.unknown_tokens(args).unwrap()[0].part, ArgPart { offset: 22, length: 1 });
+        assert_eq!(locator.get_terminator(args), Some(ArgPart { offset: 24, length: 2 }));
+
+        // Dropping the wrapper tokens from the displayed command line.
+        let shifted = location.shift(-8).unwrap();
+        assert_eq!(shifted.whole(), ArgPart { offset: 4, length: 8 });
+        assert_eq!(&args[2..].join(" ")[4..12], "--port=1");
+        assert_eq!(location.shift(-13), None);
+    }
+
+    #[test]
+    fn test_allow_negative_numbers() {
+        use clap::{Arg, ArgAction, Command};
+
+        let command = Command::new("app")
+            .arg(Arg::new("offset").long("offset").allow_negative_numbers(true))
+            .arg(Arg::new("one").short('1').action(ArgAction::SetTrue))
+            .arg(Arg::new("port").long("port"));
+        let locator = ArgLocator::from_command(&command);
+        let args = ["app", "--offset", "-12", "--port", "80"];
+        let matches = command.clone().try_get_matches_from(args).unwrap();
+        assert_eq!(matches.get_one::<String>("offset").map(String::as_str), Some("-12"));
+
+        let location = locator.get_location(args, "offset").unwrap();
+        assert_eq!(location.content(), Some(&ArgPart { offset: 13, length: 3 }));
+        assert_eq!(locator.get_location(args, "one"), None);
+        assert_eq!(locator.get_location(args, "port").unwrap().name().offset, 19);
+
+        let mut locator = locator;
+        locator.strategy = LocateStrategy::IndexDriven;
+        let location = locator.get_location_in_matches(args, &matches, "offset").unwrap();
+        assert_eq!(location.content(), Some(&ArgPart { offset: 13, length: 3 }));
+        assert_eq!(locator.get_location_in_matches(args, &matches, "port").unwrap().name().offset, 19);
+
+        // Other options still take `-1` as the short.
+        assert!(matches!(ArgLocator::from_command(&command).get_location(["app", "--port", "-1"], "port"), Some(ArgLocation::Discrete { .. })));
+    }
+
+    #[test]
+    fn test_num_args_ranges() {
+        use clap::{Arg, ArgAction, Command};
+
+        let command = Command::new("app")
+            .arg(Arg::new("opt").long("opt").num_args(0..=1).default_missing_value("dm").action(ArgAction::Append))
+            .arg(Arg::new("many").long("many").num_args(1..))
+            .arg(Arg::new("two").long("two").num_args(2))
+            .arg(Arg::new("verbose").short('v').action(ArgAction::SetTrue))
+            .arg(Arg::new("rest").num_args(0..));
+        let args = ["app", "--opt", "-v", "--many", "a", "b", "--two", "c", "d", "e", "--opt", "f"];
+        let matches = command.clone().try_get_matches_from(args).unwrap();
+        let values = |id| matches.get_many::<String>(id).unwrap().map(String::as_str).collect::<Vec<_>>();
+        assert_eq!((values("opt"), values("many"), values("two"), values("rest")), (vec!["dm", "f"], vec!["a", "b"], vec!["c", "d"], vec!["e"]));
+
+        let mut locator = ArgLocator::from_command(&command);
+        let content = |location: Option<ArgLocation>| location.and_then(|location| location.content().cloned());
+        assert!(matches!(locator.get_location(args, "opt"), Some(ArgLocation::Discrete { .. })));
+        assert_eq!(locator.get_location(args, "verbose").unwrap().name().offset, 11);
+        assert_eq!(content(locator.get_location(args, "many")), Some(ArgPart { offset: 20, length: 1 }));
+        assert_eq!(content(locator.get_location(args, "two")), Some(ArgPart { offset: 30, length: 1 }));
+
+        // The default missing value takes an index after `--opt`.
+        locator.strategy = LocateStrategy::IndexDriven;
+        assert!(matches!(locator.get_location_in_matches(args, &matches, "opt"), Some(ArgLocation::Discrete { .. })));
+        assert_eq!(locator.get_location_in_matches(args, &matches, "verbose").unwrap().name().offset, 11);
+        assert_eq!(content(locator.get_location_in_matches(args, &matches, "two")), Some(ArgPart { offset: 30, length: 1 }));
+        assert_eq!(locator.locate_positional(args, &matches, "rest"), [ArgPart { offset: 34, length: 1 }]);
+    }
+
+    #[test]
+    fn test_value_terminator() {
+        use clap::{Arg, ArgAction, Command};
+
+        let command = Command::new("app")
+            .arg(Arg::new("exec").long("exec").short('e').num_args(1..).allow_hyphen_values(true).value_terminator(";"))
+            .arg(Arg::new("pair").long("pair").num_args(2).value_terminator(";"))
+            .arg(Arg::new("verbose").short('v').action(ArgAction::SetTrue))
+            .arg(Arg::new("files").num_args(0..));
+        let mut locator = ArgLocator::from_command(&command);
+        let args = ["app", "-e", "rm", "-v", ";", "-v", "a"];
+        let matches = command.clone().try_get_matches_from(args).unwrap();
+        assert_eq!(matches.get_many::<String>("exec").unwrap().collect::<Vec<_>>(), ["rm", "-v"]);
+
+        assert_eq!(locator.get_location(args, "exec").unwrap().content(), Some(&ArgPart { offset: 7, length: 2 }));
+        assert_eq!(locator.get_value_terminator(args, "exec"), Some(ArgPart { offset: 13, length: 1 }));
+        assert_eq!(locator.get_location(args, "verbose").unwrap().name().offset, 16);
+        assert_eq!(locator.get_terminator(args), None);
+        locator.strategy = LocateStrategy::IndexDriven;
+        assert_eq!(locator.get_location_in_matches(args, &matches, "verbose").unwrap().name().offset, 16);
+        assert_eq!(locator.locate_positional(args, &matches, "files"), [ArgPart { offset: 18, length: 1 }]);
+
+        // The values end at the maximum before the terminator.
+        let args = ["app", "--pair", "a", "b", ";"];
+        let matches = command.clone().try_get_matches_from(args).unwrap();
+        assert_eq!(locator.get_value_terminator(args, "pair"), None);
+        assert_eq!(locator.locate_positional(args, &matches, "files"), [ArgPart { offset: 15, length: 1 }]);
+    }
+
+    #[test]
+    fn test_terminator() {
+        use clap::{Arg, ArgAction, Command};
+
+        let command = Command::new("app")
+            .arg(Arg::new("name").long("name"))
+            .arg(Arg::new("raw").long("raw").allow_hyphen_values(true))
+            .arg(Arg::new("verbose").short('v').action(ArgAction::SetTrue))
+            .arg(Arg::new("files").num_args(0..));
+        let locator = ArgLocator::from_command(&command);
+
+        // clap ends the options at `--` rather than taking it as a value.
+        let args = ["app", "--name", "--", "-v"];
+        assert!(matches!(locator.get_location(args, "name"), Some(ArgLocation::Discrete { .. })));
+        assert_eq!(locator.get_terminator(args), Some(ArgPart { offset: 11, length: 2 }));
+        assert_eq!(locator.get_location(args, "verbose"), None);
+
+        let args = ["app", "--raw", "--", "-v", "--", "-v"];
+        let matches = command.clone().try_get_matches_from(args).unwrap();
+        assert_eq!(matches.get_one::<String>("raw").map(String::as_str), Some("--"));
+        assert_eq!(locator.get_location(args, "raw").unwrap().content(), Some(&ArgPart { offset: 10, length: 2 }));
+        assert_eq!(locator.get_terminator(args), Some(ArgPart { offset: 16, length: 2 }));
+        assert_eq!(locator.get_location(args, "verbose").unwrap().name().offset, 14);
+        assert_eq!(locator.locate_positional(args, &matches, "files"), [ArgPart { offset: 19, length: 2 }]);
+
+        assert_eq!(locator.get_terminator(["app", "-v"]), None);
+    }
+
+    #[test]
+    fn test_get_location_unknown_and_weird_args() {
+        #[derive(clap::Parser)]
+        struct Args {
+            #[clap(short, long)]
+            port: u16,
+        }
+
+        let locator = ArgLocator::from_command_factory::<Args>();
+        let env_args = ["program_name", "--unknown", "-x", "-", "-=", "---", "--=", "--port=1"];
+        assert_eq!(locator.get_location(env_args, "port"), Some(ArgLocation::Complete {
+            declaration: ArgPart { offset: 39, length: 2 },
+            name: ArgPart { offset: 41, length: 4 },
+            delimiter: ArgPart { offset: 45, length: 1 },
+            content: ArgPart { offset: 46, length: 1 },
+        }));
+        assert_eq!(locator.get_location(["program_name", "--", "-p"], "nothing"), None);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::ffi::OsStrExt;
+            let invalid = OsStr::from_bytes(b"-\xff\xfep");
+            let env_args = [OsStr::new("program_name"), invalid, OsStr::new("--port"), OsStr::new("1")];
+            assert_eq!(locator.get_location(env_args, "port"), Some(ArgLocation::Complete {
+                declaration: ArgPart { offset: 18, length: 2 },
+                name: ArgPart { offset: 20, length: 4 },
+                delimiter: ArgPart { offset: 24, length: 1 },
+                content: ArgPart { offset: 25, length: 1 },
+            }));
+        }
+    }
+
+    #[test]
+    fn test_get_location_stuck_new_bool_and_hyphen_string() {
+        #[derive(Clone)]
+        #[allow(dead_code)]
+        struct NewFlag(bool);
+        #[derive(clap::Parser)]
         struct Args {
-            #[clap(short, value_parser=clap::builder::BoolishValueParser::new())]
+            #[clap(short, action=clap::ArgAction::SetTrue, value_parser=clap::builder::TypedValueParser::map(clap::builder::BoolishValueParser::new(), NewFlag))]
             new_flag: NewFlag,
             #[clap(short)]
             should_stick: String,
+            #[clap(short)]
             primitive_flag: bool,
         }
 
@@ -390,12 +2394,411 @@ mod tests {
             declaration: ArgPart { offset: 13, length: 1 },
             name: ArgPart { offset: 15, length: 1 },
         }));
-        assert_eq!(locator.get_location(args_env.clone(), "should_stuck"), Some(ArgLocation::Stuck {
+        assert_eq!(locator.get_location(args_env.clone(), "should_stick"), Some(ArgLocation::Stuck {
             declaration: ArgPart { offset: 13, length: 1 },
             name: ArgPart { offset: 16, length: 1 },
             content: ArgPart { offset: 17, length: 2 },
         }));
     }
+
+    #[test]
+    fn test_classify_arg() {
+        use clap::{Arg, ArgAction};
+
+        assert_eq!(classify_arg(&Arg::new("quiet").long("quiet").action(ArgAction::SetFalse)), ArgClass::Flag);
+        assert_eq!(classify_arg(&Arg::new("verbose").short('v').action(ArgAction::Count)), ArgClass::Flag);
+        assert_eq!(classify_arg(&Arg::new("dry").long("dry").num_args(0)), ArgClass::Flag);
+        assert_eq!(classify_arg(&Arg::new("color").long("color").num_args(0..=1).require_equals(true)), ArgClass::Option {
+            min_values: 0,
+            max_values: 1,
+            require_equals: true,
+        });
+        assert_eq!(classify_arg(&Arg::new("name")), ArgClass::Positional);
+
+        let command = clap::Command::new("app")
+            .arg(Arg::new("color").long("color").short('c').num_args(0..=1).require_equals(true).default_missing_value("always"))
+            .arg(Arg::new("name"));
+        let mut locator = ArgLocator::from_command(&command);
+        assert_eq!(locator.get_location(["app", "--color", "auto"], "color").and_then(|location| location.content().cloned()), None);
+
+        // clap takes the adjacent token as a positional rather than the value.
+        for args in [["app", "--color", "auto"], ["app", "-c", "auto"]] {
+            let matches = command.clone().try_get_matches_from(args).unwrap();
+            assert_eq!(matches.get_one::<String>("name").map(String::as_str), Some("auto"));
+            locator.strategy = LocateStrategy::IndexDriven;
+            assert!(matches!(locator.get_location_in_matches(args, &matches, "color"), Some(ArgLocation::Discrete { .. })), "{args:?}");
+            let offset = args[..2].join(" ").len() + 1;
+            assert_eq!(locator.locate_positional(args, &matches, "name"), [ArgPart { offset, length: 4 }]);
+        }
+    }
+
+    #[test]
+    fn test_get_label_span() {
+        #[derive(clap::Parser)]
+        struct Args {
+            #[clap(long)]
+            port: u16,
+        }
+
+        let mut locator = ArgLocator::from_command_factory::<Args>();
+        let env_args = ["program_name", "--port", "80"];
+        let span = |locator: &ArgLocator<_, _>| locator.get_label_span(env_args, "port").map(|(_, part)| part);
+        assert_eq!(span(&locator), Some(ArgPart { offset: 20, length: 2 }));
+        locator.include_arg_name = true;
+        assert_eq!(span(&locator), Some(ArgPart { offset: 13, length: 9 }));
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::ffi::OsStrExt;
+            let env_args = [OsStr::new("program_name"), OsStr::from_bytes(b"\xff"), OsStr::new("--port=1")];
+            assert_eq!(locator.get_label_span(env_args, "port"), None);
+            locator.force_lossy_os_string = true;
+            let (full_command, part) = locator.get_label_span(env_args, "port").unwrap();
+            assert_eq!(&full_command[part.offset..part.offset + part.length], "--port=1");
+        }
+    }
+
+    #[test]
+    fn test_get_location_in_matches() {
+        use clap::{Arg, ArgAction, Command};
+
+        let command = Command::new("app")
+            .arg(Arg::new("verbose").short('v').action(ArgAction::SetTrue))
+            .arg(Arg::new("define").long("define").value_delimiter(','))
+            .arg(Arg::new("output").short('o').action(ArgAction::Append))
+            .arg(Arg::new("pair").long("pair").num_args(2))
+            .arg(Arg::new("rest").num_args(0..));
+        let mut locator = ArgLocator::from_command(&command);
+        locator.strategy = LocateStrategy::IndexDriven;
+        let locate = |args: &[&str], arg| locator.get_location_in_matches(args, &command.clone().get_matches_from(args), arg);
+
+        let args = ["app", "-vo=X", "--define=1,2", "p", "--", "q", "-v"];
+        assert_eq!(locate(&args, "verbose"), Some(ArgLocation::Discrete {
+            declaration: ArgPart { offset: 4, length: 1 },
+            name: ArgPart { offset: 5, length: 1 },
+        }));
+        assert_eq!(locate(&args, "output"), Some(ArgLocation::Complete {
+            declaration: ArgPart { offset: 4, length: 1 },
+            name: ArgPart { offset: 6, length: 1 },
+            delimiter: ArgPart { offset: 7, length: 1 },
+            content: ArgPart { offset: 8, length: 1 },
+        }));
+        assert_eq!(locate(&args, "define").unwrap().content(), Some(&ArgPart { offset: 19, length: 3 }));
+        assert_eq!(locate(&args, "rest"), None);
+
+        let args = ["app", "--define", "1,2", "--pair", "3", "4", "-oY"];
+        assert_eq!(locate(&args, "define").unwrap().content(), Some(&ArgPart { offset: 13, length: 3 }));
+        assert_eq!(locate(&args, "pair").unwrap().content(), Some(&ArgPart { offset: 24, length: 1 }));
+        assert_eq!(locate(&args, "output"), Some(ArgLocation::Stuck {
+            declaration: ArgPart { offset: 28, length: 1 },
+            name: ArgPart { offset: 29, length: 1 },
+            content: ArgPart { offset: 30, length: 1 },
+        }));
+    }
+
+    #[test]
+    fn test_to_token_location() {
+        #[derive(clap::Parser)]
+        struct Args {
+            #[clap(short)]
+            verbose: bool,
+            #[clap(long)]
+            port: u16,
+        }
+
+        let locator = ArgLocator::from_command_factory::<Args>();
+        let env_args = ["program_name", "-v", "--port", "80"];
+        let location = locator.get_location(env_args, "port").unwrap();
+        assert_eq!(location.content().unwrap().to_token_location(&env_args), Some(TokenLocation {
+            token_index: 3,
+            intra_token_range: 0..2,
+        }));
+        assert_eq!(location.name().to_token_location(&env_args), Some(TokenLocation {
+            token_index: 2,
+            intra_token_range: 2..6,
+        }));
+        assert_eq!(location.whole().to_token_location(&env_args), None);
+        let location = locator.get_location(env_args, "verbose").unwrap();
+        assert_eq!(location.name().to_token_location(&env_args).unwrap().token_index, 1);
+    }
+
+    #[test]
+    fn test_locate_scratch() {
+        #[derive(clap::Parser)]
+        struct Args {
+            #[clap(short)]
+            verbose: bool,
+            #[clap(long)]
+            port: u16,
+        }
+
+        let locator = ArgLocator::from_command_factory::<Args>();
+        let mut scratch = LocateScratch::with_capacity(4, 16);
+        let mut locations = vec![];
+        for env_args in [&["program_name", "--port", "80", "-v"][..], &["program_name", "--port=1"]] {
+            assert_eq!(locator.get_location_in(&mut scratch, env_args, "port"), locator.get_location(env_args, "port"));
+            locator.locate_into(&mut scratch, env_args, &["verbose", "port"], &mut locations);
+            assert_eq!(locations, [locator.get_location(env_args, "verbose"), locator.get_location(env_args, "port")]);
+        }
+        assert_eq!(scratch.args.len(), 4);
+    }
+
+    #[test]
+    fn test_unknown_tokens() {
+        #[derive(clap::Parser)]
+        struct Args {
+            #[clap(short)]
+            verbose: bool,
+            #[clap(long)]
+            port: u16,
+        }
+
+        let mut locator = ArgLocator::from_command_factory::<Args>();
+        let env_args = ["program_name", "--nope", "-1", "-vx", "--port=1"];
+        assert_eq!(locator.unknown_tokens(env_args), Ok(vec![]));
+        assert!(locator.get_location(env_args, "port").is_some());
+
+        locator.unknown_tokens = UnknownTokenPolicy::CollectAndContinue;
+        let unknown = locator.unknown_tokens(env_args).unwrap();
+        assert_eq!(unknown.iter().map(|token| token.alias.to_string()).collect::<Vec<_>>(), ["--nope", "-x"]);
+        assert_eq!(unknown[1].part, ArgPart { offset: 25, length: 1 });
+        assert!(locator.get_location(env_args, "port").is_some());
+
+        locator.unknown_tokens = UnknownTokenPolicy::Error;
+        let error = locator.unknown_tokens(env_args).unwrap_err();
+        assert_eq!(error.to_string(), "unknown argument `--nope`");
+        assert_eq!(error.part, ArgPart { offset: 13, length: 6 });
+        assert!(locator.get_location(env_args, "port").is_none());
+        assert_eq!(locator.try_get_location(env_args, "port"), Err(error));
+        assert!(locator.get_location(["program_name", "-v", "--nope"], "verbose").is_some());
+        locator.wrapper_tokens = 1;
+        assert_eq!(locator.try_get_location(["sudo", "program_name", "-vx"], "port").unwrap_err().part, ArgPart { offset: 20, length: 1 });
+    }
+
+    #[test]
+    fn test_try_get_label_span() {
+        #[derive(clap::Parser)]
+        struct Args {
+            #[clap(long)]
+            port: u16,
+        }
+
+        let mut locator = ArgLocator::from_command_factory::<Args>();
+        locator.force_lossy_os_string = true;
+        assert_eq!(locator.try_get_label_span(["program_name", "--port=1"], "port").unwrap().unwrap().1, ArgPart { offset: 20, length: 1 });
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::ffi::OsStrExt;
+            let env_args = [OsStr::new("program_name"), OsStr::new("--port=1"), OsStr::from_bytes(b"a\xffb")];
+            let error = locator.try_get_label_span(env_args, "port").unwrap_err();
+            assert_eq!(error.to_string(), "argument 2 is not valid unicode");
+            assert_eq!((error.index, error.bytes.as_slice()), (2, b"a\xffb".as_slice()));
+            assert_eq!(&error.lossy[error.span.offset..error.span.offset + error.span.length], "a\u{fffd}b");
+            #[cfg(feature = "miette")]
+            assert_eq!(miette::Diagnostic::labels(&error).unwrap().next().unwrap().label(), Some("raw bytes: b\"a\\xffb\""));
+        }
+    }
+
+    #[test]
+    fn test_typed_alias() {
+        #[derive(clap::Parser)]
+        struct Args {
+            #[clap(short, long, alias = "col", short_alias = 'C')]
+            color: bool,
+        }
+
+        let locator = ArgLocator::from_command_factory::<Args>();
+        for (typed, expected) in [("--col", ArgAlias::Long("col".to_owned())), ("-C", ArgAlias::Short('C')), ("-c", ArgAlias::Short('c'))] {
+            let location = locator.get_location(["program_name", typed], "color").unwrap();
+            assert_eq!(location.typed_alias(&format!("program_name {typed}")), Some(expected));
+        }
+    }
+
+    #[test]
+    fn test_locate_batch() {
+        #[derive(clap::Parser)]
+        struct Args {
+            #[clap(short)]
+            verbose: bool,
+            #[clap(long)]
+            port: u16,
+        }
+
+        let records = vec![
+            vec!["program_name".into(), "--port".into(), "1".into(), "-v".into()],
+            vec!["program_name".into(), "--port=22".into()],
+        ];
+        let locator = ArgLocator::from_command_factory::<Args>();
+        let targets: &[&str] = &["verbose", "port", "verbose"];
+        let batch = locator.locate_batch(records.clone(), targets);
+        for (record, locations) in records.iter().zip(&batch) {
+            for (target, location) in targets.iter().zip(locations) {
+                assert_eq!(location, &locator.get_location(record, *target));
+            }
+        }
+        assert_eq!(batch[1][0], None);
+        assert_eq!(batch[1][1].as_ref().map(ArgLocation::whole), Some(ArgPart { offset: 13, length: 9 }));
+
+        #[cfg(feature = "rayon")]
+        assert_eq!(ArgLocator::locate_batch_par(&<Args as CommandFactory>::command(), &records, targets), batch);
+    }
+
+    #[test]
+    fn test_visit_locations() {
+        #[derive(clap::Parser)]
+        struct Args {
+            #[clap(short)]
+            verbose: bool,
+            #[clap(short, long)]
+            port: u16,
+            #[clap(long)]
+            name: Option<String>,
+        }
+
+        let locator = ArgLocator::from_command_factory::<Args>();
+        let env_args = ["program_name", "-vp1", "--name", "a", "-v"];
+        let mut visited = vec![];
+        let stopped = locator.visit_locations(env_args, |id, location| {
+            visited.push((id.to_string(), location.whole()));
+            match visited.len() {
+                3 => ControlFlow::Break("name"),
+                _ => ControlFlow::Continue(()),
+            }
+        });
+        assert_eq!(stopped, Some("name"));
+        assert_eq!(visited, [
+            ("verbose".to_owned(), ArgPart { offset: 13, length: 2 }),
+            ("port".to_owned(), ArgPart { offset: 13, length: 4 }),
+            ("name".to_owned(), ArgPart { offset: 18, length: 8 }),
+        ]);
+        assert_eq!(locator.visit_locations(env_args, |_, _| ControlFlow::<()>::Continue(())), None);
+    }
+
+    #[test]
+    fn test_locate_values() {
+        let command = clap::Command::new("app")
+            .arg(clap::Arg::new("verbose").short('v').action(clap::ArgAction::SetTrue))
+            .arg(clap::Arg::new("files").short('f').long("files").num_args(1..).value_delimiter(',').action(clap::ArgAction::Append))
+            .arg(clap::Arg::new("name").long("name"))
+            .arg(clap::Arg::new("color").long("color").num_args(0..=1).default_missing_value("always"));
+        let locator = ArgLocator::from_command(&command);
+        let args = ["app", "-f", "a,b", "c", "-v", "--files=d", "--name", "n", "--color"];
+        let matches = command.clone().get_matches_from(args);
+        assert_eq!(locator.locate_values(args, &matches, "files"), [
+            ArgPart { offset: 7, length: 1 },
+            ArgPart { offset: 9, length: 1 },
+            ArgPart { offset: 11, length: 1 },
+            ArgPart { offset: 24, length: 1 },
+        ]);
+        assert_eq!(locator.locate_values(args, &matches, "name"), [ArgPart { offset: 33, length: 1 }]);
+        assert_eq!(locator.locate_values(args, &matches, "color"), []);
+        assert_eq!(locator.locate_values(args, &matches, "verbose"), []);
+    }
+
+    #[test]
+    fn test_get_locations_all() {
+        let command = clap::Command::new("app")
+            .arg(clap::Arg::new("verbose").short('v').long("verbose").action(clap::ArgAction::Count))
+            .arg(clap::Arg::new("quiet").short('q').action(clap::ArgAction::SetTrue));
+        let locator = ArgLocator::from_command(&command);
+        // Every repetition of a short in a cluster is an occurrence.
+        let args = ["app", "-vqvv", "--verbose", "-v"];
+        let locations = locator.get_locations_all(args, "verbose");
+        let names = locations.iter().map(|(index, location)| (*index, location.name().clone())).collect::<Vec<_>>();
+        assert_eq!(names, [
+            (0, ArgPart { offset: 5, length: 1 }),
+            (1, ArgPart { offset: 7, length: 1 }),
+            (2, ArgPart { offset: 8, length: 1 }),
+            (3, ArgPart { offset: 12, length: 7 }),
+            (4, ArgPart { offset: 21, length: 1 }),
+        ]);
+        assert_eq!(locations[2].1.declaration(), &ArgPart { offset: 4, length: 1 });
+        assert!(locator.get_locations_all(args, "nope").is_empty());
+        for (index, location) in &locations {
+            assert_eq!(locator.get_location_nth(args, "verbose", *index).as_ref(), Some(location));
+        }
+        assert_eq!(locator.get_location_nth(args, "verbose", 5), None);
+    }
+
+    #[test]
+    fn test_offset_unit() {
+        use clap::{Arg, ArgAction, Command};
+
+        let command = Command::new("app")
+            .arg(Arg::new("name").long("name"))
+            .arg(Arg::new("verbose").short('v').action(ArgAction::SetTrue))
+            .arg(Arg::new("ids").long("ids").value_delimiter(','));
+        // `é` is 2 bytes and 1 UTF-16 unit, `😀` is 4 bytes and 2 units.
+        let args = ["app", "--name", "é😀", "-v", "--ids=😀,x"];
+        let mut locator = ArgLocator::from_command(&command);
+        let parts = |locator: &ArgLocator<_, _>| {
+            let name = locator.get_location(args, "name").unwrap().content().unwrap().clone();
+            let verbose = locator.get_location(args, "verbose").unwrap().name().clone();
+            let elements = locator.get_value_elements(args, "ids").unwrap();
+            (name, verbose, elements)
+        };
+        let part = |offset, length| ArgPart { offset, length };
+        assert_eq!(parts(&locator), (part(11, 6), part(19, 1), vec![part(27, 4), part(32, 1)]));
+        locator.offset_unit = OffsetUnit::Chars;
+        assert_eq!(parts(&locator), (part(11, 2), part(15, 1), vec![part(23, 1), part(25, 1)]));
+        locator.offset_unit = OffsetUnit::Utf16;
+        assert_eq!(parts(&locator), (part(11, 3), part(16, 1), vec![part(24, 2), part(27, 1)]));
+
+        // Every API converts the same way.
+        let location = locator.get_location(args, "verbose");
+        assert_eq!(locator.get_location_nth(args, "verbose", 0), location);
+        assert_eq!(locator.get_locations_all(args, "verbose")[0].1, location.clone().unwrap());
+        assert_eq!(locator.get_locations(args, &["verbose"])[0], location);
+        assert_eq!(locator.index(args).get("verbose"), location.as_ref());
+        assert_eq!(locator.iter_locations(args).nth(1).map(|(_, location)| location), location);
+        assert_eq!(locator.locate_batch([args.map(OsString::from).to_vec()], &["verbose"])[0][0], location);
+        let matches = command.clone().get_matches_from(args);
+        assert_eq!(locator.locate_values(args, &matches, "name"), [part(11, 3)]);
+    }
+
+    #[test]
+    fn test_get_locations() {
+        let command = clap::Command::new("app")
+            .arg(clap::Arg::new("verbose").short('v').action(clap::ArgAction::Count))
+            .arg(clap::Arg::new("port").long("port"))
+            .arg(clap::Arg::new("name").long("name"));
+        let locator = ArgLocator::from_command(&command);
+        let args = ["app", "--port", "1", "-vv", "--name=x"];
+        let [name, verbose, port, nope] = locator.get_locations(args, &["name", "verbose", "port", "nope"]);
+        assert_eq!(name, locator.get_location(args, "name"));
+        assert_eq!(verbose, locator.get_location(args, "verbose"));
+        assert_eq!(port, locator.get_location(args, "port"));
+        assert_eq!(nope, None);
+        assert_eq!(locator.get_locations(args, &[] as &[&str; 0]), []);
+    }
+
+    #[test]
+    fn test_get_value_elements() {
+        let command = clap::Command::new("app")
+            .arg(clap::Arg::new("verbose").short('v').action(clap::ArgAction::SetTrue))
+            .arg(clap::Arg::new("ids").short('i').value_delimiter(','))
+            .arg(clap::Arg::new("name").long("name"));
+        let locator = ArgLocator::from_command(&command);
+        let args = ["app", "-vi1,,x", "--name", "a,b"];
+        assert_eq!(locator.get_value_elements(args, "ids"), Some(vec![
+            ArgPart { offset: 7, length: 1 },
+            ArgPart { offset: 9, length: 0 },
+            ArgPart { offset: 10, length: 1 },
+        ]));
+        assert_eq!(locator.get_value_elements(args, "name"), Some(vec![ArgPart { offset: 19, length: 3 }]));
+        assert_eq!(locator.get_value_elements(args, "verbose"), None);
+        assert_eq!(ArgPart { offset: 20, length: 5 }.split("app", ','), [ArgPart { offset: 20, length: 5 }]);
+        // Invalid unicode before the value does not shift the elements.
+        #[cfg(unix)]
+        {
+            use std::os::unix::ffi::OsStrExt;
+            let args = [OsStr::new("app"), OsStr::from_bytes(b"\xff\xfe"), OsStr::new("-i1,x")];
+            assert_eq!(locator.get_value_elements(args, "ids"), Some(vec![ArgPart { offset: 9, length: 1 }, ArgPart { offset: 11, length: 1 }]));
+            let args = [OsStr::new("app"), OsStr::new("-i"), OsStr::from_bytes(b"\xff,x")];
+            assert_eq!(locator.get_value_elements(args, "ids"), Some(vec![ArgPart { offset: 7, length: 1 }, ArgPart { offset: 9, length: 1 }]));
+        }
+    }
     //
     // #[bench]
     // fn bench_get_location_repeated() {
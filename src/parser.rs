@@ -0,0 +1,402 @@
+//! Parsing with `clap::Parser` while reporting the errors of clap as
+//! `miette` reports that label the offending part of the command line.
+
+use std::ffi::OsString;
+
+use clap::{
+    error::{ContextKind, ContextValue, ErrorKind},
+    ArgMatches, Command, Parser,
+};
+use miette::{LabeledSpan, MietteDiagnostic, Report, SourceSpan};
+
+use crate::{
+    code,
+    deprecation::Deprecations,
+    lossy::LossyArgv,
+    parse::{ArgLocator, UnknownTokenPolicy},
+    provenance::{provenances, Provenance},
+    report::ReportContext,
+    scope::{locate_scoped, scan_scoped},
+    suggest::{env_override_advisories, missing_required_hint, suggest_subcommand, Suggested},
+};
+
+/// Extends every `clap::Parser` with parsing functions that report
+/// errors with spans. See `error_report()`.
+pub trait FancyParser: Parser {
+    /// Same as `Parser::parse()` but prints a report for the errors
+    /// that `error_report()` can label. Help and version are still
+    /// printed by clap.
+    fn parse_fancy() -> Self {
+        Self::parse_fancy_from(std::env::args_os())
+    }
+
+    /// Same as `Self::parse_fancy()` but with the given `args`.
+    fn parse_fancy_from<I, T>(args: I) -> Self
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone,
+    {
+        let args = args.into_iter().map(Into::into).collect::<Vec<OsString>>();
+        match Self::try_parse_from(&args) {
+            Ok(parsed) => parsed,
+            Err(error) => match error_report(&Self::command(), &args, &error) {
+                Some(report) if error.use_stderr() => {
+                    eprintln!("{report:?}");
+                    std::process::exit(error.exit_code());
+                }
+                _ => error.exit(),
+            },
+        }
+    }
+
+    /// Same as `Parser::try_parse()` but the error is a report, which
+    /// is labelled if `error_report()` can, or has the message of clap
+    /// otherwise.
+    fn try_parse_fancy() -> Result<Self, Report> {
+        Self::try_parse_fancy_from(std::env::args_os())
+    }
+
+    /// Same as `Self::try_parse_fancy()` but with the given `args`.
+    fn try_parse_fancy_from<I, T>(args: I) -> Result<Self, Report>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone,
+    {
+        let args = args.into_iter().map(Into::into).collect::<Vec<OsString>>();
+        Self::try_parse_from(&args).map_err(|error| {
+            error_report(&Self::command(), &args, &error).unwrap_or_else(|| Report::msg(error.render().to_string().trim_end().to_owned()))
+        })
+    }
+}
+
+impl<P: Parser> FancyParser for P {}
+
+/// Runs a `clap::Command` of the builder API end to end: parsing,
+/// reporting the errors of clap with spans, checking deprecations and
+/// env variables that are overridden, and computing the provenance of
+/// the top-level arguments. The counterpart of `FancyParser` for
+/// derive users.
+pub struct FancyCommand {
+    command: Command,
+    pub deprecations: Deprecations,
+    /// See `provenances()`. Filled by `Self::try_run_from()`.
+    pub provenances: Vec<(String, Provenance)>,
+}
+
+impl FancyCommand {
+    pub fn wrap(command: Command) -> Self {
+        Self {
+            command,
+            deprecations: Deprecations::default(),
+            provenances: vec![],
+        }
+    }
+
+    pub fn deprecations(mut self, deprecations: Deprecations) -> Self {
+        self.deprecations = deprecations;
+        self
+    }
+
+    pub fn command(&self) -> &Command {
+        &self.command
+    }
+
+    /// Same as `Self::try_run_from()` with `std::env::args_os()`, but
+    /// prints the report and exits on errors like `FancyParser::parse_fancy()`.
+    pub fn run(&mut self) -> (ArgMatches, ReportContext) {
+        let args = std::env::args_os().collect::<Vec<_>>();
+        match self.command.clone().try_get_matches_from(&args) {
+            Ok(matches) => {
+                let context = self.check(&args, &matches);
+                (matches, context)
+            }
+            Err(error) => match error_report(&self.command, &args, &error) {
+                Some(report) if error.use_stderr() => {
+                    eprintln!("{report:?}");
+                    std::process::exit(error.exit_code());
+                }
+                _ => error.exit(),
+            },
+        }
+    }
+
+    /// Parses `args` and returns the matches and a context with the
+    /// warnings about deprecations and overridden env variables queued,
+    /// and configured by the arguments of the `augment_*()` functions.
+    /// Errors of clap are returned as reports, see `error_report()`.
+    pub fn try_run_from<I, T>(&mut self, args: I) -> Result<(ArgMatches, ReportContext), Report>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone,
+    {
+        let args = args.into_iter().map(Into::into).collect::<Vec<OsString>>();
+        let matches = self.command.clone().try_get_matches_from(&args).map_err(|error| {
+            error_report(&self.command, &args, &error).unwrap_or_else(|| Report::msg(error.render().to_string().trim_end().to_owned()))
+        })?;
+        let context = self.check(&args, &matches);
+
+        Ok((matches, context))
+    }
+
+    fn check(&mut self, args: &[OsString], matches: &ArgMatches) -> ReportContext {
+        let mut context = ReportContext::default();
+        context.configure(matches);
+        for warning in self.deprecations.check(&self.command, args) {
+            context.warn(warning);
+        }
+        for advisory in env_override_advisories(&self.command, args, matches) {
+            context.warn(advisory);
+        }
+        self.provenances = provenances(&self.command, args, matches);
+
+        context
+    }
+}
+
+/// Returns a report that labels where `error` of clap happened in
+/// `args`, for unknown arguments, invalid values, missing required
+/// arguments, unrecognized subcommands and conflicting arguments, which
+/// are all labelled in the same report. Returns `None` for the other
+/// kinds of errors, or if the offending part cannot be found.
+pub fn error_report<R>(command: &Command, args: R, error: &clap::Error) -> Option<Report>
+where
+    R: IntoIterator<Item: Into<OsString>>,
+{
+    let args = args.into_iter().map(Into::into).collect::<Vec<OsString>>();
+    match error.kind() {
+        ErrorKind::MissingRequiredArgument => missing_required_hint(command, args, error),
+        ErrorKind::InvalidSubcommand => suggest_subcommand(command, args),
+        ErrorKind::UnknownArgument => unknown_argument(command, args, error),
+        ErrorKind::InvalidValue | ErrorKind::ValueValidation => invalid_value(command, args, error),
+        ErrorKind::ArgumentConflict => argument_conflict(command, args, error),
+        _ => None,
+    }
+}
+
+fn context_str(error: &clap::Error, kind: ContextKind) -> Option<&str> {
+    match error.get(kind)? {
+        ContextValue::String(value) => Some(value),
+        _ => None,
+    }
+}
+
+fn unknown_argument(command: &Command, args: Vec<OsString>, error: &clap::Error) -> Option<Report> {
+    let invalid = context_str(error, ContextKind::InvalidArg)?;
+    let mut built = command.clone();
+    built.build();
+    // The arguments of a subcommand start at its name, and the token is
+    // unknown to the subcommand that it was typed after.
+    let (_, subcommands) = scan_scoped(&built, &args);
+    let mut levels = vec![(0, &built)];
+    for &start in &subcommands {
+        levels.push((start, levels.last()?.1.find_subcommand(&args[start])?));
+    }
+    let ends = subcommands.iter().copied().chain([args.len()]);
+    let part = levels.into_iter().zip(ends).find_map(|((start, level), end)| {
+        let mut locator = ArgLocator::from_command(level);
+        locator.unknown_tokens = UnknownTokenPolicy::Error;
+        let token = locator.unknown_tokens(&args[start..end]).err().filter(|token| token.alias.to_string() == invalid)?;
+        let shift = args[..start].iter().map(|arg| arg.len() + 1).sum::<usize>();
+        token.part.shift(shift as isize)
+    })?;
+    let argv = LossyArgv::new(&args);
+    let part = argv.remap_part(&part);
+    let span = SourceSpan::new(part.offset.into(), part.length);
+
+    let mut diagnostic = MietteDiagnostic::new(format!("unexpected argument `{invalid}`"))
+        .with_code(code::UNKNOWN_TOKEN)
+        .with_label(LabeledSpan::new_primary_with_span(Some("not an argument of this command".to_owned()), span));
+    let suggested = context_str(error, ContextKind::SuggestedArg);
+    if let Some(suggested) = suggested {
+        diagnostic = diagnostic.with_help(format!("did you mean `{suggested}`?"));
+    }
+    let mut report = Suggested::new(diagnostic).with_source_code(argv.lossy);
+    // Only longs are replaced, a short may be in the middle of a cluster.
+    if let Some(suggested) = suggested.filter(|suggested| suggested.starts_with("--") && invalid.starts_with("--")) {
+        report = report.with_suggestion(span, suggested);
+    }
+
+    Some(Report::from(report))
+}
+
+fn invalid_value(command: &Command, args: Vec<OsString>, error: &clap::Error) -> Option<Report> {
+    let rendered = context_str(error, ContextKind::InvalidArg)?;
+    let value = context_str(error, ContextKind::InvalidValue)?;
+    let mut built = command.clone();
+    built.build();
+    let id = find_id(&built, rendered)?;
+    let full_command = args.iter().map(|arg| arg.to_string_lossy()).collect::<Vec<_>>().join(" ");
+    let content = locate_scoped(&built, &args).into_iter().find_map(|located| {
+        let content = located.location.content()?;
+        (located.id == id && full_command.get(content.offset..content.offset + content.length) == Some(value)).then(|| content.clone())
+    })?;
+    let span = SourceSpan::new(content.offset.into(), content.length);
+
+    // Validators explain themselves, e.g. "99999 is not in 0..=65535".
+    let label = std::error::Error::source(error).map_or_else(|| "invalid value".to_owned(), ToString::to_string);
+    let mut diagnostic = MietteDiagnostic::new(format!("invalid value `{value}` for `{rendered}`"))
+        .with_code(code::INVALID_VALUE)
+        .with_label(LabeledSpan::new_primary_with_span(Some(label), span));
+    if let Some(ContextValue::Strings(possible)) = error.get(ContextKind::ValidValue) {
+        let possible = possible.iter().map(|value| format!("`{value}`")).collect::<Vec<_>>();
+        diagnostic = diagnostic.with_help(format!("possible values: {}", possible.join(", ")));
+    }
+    let mut report = Suggested::new(diagnostic).with_source_code(full_command);
+    if let Some(suggested) = context_str(error, ContextKind::SuggestedValue) {
+        report = report.with_suggestion(span, suggested);
+    }
+
+    Some(Report::from(report))
+}
+
+fn argument_conflict(command: &Command, args: Vec<OsString>, error: &clap::Error) -> Option<Report> {
+    let rendered = context_str(error, ContextKind::InvalidArg)?;
+    let prior = match error.get(ContextKind::PriorArg)? {
+        ContextValue::String(prior) => vec![prior.as_str()],
+        ContextValue::Strings(prior) => prior.iter().map(String::as_str).collect(),
+        _ => return None,
+    };
+    let mut built = command.clone();
+    built.build();
+    let id = find_id(&built, rendered)?;
+    let located = locate_scoped(&built, &args);
+    let whole = |id: &str| located.iter().find(|located| located.id == id).map(|located| located.location.whole());
+    let part = whole(&id)?;
+
+    let mut labels = vec![LabeledSpan::new_primary_with_span(Some("used here".to_owned()), SourceSpan::new(part.offset.into(), part.length))];
+    // Values from env variables are not in the Argv.
+    for part in prior.iter().filter_map(|prior| whole(&find_id(&built, prior)?)) {
+        labels.push(LabeledSpan::new_with_span(Some(format!("conflicts with `{rendered}`")), SourceSpan::new(part.offset.into(), part.length)));
+    }
+    labels.sort_by_key(LabeledSpan::offset);
+    let prior = prior.iter().map(|prior| format!("`{prior}`")).collect::<Vec<_>>();
+    let diagnostic = MietteDiagnostic::new(format!("`{rendered}` cannot be used with {}", prior.join(", ")))
+        .with_code(code::ARGUMENT_CONFLICT)
+        .with_labels(labels);
+    let full_command = args.iter().map(|arg| arg.to_string_lossy()).collect::<Vec<_>>().join(" ");
+
+    Some(Report::from(diagnostic).with_source_code(full_command))
+}
+
+/// Returns the id of the argument rendered as `rendered` by clap, e.g.
+/// `--port <PORT>`, in the whole subcommand tree of the built `command`.
+fn find_id(command: &Command, rendered: &str) -> Option<String> {
+    command
+        .get_arguments()
+        .find(|arg| arg.to_string() == rendered)
+        .map(|arg| arg.get_id().to_string())
+        .or_else(|| command.get_subcommands().find_map(|subcommand| find_id(subcommand, rendered)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(clap::Parser, Debug)]
+    struct Args {
+        #[clap(long)]
+        port: u16,
+        #[clap(long, value_parser = ["always", "never"])]
+        color: Option<String>,
+        #[clap(short)]
+        verbose: bool,
+    }
+
+    fn label(report: &Report) -> (usize, usize, Option<String>) {
+        let label = report.labels().unwrap().next().unwrap();
+        (label.offset(), label.len(), label.label().map(ToOwned::to_owned))
+    }
+
+    #[test]
+    fn test_try_parse_fancy() {
+        assert_eq!(Args::try_parse_fancy_from(["app", "--port=80"]).unwrap().port, 80);
+
+        let report = Args::try_parse_fancy_from(["app", "--port", "99999"]).unwrap_err();
+        assert_eq!(report.to_string(), "invalid value `99999` for `--port <PORT>`");
+        assert_eq!(report.code().unwrap().to_string(), code::INVALID_VALUE);
+        assert_eq!(label(&report), (11, 5, Some("99999 is not in 0..=65535".to_owned())));
+
+        let report = Args::try_parse_fancy_from(["app", "--port=1", "--color=alwys"]).unwrap_err();
+        assert_eq!(report.help().unwrap().to_string(), "possible values: `always`, `never`");
+        assert_eq!(report.downcast_ref::<Suggested>().unwrap().suggestions[0].replacement, "always");
+        assert_eq!(label(&report).0, 21);
+
+        let report = Args::try_parse_fancy_from(["app", "--prot=1"]).unwrap_err();
+        assert_eq!(report.to_string(), "unexpected argument `--prot`");
+        assert_eq!(report.help().unwrap().to_string(), "did you mean `--port`?");
+        assert_eq!(label(&report), (4, 6, Some("not an argument of this command".to_owned())));
+
+        let report = Args::try_parse_fancy_from(["app", "--port=1", "-vq"]).unwrap_err();
+        assert_eq!(label(&report).0, 15);
+
+        let report = Args::try_parse_fancy_from(["app", "-v"]).unwrap_err();
+        assert_eq!(report.code().unwrap().to_string(), code::MISSING_REQUIRED);
+    }
+
+    #[test]
+    fn test_unknown_argument() {
+        use clap::{Arg, ArgAction};
+
+        let command = Command::new("app")
+            .arg(Arg::new("verbose").long("verbose").action(ArgAction::SetTrue))
+            .arg(Arg::new("name").long("name").value_parser(clap::value_parser!(OsString)))
+            .subcommand(Command::new("remote").arg(Arg::new("url").long("url")));
+        let report_of = |args: &[OsString]| error_report(&command, args, &command.clone().try_get_matches_from(args).unwrap_err()).unwrap();
+
+        // `--verbose` is not an argument of `remote`.
+        let report = report_of(&["app", "--verbose", "remote", "--url=x", "--verbose"].map(OsString::from));
+        assert_eq!(report.to_string(), "unexpected argument `--verbose`");
+        assert_eq!(label(&report), (29, 9, Some("not an argument of this command".to_owned())));
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::ffi::OsStringExt;
+            let args = [OsString::from("app"), OsString::from("--name"), OsString::from_vec(b"\xff\xfe".to_vec()), OsString::from("--nope")];
+            let report = report_of(&args);
+            assert_eq!(label(&report).0, 18);
+        }
+    }
+
+    #[test]
+    fn test_argument_conflict() {
+        #[derive(clap::Parser, Debug)]
+        struct Args {
+            #[clap(long, conflicts_with = "color")]
+            json: bool,
+            #[clap(long)]
+            color: Option<String>,
+            #[clap(short)]
+            verbose: bool,
+        }
+
+        let report = Args::try_parse_fancy_from(["app", "--color=always", "-v", "--json"]).unwrap_err();
+        assert_eq!(report.code().unwrap().to_string(), code::ARGUMENT_CONFLICT);
+        assert_eq!(report.to_string(), "`--color <COLOR>` cannot be used with `--json`");
+        let labels = report.labels().unwrap().map(|label| (label.offset(), label.len(), label.primary(), label.label().unwrap().to_owned())).collect::<Vec<_>>();
+        assert_eq!(labels, [(4, 14, true, "used here".to_owned()), (22, 6, false, "conflicts with `--color <COLOR>`".to_owned())]);
+    }
+
+    #[test]
+    fn test_fancy_command() {
+        use clap::Arg;
+
+        let mut deprecations = Deprecations::default();
+        deprecations.deprecate_alias("color", crate::ArgAlias::Long("colour".to_owned()));
+        let command = Command::new("app")
+            .arg(Arg::new("color").long("color").alias("colour"))
+            .arg(Arg::new("port").long("port").default_value("80"));
+        let mut fancy = FancyCommand::wrap(command).deprecations(deprecations);
+
+        let (matches, context) = fancy.try_run_from(["app", "--colour=auto"]).unwrap();
+        assert_eq!(matches.get_one::<String>("color").map(String::as_str), Some("auto"));
+        assert_eq!(context.warns.len(), 1);
+        assert_eq!(context.warns[0].report.to_string(), "`--colour` is deprecated");
+        assert!(matches!(&fancy.provenances[0], (id, Provenance::CommandLine(Some(_))) if id == "color"));
+        assert_eq!(fancy.provenances[1], ("port".to_owned(), Provenance::DefaultValue));
+
+        let Err(report) = fancy.try_run_from(["app", "--colr"]) else {
+            panic!("--colr is unknown");
+        };
+        assert_eq!(report.code().unwrap().to_string(), code::UNKNOWN_TOKEN);
+    }
+}
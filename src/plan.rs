@@ -0,0 +1,182 @@
+//! Locating a fixed set of arguments in many Argv strings, e.g. when
+//! auditing the invocations received by a server. The aliases of the
+//! whole command are resolved once into a flat sorted table, so every
+//! run only does binary searches over it.
+
+use std::{ffi::OsString, ops::ControlFlow};
+
+use crate::parse::{all_aliases, walk, ArgAlias, ArgLocation, ArgShape};
+
+/// Locations of `targets` compiled from a `clap::Command`.
+/// See `Self::new()` and `Self::run()`.
+///
+/// Unlike `ArgLocator`, a plan holds no reference counted pointers or
+/// lazy caches, so it can be shared across threads.
+#[derive(Clone, Debug)]
+pub struct LocationPlan {
+    /// Every alias of the command, sorted for binary search.
+    table: Vec<(ArgAlias, PlanEntry)>,
+    targets: usize,
+}
+
+#[derive(Clone, Debug)]
+struct PlanEntry {
+    shape: ArgShape,
+    /// Indices of the targets that are this argument.
+    slots: Vec<usize>,
+}
+
+impl LocationPlan {
+    /// Compiles a plan for locating `targets` with the aliases of
+    /// `command`. Subcommands are not included.
+    pub fn new<A>(command: &clap::Command, targets: &[&A]) -> Self
+    where
+        A: PartialEq<clap::Id> + ?Sized,
+    {
+        let mut command = command.clone();
+        command.build();
+        let mut table = vec![];
+        for arg in command.get_arguments() {
+            let entry = PlanEntry {
+                shape: ArgShape::of(arg),
+                slots: (0..targets.len()).filter(|&index| *targets[index] == *arg.get_id()).collect(),
+            };
+            table.extend(all_aliases(arg).map(|alias| (alias, entry.clone())));
+        }
+        table.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+
+        Self { table, targets: targets.len() }
+    }
+
+    /// Returns the location of every target in `args`, in the order of
+    /// the targets passed to `Self::new()`.
+    pub fn run<R>(&self, args: R) -> Vec<Option<ArgLocation>>
+    where
+        R: IntoIterator<Item: Into<OsString>>,
+    {
+        let args = args.into_iter().map(Into::into).collect::<Vec<OsString>>();
+        let mut locations = vec![];
+        self.run_into(&args, &mut locations);
+
+        locations
+    }
+
+    /// Same as `Self::run()` but writes into `locations` so that its
+    /// allocation can be reused across runs.
+    pub fn run_into(&self, args: &[OsString], locations: &mut Vec<Option<ArgLocation>>) {
+        locations.clear();
+        locations.resize(self.targets, None);
+        self.scan(args, SearchStrategy::FirstHitPerTarget, |slot, location| {
+            locations[slot] = Some(location.clone());
+        });
+    }
+
+    /// Returns the locations of the targets in `args` found by
+    /// `strategy`, in the order of the targets passed to `Self::new()`
+    /// and then the order they appear in `args`.
+    pub fn search<R>(&self, args: R, strategy: SearchStrategy) -> Vec<Vec<ArgLocation>>
+    where
+        R: IntoIterator<Item: Into<OsString>>,
+    {
+        let args = args.into_iter().map(Into::into).collect::<Vec<OsString>>();
+        let mut hits = vec![vec![]; self.targets];
+        self.scan(&args, strategy, |slot, location| hits[slot].push(location.clone()));
+
+        hits
+    }
+
+    fn scan(&self, args: &[OsString], strategy: SearchStrategy, mut hit: impl FnMut(usize, &ArgLocation)) {
+        let limit = match strategy {
+            SearchStrategy::Exhaustive => usize::MAX,
+            SearchStrategy::FirstHitPerTarget | SearchStrategy::StopAtFirstAnyTarget => 1,
+        };
+        let mut counts = vec![0; self.targets];
+        let mut remaining = self.targets;
+        let lookup = |alias: &ArgAlias| {
+            let index = self.table.binary_search_by(|(key, _)| key.cmp(alias)).ok()?;
+            let entry = &self.table[index].1;
+            Some((entry, entry.shape.clone()))
+        };
+        let visit = |entry: &PlanEntry, location: ArgLocation| {
+            #[cfg(any(test, feature = "span-checks"))]
+            crate::check::check_location(args, &location);
+            // Targets that reached their limit are skipped while the
+            // others are still searched.
+            for &slot in &entry.slots {
+                if counts[slot] == limit {
+                    continue;
+                }
+                counts[slot] += 1;
+                hit(slot, &location);
+                if counts[slot] == limit {
+                    remaining -= 1;
+                }
+            }
+            if remaining == 0 || strategy == SearchStrategy::StopAtFirstAnyTarget && !entry.slots.is_empty() {
+                return ControlFlow::Break(());
+            }
+            ControlFlow::Continue(())
+        };
+        // Unknown arguments are never targets.
+        walk(args, lookup, visit, |_, _| ControlFlow::Continue(()));
+    }
+}
+
+/// How far `LocationPlan::search()` scans the Argv, trading
+/// completeness for speed.
+#[derive(Eq, PartialEq, Default, Clone, Copy, Debug)]
+pub enum SearchStrategy {
+    /// Finds the first occurrence of every target, and stops once all
+    /// of them are found.
+    #[default]
+    FirstHitPerTarget,
+    /// Finds every occurrence of every target, scanning the whole Argv.
+    Exhaustive,
+    /// Stops at the first occurrence of any target.
+    StopAtFirstAnyTarget,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ArgLocator;
+    use clap::{Arg, ArgAction, Command};
+
+    #[test]
+    fn test_run_matches_locator() {
+        let command = Command::new("app")
+            .arg(Arg::new("verbose").short('v').action(ArgAction::SetTrue))
+            .arg(Arg::new("port").short('p').long("port").visible_alias("p0rt"))
+            .arg(Arg::new("name").long("name"));
+        let targets: &[&str] = &["port", "verbose", "nope", "port"];
+        let plan = LocationPlan::new(&command, targets);
+        let locator = ArgLocator::from_command(&command);
+
+        let mut locations = vec![];
+        for args in [
+            &["app", "-vp1", "--name", "x"][..],
+            &["app", "--name", "--p0rt", "--p0rt=2"],
+            &["app"],
+        ] {
+            let args = args.iter().map(OsString::from).collect::<Vec<_>>();
+            plan.run_into(&args, &mut locations);
+            let expected = targets.iter().map(|target| locator.get_location(&args, *target)).collect::<Vec<_>>();
+            assert_eq!(locations, expected);
+        }
+        assert!(plan.run(["app", "-v"])[1].is_some());
+    }
+
+    #[test]
+    fn test_search_strategies() {
+        let command = Command::new("app")
+            .arg(Arg::new("verbose").short('v').action(ArgAction::Count))
+            .arg(Arg::new("port").long("port").action(ArgAction::Append));
+        let plan = LocationPlan::new(&command, &["verbose", "port"]);
+        let args = ["app", "-v", "--port=1", "-v", "--port=2"];
+        let counts = |strategy| plan.search(args, strategy).iter().map(Vec::len).collect::<Vec<_>>();
+        assert_eq!(counts(SearchStrategy::FirstHitPerTarget), [1, 1]);
+        assert_eq!(counts(SearchStrategy::Exhaustive), [2, 2]);
+        assert_eq!(counts(SearchStrategy::StopAtFirstAnyTarget), [1, 0]);
+        assert_eq!(plan.search(args, SearchStrategy::Exhaustive)[1][1].whole(), crate::ArgPart { offset: 19, length: 8 });
+    }
+}
@@ -0,0 +1,158 @@
+//! Reporting spans against the command line that users typed when the
+//! application preprocesses the Argv before clap, e.g. by stripping
+//! wrapper flags or inserting defaults.
+
+use std::ffi::OsString;
+
+use crate::parse::{ArgLocation, ArgLocator, ArgPart, UnitConverter};
+
+/// Which argument of the preprocessed Argv came from which argument of
+/// the original one. Arguments that are not mapped were inserted by the
+/// preprocessing and cannot be located in the original Argv.
+#[derive(Clone, Debug)]
+pub struct ArgvMap {
+    original: Vec<String>,
+    processed: Vec<String>,
+    /// Original index of every processed argument.
+    origins: Vec<Option<usize>>,
+}
+
+impl ArgvMap {
+    /// Returns a map where no argument is mapped yet. See `Self::map()`.
+    pub fn new<O, P>(original: O, processed: P) -> Self
+    where
+        O: IntoIterator<Item: Into<OsString>>,
+        P: IntoIterator<Item: Into<OsString>>,
+    {
+        let lossy = |arg: OsString| arg.to_string_lossy().to_string();
+        let original = original.into_iter().map(Into::into).map(lossy).collect::<Vec<_>>();
+        let processed = processed.into_iter().map(Into::into).map(lossy).collect::<Vec<_>>();
+        let origins = vec![None; processed.len()];
+
+        Self { original, processed, origins }
+    }
+
+    /// Records that the processed argument at `processed_index` came
+    /// from the original argument at `original_index`. Indices out of
+    /// bounds are ignored.
+    pub fn map(&mut self, original_index: usize, processed_index: usize) -> &mut Self {
+        if let Some(origin) = self.origins.get_mut(processed_index).filter(|_| original_index < self.original.len()) {
+            *origin = Some(original_index);
+        }
+        self
+    }
+
+    /// The original Argv joined by spaces, which the remapped locations
+    /// are in.
+    pub fn original_command(&self) -> String {
+        self.original.join(" ")
+    }
+
+    /// Locates `arg` in the processed Argv and returns the location in
+    /// the original one, in the `ArgLocator::offset_unit` of `locator`.
+    /// See `Self::remap_location()`.
+    pub fn get_location<T, V, A>(&self, locator: &ArgLocator<T, V>, arg: &A) -> Option<ArgLocation>
+    where
+        T: Default,
+        V: AsRef<clap::Arg>,
+        A: PartialEq<clap::Id> + ?Sized,
+    {
+        let processed = self.processed.iter().map(OsString::from).collect::<Vec<_>>();
+        let location = self.remap_location(&locator.locate_bytes(&processed, arg)?)?;
+
+        Some(UnitConverter::of_text(locator.offset_unit, &self.original_command()).location(&location))
+    }
+
+    /// Converts `location` in the processed Argv joined by spaces to the
+    /// original one. Parts of arguments that were rewritten, rather than
+    /// only moved, cover the whole original argument. Returns `None` if
+    /// any part is in an argument that was inserted.
+    pub fn remap_location(&self, location: &ArgLocation) -> Option<ArgLocation> {
+        let mut unmapped = false;
+        let remapped = location.map_parts(|part| {
+            self.remap_part(part).unwrap_or_else(|| {
+                unmapped = true;
+                part.clone()
+            })
+        });
+
+        (!unmapped).then_some(remapped)
+    }
+
+    /// Same as `Self::remap_location()` but for a single part.
+    pub fn remap_part(&self, part: &ArgPart) -> Option<ArgPart> {
+        let processed_starts = starts(&self.processed);
+        let original_starts = starts(&self.original);
+        let index = processed_starts.partition_point(|&start| start <= part.offset).checked_sub(1)?;
+        let original_index = self.origins[index]?;
+        let intra = part.offset - processed_starts[index];
+        let (processed, original) = (&self.processed[index], &self.original[original_index]);
+        let original_start = original_starts[original_index];
+
+        if intra >= processed.len() {
+            // The space after the argument is only kept if the next one
+            // is still next to it.
+            let next = self.origins.get(index + 1).copied().flatten();
+            return (next == Some(original_index + 1)).then(|| ArgPart {
+                offset: original_start + original.len(),
+                length: part.length,
+            });
+        }
+        if processed != original {
+            return Some(ArgPart {
+                offset: original_start,
+                length: original.len(),
+            });
+        }
+
+        Some(ArgPart {
+            offset: original_start + intra,
+            length: part.length,
+        })
+    }
+}
+
+/// Returns the offset of every argument in `args` joined by spaces.
+fn starts(args: &[String]) -> Vec<usize> {
+    let mut starts = vec![];
+    let mut offset = 0;
+    for arg in args {
+        starts.push(offset);
+        offset += arg.len() + 1;
+    }
+
+    starts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(clap::Parser)]
+    struct Args {
+        #[clap(long)]
+        port: u16,
+        #[clap(long)]
+        name: String,
+        #[clap(long)]
+        color: String,
+    }
+
+    #[test]
+    fn test_argv_map() {
+        let original = ["app", "--wrapper", "x", "--port", "80", "--name=A"];
+        let processed = ["app", "--port", "80", "--name=a", "--color=auto"];
+        let mut map = ArgvMap::new(original, processed);
+        map.map(0, 0).map(3, 1).map(4, 2).map(5, 3);
+        let locator = ArgLocator::from_command_factory::<Args>();
+        let command = map.original_command();
+
+        let location = map.get_location(&locator, "port").unwrap();
+        let whole = location.whole();
+        assert_eq!(&command[whole.offset..whole.offset + whole.length], "--port 80");
+        // Rewritten arguments are covered as a whole.
+        let content = map.get_location(&locator, "name").unwrap().content().unwrap().clone();
+        assert_eq!(&command[content.offset..content.offset + content.length], "--name=A");
+        assert_eq!(map.get_location(&locator, "color"), None);
+    }
+}
@@ -0,0 +1,96 @@
+//! Where the value of an argument came from, combining
+//! `ArgMatches::value_source()` with its location in the Argv.
+
+use std::ffi::OsString;
+
+use clap::{parser::ValueSource, ArgMatches, Command};
+
+use crate::{
+    parse::ArgLocation,
+    scope::{locate_in_scope, subcommand_scope},
+};
+
+/// See `provenance()`.
+#[derive(Eq, PartialEq, Clone, Debug)]
+#[non_exhaustive]
+pub enum Provenance {
+    /// The location of the first occurrence, or `None` if it cannot be
+    /// found, e.g. for positional arguments or an Argv that `matches`
+    /// was not parsed from.
+    CommandLine(Option<ArgLocation>),
+    EnvVariable { var: String },
+    DefaultValue,
+    Unset,
+}
+
+/// Returns where the value of `arg` in `matches`, which should be
+/// parsed from `args` by `command`, came from. `arg` is looked up in
+/// the subcommand that it was parsed under, see `subcommand_scope()`.
+pub fn provenance<R>(command: &Command, args: R, matches: &ArgMatches, arg: &str) -> Provenance
+where
+    R: IntoIterator<Item: Into<OsString>>,
+{
+    let scope = subcommand_scope(matches, arg);
+    let scoped_matches = scope.iter().try_fold(matches, |current, name| current.subcommand_matches(name));
+    let Some(scoped_matches) = scoped_matches else {
+        return Provenance::Unset;
+    };
+    match scoped_matches.value_source(arg) {
+        Some(ValueSource::CommandLine) => Provenance::CommandLine(locate_in_scope(command, args, &scope, arg)),
+        Some(ValueSource::EnvVariable) => {
+            let scoped_command = scope.iter().try_fold(command, |current, name| current.find_subcommand(name));
+            let var = scoped_command
+                .and_then(|current| current.get_arguments().find(|candidate| candidate.get_id() == arg))
+                .and_then(|found| found.get_env())
+                .map(|var| var.to_string_lossy().to_string())
+                .unwrap_or_default();
+            Provenance::EnvVariable { var }
+        }
+        Some(ValueSource::DefaultValue) => Provenance::DefaultValue,
+        _ => Provenance::Unset,
+    }
+}
+
+/// Returns the provenance of every argument of the top-level `command`
+/// in the order they are defined.
+pub fn provenances<R>(command: &Command, args: R, matches: &ArgMatches) -> Vec<(String, Provenance)>
+where
+    R: IntoIterator<Item: Into<OsString>>,
+{
+    let args = args.into_iter().map(Into::into).collect::<Vec<OsString>>();
+    command
+        .get_arguments()
+        .map(|arg| arg.get_id().to_string())
+        .map(|id| {
+            let provenance = provenance(command, &args, matches, &id);
+            (id, provenance)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Arg;
+
+    #[test]
+    fn test_provenance() {
+        std::env::set_var("FANCY_CLAP_TEST_PROVENANCE_NAME", "a");
+        let command = Command::new("app")
+            .arg(Arg::new("name").long("name").env("FANCY_CLAP_TEST_PROVENANCE_NAME"))
+            .arg(Arg::new("port").long("port").default_value("80"))
+            .arg(Arg::new("host").long("host"))
+            .subcommand(Command::new("push").arg(Arg::new("force").long("force").action(clap::ArgAction::SetTrue)));
+        let args = ["app", "push", "--force"];
+        let matches = command.clone().get_matches_from(args);
+        assert_eq!(provenances(&command, args, &matches), [
+            ("name".to_owned(), Provenance::EnvVariable { var: "FANCY_CLAP_TEST_PROVENANCE_NAME".to_owned() }),
+            ("port".to_owned(), Provenance::DefaultValue),
+            ("host".to_owned(), Provenance::Unset),
+        ]);
+        let Provenance::CommandLine(Some(location)) = provenance(&command, args, &matches, "force") else {
+            panic!("`--force` is on the command line");
+        };
+        assert_eq!(location.declaration().offset, 9);
+    }
+}
@@ -0,0 +1,528 @@
+//! Rendering the queued reports in machine-readable formats.
+//! See `DiagnosticFormat`.
+
+use std::fmt;
+
+use miette::{Diagnostic, LabeledSpan, MietteError, MietteSpanContents, Severity, SourceCode, SourceSpan, SpanContents};
+
+use crate::suggest::Suggestion;
+
+/// Whether a queued report is an error or a warning in `ReportContext`,
+/// or a note if it is an advice in either.
+#[derive(Ord, PartialOrd, Eq, PartialEq, Clone, Copy, Debug)]
+pub(crate) enum Level {
+    Error,
+    Warning,
+    Note,
+}
+
+impl Level {
+    /// Returns the level of `diagnostic` queued as `queued`.
+    pub(crate) fn of(diagnostic: &dyn Diagnostic, queued: Self) -> Self {
+        match diagnostic.severity() {
+            Some(Severity::Advice) => Self::Note,
+            _ => queued,
+        }
+    }
+}
+
+/// A queued report to be rendered in a machine-readable format, its
+/// fixes and what it is about. See `Suggested` and `ScopedReport`.
+pub(crate) struct Machine<'a> {
+    pub(crate) level: Level,
+    pub(crate) diagnostic: &'a dyn Diagnostic,
+    pub(crate) suggestions: &'a [Suggestion],
+    pub(crate) scope: &'a [String],
+    pub(crate) arg: Option<&'a str>,
+}
+
+/// Writes the reports as a JSON array with an object for every report.
+/// See `DiagnosticFormat::Json` for the schema.
+pub(crate) fn json<'a>(f: &mut impl fmt::Write, reports: impl IntoIterator<Item = Machine<'a>>) -> fmt::Result {
+    write!(f, "[")?;
+    for (index, report) in reports.into_iter().enumerate() {
+        if index > 0 {
+            write!(f, ",")?;
+        }
+        json_object(f, report.level, report.diagnostic, report.suggestions)?;
+    }
+    writeln!(f, "]")
+}
+
+/// Writes a report as an object of the schema of `json()`.
+fn json_object(f: &mut impl fmt::Write, level: Level, diagnostic: &dyn Diagnostic, suggestions: &[Suggestion]) -> fmt::Result {
+    let severity = match level {
+        Level::Error => "error",
+        Level::Warning => "warning",
+        Level::Note => "advice",
+    };
+    write!(
+        f,
+        r#"{{"message":"{}","severity":"{severity}","code":{},"help":{},"url":{},"labels":["#,
+        Escape(&diagnostic.to_string()),
+        Nullable(diagnostic.code()),
+        Nullable(diagnostic.help()),
+        Nullable(diagnostic.url()),
+    )?;
+    for (index, label) in diagnostic.labels().into_iter().flatten().enumerate() {
+        if index > 0 {
+            write!(f, ",")?;
+        }
+        write!(
+            f,
+            r#"{{"label":{},"offset":{},"length":{},"primary":{}}}"#,
+            Nullable(label.label()),
+            label.offset(),
+            label.len(),
+            label.primary(),
+        )?;
+    }
+    write!(f, r#"],"source":{},"related":["#, Nullable(source_text(diagnostic)))?;
+    for (index, related) in diagnostic.related().into_iter().flatten().enumerate() {
+        if index > 0 {
+            write!(f, ",")?;
+        }
+        let level = match related.severity() {
+            Some(Severity::Advice) => Level::Note,
+            Some(Severity::Warning) => Level::Warning,
+            Some(Severity::Error) | None => Level::Error,
+        };
+        json_object(f, level, related, &[])?;
+    }
+    write!(f, r#"],"suggestions":["#)?;
+    for (index, suggestion) in suggestions.iter().enumerate() {
+        if index > 0 {
+            write!(f, ",")?;
+        }
+        write!(
+            f,
+            r#"{{"offset":{},"length":{},"replacement":"{}"}}"#,
+            suggestion.span.offset(),
+            suggestion.span.len(),
+            Escape(&suggestion.replacement),
+        )?;
+    }
+    write!(f, "]}}")
+}
+
+/// Returns the whole source code of `diagnostic`, with the invalid
+/// UTF-8 replaced.
+fn source_text(diagnostic: &dyn Diagnostic) -> Option<String> {
+    // Reads the whole source as the context after an empty span.
+    let contents = diagnostic.source_code()?.read_span(&SourceSpan::new(0.into(), 0), 0, usize::MAX).ok()?;
+
+    Some(String::from_utf8_lossy(contents.data()).into_owned())
+}
+
+/// Writes the reports as a SARIF 2.1.0 log with a single run. The
+/// primary label of a report is its location in the artifact named
+/// after the source code, or `argv` if it has no name, and the reported
+/// argument is its logical location, named by the subcommand path and
+/// the `clap::Id`. The other labels are related locations, and the
+/// suggestions are `fixes` that replace regions of the source code.
+/// Regions are in bytes.
+pub(crate) fn sarif<'a>(f: &mut impl fmt::Write, reports: impl IntoIterator<Item = Machine<'a>>) -> fmt::Result {
+    write!(f, r#"{{"$schema":"https://json.schemastore.org/sarif-2.1.0.json","version":"2.1.0","runs":[{{"#)?;
+    write!(f, r#""tool":{{"driver":{{"name":"{}","version":"{}"}}}},"#, env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"))?;
+    write!(f, r#""results":["#)?;
+    for (index, Machine { level, diagnostic, suggestions, scope, arg }) in reports.into_iter().enumerate() {
+        if index > 0 {
+            write!(f, ",")?;
+        }
+        let level = match level {
+            Level::Error => "error",
+            Level::Warning => "warning",
+            Level::Note => "note",
+        };
+        write!(f, r#"{{"level":"{level}","message":{{"text":"{}"}}"#, Escape(&diagnostic.to_string()))?;
+        if let Some(code) = diagnostic.code() {
+            write!(f, r#","ruleId":"{}""#, Escape(&code.to_string()))?;
+        }
+
+        let uri = artifact(diagnostic);
+        let mut labels = diagnostic.labels().into_iter().flatten().collect::<Vec<_>>();
+        let primary = labels.iter().position(LabeledSpan::primary).map(|index| labels.remove(index));
+        let primary = primary.or_else(|| (!labels.is_empty()).then(|| labels.remove(0)));
+        let logical = match (arg, scope) {
+            (Some(arg), scope) => Some((arg, scope.iter().map(String::as_str).chain([arg]).collect::<Vec<_>>().join(" "), "parameter")),
+            (None, [.., last]) => Some((last.as_str(), scope.join(" "), "module")),
+            (None, []) => None,
+        };
+        if primary.is_some() || logical.is_some() {
+            write!(f, r#","locations":[{{"#)?;
+            if let Some(primary) = &primary {
+                write!(f, r#""physicalLocation":{}"#, Region(primary, &uri))?;
+            }
+            if let Some((name, qualified, kind)) = logical {
+                if primary.is_some() {
+                    write!(f, ",")?;
+                }
+                write!(f, r#""logicalLocations":[{{"name":"{}","fullyQualifiedName":"{}","kind":"{kind}"}}]"#, Escape(name), Escape(&qualified))?;
+            }
+            write!(f, "}}]")?;
+        }
+        if !labels.is_empty() {
+            write!(f, r#","relatedLocations":["#)?;
+            for (index, label) in labels.iter().enumerate() {
+                if index > 0 {
+                    write!(f, ",")?;
+                }
+                write!(f, r#"{{"id":{index},"physicalLocation":{}"#, Region(label, &uri))?;
+                if let Some(text) = label.label() {
+                    write!(f, r#","message":{{"text":"{}"}}"#, Escape(text))?;
+                }
+                write!(f, "}}")?;
+            }
+            write!(f, "]")?;
+        }
+
+        if !suggestions.is_empty() {
+            write!(f, r#","fixes":["#)?;
+            for (index, suggestion) in suggestions.iter().enumerate() {
+                if index > 0 {
+                    write!(f, ",")?;
+                }
+                write!(
+                    f,
+                    r#"{{"description":{{"text":"replace with `{0}`"}},"artifactChanges":[{{"artifactLocation":{{"uri":"{1}"}},"replacements":[{{"deletedRegion":{{"byteOffset":{2},"byteLength":{3}}},"insertedContent":{{"text":"{0}"}}}}]}}]}}"#,
+                    Escape(&suggestion.replacement),
+                    Escape(&uri),
+                    suggestion.span.offset(),
+                    suggestion.span.len(),
+                )?;
+            }
+            write!(f, "]")?;
+        }
+        write!(f, "}}")?;
+    }
+    writeln!(f, "]}}]}}")
+}
+
+/// Writes the reports as GitHub Actions workflow commands, one per
+/// line. The code of a report is the title of its annotation, and the
+/// snippet of the source under every label is appended to the message.
+pub(crate) fn github<'a>(f: &mut impl fmt::Write, reports: impl IntoIterator<Item = Machine<'a>>) -> fmt::Result {
+    for Machine { level, diagnostic, .. } in reports {
+        let command = match level {
+            Level::Error => "error",
+            Level::Warning => "warning",
+            Level::Note => "notice",
+        };
+        write!(f, "::{command}")?;
+        if let Some(code) = diagnostic.code() {
+            write!(f, " title={}", WorkflowEscape(&code.to_string(), true))?;
+        }
+        let mut message = diagnostic.to_string();
+        let source = diagnostic.source_code();
+        for label in diagnostic.labels().into_iter().flatten() {
+            let snippet = source.and_then(|source| source.read_span(label.inner(), 0, 0).ok());
+            let snippet = snippet.map(|contents| String::from_utf8_lossy(contents.data()).into_owned()).unwrap_or_default();
+            message += &format!("\n  `{snippet}`");
+            if let Some(text) = label.label() {
+                message += &format!(": {text}");
+            }
+        }
+        writeln!(f, "::{}", WorkflowEscape(&message, false))?;
+    }
+
+    Ok(())
+}
+
+/// Escapes the data of a workflow command, or the value of one of its
+/// properties if the second field is set.
+struct WorkflowEscape<'a>(&'a str, bool);
+
+impl fmt::Display for WorkflowEscape<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for c in self.0.chars() {
+            match c {
+                '%' => f.write_str("%25")?,
+                '\r' => f.write_str("%0D")?,
+                '\n' => f.write_str("%0A")?,
+                ':' if self.1 => f.write_str("%3A")?,
+                ',' if self.1 => f.write_str("%2C")?,
+                c => fmt::Write::write_char(f, c)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns the SARIF artifact of the labels of `diagnostic`: the name of
+/// its source code, e.g. of a `FileSource` or `EnvSource`, or `argv`.
+fn artifact(diagnostic: &dyn Diagnostic) -> String {
+    let contents = diagnostic.source_code().and_then(|source| source.read_span(&SourceSpan::new(0.into(), 0), 0, 0).ok());
+
+    contents.and_then(|contents| contents.name().map(ToOwned::to_owned)).unwrap_or_else(|| "argv".to_owned())
+}
+
+/// Writes the SARIF physical location of a label in an artifact, with
+/// the region in bytes like the spans of `miette`.
+struct Region<'a>(&'a LabeledSpan, &'a str);
+
+impl fmt::Display for Region<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, r#"{{"artifactLocation":{{"uri":"{}"}},"region":{{"byteOffset":{},"byteLength":{}}}}}"#, Escape(self.1), self.0.offset(), self.0.len())
+    }
+}
+
+/// Escapes a string to be put in between the quotes of a JSON string.
+pub(crate) struct Escape<'a>(pub &'a str);
+
+/// Writes a JSON string of the value, or `null` if there is none.
+struct Nullable<T>(Option<T>);
+
+impl<T: fmt::Display> fmt::Display for Nullable<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.0 {
+            Some(value) => write!(f, r#""{}""#, Escape(&value.to_string())),
+            None => f.write_str("null"),
+        }
+    }
+}
+
+impl fmt::Display for Escape<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for c in self.0.chars() {
+            match c {
+                '"' => f.write_str(r#"\""#)?,
+                '\\' => f.write_str(r"\\")?,
+                '\n' => f.write_str(r"\n")?,
+                '\r' => f.write_str(r"\r")?,
+                '\t' => f.write_str(r"\t")?,
+                c if c.is_control() => write!(f, r"\u{:04x}", c as u32)?,
+                c => fmt::Write::write_char(f, c)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Bytes of context kept around every label when a source is elided.
+const ELISION_CONTEXT: usize = 16;
+const ELLIPSIS: &str = "…";
+
+/// Source code of a report as it is rendered, with its control
+/// characters escaped, e.g. tabs as `\t` and ANSI escapes as `\x1b`,
+/// which would otherwise shift the underlines or be interpreted by the
+/// terminal, and the parts far from the labels elided if it is too
+/// long. The labels are moved along by `Self::span()`.
+pub(crate) struct DisplayedSource {
+    text: String,
+    name: Option<String>,
+    /// Offset in `text` of every byte of the original source, and of
+    /// the end.
+    offsets: Vec<usize>,
+    /// Number of bytes of the original source that were elided.
+    pub(crate) elided: usize,
+}
+
+impl DisplayedSource {
+    /// Returns `None` if `source` has nothing to escape or elide, or
+    /// cannot be read as UTF-8. Sources longer than `max_length` keep
+    /// only the parts around `spans`, or the beginning if there are no
+    /// spans.
+    pub(crate) fn of(source: &dyn SourceCode, max_length: Option<usize>, spans: &[SourceSpan]) -> Option<Self> {
+        // Reads the whole source as the context after an empty span.
+        let contents = source.read_span(&SourceSpan::new(0.into(), 0), 0, usize::MAX).ok()?;
+        let name = contents.name().map(ToOwned::to_owned);
+        let source = std::str::from_utf8(contents.data()).ok()?;
+        let elide = max_length.is_some_and(|max_length| source.len() > max_length);
+        if !elide && !source.chars().any(char::is_control) {
+            return None;
+        }
+        let mut kept = match (elide, spans) {
+            (false, _) => std::iter::once(0..source.len()).collect(),
+            (true, []) => std::iter::once(0..max_length.unwrap_or_default()).collect(),
+            (true, spans) => spans
+                .iter()
+                .map(|span| span.offset().saturating_sub(ELISION_CONTEXT)..span.offset() + span.len() + ELISION_CONTEXT)
+                .collect::<Vec<_>>(),
+        };
+        kept.sort_by_key(|range| range.start);
+
+        let mut text = String::with_capacity(source.len().min(max_length.unwrap_or(usize::MAX)));
+        let mut offsets = Vec::with_capacity(source.len() + 1);
+        let mut elided = 0;
+        let mut in_gap = false;
+        for (index, c) in source.char_indices() {
+            if !kept.iter().any(|range| range.contains(&index)) {
+                if !in_gap {
+                    text.push_str(ELLIPSIS);
+                    in_gap = true;
+                }
+                offsets.extend(std::iter::repeat_n(text.len() - ELLIPSIS.len(), c.len_utf8()));
+                elided += c.len_utf8();
+                continue;
+            }
+            in_gap = false;
+            offsets.extend(std::iter::repeat_n(text.len(), c.len_utf8()));
+            match c {
+                '\t' => text.push_str(r"\t"),
+                '\n' => text.push_str(r"\n"),
+                '\r' => text.push_str(r"\r"),
+                c if c.is_control() && c.is_ascii() => text += &format!(r"\x{:02x}", c as u32),
+                c if c.is_control() => text += &format!(r"\u{{{:x}}}", c as u32),
+                c => text.push(c),
+            }
+        }
+        offsets.push(text.len());
+
+        Some(Self { text, name, offsets, elided })
+    }
+
+    /// Returns `span` of the original source in the displayed one.
+    pub(crate) fn span(&self, span: &SourceSpan) -> SourceSpan {
+        let end = self.offsets.len() - 1;
+        let start = self.offsets[span.offset().min(end)];
+        let stop = self.offsets[(span.offset() + span.len()).min(end)];
+
+        SourceSpan::new(start.into(), stop - start)
+    }
+}
+
+impl SourceCode for DisplayedSource {
+    fn read_span<'a>(&'a self, span: &SourceSpan, context_lines_before: usize, context_lines_after: usize) -> Result<Box<dyn SpanContents<'a> + 'a>, MietteError> {
+        let contents = self.text.read_span(span, context_lines_before, context_lines_after)?;
+        let Some(name) = &self.name else {
+            return Ok(contents);
+        };
+
+        Ok(Box::new(MietteSpanContents::new_named(
+            name.clone(),
+            contents.data(),
+            *contents.span(),
+            contents.line(),
+            contents.column(),
+            contents.line_count(),
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use miette::MietteDiagnostic;
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(Escape("a\"b\\c\nd\u{1b}").to_string(), r#"a\"b\\c\nd\u001b"#);
+    }
+
+    #[test]
+    fn test_displayed_source_escaped() {
+        let source = "app\t--name=\u{1b}[31mx";
+        let displayed = DisplayedSource::of(&source, None, &[]).unwrap();
+        assert_eq!(displayed.text, r"app\t--name=\x1b[31mx");
+        // `--name`
+        assert_eq!(displayed.span(&SourceSpan::new(4.into(), 6)), SourceSpan::new(5.into(), 6));
+        // `\x1b[31mx`
+        assert_eq!(displayed.span(&SourceSpan::new(11.into(), 6)), SourceSpan::new(12.into(), 9));
+        assert!(DisplayedSource::of(&"app --name", None, &[]).is_none());
+        assert!(DisplayedSource::of(&"app --name", Some(10), &[]).is_none());
+    }
+
+    #[test]
+    fn test_displayed_source_elided() {
+        let source = format!("app {} --name=x {}", "a ".repeat(100), "b ".repeat(100));
+        let span = SourceSpan::new(source.find("--name").unwrap().into(), 8);
+        let displayed = DisplayedSource::of(&source, Some(64), &[span]).unwrap();
+        assert_eq!(displayed.text, format!("…{}--name=x{}…", &source[span.offset() - 16..span.offset()], &source[span.offset() + 8..span.offset() + 24]));
+        assert_eq!(displayed.elided, source.len() - 40);
+        let moved = displayed.span(&span);
+        assert_eq!(&displayed.text[moved.offset()..moved.offset() + moved.len()], "--name=x");
+
+        let displayed = DisplayedSource::of(&source, Some(10), &[]).unwrap();
+        assert_eq!(displayed.text, "app a a a …");
+    }
+
+    #[test]
+    fn test_sarif() {
+        let machine = |level, diagnostic, suggestions| Machine {
+            level,
+            diagnostic,
+            suggestions,
+            scope: &[],
+            arg: None,
+        };
+        let diagnostic = MietteDiagnostic::new("bad \"port\"").with_code("fancy_clap::test");
+        let mut rendered = String::new();
+        sarif(&mut rendered, [machine(Level::Warning, &diagnostic as &dyn Diagnostic, &[][..])]).unwrap();
+        assert!(rendered.contains(r#""version":"2.1.0""#));
+        assert!(rendered.contains(r#"{"level":"warning","message":{"text":"bad \"port\""},"ruleId":"fancy_clap::test"}"#));
+
+        let suggestions = [Suggestion {
+            span: SourceSpan::new(4.into(), 5),
+            replacement: "add".to_owned(),
+        }];
+        let mut rendered = String::new();
+        sarif(&mut rendered, [machine(Level::Error, &diagnostic as &dyn Diagnostic, &suggestions[..])]).unwrap();
+        assert!(rendered.contains(r#""replacements":[{"deletedRegion":{"byteOffset":4,"byteLength":5},"insertedContent":{"text":"add"}}]"#));
+        let mut rendered = String::new();
+        json(&mut rendered, [machine(Level::Error, &diagnostic as &dyn Diagnostic, &suggestions[..])]).unwrap();
+        assert!(rendered.trim_end().ends_with(r#","suggestions":[{"offset":4,"length":5,"replacement":"add"}]}]"#), "{rendered}");
+    }
+
+    #[test]
+    fn test_github() {
+        let diagnostic = MietteDiagnostic::new("bad port: 80%")
+            .with_code("myapp::port")
+            .with_label(LabeledSpan::new_primary_with_span(Some("here".to_owned()), 4..13));
+        let report = miette::Report::from(diagnostic).with_source_code("app --port=80");
+        let advice = MietteDiagnostic::new("try --tls").with_severity(Severity::Advice);
+        let machine = |level, diagnostic| Machine {
+            level,
+            diagnostic,
+            suggestions: &[],
+            scope: &[],
+            arg: None,
+        };
+        let mut rendered = String::new();
+        github(&mut rendered, [machine(Level::Error, report.as_ref()), machine(Level::of(&advice, Level::Warning), &advice)]).unwrap();
+        assert_eq!(rendered, "::error title=myapp%3A%3Aport::bad port: 80%25%0A  `--port=80`: here\n::notice::try --tls\n");
+        let mut rendered = String::new();
+        json(&mut rendered, [machine(Level::of(&advice, Level::Error), &advice)]).unwrap();
+        assert!(rendered.starts_with(r#"[{"message":"try --tls","severity":"advice","#), "{rendered}");
+    }
+
+    #[test]
+    fn test_sarif_locations() {
+        let diagnostic = MietteDiagnostic::new("conflict").with_labels([
+            LabeledSpan::new_with_span(Some("first".to_owned()), 4..10),
+            LabeledSpan::new_primary_with_span(Some("here".to_owned()), 11..17),
+        ]);
+        let scope = ["remote".to_owned()];
+        let report = Machine {
+            level: Level::Error,
+            diagnostic: &diagnostic,
+            suggestions: &[],
+            scope: &scope,
+            arg: Some("url"),
+        };
+        let mut rendered = String::new();
+        sarif(&mut rendered, [report]).unwrap();
+        assert!(rendered.contains(concat!(
+            r#""locations":[{"physicalLocation":{"artifactLocation":{"uri":"argv"},"region":{"byteOffset":11,"byteLength":6}},"#,
+            r#""logicalLocations":[{"name":"url","fullyQualifiedName":"remote url","kind":"parameter"}]}]"#,
+        )), "{rendered}");
+        assert!(rendered.contains(
+            r#""relatedLocations":[{"id":0,"physicalLocation":{"artifactLocation":{"uri":"argv"},"region":{"byteOffset":4,"byteLength":6}},"message":{"text":"first"}}]"#
+        ), "{rendered}");
+
+        let advice = MietteDiagnostic::new("try `--url`")
+            .with_severity(Severity::Advice)
+            .with_label(LabeledSpan::new_primary_with_span(None, 15..22));
+        let report = miette::Report::from(advice).with_source_code(miette::NamedSource::new("deploy.sh", "app --name=\u{540d} --url=x".to_owned()));
+        let report = Machine {
+            level: Level::of(report.as_ref(), Level::Warning),
+            diagnostic: report.as_ref(),
+            suggestions: &[],
+            scope: &[],
+            arg: None,
+        };
+        let mut rendered = String::new();
+        sarif(&mut rendered, [report]).unwrap();
+        assert!(rendered.contains(r#"{"level":"note","#), "{rendered}");
+        assert!(rendered.contains(r#""physicalLocation":{"artifactLocation":{"uri":"deploy.sh"},"region":{"byteOffset":15,"byteLength":7}}"#), "{rendered}");
+    }
+}
@@ -0,0 +1,1382 @@
+//! Collecting `miette` reports about the command-line arguments so
+//! they can be printed all at once after the arguments are validated.
+//! This and every other module that builds reports require the `miette`
+//! feature, which is enabled by default.
+
+use std::{collections::BTreeMap, ffi::OsString, fmt};
+
+use clap::{parser::ValueSource, Arg, ArgMatches, CommandFactory};
+use miette::{
+    Diagnostic, LabeledSpan, MietteDiagnostic, MietteError, MietteSpanContents, Report, ReportHandler, Severity, SourceCode,
+    SourceSpan, SpanContents,
+};
+
+use crate::{
+    code,
+    parse::{all_aliases, ArgAlias, ArgLocation, ArgLocator, ArgPart},
+    caret,
+    metadata::ArgMetadataRegistry,
+    render::{self, DisplayedSource, Level},
+    source::{ArgvSource, EnvSource, SourceProvider, SourceRegistry},
+    suggest::Suggested,
+};
+
+#[derive(Default)]
+pub struct ReportContext {
+    pub arg_highlighter: ArgHighlighter,
+    pub warns: Vec<ScopedReport>,
+    pub errs: Vec<ScopedReport>,
+    /// Prints the reports under a header of the subcommand path they
+    /// belong to rather than in the order they were queued, so users
+    /// of deep CLIs can tell which level each problem is at.
+    pub group_by_subcommand: bool,
+    /// Appends a line that counts the queued warnings and errors after
+    /// the reports. See `ReportCounts`.
+    pub summary: bool,
+    /// How much detail of the queued reports is rendered.
+    pub verbosity: Verbosity,
+    /// Skips rendering the queued warnings. They are still counted by
+    /// `Self::counts()` but not the summary line.
+    pub silence_warnings: bool,
+    /// The format that the queued reports are rendered in.
+    pub format: DiagnosticFormat,
+    /// Renders the reports at this width instead of the terminal width,
+    /// e.g. for fixed-width panes or log files. Requires the `fancy`
+    /// feature, otherwise the reports are not wrapped at all.
+    pub width: Option<usize>,
+    /// Whether lines longer than the width are wrapped. `None` keeps
+    /// the behaviour of the installed handler. Requires the `fancy`
+    /// feature.
+    pub wrap_lines: Option<bool>,
+    /// Where the command line came from, e.g. "systemd unit
+    /// myapp.service" or "Dockerfile CMD", shown in the header of the
+    /// source code in each report so operators know which invocation
+    /// site to fix. Sources that are already named keep their names.
+    /// Requires the `fancy` feature.
+    pub origin: Option<String>,
+    /// Renders only the parts of the source code around the labels if
+    /// it is longer than this many bytes, e.g. for a huge Argv built by
+    /// `xargs`. A note tells how much was elided.
+    pub max_source_length: Option<usize>,
+    /// Renders at most this many labels of each report. A note tells how
+    /// many were left out.
+    pub max_labels: Option<usize>,
+    /// Source providers of the application, e.g. for where the Argv was
+    /// defined, to attach to the reports it queues.
+    pub sources: SourceRegistry,
+}
+
+/// Formats of `ReportContext::render()`. Implements `clap::ValueEnum`
+/// for switching it with an argument. See `augment_diagnostic_format()`.
+#[derive(Ord, PartialOrd, Eq, PartialEq, Default, Clone, Copy, Debug)]
+pub enum DiagnosticFormat {
+    /// Rendered by the installed `miette` report handler.
+    #[default]
+    Human,
+    /// A JSON array with an object for every report, e.g. for
+    /// `--error-format=json` like `rustc`. Every key is always present:
+    /// `message`, `severity` (`"error"`, `"warning"` or `"advice"`),
+    /// `code`, `help` and `url` (`null` if absent), `labels` (`label`,
+    /// `offset`, `length` and `primary`), `source` (the whole Argv or
+    /// `null`), `related` (objects of the same keys) and `suggestions`
+    /// (`offset`, `length` and `replacement`). Grouping, verbosity and
+    /// the summary line are not applied.
+    Json,
+    /// A SARIF 2.1.0 log, e.g. for code-scanning UIs in CI. The labels
+    /// are byte regions of the artifact named after the source code, or
+    /// `argv`, advices are notes, and the argument of a report queued by
+    /// `ReportContext::error_about()` is its logical location.
+    /// Grouping, verbosity and the summary line are not applied.
+    Sarif,
+    /// GitHub Actions workflow commands, e.g. `::error title=...::`, so
+    /// a CI job annotates the reports inline, advices as `::notice`. The
+    /// snippets of the Argv under the labels are listed in the message.
+    /// Grouping, verbosity
+    /// and the summary line are not applied.
+    Github,
+}
+
+/// Controls which parts of the queued reports are rendered. Applied
+/// when rendering, so reports can be queued without checking it.
+#[derive(Ord, PartialOrd, Eq, PartialEq, Default, Clone, Copy, Debug)]
+pub enum Verbosity {
+    /// Only the message and the primary labels.
+    Terse,
+    /// Also the help text (suggestions) and the secondary labels.
+    #[default]
+    Normal,
+    /// Also the related diagnostics, i.e. the provenance notes.
+    Verbose,
+}
+
+/// The numbers of queued reports, returned by `ReportContext::finish()`
+/// for deciding the exit code. Displays as the summary line.
+#[derive(Ord, PartialOrd, Eq, PartialEq, Default, Clone, Copy, Debug)]
+pub struct ReportCounts {
+    pub errors: usize,
+    pub warnings: usize,
+}
+
+/// A queued report and the path of subcommand names that the reported
+/// argument belongs to. An empty `scope` means the top-level command.
+pub struct ScopedReport {
+    pub scope: Vec<String>,
+    /// `clap::Id` of the reported argument, if queued by
+    /// `ReportContext::warn_about()` or `ReportContext::error_about()`.
+    pub arg: Option<String>,
+    pub report: Report,
+}
+
+/// Labels arguments in the Argv string. The options are forwarded to
+/// the `ArgLocator` that does the work.
+#[derive(Default)]
+pub struct ArgHighlighter {
+    /// See `ArgLocator::include_arg_name`.
+    pub include_arg_name: bool,
+    /// See `ArgLocator::force_lossy_os_string`.
+    pub force_lossy_os_string: bool,
+    /// Labels the long or short that was typed rather than the value,
+    /// and adds a note listing the canonical form and every other
+    /// accepted spelling of the argument, for deprecation and
+    /// education diagnostics.
+    pub list_spellings: bool,
+    /// Names the argument in the label as the help does, with its value
+    /// names, e.g. "here: `--output <FILE>`", so reports read like the
+    /// help text users already know.
+    pub usage_names: bool,
+    /// Contents that the values of arguments were read from instead of
+    /// the command line, keyed by the argument id. See
+    /// `Self::register_value_file()`.
+    pub value_files: BTreeMap<String, ValueFile>,
+    /// Formats the `FallbackNote` appended to the help when the
+    /// argument cannot be labelled, instead of its `Display`.
+    pub fallback_format: Option<FallbackFormat>,
+    /// Metadata of the arguments that the reports about them are
+    /// enriched with, see `ArgMetadataRegistry::enrich()`.
+    pub metadata: ArgMetadataRegistry,
+    /// Whether the label of an argument with a docs page is an OSC-8
+    /// hyperlink to it. `None` detects if the terminal supports them,
+    /// see `Self::register_doc_url()`. The link is only applied when
+    /// the `ReportContext` that holds this highlighter renders in
+    /// `DiagnosticFormat::Human`, so the label text stays plain.
+    pub hyperlinks: Option<bool>,
+}
+
+/// See `ArgHighlighter::fallback_format`.
+pub type FallbackFormat = Box<dyn Fn(&FallbackNote) -> String>;
+
+/// What is known about an argument that `ArgHighlighter` cannot label,
+/// because its value did not come from the command line or cannot be
+/// found in the Argv. Displays as a block like:
+/// ```text
+/// arg: `port`
+/// value: `80`
+/// source: default value
+/// ```
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct FallbackNote {
+    /// The id of the argument.
+    pub arg: String,
+    /// The raw values, lossily converted. Values of env variables and
+    /// defaults that are hidden from the help are left out.
+    pub values: Vec<String>,
+    pub hidden: bool,
+    pub source: Option<ValueSource>,
+    /// The env variable that the value came from, unless it is hidden
+    /// from the help.
+    pub env: Option<String>,
+}
+
+/// A file, or stdin, that the real value of an argument was read from
+/// because its value on the command line was like `@file` or `-`.
+#[derive(Clone, Debug)]
+pub struct ValueFile {
+    /// Shown in the header of the source code, e.g. the path or
+    /// "<stdin>".
+    pub name: String,
+    pub content: String,
+}
+
+impl SourceProvider for ValueFile {
+    fn name(&self) -> Option<String> {
+        Some(self.name.clone())
+    }
+
+    fn content(&self) -> String {
+        self.content.clone()
+    }
+
+    fn remap(&self, _: &ArgPart) -> Option<ArgPart> {
+        None
+    }
+}
+
+impl ReportContext {
+    /// Queues a warning about the top-level command.
+    pub fn warn(&mut self, report: impl Into<Report>) {
+        self.warn_in(Vec::<String>::new(), report);
+    }
+
+    /// Queues a warning about an argument of the subcommand `scope`.
+    /// See `subcommand_scope()`.
+    pub fn warn_in<S: Into<String>>(&mut self, scope: impl IntoIterator<Item = S>, report: impl Into<Report>) {
+        self.warns.push(ScopedReport::new(scope, report));
+    }
+
+    /// Queues an error about the top-level command.
+    pub fn error(&mut self, report: impl Into<Report>) {
+        self.error_in(Vec::<String>::new(), report);
+    }
+
+    /// Queues an error about an argument of the subcommand `scope`.
+    /// See `subcommand_scope()`.
+    pub fn error_in<S: Into<String>>(&mut self, scope: impl IntoIterator<Item = S>, report: impl Into<Report>) {
+        self.errs.push(ScopedReport::new(scope, report));
+    }
+
+    /// Queues a warning about `arg`, which can then be queried by
+    /// `Self::diagnostics_for_arg()`.
+    pub fn warn_about(&mut self, arg: &str, report: impl Into<Report>) {
+        let mut queued = ScopedReport::new(Vec::<String>::new(), report);
+        queued.arg = Some(arg.to_owned());
+        self.warns.push(queued);
+    }
+
+    /// Queues an error about `arg`, which can then be queried by
+    /// `Self::diagnostics_for_arg()`.
+    pub fn error_about(&mut self, arg: &str, report: impl Into<Report>) {
+        let mut queued = ScopedReport::new(Vec::<String>::new(), report);
+        queued.arg = Some(arg.to_owned());
+        self.errs.push(queued);
+    }
+
+    pub fn has_errors(&self) -> bool {
+        !self.errs.is_empty()
+    }
+
+    /// Returns the queued warnings and errors about `arg`.
+    pub fn diagnostics_for_arg<'a>(&'a self, arg: &'a str) -> impl Iterator<Item = &'a ScopedReport> + 'a {
+        self.warns.iter().chain(&self.errs).filter(move |queued| queued.arg.as_deref() == Some(arg))
+    }
+
+    /// Returns the queued reports of `severity`. Queued errors are of
+    /// `Severity::Error`, and queued warnings are of `Severity::Warning`
+    /// unless the report itself is an advice.
+    pub fn iter_by_severity(&self, severity: Severity) -> impl Iterator<Item = &ScopedReport> {
+        let warns = self.warns.iter().filter(move |queued| {
+            let advice = queued.report.severity() == Some(Severity::Advice);
+            match severity {
+                Severity::Advice => advice,
+                Severity::Warning => !advice,
+                Severity::Error => false,
+            }
+        });
+        let errs = self.errs.iter().filter(move |_| severity == Severity::Error);
+
+        warns.chain(errs)
+    }
+
+    /// Keeps only the queued warnings and errors that satisfy `keep`,
+    /// e.g. to drop those about arguments the user acknowledged.
+    pub fn retain(&mut self, mut keep: impl FnMut(&ScopedReport) -> bool) {
+        self.warns.retain(&mut keep);
+        self.errs.retain(keep);
+    }
+
+    /// Moves the queued errors that satisfy `downgrade` to the warnings,
+    /// so they are rendered and counted as warnings.
+    pub fn downgrade(&mut self, mut downgrade: impl FnMut(&ScopedReport) -> bool) {
+        let (downgraded, errs): (Vec<_>, Vec<_>) = std::mem::take(&mut self.errs).into_iter().partition(|queued| downgrade(queued));
+        self.errs = errs;
+        self.warns.extend(downgraded);
+    }
+
+    /// Keeps only the queued reports whose code, see `crate::code`,
+    /// satisfies `keep`, e.g. to filter the machine-readable output.
+    /// Reports without a code are passed `None`.
+    pub fn retain_codes(&mut self, keep: impl Fn(Option<&str>) -> bool) {
+        self.retain(|queued| keep(queued.report.code().map(|code| code.to_string()).as_deref()));
+    }
+
+    /// Returns the numbers of queued warnings and errors.
+    pub fn counts(&self) -> ReportCounts {
+        ReportCounts {
+            errors: self.errs.len(),
+            warnings: self.warns.len(),
+        }
+    }
+
+    /// Writes all queued warnings followed by all queued errors with
+    /// the currently installed `miette` report handler.
+    pub fn render(&self, f: &mut impl fmt::Write) -> fmt::Result {
+        let warns = if self.silence_warnings { &[][..] } else { &self.warns[..] };
+        let reports = warns.iter().map(|queued| (Level::Warning, queued))
+            .chain(self.errs.iter().map(|queued| (Level::Error, queued)))
+            .map(|(level, queued)| render::Machine {
+                level: Level::of(&*queued.report, level),
+                diagnostic: &*queued.report,
+                suggestions: queued.report.downcast_ref::<Suggested>().map_or(&[][..], |suggested| &suggested.suggestions[..]),
+                scope: &queued.scope,
+                arg: queued.arg.as_deref(),
+            });
+        match self.format {
+            DiagnosticFormat::Human => (),
+            DiagnosticFormat::Json => return render::json(f, reports),
+            DiagnosticFormat::Sarif => return render::sarif(f, reports),
+            DiagnosticFormat::Github => return render::github(f, reports),
+        }
+
+        self.render_reports(f)?;
+        let mut counts = self.counts();
+        if self.silence_warnings {
+            counts.warnings = 0;
+        }
+        if self.summary && counts != ReportCounts::default() {
+            writeln!(f, "{counts}")?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns a handler that replaces those of the reports if the
+    /// layout is overridden.
+    fn layout_handler(&self) -> Option<Box<dyn ReportHandler>> {
+        #[cfg(feature = "fancy")]
+        if self.width.is_some() || self.wrap_lines.is_some() {
+            let mut opts = miette::MietteHandlerOpts::new();
+            if let Some(width) = self.width {
+                opts = opts.width(width);
+            }
+            if let Some(wrap_lines) = self.wrap_lines {
+                opts = opts.wrap_lines(wrap_lines);
+            }
+            return Some(Box::new(opts.build()));
+        }
+
+        None
+    }
+
+    fn render_reports(&self, f: &mut impl fmt::Write) -> fmt::Result {
+        let warns = if self.silence_warnings { &[][..] } else { &self.warns[..] };
+        let handler = self.layout_handler();
+        let rendered = |report| Rendered {
+            report,
+            context: self,
+            handler: handler.as_deref(),
+        };
+        if !self.group_by_subcommand {
+            for queued in warns.iter().chain(&self.errs) {
+                writeln!(f, "{:?}", rendered(&queued.report))?;
+            }
+            return Ok(());
+        }
+
+        let mut groups = BTreeMap::<&[String], Vec<&ScopedReport>>::new();
+        for queued in warns.iter().chain(&self.errs) {
+            groups.entry(&queued.scope).or_default().push(queued);
+        }
+        for (scope, queued) in groups {
+            if scope.is_empty() {
+                writeln!(f, "top-level arguments:")?;
+            } else {
+                writeln!(f, "arguments of `{}`:", scope.join(" "))?;
+            }
+            for queued in queued {
+                writeln!(f, "{:?}", rendered(&queued.report))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Prints all queued reports to the standard error and returns
+    /// their numbers.
+    pub fn finish(self) -> ReportCounts {
+        let mut rendered = String::new();
+        self.render(&mut rendered).expect("Writing to a String never fails");
+        eprint!("{rendered}");
+
+        self.counts()
+    }
+}
+
+impl clap::ValueEnum for DiagnosticFormat {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[Self::Human, Self::Json, Self::Sarif, Self::Github]
+    }
+
+    fn to_possible_value(&self) -> Option<clap::builder::PossibleValue> {
+        Some(clap::builder::PossibleValue::new(match self {
+            Self::Human => "human",
+            Self::Json => "json",
+            Self::Sarif => "sarif",
+            Self::Github => "github",
+        }))
+    }
+}
+
+impl Verbosity {
+    /// Returns the verbosity for the numbers of occurrences of `-v` and
+    /// `-q` flags (`ArgAction::Count`), where they cancel out each other.
+    pub fn from_counts(verbose: u8, quiet: u8) -> Self {
+        match verbose.cmp(&quiet) {
+            std::cmp::Ordering::Less => Self::Terse,
+            std::cmp::Ordering::Equal => Self::Normal,
+            std::cmp::Ordering::Greater => Self::Verbose,
+        }
+    }
+}
+
+/// Renders a report with the handler of itself, or the given one,
+/// while hiding the parts that are excluded by the verbosity.
+struct Rendered<'a> {
+    report: &'a Report,
+    context: &'a ReportContext,
+    handler: Option<&'a dyn ReportHandler>,
+}
+
+impl fmt::Debug for Rendered<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let inner = &**self.report;
+        let mut spans = inner.labels().map(|labels| labels.map(|label| *label.inner()).collect::<Vec<_>>()).unwrap_or_default();
+        spans.truncate(self.context.max_labels.unwrap_or(usize::MAX));
+        let displayed = inner.source_code().and_then(|source| DisplayedSource::of(source, self.context.max_source_length, &spans));
+        let source = match &displayed {
+            Some(displayed) => Some(displayed as &dyn SourceCode),
+            None => inner.source_code(),
+        };
+        let origin = source.zip(self.context.origin.as_deref()).map(|(source, name)| Origin { source, name });
+        let hyperlinks = self.context.arg_highlighter.hyperlinks;
+        let link = inner.url().map(|url| url.to_string()).filter(|_| hyperlinks.unwrap_or_else(caret::supports_hyperlinks));
+        let detailed = Detailed {
+            inner,
+            link,
+            verbosity: self.context.verbosity,
+            origin,
+            displayed: displayed.as_ref(),
+            max_labels: self.context.max_labels,
+        };
+
+        self.handler.unwrap_or(self.report.handler()).debug(&detailed, f)
+    }
+}
+
+struct Detailed<'a> {
+    inner: &'a dyn Diagnostic,
+    /// The URL that the primary label is an OSC-8 hyperlink to.
+    link: Option<String>,
+    verbosity: Verbosity,
+    origin: Option<Origin<'a>>,
+    displayed: Option<&'a DisplayedSource>,
+    max_labels: Option<usize>,
+}
+
+/// Names a source code that has no name. See `ReportContext::origin`.
+struct Origin<'a> {
+    source: &'a dyn SourceCode,
+    name: &'a str,
+}
+
+impl SourceCode for Origin<'_> {
+    fn read_span<'b>(&'b self, span: &SourceSpan, context_lines_before: usize, context_lines_after: usize) -> Result<Box<dyn SpanContents<'b> + 'b>, MietteError> {
+        let contents = self.source.read_span(span, context_lines_before, context_lines_after)?;
+        if contents.name().is_some() {
+            return Ok(contents);
+        }
+
+        Ok(Box::new(MietteSpanContents::new_named(
+            self.name.to_owned(),
+            contents.data(),
+            *contents.span(),
+            contents.line(),
+            contents.column(),
+            contents.line_count(),
+        )))
+    }
+}
+
+impl fmt::Display for Detailed<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self.inner, f)
+    }
+}
+
+impl fmt::Debug for Detailed<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.inner, f)
+    }
+}
+
+impl std::error::Error for Detailed<'_> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.inner.source()
+    }
+}
+
+impl Diagnostic for Detailed<'_> {
+    fn code<'b>(&'b self) -> Option<Box<dyn fmt::Display + 'b>> {
+        self.inner.code()
+    }
+
+    fn severity(&self) -> Option<miette::Severity> {
+        self.inner.severity()
+    }
+
+    fn help<'b>(&'b self) -> Option<Box<dyn fmt::Display + 'b>> {
+        if self.verbosity == Verbosity::Terse {
+            return None;
+        }
+        let mut notes = self.inner.help().map(|help| help.to_string()).into_iter().collect::<Vec<_>>();
+        if let Some(displayed) = self.displayed.filter(|displayed| displayed.elided > 0) {
+            notes.push(format!("{} bytes of the source code are elided", displayed.elided));
+        }
+        let labels = self.inner.labels().map_or(0, Iterator::count);
+        if let Some(omitted) = self.max_labels.and_then(|max_labels| labels.checked_sub(max_labels)).filter(|&omitted| omitted > 0) {
+            notes.push(format!("{omitted} more labels are not shown"));
+        }
+        match notes.is_empty() {
+            true => None,
+            false => Some(Box::new(notes.join("\n"))),
+        }
+    }
+
+    fn url<'b>(&'b self) -> Option<Box<dyn fmt::Display + 'b>> {
+        self.inner.url()
+    }
+
+    fn source_code(&self) -> Option<&dyn SourceCode> {
+        match (&self.origin, self.displayed) {
+            (Some(origin), _) => Some(origin),
+            (None, Some(displayed)) => Some(displayed),
+            (None, None) => self.inner.source_code(),
+        }
+    }
+
+    fn labels(&self) -> Option<Box<dyn Iterator<Item = LabeledSpan> + '_>> {
+        let mut labels = self.inner.labels()?;
+        if let Some(max_labels) = self.max_labels {
+            labels = Box::new(labels.take(max_labels));
+        }
+        if self.displayed.is_some() || self.link.is_some() {
+            labels = Box::new(labels.map(|label| {
+                let span = self.displayed.map_or(*label.inner(), |displayed| displayed.span(label.inner()));
+                let text = match (label.label(), &self.link) {
+                    (Some(text), Some(url)) if label.primary() => Some(caret::hyperlink(text, url)),
+                    (text, _) => text.map(ToOwned::to_owned),
+                };
+                match label.primary() {
+                    true => LabeledSpan::new_primary_with_span(text, span),
+                    false => LabeledSpan::new_with_span(text, span),
+                }
+            }));
+        }
+        if self.verbosity == Verbosity::Terse {
+            return Some(Box::new(labels.filter(LabeledSpan::primary)));
+        }
+        Some(labels)
+    }
+
+    fn related<'b>(&'b self) -> Option<Box<dyn Iterator<Item = &'b dyn Diagnostic> + 'b>> {
+        if self.verbosity != Verbosity::Verbose {
+            return None;
+        }
+        self.inner.related()
+    }
+
+    fn diagnostic_source(&self) -> Option<&dyn Diagnostic> {
+        self.inner.diagnostic_source()
+    }
+}
+
+impl ReportCounts {
+    /// Returns `true` if any error was queued, which usually means the
+    /// program should exit with a failure code.
+    pub fn has_errors(&self) -> bool {
+        self.errors > 0
+    }
+}
+
+impl fmt::Display for ReportCounts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let plural = |count: usize| if count == 1 { "" } else { "s" };
+        write!(
+            f,
+            "{} error{}, {} warning{} generated from command-line arguments",
+            self.errors,
+            plural(self.errors),
+            self.warnings,
+            plural(self.warnings),
+        )
+    }
+}
+
+impl ScopedReport {
+    fn new<S: Into<String>>(scope: impl IntoIterator<Item = S>, report: impl Into<Report>) -> Self {
+        Self {
+            scope: scope.into_iter().map(Into::into).collect(),
+            arg: None,
+            report: report.into(),
+        }
+    }
+}
+
+impl ArgHighlighter {
+    /// Labels where `arg` appears in `std::env::args_os()` and attaches
+    /// the Argv string as the source code of `diagnostic`. Values from
+    /// env variables are labelled in a source like `PORT=80` instead,
+    /// unless they are hidden from the help. If no location can be
+    /// found, e.g. for a default value, a `FallbackNote` is appended to
+    /// the help instead.
+    pub fn highlight<T: CommandFactory>(&mut self, diagnostic: MietteDiagnostic, arg: &str, label: &str) -> Report {
+        let matches = T::command().get_matches();
+
+        self.highlight_args::<T, _>(std::env::args_os(), &matches, diagnostic, arg, label)
+    }
+
+    /// Same as `Self::highlight()` but with the given `args` and their
+    /// `matches` instead of those of the current process.
+    ///
+    /// If the argument was typed as an alias, the label is followed by
+    /// the canonical name, e.g. "here: `--col` (alias of `--color`)".
+    pub fn highlight_args<T, R>(&self, args: R, matches: &ArgMatches, mut diagnostic: MietteDiagnostic, arg: &str, label: &str) -> Report
+    where
+        T: CommandFactory,
+        R: IntoIterator<Item: Into<OsString>>,
+    {
+        let url = self.metadata.get(arg).and_then(|metadata| metadata.doc_url.clone());
+        let hyperlinks = url.is_some() && self.hyperlinks.unwrap_or_else(caret::supports_hyperlinks);
+        diagnostic = self.metadata.enrich(arg, diagnostic, hyperlinks);
+        // Values from env variables and defaults are not in the Argv.
+        if matches.value_source(arg) == Some(ValueSource::CommandLine) {
+            let args = args.into_iter().map(Into::into).collect::<Vec<OsString>>();
+            let mut locator = ArgLocator::from_command_factory::<T>();
+            locator.include_arg_name = self.include_arg_name;
+            locator.force_lossy_os_string = self.force_lossy_os_string;
+            if let Some(report) = self.highlight_positional(&locator, &args, matches, &mut diagnostic, arg, label) {
+                return report;
+            }
+            if let Some((full_command, location, mut part)) = locator.label_span(&args, arg) {
+                let mut command = T::command();
+                // Value names and actions are inferred while building.
+                command.build();
+                let found = command.get_arguments().find(|candidate| candidate.get_id() == arg);
+                let note = found.and_then(|found| alias_note(&full_command, &location, found, self.usage_names));
+                let label = match (note, found.filter(|_| self.usage_names)) {
+                    (Some(note), _) => format!("{label}: {note}"),
+                    (None, Some(found)) => format!("{label}: `{found}`"),
+                    (None, None) => label.to_owned(),
+                };
+                if let Some(found) = found.filter(|_| self.list_spellings) {
+                    let name = location.name();
+                    part = ArgPart {
+                        offset: location.declaration().offset,
+                        length: name.offset + name.length - location.declaration().offset,
+                    };
+                    let note = spellings_note(found, location.typed_alias(&full_command).as_ref());
+                    diagnostic.help = Some(match diagnostic.help {
+                        Some(help) => format!("{help}\n{note}"),
+                        None => note,
+                    });
+                }
+                let label = LabeledSpan::new_primary_with_span(Some(label), SourceSpan::new(part.offset.into(), part.length));
+                diagnostic.labels.get_or_insert_with(Vec::new).push(label);
+
+                return ArgvSource::new(full_command).attach(Report::from(diagnostic));
+            }
+        }
+        let mut command = T::command();
+        command.build();
+        let found = command.get_arguments().find(|candidate| candidate.get_id() == arg);
+        if let Some(report) = found.and_then(|found| env_report(found, matches, &mut diagnostic, label)) {
+            return report;
+        }
+        let note = FallbackNote::new(found, matches, arg);
+        let note = match &self.fallback_format {
+            Some(format) => format(&note),
+            None => note.to_string(),
+        };
+        diagnostic.help = Some(match diagnostic.help {
+            Some(help) => format!("{help}\n{note}"),
+            None => note,
+        });
+
+        Report::from(diagnostic)
+    }
+
+    /// Labels all the values of `arg` if it is positional.
+    fn highlight_positional(&self, locator: &ArgLocator<impl Default, impl AsRef<Arg>>, args: &[OsString], matches: &ArgMatches, diagnostic: &mut MietteDiagnostic, arg: &str, label: &str) -> Option<Report> {
+        if !self.force_lossy_os_string && args.iter().any(|arg| arg.to_str().is_none()) {
+            return None;
+        }
+        let values = locator.locate_positional(args, matches, arg);
+        let (first, last) = (values.first()?, values.last()?);
+        let full_command = args.iter().map(|arg| arg.to_string_lossy()).collect::<Vec<_>>().join(" ");
+        let span = SourceSpan::new(first.offset.into(), last.offset + last.length - first.offset);
+        diagnostic.labels.get_or_insert_with(Vec::new).push(LabeledSpan::new_primary_with_span(Some(label.to_owned()), span));
+
+        Some(ArgvSource::new(full_command).attach(Report::from(diagnostic.clone())))
+    }
+
+    /// Registers `url` as the docs page of `arg`. If the terminal supports
+    /// OSC-8 hyperlinks, the page is the `Diagnostic::url()` of the
+    /// reports about `arg`, and their primary label is rendered as a
+    /// hyperlink to it, see `Self::hyperlinks`. Otherwise, the URL is
+    /// appended to the help.
+    pub fn register_doc_url(&mut self, arg: &str, url: impl Into<String>) {
+        self.metadata.args.entry(arg.to_owned()).or_default().doc_url = Some(url.into());
+    }
+
+    /// Registers `content` as what the value of `arg` was read from, so
+    /// that `Self::highlight_value_file()` can point into it.
+    pub fn register_value_file(&mut self, arg: &str, name: impl Into<String>, content: impl Into<String>) {
+        self.value_files.insert(arg.to_owned(), ValueFile {
+            name: name.into(),
+            content: content.into(),
+        });
+    }
+
+    /// Attaches the value file registered for `arg` as the source code
+    /// of `diagnostic`, whose labels should be spans in the file. The
+    /// `@file` or `-` token in `args` is labelled with `token_label` by
+    /// a related advice, since a report has only one source code.
+    ///
+    /// Returns the diagnostic without source code if no file was
+    /// registered for `arg`.
+    pub fn highlight_value_file<T, R>(&self, args: R, matches: &ArgMatches, diagnostic: MietteDiagnostic, arg: &str, token_label: &str) -> Report
+    where
+        T: CommandFactory,
+        R: IntoIterator<Item: Into<OsString>>,
+    {
+        let diagnostic = self.metadata.enrich(arg, diagnostic, self.hyperlinks.unwrap_or_else(caret::supports_hyperlinks));
+        let Some(file) = self.value_files.get(arg) else {
+            return Report::from(diagnostic);
+        };
+        let mut reference = None;
+        if matches.value_source(arg) == Some(ValueSource::CommandLine) {
+            let mut locator = ArgLocator::from_command_factory::<T>();
+            locator.force_lossy_os_string = self.force_lossy_os_string;
+            if let Some((full_command, location, _)) = locator.label_span(args, arg) {
+                let part = location.content().cloned().unwrap_or_else(|| location.whole());
+                let advice = MietteDiagnostic::new(format!("the value of `{arg}` was read from {}", file.name))
+                    .with_severity(Severity::Advice)
+                    .with_code(code::VALUE_FILE)
+                    .with_label(LabeledSpan::new_primary_with_span(Some(token_label.to_owned()), SourceSpan::new(part.offset.into(), part.length)));
+                reference = Some(ArgvSource::new(full_command).attach(Report::from(advice)));
+            }
+        }
+
+        file.attach(Report::from(FromValueFile { diagnostic, reference }))
+    }
+}
+
+/// A diagnostic about the content of a `ValueFile`, related to an
+/// advice that labels where the file was referenced.
+struct FromValueFile {
+    diagnostic: MietteDiagnostic,
+    reference: Option<Report>,
+}
+
+impl fmt::Display for FromValueFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.diagnostic, f)
+    }
+}
+
+impl fmt::Debug for FromValueFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.diagnostic, f)
+    }
+}
+
+impl std::error::Error for FromValueFile {}
+
+impl Diagnostic for FromValueFile {
+    fn code<'a>(&'a self) -> Option<Box<dyn fmt::Display + 'a>> {
+        self.diagnostic.code()
+    }
+
+    fn severity(&self) -> Option<Severity> {
+        self.diagnostic.severity()
+    }
+
+    fn help<'a>(&'a self) -> Option<Box<dyn fmt::Display + 'a>> {
+        self.diagnostic.help()
+    }
+
+    fn url<'a>(&'a self) -> Option<Box<dyn fmt::Display + 'a>> {
+        self.diagnostic.url()
+    }
+
+    fn labels(&self) -> Option<Box<dyn Iterator<Item = LabeledSpan> + '_>> {
+        self.diagnostic.labels()
+    }
+
+    fn related<'a>(&'a self) -> Option<Box<dyn Iterator<Item = &'a dyn Diagnostic> + 'a>> {
+        let reference = self.reference.as_ref()?;
+
+        Some(Box::new(std::iter::once(reference.as_ref())))
+    }
+}
+
+impl FallbackNote {
+    fn new(found: Option<&Arg>, matches: &ArgMatches, arg: &str) -> Self {
+        let source = matches.value_source(arg);
+        // Same as `explain()`, hidden env variables and defaults may
+        // hold secrets.
+        let hidden = found.is_some_and(|found| match source {
+            Some(ValueSource::EnvVariable) => found.is_hide_env_set() || found.is_hide_env_values_set(),
+            Some(ValueSource::DefaultValue) => found.is_hide_default_value_set(),
+            _ => false,
+        });
+        let values = match matches.try_get_raw(arg) {
+            Ok(Some(values)) if !hidden => values.map(|value| value.to_string_lossy().to_string()).collect(),
+            _ => vec![],
+        };
+        let env = found
+            .filter(|found| source == Some(ValueSource::EnvVariable) && !found.is_hide_env_set())
+            .and_then(Arg::get_env)
+            .map(|env| env.to_string_lossy().to_string());
+
+        Self {
+            arg: arg.to_owned(),
+            values,
+            hidden,
+            source,
+            env,
+        }
+    }
+}
+
+impl fmt::Display for FallbackNote {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "arg: `{}`", self.arg)?;
+        if self.hidden {
+            writeln!(f, "value: (hidden)")?;
+        } else if !self.values.is_empty() {
+            let values = self.values.iter().map(|value| format!("`{value}`")).collect::<Vec<_>>();
+            writeln!(f, "value: {}", values.join(", "))?;
+        }
+        match (self.source, &self.env) {
+            (Some(ValueSource::EnvVariable), Some(env)) => write!(f, "source: env variable `{env}`"),
+            (Some(ValueSource::EnvVariable), None) => write!(f, "source: env variable"),
+            (Some(ValueSource::DefaultValue), _) => write!(f, "source: default value"),
+            (Some(ValueSource::CommandLine), _) => write!(f, "source: command line"),
+            _ => write!(f, "source: not set"),
+        }
+    }
+}
+
+/// Returns `diagnostic` with a source like `PORT=80` and `label` on the
+/// value, if the value of `found` came from an env variable that is not
+/// hidden from the help.
+fn env_report(found: &Arg, matches: &ArgMatches, diagnostic: &mut MietteDiagnostic, label: &str) -> Option<Report> {
+    if matches.value_source(found.get_id().as_str()) != Some(ValueSource::EnvVariable) || found.is_hide_env_set() || found.is_hide_env_values_set() {
+        return None;
+    }
+    let var = found.get_env()?.to_string_lossy().to_string();
+    let value = std::env::var_os(&var)?.to_string_lossy().to_string();
+    let source = EnvSource { var, value };
+    let part = source.value_part();
+    let span = SourceSpan::new(part.offset.into(), part.length);
+    diagnostic.labels.get_or_insert_with(Vec::new).push(LabeledSpan::new_primary_with_span(Some(label.to_owned()), span));
+    let note = format!("the value came from the env variable `{}`", source.var);
+    diagnostic.help = Some(match diagnostic.help.take() {
+        Some(help) => format!("{help}\n{note}"),
+        None => note,
+    });
+
+    Some(source.attach(Report::from(diagnostic.clone())))
+}
+
+/// Returns the long of `arg`, or the short if there is no long.
+fn canonical(arg: &Arg) -> Option<ArgAlias> {
+    match (arg.get_long(), arg.get_short()) {
+        (Some(long), _) => Some(ArgAlias::Long(long.to_owned())),
+        (None, Some(short)) => Some(ArgAlias::Short(short)),
+        (None, None) => None,
+    }
+}
+
+/// Returns a note like "canonical form: `--color`; other accepted
+/// spellings: `-c`, `--col`" that excludes the `typed` spelling.
+fn spellings_note(found: &Arg, typed: Option<&ArgAlias>) -> String {
+    let Some(canonical) = canonical(found) else {
+        return format!("`{}` has no other spellings", found.get_id());
+    };
+    let others = all_aliases(found)
+        .filter(|alias| *alias != canonical && Some(alias) != typed)
+        .map(|alias| format!("`{alias}`"))
+        .collect::<Vec<_>>();
+    if others.is_empty() {
+        return format!("canonical form: `{canonical}`");
+    }
+
+    format!("canonical form: `{canonical}`; other accepted spellings: {}", others.join(", "))
+}
+
+/// Returns a note like "`--col` (alias of `--color`)" if `found` was
+/// typed as an alias at `location`, so users learn the canonical name.
+/// The canonical name is the usage, e.g. `--color <WHEN>`, if `usage`.
+fn alias_note(full_command: &str, location: &ArgLocation, found: &Arg, usage: bool) -> Option<String> {
+    let typed = location.typed_alias(full_command)?;
+    let canonical = canonical(found)?;
+    if typed == canonical || found.get_short().is_some_and(|short| typed == ArgAlias::Short(short)) {
+        return None;
+    }
+    if usage {
+        return Some(format!("`{typed}` (alias of `{found}`)"));
+    }
+
+    Some(format!("`{typed}` (alias of `{canonical}`)"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(clap::Parser)]
+    struct Args {
+        #[clap(long, global = true)]
+        config: Option<String>,
+        #[clap(subcommand)]
+        command: Command,
+    }
+
+    #[derive(clap::Subcommand)]
+    enum Command {
+        Remote {
+            #[clap(subcommand)]
+            command: RemoteCommand,
+        },
+    }
+
+    #[derive(clap::Subcommand)]
+    enum RemoteCommand {
+        Add {
+            #[clap(long)]
+            url: String,
+        },
+    }
+
+    #[test]
+    fn test_render_group_by_subcommand() {
+        let mut context = ReportContext {
+            group_by_subcommand: true,
+            ..Default::default()
+        };
+        context.warn_in(["remote", "add"], MietteDiagnostic::new("bad url"));
+        context.error(MietteDiagnostic::new("bad config"));
+        context.warn_in(["remote", "add"], MietteDiagnostic::new("insecure url"));
+
+        let mut rendered = String::new();
+        context.render(&mut rendered).unwrap();
+        let position = |needle| rendered.find(needle).unwrap();
+        assert!(position("top-level arguments:") < position("bad config"));
+        assert!(position("bad config") < position("arguments of `remote add`:"));
+        assert!(position("arguments of `remote add`:") < position("bad url"));
+        assert!(position("bad url") < position("insecure url"));
+    }
+
+    #[test]
+    fn test_render_summary() {
+        let mut context = ReportContext {
+            summary: true,
+            ..Default::default()
+        };
+        context.warn(MietteDiagnostic::new("a"));
+        context.warn(MietteDiagnostic::new("b"));
+        context.error(MietteDiagnostic::new("c"));
+
+        let mut rendered = String::new();
+        context.render(&mut rendered).unwrap();
+        assert!(rendered.ends_with("1 error, 2 warnings generated from command-line arguments\n"));
+        assert_eq!(context.finish(), ReportCounts { errors: 1, warnings: 2 });
+    }
+
+    #[test]
+    fn test_render_verbosity() {
+        let mut diagnostic = MietteDiagnostic::new("bad port")
+            .with_help("use a port above 1024")
+            .with_labels([
+                LabeledSpan::new_primary_with_span(Some("primary".to_owned()), 0..1),
+                LabeledSpan::new_with_span(Some("secondary".to_owned()), 1..2),
+            ]);
+        diagnostic.severity = Some(miette::Severity::Warning);
+
+        let mut context = ReportContext {
+            verbosity: Verbosity::Terse,
+            ..Default::default()
+        };
+        context.warn(Report::from(diagnostic).with_source_code("ab"));
+        let mut rendered = String::new();
+        context.render(&mut rendered).unwrap();
+        assert!(rendered.contains("primary"));
+        assert!(!rendered.contains("secondary"));
+        assert!(!rendered.contains("use a port above 1024"));
+
+        context.verbosity = Verbosity::Normal;
+        let mut rendered = String::new();
+        context.render(&mut rendered).unwrap();
+        assert!(rendered.contains("secondary"));
+        assert!(rendered.contains("use a port above 1024"));
+    }
+
+    #[test]
+    #[cfg(feature = "fancy")]
+    fn test_render_width() {
+        let help = "use a port above 1024 since the lower ones need privileges on most systems";
+        let mut context = ReportContext {
+            width: Some(40),
+            ..Default::default()
+        };
+        context.error(MietteDiagnostic::new("bad port").with_help(help));
+        let longest = |context: &ReportContext| {
+            let mut rendered = String::new();
+            context.render(&mut rendered).unwrap();
+            rendered.lines().map(|line| line.chars().count()).max().unwrap_or_default()
+        };
+        assert!(longest(&context) <= 40);
+        context.wrap_lines = Some(false);
+        assert!(longest(&context) > 40);
+    }
+
+    #[test]
+    #[cfg(feature = "fancy")]
+    fn test_render_origin() {
+        let diagnostic = MietteDiagnostic::new("bad port").with_label(LabeledSpan::new_primary_with_span(None, 13..19));
+        let mut context = ReportContext {
+            origin: Some("systemd unit myapp.service".to_owned()),
+            ..Default::default()
+        };
+        context.error(Report::from(diagnostic.clone()).with_source_code("program_name --port"));
+        context.error(Report::from(diagnostic).with_source_code(miette::NamedSource::new("deploy.sh", "program_name --port".to_owned())));
+        let mut rendered = String::new();
+        context.render(&mut rendered).unwrap();
+        assert!(rendered.contains("[systemd unit myapp.service:1:14]"));
+        assert!(rendered.contains("[deploy.sh:1:14]"));
+    }
+
+    #[test]
+    fn test_render_budget() {
+        let source = format!("app {}--name=x", "a ".repeat(1000));
+        let offset = source.find("--name").unwrap();
+        let diagnostic = MietteDiagnostic::new("bad name").with_labels([
+            LabeledSpan::new_primary_with_span(Some("first".to_owned()), offset..offset + 6),
+            LabeledSpan::new_with_span(Some("second".to_owned()), 0..3),
+        ]);
+        let mut context = ReportContext {
+            max_source_length: Some(256),
+            max_labels: Some(1),
+            ..Default::default()
+        };
+        context.error(Report::from(diagnostic).with_source_code(source));
+        let mut rendered = String::new();
+        context.render(&mut rendered).unwrap();
+        assert!(rendered.contains("1988 bytes of the source code are elided"));
+        assert!(rendered.contains("1 more labels are not shown"));
+        assert!(!rendered.contains("second"));
+    }
+
+    #[test]
+    #[cfg(feature = "fancy")]
+    fn test_render_escaped_source() {
+        let diagnostic = MietteDiagnostic::new("bad name").with_label(LabeledSpan::new_primary_with_span(Some("here".to_owned()), 11..17));
+        let mut context = ReportContext::default();
+        context.error(Report::from(diagnostic).with_source_code("app\t--name=\u{1b}[31mx"));
+        let mut rendered = String::new();
+        context.render(&mut rendered).unwrap();
+        assert!(rendered.contains(r"app\t--name=\x1b[31mx"));
+        assert!(crate::testing::is_underlined(&rendered, r"\x1b[31m"));
+    }
+
+    #[test]
+    fn test_render_json() {
+        let mut context = ReportContext {
+            format: DiagnosticFormat::Json,
+            ..Default::default()
+        };
+        context.warn(MietteDiagnostic::new("a"));
+        context.error(
+            Report::from(MietteDiagnostic::new("b").with_code("myapp::port").with_label(LabeledSpan::new_primary_with_span(Some("here".to_owned()), 11..13)))
+                .with_source_code("app --port=80"),
+        );
+
+        let mut rendered = String::new();
+        context.render(&mut rendered).unwrap();
+        assert_eq!(
+            rendered,
+            [
+                r#"[{"message":"a","severity":"warning","code":null,"help":null,"url":null,"labels":[],"source":null,"related":[],"suggestions":[]},"#,
+                r#"{"message":"b","severity":"error","code":"myapp::port","help":null,"url":null,"labels":[{"label":"here","offset":11,"length":2,"primary":true}],"source":"app --port=80","related":[],"suggestions":[]}]"#,
+                "\n",
+            ]
+            .concat()
+        );
+    }
+
+    #[test]
+    fn test_retain_codes() {
+        let mut context = ReportContext::default();
+        context.warn(MietteDiagnostic::new("a").with_code(code::STUCK_VALUE));
+        context.error(MietteDiagnostic::new("b").with_code("myapp::port"));
+        context.error(MietteDiagnostic::new("c"));
+        context.retain_codes(|code| code.is_some_and(|code| code.starts_with("myapp::")));
+        assert_eq!(context.counts(), ReportCounts { errors: 1, warnings: 0 });
+        assert_eq!(context.errs[0].report.to_string(), "b");
+    }
+
+    #[test]
+    fn test_query_and_downgrade() {
+        let mut context = ReportContext::default();
+        context.error_about("port", MietteDiagnostic::new("privileged port"));
+        context.warn_about("port", MietteDiagnostic::new("insecure port"));
+        context.warn(MietteDiagnostic::new("try --tls").with_severity(Severity::Advice));
+        context.error_about("name", MietteDiagnostic::new("no name"));
+        assert!(context.has_errors());
+        let messages = |reports: Vec<&ScopedReport>| reports.iter().map(|queued| queued.report.to_string()).collect::<Vec<_>>();
+        assert_eq!(messages(context.diagnostics_for_arg("port").collect()), ["insecure port", "privileged port"]);
+        assert_eq!(messages(context.iter_by_severity(Severity::Advice).collect()), ["try --tls"]);
+        assert_eq!(messages(context.iter_by_severity(Severity::Warning).collect()), ["insecure port"]);
+
+        context.downgrade(|queued| queued.arg.as_deref() == Some("port"));
+        assert_eq!(context.counts(), ReportCounts { errors: 1, warnings: 3 });
+        context.retain(|queued| queued.arg.as_deref() != Some("name"));
+        assert!(!context.has_errors());
+    }
+
+    #[test]
+    fn test_verbosity_from_counts() {
+        assert_eq!(Verbosity::from_counts(0, 1), Verbosity::Terse);
+        assert_eq!(Verbosity::from_counts(2, 2), Verbosity::Normal);
+        assert_eq!(Verbosity::from_counts(1, 0), Verbosity::Verbose);
+    }
+
+    #[test]
+    fn test_highlight_args_command_line() {
+        #[derive(clap::Parser)]
+        struct Args {
+            #[clap(long)]
+            port: u16,
+        }
+
+        let args = ["program_name", "--port=80"];
+        let matches = Args::command().get_matches_from(args);
+        let highlighter = ArgHighlighter::default();
+        let report = highlighter.highlight_args::<Args, _>(args, &matches, MietteDiagnostic::new("privileged port"), "port", "here");
+        let labels = report.labels().unwrap().collect::<Vec<_>>();
+        assert_eq!(labels[0].offset(), 20);
+        assert_eq!(labels[0].len(), 2);
+        assert_eq!(labels[0].label(), Some("here"));
+    }
+
+    #[test]
+    fn test_highlight_args_positional() {
+        #[derive(clap::Parser)]
+        struct Args {
+            #[clap(short)]
+            verbose: bool,
+            files: Vec<String>,
+        }
+
+        let args = ["program_name", "a.txt", "-v", "b.txt"];
+        let matches = Args::command().get_matches_from(args);
+        let report = ArgHighlighter::default().highlight_args::<Args, _>(args, &matches, MietteDiagnostic::new("not found"), "files", "here");
+        let label = report.labels().unwrap().next().unwrap();
+        assert_eq!((label.offset(), label.len()), (13, 14));
+    }
+
+    #[test]
+    fn test_highlight_args_fallback() {
+        #[derive(clap::Parser)]
+        struct Args {
+            #[clap(long, default_value = "80")]
+            port: u16,
+            #[clap(long, env = "FANCY_CLAP_TEST_TOKEN", hide_env_values = true)]
+            token: Option<String>,
+        }
+
+        let args = ["program_name"];
+        let matches = Args::command().get_matches_from(args);
+        let mut highlighter = ArgHighlighter::default();
+        let report = highlighter.highlight_args::<Args, _>(args, &matches, MietteDiagnostic::new("privileged port").with_help("use 1024 or above"), "port", "here");
+        assert!(report.labels().is_none());
+        assert_eq!(report.to_string(), "privileged port");
+        assert_eq!(report.help().unwrap().to_string(), "use 1024 or above\narg: `port`\nvalue: `80`\nsource: default value");
+
+        let note = FallbackNote::new(Args::command().get_arguments().find(|arg| arg.get_id() == "token"), &matches, "token");
+        assert_eq!(note.to_string(), "arg: `token`\nsource: not set");
+
+        highlighter.fallback_format = Some(Box::new(|note| format!("{} is {:?}", note.arg, note.source)));
+        let report = highlighter.highlight_args::<Args, _>(args, &matches, MietteDiagnostic::new("privileged port"), "port", "here");
+        assert_eq!(report.help().unwrap().to_string(), "port is Some(DefaultValue)");
+    }
+
+    #[test]
+    fn test_highlight_args_env() {
+        #[derive(clap::Parser)]
+        struct Args {
+            #[clap(long, env = "FANCY_CLAP_TEST_HIGHLIGHT_PORT")]
+            port: u16,
+        }
+
+        std::env::set_var("FANCY_CLAP_TEST_HIGHLIGHT_PORT", "80");
+        let args = ["program_name"];
+        let matches = Args::command().get_matches_from(args);
+        let report = ArgHighlighter::default().highlight_args::<Args, _>(args, &matches, MietteDiagnostic::new("privileged port"), "port", "here");
+        let label = report.labels().unwrap().next().unwrap();
+        let contents = report.source_code().unwrap().read_span(label.inner(), 0, 0).unwrap();
+        assert_eq!((contents.name(), contents.data()), (Some("environment"), &b"80"[..]));
+        assert_eq!(report.help().unwrap().to_string(), "the value came from the env variable `FANCY_CLAP_TEST_HIGHLIGHT_PORT`");
+    }
+
+    #[test]
+    fn test_highlight_args_alias() {
+        #[derive(clap::Parser)]
+        struct Args {
+            #[clap(short, long, alias = "col", short_alias = 'C')]
+            color: bool,
+        }
+
+        let highlighter = ArgHighlighter::default();
+        let label = |args: [&str; 2]| {
+            let matches = Args::command().get_matches_from(args);
+            let report = highlighter.highlight_args::<Args, _>(args, &matches, MietteDiagnostic::new("no colors"), "color", "here");
+            let label = report.labels().unwrap().next().unwrap();
+            label.label().map(ToOwned::to_owned)
+        };
+        assert_eq!(label(["program_name", "--col"]).as_deref(), Some("here: `--col` (alias of `--color`)"));
+        assert_eq!(label(["program_name", "-C"]).as_deref(), Some("here: `-C` (alias of `--color`)"));
+        assert_eq!(label(["program_name", "-c"]).as_deref(), Some("here"));
+        assert_eq!(label(["program_name", "--color"]).as_deref(), Some("here"));
+    }
+
+    #[test]
+    fn test_highlight_args_usage_names() {
+        #[derive(clap::Parser)]
+        struct Args {
+            #[clap(long, alias = "out", value_name = "FILE")]
+            output: String,
+            #[clap(short)]
+            verbose: bool,
+        }
+
+        let highlighter = ArgHighlighter {
+            usage_names: true,
+            ..Default::default()
+        };
+        let label = |args: &[&str], arg| {
+            let matches = Args::command().get_matches_from(args);
+            let report = highlighter.highlight_args::<Args, _>(args, &matches, MietteDiagnostic::new("bad"), arg, "here");
+            let label = report.labels().unwrap().next().unwrap();
+            label.label().map(ToOwned::to_owned)
+        };
+        assert_eq!(label(&["program_name", "--output=a"], "output").as_deref(), Some("here: `--output <FILE>`"));
+        assert_eq!(label(&["program_name", "--out=a"], "output").as_deref(), Some("here: `--out` (alias of `--output <FILE>`)"));
+        assert_eq!(label(&["program_name", "--out=a", "-v"], "verbose").as_deref(), Some("here: `-v`"));
+    }
+
+    #[test]
+    fn test_highlight_args_doc_url() {
+        #[derive(clap::Parser)]
+        struct Args {
+            #[clap(long)]
+            port: u16,
+        }
+
+        let args = ["program_name", "--port=80"];
+        let matches = Args::command().get_matches_from(args);
+        let mut highlighter = ArgHighlighter {
+            hyperlinks: Some(true),
+            ..Default::default()
+        };
+        highlighter.register_doc_url("port", "https://example.com/port");
+        let report = highlighter.highlight_args::<Args, _>(args, &matches, MietteDiagnostic::new("privileged port"), "port", "here");
+        assert_eq!(report.url().map(|url| url.to_string()).as_deref(), Some("https://example.com/port"));
+        assert_eq!(report.labels().unwrap().next().unwrap().label(), Some("here"));
+
+        // The label is only linked when rendered for humans.
+        let mut context = ReportContext {
+            arg_highlighter: ArgHighlighter {
+                hyperlinks: Some(true),
+                ..Default::default()
+            },
+            format: DiagnosticFormat::Json,
+            ..Default::default()
+        };
+        context.error(report);
+        let mut rendered = String::new();
+        context.render(&mut rendered).unwrap();
+        assert!(!rendered.contains("]8;;"), "{rendered}");
+        context.format = DiagnosticFormat::Human;
+        let mut rendered = String::new();
+        context.render(&mut rendered).unwrap();
+        // Without the `fancy` feature, the handler escapes the label.
+        assert!(rendered.contains("]8;;https://example.com/port"), "{rendered}");
+
+        highlighter.hyperlinks = Some(false);
+        let report = highlighter.highlight_args::<Args, _>(args, &matches, MietteDiagnostic::new("privileged port").with_help("use 8080"), "port", "here");
+        assert!(report.url().is_none());
+        assert_eq!(report.labels().unwrap().next().unwrap().label(), Some("here"));
+        assert_eq!(report.help().map(|help| help.to_string()).as_deref(), Some("use 8080\ndocs: https://example.com/port"));
+
+        // The other metadata is pulled in too.
+        highlighter.metadata.register("port", crate::ArgMetadata {
+            owner: Some("net team".to_owned()),
+            code_namespace: Some("myapp".to_owned()),
+            ..Default::default()
+        });
+        let report = highlighter.highlight_args::<Args, _>(args, &matches, MietteDiagnostic::new("privileged port").with_code("port"), "port", "here");
+        assert_eq!(report.code().map(|code| code.to_string()).as_deref(), Some("myapp::port"));
+        assert_eq!(report.help().map(|help| help.to_string()).as_deref(), Some("owned by net team"));
+    }
+
+    #[test]
+    fn test_highlight_value_file() {
+        #[derive(clap::Parser)]
+        struct Args {
+            #[clap(long)]
+            config: String,
+        }
+
+        let mut highlighter = ArgHighlighter::default();
+        let args = ["program_name", "--config", "@deploy.toml"];
+        let matches = Args::command().get_matches_from(args);
+        let diagnostic = MietteDiagnostic::new("bad port").with_label(LabeledSpan::at(7..11, "not a number"));
+        let report = highlighter.highlight_value_file::<Args, _>(args, &matches, diagnostic.clone(), "config", "read from here");
+        assert!(report.source_code().is_none());
+
+        highlighter.register_value_file("config", "deploy.toml", "port = abcd\n");
+        let report = highlighter.highlight_value_file::<Args, _>(args, &matches, diagnostic, "config", "read from here");
+        let contents = report.source_code().unwrap().read_span(&SourceSpan::new(7.into(), 4), 0, 0).unwrap();
+        assert_eq!((contents.name(), contents.data()), (Some("deploy.toml"), &b"abcd"[..]));
+        let related = report.related().unwrap().collect::<Vec<_>>();
+        assert_eq!(related[0].to_string(), "the value of `config` was read from deploy.toml");
+        let label = related[0].labels().unwrap().next().unwrap();
+        assert_eq!((label.offset(), label.len(), label.label()), (22, 12, Some("read from here")));
+    }
+
+    #[test]
+    fn test_highlight_args_list_spellings() {
+        #[derive(clap::Parser)]
+        struct Args {
+            #[clap(short, long, alias = "col", short_alias = 'C')]
+            color: Option<String>,
+        }
+
+        let highlighter = ArgHighlighter {
+            list_spellings: true,
+            ..Default::default()
+        };
+        let args = ["program_name", "--col=always"];
+        let matches = Args::command().get_matches_from(args);
+        let report = highlighter.highlight_args::<Args, _>(args, &matches, MietteDiagnostic::new("deprecated"), "color", "here");
+        let label = report.labels().unwrap().next().unwrap();
+        assert_eq!((label.offset(), label.len()), (13, 5));
+        assert_eq!(report.help().unwrap().to_string(), "canonical form: `--color`; other accepted spellings: `-C`, `-c`");
+
+        // The note is on a line of its own after the help of the caller.
+        let diagnostic = MietteDiagnostic::new("deprecated").with_help("`--col` goes away in 2.0");
+        let report = highlighter.highlight_args::<Args, _>(args, &matches, diagnostic, "color", "here");
+        assert_eq!(report.help().unwrap().to_string(), "`--col` goes away in 2.0\ncanonical form: `--color`; other accepted spellings: `-C`, `-c`");
+    }
+}
@@ -0,0 +1,174 @@
+//! Locating the arguments of a whole subcommand tree, where every
+//! location is annotated with the path of the subcommand it was parsed
+//! under.
+
+use std::{ffi::OsString, ops::ControlFlow};
+
+use clap::{ArgMatches, Command};
+
+use crate::parse::{ArgLocation, ArgLocator, ArgPart};
+
+/// An argument located by `locate_scoped()`.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct ScopedLocation {
+    /// Names of the subcommands that the argument was parsed under,
+    /// empty for the top-level command. See `subcommand_scope()`.
+    pub scope: Vec<String>,
+    /// `clap::Id` of the argument.
+    pub id: String,
+    /// Location in `args` joined by spaces.
+    pub location: ArgLocation,
+}
+
+/// Returns the locations of every known argument in `args` in the
+/// order they appear, descending into the subcommands of `command`
+/// like clap does, so that the same argument at different levels can
+/// be told apart. Global arguments belong to the subcommand they were
+/// typed after.
+pub fn locate_scoped<R>(command: &Command, args: R) -> Vec<ScopedLocation>
+where
+    R: IntoIterator<Item: Into<OsString>>,
+{
+    scan_scoped(command, args).0
+}
+
+/// Returns the first location of `arg` of the subcommand at `path` in
+/// `args`, e.g. of `url` under `["remote", "add"]` in
+/// `app remote add --url=...`, with the offsets in the whole `args`.
+/// An empty `path` is the top-level command.
+pub fn locate_in_scope<R, S>(command: &Command, args: R, path: &[S], arg: &str) -> Option<ArgLocation>
+where
+    R: IntoIterator<Item: Into<OsString>>,
+    S: AsRef<str>,
+{
+    scan_scoped(command, args)
+        .0
+        .into_iter()
+        .find(|located| located.id == arg && located.scope.iter().map(String::as_str).eq(path.iter().map(AsRef::as_ref)))
+        .map(|located| located.location)
+}
+
+/// Same as `locate_scoped()` but also returns the Argv indices of the
+/// subcommands.
+pub(crate) fn scan_scoped<R>(command: &Command, args: R) -> (Vec<ScopedLocation>, Vec<usize>)
+where
+    R: IntoIterator<Item: Into<OsString>>,
+{
+    let args = args.into_iter().map(|arg| arg.into().to_string_lossy().to_string()).collect::<Vec<_>>();
+    let mut offsets = vec![];
+    let mut offset = 0;
+    for arg in &args {
+        offsets.push(offset);
+        offset += arg.len() + 1;
+    }
+    let mut command = command.clone();
+    command.build();
+
+    let mut located = vec![];
+    let mut subcommands = vec![];
+    let mut scope = vec![];
+    let mut current = &command;
+    let mut start = 0;
+    loop {
+        let level = &args[start..];
+        let mut found = vec![];
+        let locator = ArgLocator::from_command(current);
+        locator.visit_locations(level, |id, location| {
+            let location = location.map_parts(|part| ArgPart {
+                offset: offsets[start] + part.offset,
+                length: part.length,
+            });
+            found.push((id.to_string(), location));
+            ControlFlow::<()>::Continue(())
+        });
+        // Everything after `--` is positional.
+        let terminator = locator.get_terminator(level).map_or(usize::MAX, |part| offsets[start] + part.offset);
+        let subcommand = level.iter().enumerate().skip(1).take_while(|(index, _)| offsets[start + index] < terminator).find_map(|(index, arg)| {
+            let offset = offsets[start + index];
+            let covered = found.iter().any(|(_, location)| {
+                let whole = location.whole();
+                (whole.offset..whole.offset + whole.length).contains(&offset)
+            });
+            if covered || arg.len() > 1 && arg.starts_with('-') {
+                return None;
+            }
+            current.find_subcommand(arg).map(|subcommand| (index, subcommand))
+        });
+        // Arguments after the subcommand are its own.
+        let end = subcommand.map_or(usize::MAX, |(index, _)| offsets[start + index]);
+        located.extend(found.into_iter().filter(|(_, location)| location.declaration().offset < end).map(|(id, location)| ScopedLocation {
+            scope: scope.clone(),
+            id,
+            location,
+        }));
+        let Some((index, subcommand)) = subcommand else {
+            break;
+        };
+        scope.push(subcommand.get_name().to_owned());
+        current = subcommand;
+        start += index;
+        subcommands.push(start);
+    }
+
+    (located, subcommands)
+}
+
+/// Returns the path of subcommand names under which `arg` was parsed,
+/// i.e. the shallowest level of `matches` that knows `arg`. Global
+/// arguments therefore always belong to the top-level command.
+pub fn subcommand_scope(matches: &ArgMatches, arg: &str) -> Vec<String> {
+    let mut scope = vec![];
+    let mut current = matches;
+    while !matches!(current.try_contains_id(arg), Ok(true)) {
+        let Some((name, sub_matches)) = current.subcommand() else {
+            return vec![];
+        };
+        scope.push(name.to_owned());
+        current = sub_matches;
+    }
+
+    scope
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::{Arg, ArgAction};
+
+    #[test]
+    fn test_locate_scoped() {
+        let command = Command::new("app")
+            .arg(Arg::new("force").long("force").action(ArgAction::SetTrue))
+            .arg(Arg::new("config").long("config").global(true))
+            .subcommand(
+                Command::new("remote")
+                    .subcommand(Command::new("push").arg(Arg::new("force").long("force").action(ArgAction::SetTrue))),
+            );
+        let args = ["app", "--force", "--config", "push", "remote", "push", "--force", "--config=b"];
+        let located = locate_scoped(&command, args)
+            .into_iter()
+            .map(|located| (located.scope.join(" "), located.id, located.location.declaration().offset))
+            .collect::<Vec<_>>();
+        assert_eq!(located, [
+            ("".to_owned(), "force".to_owned(), 4),
+            ("".to_owned(), "config".to_owned(), 12),
+            ("remote push".to_owned(), "force".to_owned(), 38),
+            ("remote push".to_owned(), "config".to_owned(), 46),
+        ]);
+        assert!(locate_scoped(&command, ["app", "--", "remote", "--force"]).is_empty());
+
+        let location = locate_in_scope(&command, args, &["remote", "push"], "force").unwrap();
+        assert_eq!(location.declaration().offset, 38);
+        assert_eq!(locate_in_scope(&command, args, &["remote"], "force"), None);
+    }
+
+    #[test]
+    fn test_subcommand_scope() {
+        let command = Command::new("app")
+            .arg(Arg::new("config").long("config").global(true))
+            .subcommand(Command::new("remote").subcommand(Command::new("add").arg(Arg::new("url").long("url"))));
+        let matches = command.get_matches_from(["app", "remote", "add", "--url=x", "--config=y"]);
+        assert_eq!(subcommand_scope(&matches, "config"), Vec::<String>::new());
+        assert_eq!(subcommand_scope(&matches, "url"), ["remote", "add"]);
+    }
+}
@@ -0,0 +1,890 @@
+//! Splitting a command line string the way a shell would before the
+//! program receives its Argv, while remembering where every argument
+//! came from in the string. This lets labels point at the text that the
+//! user actually typed, including quotes, escapes and placeholders,
+//! rather than at the arguments joined by spaces.
+
+use std::{ffi::OsString, ops::Range};
+
+use crate::parse::{ArgLocation, ArgLocator, ArgPart, UnitConverter};
+
+/// Quoting and escaping rules of a shell. See `split_command_line()`.
+#[non_exhaustive]
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub enum ShellDialect {
+    /// `cmd.exe`: `^` escapes outside double quotes and `%VAR%`
+    /// placeholders, followed by the `CommandLineToArgvW` splitting
+    /// that the program does on Windows.
+    Cmd,
+    /// PowerShell: literal single quotes with `''` escapes, double quotes
+    /// with `` ` `` and `""` escapes, `` ` `` escapes outside quotes and
+    /// `$env:VAR` placeholders. Splatted variables (`@params`) are kept
+    /// as they were typed since their content is unknown.
+    PowerShell,
+    /// fish: single quotes with `\'` and `\\` escapes, double quotes
+    /// with `\"`, `\$` and `\\` escapes, backslash escape sequences
+    /// like `\n` outside quotes and `$VAR` placeholders.
+    Fish,
+    /// POSIX sh, as split by `shlex`: literal single quotes, double
+    /// quotes with `\"`, `\$`, `` \` `` and `\\` escapes, backslash
+    /// escapes outside quotes and `$VAR` or `${VAR}` placeholders.
+    /// Unquoted placeholders that expand to nothing are dropped, but the
+    /// others are not split into fields.
+    Posix,
+    /// zsh: literal single quotes, double quotes with `\"`, `\$`, `` \` ``
+    /// and `\\` escapes, `$'…'` with backslash escape sequences,
+    /// backslash escapes outside quotes and `$VAR` or `${VAR}`
+    /// placeholders. Unquoted placeholders that expand to nothing are
+    /// dropped like zsh does.
+    Zsh,
+}
+
+/// An argument of a split command line.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct ShellToken {
+    /// The argument that the program receives.
+    pub value: String,
+    /// Range of the whole token in the command line, including quotes.
+    pub span: Range<usize>,
+    /// Range in the command line of every byte in `value`. Bytes that
+    /// came from expanding a placeholder share the range of it.
+    ranges: Vec<Range<usize>>,
+}
+
+/// A character and the range in the command line that it came from.
+type Traced = (char, Range<usize>);
+
+/// Splits `line` by the rules of `dialect`. Placeholders are expanded
+/// with the env variables of the current process.
+pub fn split_command_line(line: &str, dialect: ShellDialect) -> Vec<ShellToken> {
+    split_command_line_with_env(line, dialect, |name| std::env::var(name).ok())
+}
+
+/// Same as `split_command_line()` but placeholders are expanded with
+/// `env` instead. Placeholders that `env` returns `None` for are kept
+/// as they were typed.
+pub fn split_command_line_with_env(line: &str, dialect: ShellDialect, env: impl Fn(&str) -> Option<String>) -> Vec<ShellToken> {
+    match dialect {
+        ShellDialect::Cmd => split_windows(&cmd_preprocess(line, &env)),
+        ShellDialect::PowerShell => split_powershell(line, &env),
+        ShellDialect::Fish | ShellDialect::Posix | ShellDialect::Zsh => split_unix(line, dialect, &env),
+    }
+}
+
+impl ShellToken {
+    fn new() -> Self {
+        Self {
+            value: String::new(),
+            span: 0..0,
+            ranges: vec![],
+        }
+    }
+
+    fn push(&mut self, (c, range): &Traced) {
+        self.value.push(*c);
+        self.ranges.extend(std::iter::repeat_n(range.clone(), c.len_utf8()));
+    }
+
+    /// Moves the ranges by `by` bytes, for when the command line is a
+    /// part of a larger source.
+    #[cfg(feature = "miette")]
+    pub(crate) fn shift(&mut self, by: usize) {
+        for range in std::iter::once(&mut self.span).chain(&mut self.ranges) {
+            *range = range.start + by..range.end + by;
+        }
+    }
+
+    /// Converts a byte range of `value` to a range in the command line.
+    /// The whole `value` converts to `span`, so quotes are included.
+    pub fn raw_range(&self, value_range: Range<usize>) -> Range<usize> {
+        if value_range.start == 0 && value_range.end >= self.value.len() {
+            return self.span.clone();
+        }
+        if value_range.is_empty() {
+            let at = self.ranges.get(value_range.start).map_or(self.span.end, |range| range.start);
+            return at..at;
+        }
+        let start = self.ranges.get(value_range.start).map_or(self.span.end, |range| range.start);
+        let end = self.ranges.get(value_range.end - 1).map_or(self.span.end, |range| range.end);
+
+        start..end
+    }
+}
+
+impl<T: Default, V: AsRef<clap::Arg>> ArgLocator<T, V> {
+    /// Same as `Self::get_location()` but for the arguments of a split
+    /// command line, returning offsets in the command line string. They
+    /// are in bytes regardless of `Self::offset_unit`, which needs the
+    /// command line, see `Self::get_location_in_line()`.
+    pub fn get_location_in_tokens<A>(&self, tokens: &[ShellToken], arg: &A) -> Option<ArgLocation>
+    where
+        A: PartialEq<clap::Id> + ?Sized,
+    {
+        let args = tokens.iter().map(|token| OsString::from(&token.value)).collect::<Vec<_>>();
+        let location = self.locate_bytes(&args, arg)?;
+
+        Some(remap_location(tokens, &location))
+    }
+
+    /// Same as `Self::get_location()` but for a command line that was
+    /// not split yet, which is split by the rules of `dialect` without
+    /// expanding placeholders, so the offsets are in `line` as typed,
+    /// quotes and escapes included. See `split_command_line()`.
+    ///
+    /// # Examples
+    /// ```
+    /// use clap::{Arg, Command};
+    /// use fancy_clap::{ArgLocator, ShellDialect};
+    ///
+    /// let command = Command::new("app").arg(Arg::new("name").long("name"));
+    /// let line = r#"app --name "a b""#;
+    /// let location = ArgLocator::from_command(&command).get_location_in_line(line, ShellDialect::Posix, "name").unwrap();
+    /// let content = location.content().unwrap();
+    /// assert_eq!(&line[content.offset..content.offset + content.length], r#""a b""#);
+    /// ```
+    pub fn get_location_in_line<A>(&self, line: &str, dialect: ShellDialect, arg: &A) -> Option<ArgLocation>
+    where
+        A: PartialEq<clap::Id> + ?Sized,
+    {
+        let location = self.get_location_in_tokens(&split_command_line_with_env(line, dialect, |_| None), arg)?;
+
+        Some(UnitConverter::of_text(self.offset_unit, line).location(&location))
+    }
+
+    /// Same as `Self::get_location_in_line()` but for a command line
+    /// that a program receives on Windows, e.g. from `raw_command_line()`,
+    /// which `cmd.exe` or PowerShell already processed and which is
+    /// split by the rules of `split_windows_command_line()`. The offsets
+    /// line up with the command line rather than with `std::env::args_os()`
+    /// joined by spaces.
+    ///
+    /// # Examples
+    /// ```
+    /// use clap::{Arg, Command};
+    /// use fancy_clap::ArgLocator;
+    ///
+    /// let command = Command::new("app").arg(Arg::new("path").long("path"));
+    /// let line = r#""C:\Program Files\app.exe" --path="C:\a b""#;
+    /// let location = ArgLocator::from_command(&command).get_location_in_windows_line(line, "path").unwrap();
+    /// assert_eq!(location.declaration().offset, 27);
+    /// ```
+    pub fn get_location_in_windows_line<A>(&self, line: &str, arg: &A) -> Option<ArgLocation>
+    where
+        A: PartialEq<clap::Id> + ?Sized,
+    {
+        let location = self.get_location_in_tokens(&split_windows_command_line(line), arg)?;
+
+        Some(UnitConverter::of_text(self.offset_unit, line).location(&location))
+    }
+}
+
+/// Converts the offsets of `location` in the values of `tokens` joined
+/// by spaces to offsets in the command line that `tokens` came from.
+pub fn remap_location(tokens: &[ShellToken], location: &ArgLocation) -> ArgLocation {
+    location.map_parts(|part| remap_part(tokens, part))
+}
+
+/// Same as `remap_location()` but for a single part.
+pub(crate) fn remap_part(tokens: &[ShellToken], part: &ArgPart) -> ArgPart {
+    let mut starts = vec![];
+    let mut offset = 0;
+    for token in tokens {
+        starts.push(offset);
+        offset += token.value.len() + 1;
+    }
+
+    let index = starts.partition_point(|&start| start <= part.offset).saturating_sub(1);
+    let Some(token) = tokens.get(index) else {
+        return part.clone();
+    };
+    let intra = part.offset - starts[index];
+    let range = if intra + part.length <= token.value.len() {
+        token.raw_range(intra..intra + part.length)
+    } else {
+        // The space in between two tokens.
+        let next = tokens.get(index + 1).map_or(token.span.end, |next| next.span.start);
+        token.span.end..next
+    };
+
+    ArgPart {
+        offset: range.start,
+        length: range.len(),
+    }
+}
+
+/// Joins `args` into a command line that `dialect` splits back into
+/// `args`, quoting and escaping the arguments that need it, and moves
+/// `locations`, which are in `args` joined by spaces, to the command
+/// line. Unlike joining `args` by spaces, the labels of values with
+/// spaces or quotes then cover the text that a user would type.
+///
+/// # Examples
+/// ```
+/// use clap::{Arg, Command};
+/// use fancy_clap::{to_source_string, ArgLocator, ShellDialect};
+///
+/// let command = Command::new("app").arg(Arg::new("name").long("name"));
+/// let args = ["app", "--name", "it's me"];
+/// let location = ArgLocator::from_command(&command).get_location(args, "name").unwrap();
+/// let (line, locations) = to_source_string(&args, ShellDialect::Zsh, &[location]);
+/// assert_eq!(line, r#"app --name 'it'\''s me'"#);
+/// let content = locations[0].content().unwrap();
+/// assert_eq!(&line[content.offset..content.offset + content.length], r#"'it'\''s me'"#);
+/// ```
+pub fn to_source_string<S: AsRef<str>>(args: &[S], dialect: ShellDialect, locations: &[ArgLocation]) -> (String, Vec<ArgLocation>) {
+    // Whether `cmd.exe` is in between double quotes, where it does not
+    // take `^` as an escape, at the end of the line so far.
+    let mut cmd_quoted = false;
+    let line = args
+        .iter()
+        .enumerate()
+        .map(|(index, arg)| quote(arg.as_ref(), dialect, index == 0, &mut cmd_quoted))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let tokens = split_command_line_with_env(&line, dialect, |_| None);
+    debug_assert!(tokens.iter().map(|token| token.value.as_str()).eq(args.iter().map(AsRef::as_ref)), "{line}");
+    let locations = locations.iter().map(|location| remap_location(&tokens, location)).collect();
+
+    (line, locations)
+}
+
+/// Returns `arg` as typed in the command line of `dialect`, quoted if it
+/// has any character that the shell would take specially. See
+/// `to_source_string()`.
+fn quote(arg: &str, dialect: ShellDialect, program: bool, cmd_quoted: &mut bool) -> String {
+    let bare = |safe: &str| !arg.is_empty() && arg.chars().all(|c| c.is_ascii_alphanumeric() || safe.contains(c));
+    match dialect {
+        // `=` leads the `=command` expansion of zsh.
+        ShellDialect::Zsh if bare("_-+=:,./@%") && !arg.starts_with('=') => arg.to_owned(),
+        ShellDialect::Posix if bare("_-+=:,./@%") => arg.to_owned(),
+        ShellDialect::Zsh | ShellDialect::Posix => format!("'{}'", arg.replace('\'', r"'\''")),
+        ShellDialect::Fish if bare("_-+=:,./@") => arg.to_owned(),
+        ShellDialect::Fish => format!("'{}'", arg.replace('\\', r"\\").replace('\'', r"\'")),
+        ShellDialect::PowerShell if bare("_-+=:./\\") => arg.to_owned(),
+        ShellDialect::PowerShell => format!("'{}'", arg.replace('\'', "''")),
+        ShellDialect::Cmd => quote_cmd(arg, program, cmd_quoted),
+    }
+}
+
+/// Same as `quote()` for `cmd.exe` followed by `CommandLineToArgvW`,
+/// where backslashes are only escapes before a double quote, and `^`
+/// escapes the characters of `cmd.exe` outside of double quotes.
+fn quote_cmd(arg: &str, program: bool, cmd_quoted: &mut bool) -> String {
+    let special = [' ', '\t', '"', '^', '&', '|', '<', '>'];
+    if !arg.is_empty() && !arg.contains(special) {
+        return arg.to_owned();
+    }
+    // The program name is only delimited by double quotes.
+    if program {
+        return format!("\"{arg}\"");
+    }
+
+    let mut quoted = String::from('"');
+    *cmd_quoted = !*cmd_quoted;
+    let mut backslashes = 0;
+    for c in arg.chars() {
+        match c {
+            '\\' => {
+                backslashes += 1;
+                continue;
+            }
+            '"' => {
+                quoted.extend(std::iter::repeat_n('\\', backslashes * 2 + 1));
+                *cmd_quoted = !*cmd_quoted;
+            }
+            '^' | '&' | '|' | '<' | '>' if !*cmd_quoted => {
+                quoted.extend(std::iter::repeat_n('\\', backslashes));
+                quoted.push('^');
+            }
+            _ => quoted.extend(std::iter::repeat_n('\\', backslashes)),
+        }
+        backslashes = 0;
+        quoted.push(c);
+    }
+    // Backslashes before the closing quote are doubled to keep it.
+    quoted.extend(std::iter::repeat_n('\\', backslashes * 2));
+    quoted.push('"');
+    *cmd_quoted = !*cmd_quoted;
+
+    quoted
+}
+
+/// Removes the `^` escapes and expands the `%VAR%` placeholders of
+/// `cmd.exe`. Carets in between double quotes are kept.
+fn cmd_preprocess(line: &str, env: &dyn Fn(&str) -> Option<String>) -> Vec<Traced> {
+    let mut traced = vec![];
+    let mut quoted = false;
+    let mut chars = line.char_indices().peekable();
+    while let Some((index, c)) = chars.next() {
+        let end = index + c.len_utf8();
+        match c {
+            '"' => {
+                quoted = !quoted;
+                traced.push((c, index..end));
+            }
+            '^' if !quoted => {
+                if let Some((escaped_index, escaped)) = chars.next() {
+                    traced.push((escaped, index..escaped_index + escaped.len_utf8()));
+                }
+            }
+            '%' => {
+                let expanded = line[end..].find('%').and_then(|length| {
+                    let name = &line[end..end + length];
+                    let value = env(name).filter(|_| !name.is_empty())?;
+                    Some((value, end + length + 1))
+                });
+                let Some((value, placeholder_end)) = expanded else {
+                    traced.push((c, index..end));
+                    continue;
+                };
+                traced.extend(value.chars().map(|c| (c, index..placeholder_end)));
+                while chars.next_if(|&(next_index, _)| next_index < placeholder_end).is_some() {}
+            }
+            _ => traced.push((c, index..end)),
+        }
+    }
+
+    traced
+}
+
+/// Splits `line` by the rules of `CommandLineToArgvW` without any
+/// shell in between, e.g. the string returned by `GetCommandLineW` or
+/// the command of a Windows service definition. The first token is the
+/// program name, which is only delimited by double quotes.
+pub fn split_windows_command_line(line: &str) -> Vec<ShellToken> {
+    let traced = line.char_indices().map(|(index, c)| (c, index..index + c.len_utf8())).collect::<Vec<_>>();
+
+    split_windows(&traced)
+}
+
+/// Returns the command line of the current process as `GetCommandLineW`
+/// does, before it is split into `std::env::args_os()`. See
+/// `ArgLocator::get_location_in_windows_line()`.
+#[cfg(windows)]
+pub fn raw_command_line() -> String {
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn GetCommandLineW() -> *const u16;
+    }
+
+    // SAFETY: `GetCommandLineW` returns a NUL-terminated string that
+    // lives as long as the process and is never written to.
+    unsafe {
+        let line = GetCommandLineW();
+        let mut length = 0;
+        while *line.add(length) != 0 {
+            length += 1;
+        }
+        String::from_utf16_lossy(std::slice::from_raw_parts(line, length))
+    }
+}
+
+/// Splits by the rules of `CommandLineToArgvW`. See
+/// `split_windows_command_line()`.
+fn split_windows(traced: &[Traced]) -> Vec<ShellToken> {
+    let is_blank = |c: char| c == ' ' || c == '\t';
+    let mut tokens = vec![];
+    let mut index = 0;
+
+    while index < traced.len() {
+        while traced.get(index).is_some_and(|(c, _)| is_blank(*c)) {
+            index += 1;
+        }
+        let Some((_, first)) = traced.get(index) else {
+            break;
+        };
+        let mut token = ShellToken::new();
+        token.span = first.start..first.end;
+
+        if tokens.is_empty() {
+            let quoted = traced[index].0 == '"';
+            if quoted {
+                index += 1;
+            }
+            while let Some(traced) = traced.get(index) {
+                index += 1;
+                match traced.0 {
+                    '"' if quoted => break,
+                    c if is_blank(c) && !quoted => {
+                        index -= 1;
+                        break;
+                    }
+                    _ => token.push(traced),
+                }
+            }
+        } else {
+            let mut quoted = false;
+            while let Some((c, _)) = traced.get(index) {
+                match c {
+                    '\\' => {
+                        let count = traced[index..].iter().take_while(|(c, _)| *c == '\\').count();
+                        let backslashes = &traced[index..index + count];
+                        index += count;
+                        if traced.get(index).is_none_or(|(c, _)| *c != '"') {
+                            backslashes.iter().for_each(|traced| token.push(traced));
+                            continue;
+                        }
+                        // Backslashes are only escapes if they are followed by a
+                        // double quote, where every pair becomes one backslash.
+                        for pair in backslashes.chunks_exact(2) {
+                            token.push(&('\\', pair[0].1.start..pair[1].1.end));
+                        }
+                        if count % 2 == 1 {
+                            let escaped = &traced[index];
+                            token.push(&('"', backslashes[count - 1].1.start..escaped.1.end));
+                            index += 1;
+                        }
+                    }
+                    '"' => {
+                        if quoted && traced.get(index + 1).is_some_and(|(c, _)| *c == '"') {
+                            token.push(&traced[index + 1]);
+                            index += 2;
+                        } else {
+                            quoted = !quoted;
+                            index += 1;
+                        }
+                    }
+                    c if is_blank(*c) && !quoted => break,
+                    _ => {
+                        token.push(&traced[index]);
+                        index += 1;
+                    }
+                }
+            }
+        }
+
+        token.span.end = traced[..index].last().map_or(token.span.end, |(_, range)| range.end);
+        tokens.push(token);
+    }
+
+    tokens
+}
+
+fn split_powershell(line: &str, env: &dyn Fn(&str) -> Option<String>) -> Vec<ShellToken> {
+    let chars = line.char_indices().collect::<Vec<_>>();
+    let offset_of = |index: usize| chars.get(index).map_or(line.len(), |(offset, _)| *offset);
+    let mut tokens = vec![];
+    let mut index = 0;
+
+    while index < chars.len() {
+        while chars.get(index).is_some_and(|(_, c)| c.is_whitespace()) {
+            index += 1;
+        }
+        if index >= chars.len() {
+            break;
+        }
+        let mut token = ShellToken::new();
+        token.span.start = offset_of(index);
+        let mut quote = None;
+
+        while let Some(&(offset, c)) = chars.get(index) {
+            match (quote, c) {
+                (None, c) if c.is_whitespace() => break,
+                (None | Some('"'), '`') => {
+                    let Some(&(_, escaped)) = chars.get(index + 1) else {
+                        index += 1;
+                        continue;
+                    };
+                    let escaped = match escaped {
+                        '0' => '\0',
+                        'a' => '\x07',
+                        'b' => '\x08',
+                        'e' => '\x1b',
+                        'f' => '\x0c',
+                        'n' => '\n',
+                        'r' => '\r',
+                        't' => '\t',
+                        'v' => '\x0b',
+                        escaped => escaped,
+                    };
+                    index += 2;
+                    token.push(&(escaped, offset..offset_of(index)));
+                }
+                (None, '\'' | '"') => {
+                    quote = Some(c);
+                    index += 1;
+                }
+                (Some(quote_char), c) if c == quote_char => {
+                    if chars.get(index + 1).is_some_and(|&(_, next)| next == quote_char) {
+                        index += 2;
+                        token.push(&(c, offset..offset_of(index)));
+                    } else {
+                        quote = None;
+                        index += 1;
+                    }
+                }
+                (None | Some('"'), '$') => {
+                    let expanded = powershell_env_placeholder(&line[offset..])
+                        .and_then(|(name, length)| Some((env(name)?, offset + length)));
+                    let Some((value, placeholder_end)) = expanded else {
+                        token.push(&(c, offset..offset_of(index + 1)));
+                        index += 1;
+                        continue;
+                    };
+                    value.chars().for_each(|c| token.push(&(c, offset..placeholder_end)));
+                    while offset_of(index) < placeholder_end {
+                        index += 1;
+                    }
+                }
+                _ => {
+                    index += 1;
+                    token.push(&(c, offset..offset_of(index)));
+                }
+            }
+        }
+
+        token.span.end = offset_of(index);
+        tokens.push(token);
+    }
+
+    tokens
+}
+
+/// Returns the name and the length of the `$env:VAR` or `${env:VAR}`
+/// placeholder at the start of `rest`.
+fn powershell_env_placeholder(rest: &str) -> Option<(&str, usize)> {
+    let is_name = |c: char| c.is_alphanumeric() || c == '_';
+    if let Some(braced) = rest.strip_prefix("${env:") {
+        let length = braced.find('}')?;
+        return Some((&braced[..length], "${env:".len() + length + 1));
+    }
+    let name = rest.strip_prefix("$env:")?;
+    let length = name.find(|c| !is_name(c)).unwrap_or(name.len());
+    if length == 0 {
+        return None;
+    }
+
+    Some((&name[..length], "$env:".len() + length))
+}
+
+/// Splits by the rules of fish or zsh, which differ mostly in the
+/// escapes they recognize in between quotes. A `#` at the start of a
+/// token comments out the rest of the line.
+fn split_unix(line: &str, dialect: ShellDialect, env: &dyn Fn(&str) -> Option<String>) -> Vec<ShellToken> {
+    let fish = dialect == ShellDialect::Fish;
+    let chars = line.char_indices().collect::<Vec<_>>();
+    let offset_of = |index: usize| chars.get(index).map_or(line.len(), |(offset, _)| *offset);
+    let char_at = |index: usize| chars.get(index).map(|(_, c)| *c);
+    let mut tokens = vec![];
+    let mut index = 0;
+
+    while index < chars.len() {
+        while char_at(index).is_some_and(char::is_whitespace) {
+            index += 1;
+        }
+        match char_at(index) {
+            None => break,
+            Some('#') => {
+                while char_at(index).is_some_and(|c| c != '\n') {
+                    index += 1;
+                }
+                continue;
+            }
+            Some(_) => {}
+        }
+        let mut token = ShellToken::new();
+        token.span.start = offset_of(index);
+        // `$` stands for `$'…'` of zsh.
+        let mut quote = None;
+        let mut quoted = false;
+
+        while let Some(&(offset, c)) = chars.get(index) {
+            let next = char_at(index + 1);
+            match (quote, c) {
+                (None, c) if c.is_whitespace() => break,
+                (None, '\'' | '"') => {
+                    quote = Some(c);
+                    quoted = true;
+                    index += 1;
+                }
+                (None, '$') if dialect == ShellDialect::Zsh && next == Some('\'') => {
+                    quote = Some('$');
+                    quoted = true;
+                    index += 2;
+                }
+                (Some('\''), '\'') | (Some('"'), '"') | (Some('$'), '\'') => {
+                    quote = None;
+                    index += 1;
+                }
+                (None | Some('"'), '\\') if next == Some('\n') => index += 2,
+                (None, '\\') | (Some('$'), '\\') if next.is_some() => {
+                    let (escaped, length) = if fish || quote == Some('$') {
+                        escape_sequence(&chars[index + 1..])
+                    } else {
+                        (next.unwrap_or(c), 1)
+                    };
+                    index += 1 + length;
+                    token.push(&(escaped, offset..offset_of(index)));
+                }
+                (Some('\''), '\\') if fish && matches!(next, Some('\'' | '\\')) => {
+                    index += 2;
+                    token.push(&(next.unwrap_or(c), offset..offset_of(index)));
+                }
+                (Some('"'), '\\') if matches!(next, Some('"' | '$' | '\\')) || !fish && next == Some('`') => {
+                    index += 2;
+                    token.push(&(next.unwrap_or(c), offset..offset_of(index)));
+                }
+                (None | Some('"'), '$') => {
+                    let expanded = unix_placeholder(&line[offset..], !fish)
+                        .and_then(|(name, length)| Some((env(name)?, offset + length)));
+                    let Some((value, placeholder_end)) = expanded else {
+                        index += 1;
+                        token.push(&(c, offset..offset_of(index)));
+                        continue;
+                    };
+                    value.chars().for_each(|c| token.push(&(c, offset..placeholder_end)));
+                    while offset_of(index) < placeholder_end {
+                        index += 1;
+                    }
+                }
+                _ => {
+                    index += 1;
+                    token.push(&(c, offset..offset_of(index)));
+                }
+            }
+        }
+
+        token.span.end = offset_of(index);
+        if fish || quoted || !token.value.is_empty() {
+            tokens.push(token);
+        }
+    }
+
+    tokens
+}
+
+/// Decodes the backslash escape sequence that `chars` starts with, not
+/// including the backslash, and returns the character and the number
+/// of `chars` it took. Unknown sequences stand for the character itself.
+fn escape_sequence(chars: &[(usize, char)]) -> (char, usize) {
+    let Some(&(_, first)) = chars.first() else {
+        return ('\\', 0);
+    };
+    let simple = match first {
+        'a' => '\x07',
+        'b' => '\x08',
+        'e' | 'E' => '\x1b',
+        'f' => '\x0c',
+        'n' => '\n',
+        'r' => '\r',
+        't' => '\t',
+        'v' => '\x0b',
+        'x' => return numeric_escape(&chars[1..], 16, 2).map_or((first, 1), |(c, length)| (c, length + 1)),
+        '0'..='7' => return numeric_escape(chars, 8, 3).unwrap_or((first, 1)),
+        first => first,
+    };
+
+    (simple, 1)
+}
+
+fn numeric_escape(chars: &[(usize, char)], radix: u32, max_digits: usize) -> Option<(char, usize)> {
+    let digits = chars.iter().take(max_digits).take_while(|(_, c)| c.is_digit(radix)).count();
+    let number = chars[..digits].iter().fold(0, |number, (_, c)| number * radix + c.to_digit(radix).unwrap_or_default());
+
+    Some((char::from_u32(number).filter(|_| digits > 0)?, digits))
+}
+
+/// Returns the name and the length of the `$VAR` placeholder, or the
+/// `${VAR}` one if `braces`, at the start of `rest`.
+fn unix_placeholder(rest: &str, braces: bool) -> Option<(&str, usize)> {
+    let is_name = |c: char| c.is_alphanumeric() || c == '_';
+    if let Some(braced) = rest.strip_prefix("${").filter(|_| braces) {
+        let length = braced.find('}')?;
+        return Some((&braced[..length], "${".len() + length + 1));
+    }
+    let name = rest.strip_prefix('$')?;
+    let length = name.find(|c| !is_name(c)).unwrap_or(name.len());
+    if length == 0 {
+        return None;
+    }
+
+    Some((&name[..length], "$".len() + length))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(tokens: &[ShellToken]) -> Vec<&str> {
+        tokens.iter().map(|token| token.value.as_str()).collect()
+    }
+
+    #[test]
+    fn test_split_cmd() {
+        let env = |name: &str| (name == "LEVEL").then(|| "3".to_owned());
+        let tokens = split_command_line_with_env(r#"app ^--port=8^0 --name "a ^b" %LEVEL% %NOPE%"#, ShellDialect::Cmd, env);
+        assert_eq!(values(&tokens), ["app", "--port=80", "--name", "a ^b", "3", "%NOPE%"]);
+        assert_eq!(tokens[1].span, 4..15);
+        assert_eq!(tokens[1].raw_range(7..9), 12..15);
+        assert_eq!(tokens[4].span, 30..37);
+    }
+
+    #[test]
+    fn test_split_powershell() {
+        let env = |name: &str| (name == "LEVEL").then(|| "3".to_owned());
+        let line = r#"app --name 'it''s' --msg "a`"b""c" `--port=80 $env:LEVEL "${env:LEVEL}x" @params $nope"#;
+        let tokens = split_command_line_with_env(line, ShellDialect::PowerShell, env);
+        assert_eq!(values(&tokens), ["app", "--name", "it's", "--msg", r#"a"b"c"#, "--port=80", "3", "3x", "@params", "$nope"]);
+        assert_eq!(tokens[2].span, 11..18);
+        assert_eq!(tokens[5].span, 35..45);
+        assert_eq!(tokens[5].raw_range(0..1), 35..37);
+        assert_eq!(tokens[6].span, 46..56);
+        assert_eq!(tokens[7].raw_range(1..2), 70..71);
+    }
+
+    #[test]
+    fn test_split_zsh() {
+        let env = |name: &str| match name {
+            "LEVEL" => Some("3".to_owned()),
+            "EMPTY" => Some(String::new()),
+            _ => None,
+        };
+        let line = r#"app --msg $'a\tb\'c\x41' "x\"\$y\q" 'lit\n' ${LEVEL} $EMPTY "$EMPTY" $NOPE a\ b # --port"#;
+        let tokens = split_command_line_with_env(line, ShellDialect::Zsh, env);
+        assert_eq!(values(&tokens), ["app", "--msg", "a\tb'cA", r#"x"$y\q"#, r"lit\n", "3", "", "$NOPE", "a b"]);
+        assert_eq!(tokens[2].span, 10..24);
+        assert_eq!(tokens[2].raw_range(1..2), 13..15);
+        assert_eq!(tokens[2].raw_range(5..6), 19..23);
+        assert_eq!(tokens[5].span, 44..52);
+        assert_eq!(tokens[6].span, 60..68);
+    }
+
+    #[test]
+    fn test_split_posix() {
+        let line = r#"app a\ b\"c 'x\y "z' "d\"e\f" $'n' "open 'q"#;
+        let tokens = split_command_line_with_env(line, ShellDialect::Posix, |_| None);
+        assert_eq!(values(&tokens), ["app", r#"a b"c"#, r#"x\y "z"#, r#"d"e\f"#, "$n", "open 'q"]);
+        assert_eq!(tokens[1].span, 4..11);
+        assert_eq!(tokens[1].raw_range(1..2), 5..7);
+        assert_eq!(tokens[1].raw_range(3..4), 8..10);
+        assert_eq!(tokens[2].span, 12..20);
+        assert_eq!(tokens[2].raw_range(1..2), 14..15);
+        assert_eq!(tokens[3].span, 21..29);
+        assert_eq!(tokens[3].raw_range(1..2), 23..25);
+        assert_eq!(tokens[3].raw_range(3..5), 26..28);
+        // An unterminated quote runs to the end of the line.
+        assert_eq!(tokens[5].span, 35..line.len());
+    }
+
+    #[test]
+    fn test_split_fish() {
+        let env = |name: &str| (name == "LEVEL").then(|| "3".to_owned());
+        let line = r#"app 'it\'s\n' "a\$b\n" a\x41\ b $LEVEL"#;
+        let tokens = split_command_line_with_env(line, ShellDialect::Fish, env);
+        assert_eq!(values(&tokens), ["app", r"it's\n", r"a$b\n", "aA b", "3"]);
+        assert_eq!(tokens[3].raw_range(1..2), 24..28);
+    }
+
+    #[test]
+    fn test_split_windows_command_line() {
+        let line = r#""C:\Program Files\app.exe" --name "a ^b" %PATH% C:\dir\"#;
+        let tokens = split_windows_command_line(line);
+        assert_eq!(values(&tokens), [r"C:\Program Files\app.exe", "--name", "a ^b", "%PATH%", r"C:\dir\"]);
+        assert_eq!(tokens[0].span, 0..26);
+        assert_eq!(tokens[2].span, 34..40);
+        assert_eq!(tokens[2].raw_range(2..4), 37..39);
+    }
+
+    #[test]
+    fn test_split_windows_backslashes() {
+        let tokens = split_command_line_with_env(r#""C:\Program Files\app.exe" a\\b a\\\"b "a\\" "x""y""#, ShellDialect::Cmd, |_| None);
+        assert_eq!(values(&tokens), [r"C:\Program Files\app.exe", r"a\\b", r#"a\"b"#, r"a\", r#"x"y"#]);
+    }
+
+    #[test]
+    fn test_to_source_string() {
+        let args = ["app", "plain", "", "a b", "it's", r"C:\dir\", r#"say "hi"\"#, "$HOME", "=x", "a^b&c", "é", "--name=a b"];
+        for dialect in [ShellDialect::Posix, ShellDialect::Zsh, ShellDialect::Fish, ShellDialect::PowerShell, ShellDialect::Cmd] {
+            let (line, _) = to_source_string(&args, dialect, &[]);
+            let tokens = split_command_line_with_env(&line, dialect, |_| None);
+            assert_eq!(values(&tokens), args, "{dialect:?}: {line}");
+            assert!(line.starts_with("app plain "), "{line}");
+        }
+        let (line, _) = to_source_string(&["app", r#"a"b^c"#, "^"], ShellDialect::Cmd, &[]);
+        assert_eq!(line, r#"app "a\"b^^c" "^^""#);
+
+        // app --name=a b
+        let location = ArgLocation::Complete {
+            declaration: ArgPart { offset: 4, length: 2 },
+            name: ArgPart { offset: 6, length: 4 },
+            delimiter: ArgPart { offset: 10, length: 1 },
+            content: ArgPart { offset: 11, length: 3 },
+        };
+        let (line, locations) = to_source_string(&["app", "--name=a b"], ShellDialect::PowerShell, &[location]);
+        assert_eq!(line, "app '--name=a b'");
+        assert_eq!(locations[0].declaration(), &ArgPart { offset: 5, length: 2 });
+        assert_eq!(locations[0].content(), Some(&ArgPart { offset: 12, length: 3 }));
+    }
+
+    #[test]
+    fn test_get_location_in_windows_line() {
+        use clap::{Arg, Command};
+
+        let command = Command::new("app").arg(Arg::new("msg").long("msg")).arg(Arg::new("caret").long("caret"));
+        let locator = ArgLocator::from_command(&command);
+        // Carets are not escapes once `cmd.exe` is out of the way.
+        let line = r#"app.exe --msg="a \"b\"" --caret ^x"#;
+        let location = locator.get_location_in_windows_line(line, "msg").unwrap();
+        assert_eq!(location.declaration(), &ArgPart { offset: 8, length: 2 });
+        assert_eq!(location.content(), Some(&ArgPart { offset: 15, length: 7 }));
+        let location = locator.get_location_in_windows_line(line, "caret").unwrap();
+        assert_eq!(location.content(), Some(&ArgPart { offset: 32, length: 2 }));
+    }
+
+    #[test]
+    fn test_get_location_in_tokens() {
+        #[derive(clap::Parser)]
+        struct Args {
+            #[clap(long)]
+            port: u16,
+            #[clap(long)]
+            name: String,
+            #[clap(short)]
+            v: u8,
+        }
+
+        let env = |name: &str| (name == "LEVEL").then(|| "3".to_owned());
+        let line = r#"app ^--port=8^0 --name "a b" -v %LEVEL%"#;
+        let tokens = split_command_line_with_env(line, ShellDialect::Cmd, env);
+        let locator = ArgLocator::from_command_factory::<Args>();
+        assert_eq!(locator.get_location_in_tokens(&tokens, "port"), Some(ArgLocation::Complete {
+            declaration: ArgPart { offset: 4, length: 3 },
+            name: ArgPart { offset: 7, length: 4 },
+            delimiter: ArgPart { offset: 11, length: 1 },
+            content: ArgPart { offset: 12, length: 3 },
+        }));
+        assert_eq!(locator.get_location_in_tokens(&tokens, "name"), Some(ArgLocation::Complete {
+            declaration: ArgPart { offset: 16, length: 2 },
+            name: ArgPart { offset: 18, length: 4 },
+            delimiter: ArgPart { offset: 22, length: 1 },
+            content: ArgPart { offset: 23, length: 5 },
+        }));
+        assert_eq!(locator.get_location_in_tokens(&tokens, "v"), Some(ArgLocation::Complete {
+            declaration: ArgPart { offset: 29, length: 1 },
+            name: ArgPart { offset: 30, length: 1 },
+            delimiter: ArgPart { offset: 31, length: 1 },
+            content: ArgPart { offset: 32, length: 7 },
+        }));
+    }
+
+    #[test]
+    fn test_get_location_in_line() {
+        use clap::{Arg, ArgAction, Command};
+
+        let command = Command::new("app")
+            .arg(Arg::new("name").long("name"))
+            .arg(Arg::new("verbose").short('v').action(ArgAction::SetTrue));
+        let locator = ArgLocator::from_command(&command);
+        let line = r#"app -\v --na'me'="$HOME x""#;
+        let location = locator.get_location_in_line(line, ShellDialect::Zsh, "name").unwrap();
+        assert_eq!(location.declaration(), &ArgPart { offset: 8, length: 2 });
+        assert_eq!(location.name(), &ArgPart { offset: 10, length: 5 });
+        assert_eq!(location.content(), Some(&ArgPart { offset: 18, length: 7 }));
+        // The escaped short is located at the escape.
+        let location = locator.get_location_in_line(line, ShellDialect::Zsh, "verbose").unwrap();
+        assert_eq!(location.name(), &ArgPart { offset: 5, length: 2 });
+        assert_eq!(locator.get_location_in_line("app 'x --name y'", ShellDialect::Zsh, "name"), None);
+        let location = locator.get_location_in_line("app --name 'a b", ShellDialect::Posix, "name").unwrap();
+        assert_eq!(location.content(), Some(&ArgPart { offset: 11, length: 4 }));
+    }
+}
@@ -0,0 +1,330 @@
+//! Where the source code that a report labels comes from: the Argv
+//! string, an env variable, a config or value file, or a command line
+//! in a script or shell history. The reports of this crate attach their
+//! source code through a `SourceProvider`, and applications implement
+//! it for their own backends, e.g. the args of a Kubernetes pod spec,
+//! and register them in `ReportContext::sources`.
+
+use std::{collections::BTreeMap, ffi::OsString};
+
+use miette::{LabeledSpan, MietteDiagnostic, MietteError, NamedSource, Report, SourceCode, SourceSpan, SpanContents};
+
+use crate::{
+    parse::ArgPart,
+    shell::{remap_part, ShellToken},
+};
+
+/// A source code that can be attached to a report.
+pub trait SourceProvider {
+    /// Shown in the header of the source code, e.g. a path. `None` for
+    /// an unnamed source, which `ReportContext::origin` names.
+    fn name(&self) -> Option<String> {
+        None
+    }
+
+    fn content(&self) -> String;
+
+    /// Converts a part of the Argv string, i.e. the arguments joined by
+    /// spaces, to a part of `Self::content()`. Returns `None` if the
+    /// Argv is not in the content. Sources that hold the Argv in another
+    /// layout, e.g. quoted or as a YAML list, convert the offsets.
+    fn remap(&self, part: &ArgPart) -> Option<ArgPart> {
+        Some(part.clone())
+    }
+
+    /// Attaches the content as the source code of `report`, whose
+    /// labels should be spans in the content.
+    fn attach(&self, report: Report) -> Report {
+        match self.name() {
+            Some(name) => report.with_source_code(NamedSource::new(name, self.content())),
+            None => report.with_source_code(self.content()),
+        }
+    }
+
+    /// Converts the labels of `diagnostic` from spans in the Argv string
+    /// with `Self::remap()` and attaches the content. Labels that are
+    /// not in the content are dropped.
+    fn attach_remapped(&self, mut diagnostic: MietteDiagnostic) -> Report {
+        if let Some(labels) = diagnostic.labels.take() {
+            let labels = labels
+                .into_iter()
+                .filter_map(|label| {
+                    let part = self.remap(&ArgPart {
+                        offset: label.offset(),
+                        length: label.len(),
+                    })?;
+                    let span = SourceSpan::new(part.offset.into(), part.length);
+                    Some(match label.primary() {
+                        true => LabeledSpan::new_primary_with_span(label.label().map(str::to_owned), span),
+                        false => LabeledSpan::new_with_span(label.label().map(str::to_owned), span),
+                    })
+                })
+                .collect();
+            diagnostic.labels = Some(labels);
+        }
+
+        self.attach(Report::from(diagnostic))
+    }
+}
+
+/// The Argv string, i.e. the arguments joined by spaces. It is also a
+/// `miette::SourceCode` that can be attached to a report as it is.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct ArgvSource {
+    pub argv: String,
+}
+
+impl ArgvSource {
+    pub fn new(argv: impl Into<String>) -> Self {
+        Self { argv: argv.into() }
+    }
+
+    /// Joins `args` by spaces, replacing invalid unicode with `U+FFFD`.
+    /// Locate the arguments in `args` converted the same way, see
+    /// `ArgLocator::force_lossy_os_string`, so that the offsets match.
+    ///
+    /// # Examples
+    /// ```
+    /// use clap::{Arg, Command};
+    /// use fancy_clap::{ArgLocator, ArgvSource};
+    /// use miette::{LabeledSpan, MietteDiagnostic, Report, SourceCode};
+    ///
+    /// let command = Command::new("app").arg(Arg::new("port").long("port"));
+    /// let args = ["app", "--port", "x"];
+    /// let content = ArgLocator::from_command(&command).get_location(args, "port").unwrap().content().unwrap().clone();
+    /// let source = ArgvSource::from_args(args);
+    /// let span = (content.offset, content.length).into();
+    /// assert_eq!(source.read_span(&span, 0, 0).unwrap().data(), b"x");
+    /// let diagnostic = MietteDiagnostic::new("invalid port").with_label(LabeledSpan::new_with_span(Some("here".to_owned()), span));
+    /// let report = Report::from(diagnostic).with_source_code(source);
+    /// ```
+    pub fn from_args<R>(args: R) -> Self
+    where
+        R: IntoIterator<Item: Into<OsString>>,
+    {
+        let args = args.into_iter().map(|arg| arg.into().to_string_lossy().into_owned()).collect::<Vec<_>>();
+        Self::new(args.join(" "))
+    }
+
+    /// Same as `Self::from_args()` with the args of the current process.
+    pub fn from_env() -> Self {
+        Self::from_args(std::env::args_os())
+    }
+}
+
+impl SourceCode for ArgvSource {
+    fn read_span<'a>(&'a self, span: &SourceSpan, context_lines_before: usize, context_lines_after: usize) -> Result<Box<dyn SpanContents<'a> + 'a>, MietteError> {
+        self.argv.read_span(span, context_lines_before, context_lines_after)
+    }
+}
+
+impl SourceProvider for ArgvSource {
+    fn content(&self) -> String {
+        self.argv.clone()
+    }
+}
+
+/// An env variable, shown as `VAR=value` in a source named
+/// "environment".
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct EnvSource {
+    pub var: String,
+    pub value: String,
+}
+
+impl EnvSource {
+    /// Returns the part of the value in the content.
+    pub fn value_part(&self) -> ArgPart {
+        ArgPart {
+            offset: self.var.len() + 1,
+            length: self.value.len(),
+        }
+    }
+}
+
+impl SourceProvider for EnvSource {
+    fn name(&self) -> Option<String> {
+        Some("environment".to_owned())
+    }
+
+    fn content(&self) -> String {
+        format!("{}={}", self.var, self.value)
+    }
+
+    fn remap(&self, _: &ArgPart) -> Option<ArgPart> {
+        None
+    }
+}
+
+/// A file that does not hold the Argv, e.g. a config file. See also
+/// `ValueFile`.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct FileSource {
+    pub name: String,
+    pub content: String,
+}
+
+impl SourceProvider for FileSource {
+    fn name(&self) -> Option<String> {
+        Some(self.name.clone())
+    }
+
+    fn content(&self) -> String {
+        self.content.clone()
+    }
+
+    fn remap(&self, _: &ArgPart) -> Option<ArgPart> {
+        None
+    }
+}
+
+/// A file with the command line that the Argv was split from, e.g. a
+/// shell script or a line of a shell history. See `split_command_line()`
+/// and `scan_history()`.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct ScriptSource {
+    pub name: String,
+    pub content: String,
+    /// The arguments of the command line, with ranges in `content`.
+    pub tokens: Vec<ShellToken>,
+}
+
+impl SourceProvider for ScriptSource {
+    fn name(&self) -> Option<String> {
+        Some(self.name.clone())
+    }
+
+    fn content(&self) -> String {
+        self.content.clone()
+    }
+
+    fn remap(&self, part: &ArgPart) -> Option<ArgPart> {
+        Some(remap_part(&self.tokens, part))
+    }
+}
+
+/// Source providers of an application, keyed by names of its choosing.
+/// See `ReportContext::sources`.
+#[derive(Default)]
+pub struct SourceRegistry {
+    pub providers: BTreeMap<String, Box<dyn SourceProvider>>,
+}
+
+impl SourceRegistry {
+    /// Registers `provider` as `key`, replacing the one registered
+    /// before if any.
+    pub fn register(&mut self, key: impl Into<String>, provider: impl SourceProvider + 'static) {
+        self.providers.insert(key.into(), Box::new(provider));
+    }
+
+    pub fn get(&self, key: &str) -> Option<&dyn SourceProvider> {
+        self.providers.get(key).map(AsRef::as_ref)
+    }
+
+    /// Same as `SourceProvider::attach_remapped()` of the provider
+    /// registered as `key`. Returns `diagnostic` without source code if
+    /// there is none.
+    pub fn attach_remapped(&self, key: &str, diagnostic: MietteDiagnostic) -> Report {
+        match self.get(key) {
+            Some(provider) => provider.attach_remapped(diagnostic),
+            None => Report::from(diagnostic),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shell::{split_command_line, ShellDialect};
+
+    /// Args of a container, like those of a Kubernetes pod spec.
+    struct YamlArgs {
+        args: Vec<String>,
+    }
+
+    impl SourceProvider for YamlArgs {
+        fn name(&self) -> Option<String> {
+            Some("pod.yaml".to_owned())
+        }
+
+        fn content(&self) -> String {
+            self.args.iter().map(|arg| format!("- {arg}\n")).collect()
+        }
+
+        fn remap(&self, part: &ArgPart) -> Option<ArgPart> {
+            let (mut argv, mut content) = (0, 0);
+            for arg in &self.args {
+                if (argv..argv + arg.len()).contains(&part.offset) {
+                    return Some(ArgPart {
+                        offset: content + 2 + part.offset - argv,
+                        length: part.length,
+                    });
+                }
+                argv += arg.len() + 1;
+                content += arg.len() + 3;
+            }
+            None
+        }
+    }
+
+    fn labels(report: &Report) -> Vec<(usize, usize)> {
+        report.labels().into_iter().flatten().map(|label| (label.offset(), label.len())).collect()
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_argv_source_lossy() {
+        use std::os::unix::ffi::OsStringExt;
+
+        use crate::parse::ArgLocator;
+
+        let command = clap::Command::new("app").arg(clap::Arg::new("name").long("name")).arg(clap::Arg::new("port").long("port"));
+        let args = vec![
+            OsString::from("app"),
+            OsString::from("--name"),
+            OsString::from_vec(b"a\xffb".to_vec()),
+            OsString::from("--port=1"),
+        ];
+        let source = ArgvSource::from_args(args.clone());
+        assert_eq!(source.argv, "app --name a\u{fffd}b --port=1");
+        // Offsets after the invalid byte line up once the args are
+        // converted the same way.
+        let mut locator = ArgLocator::from_command(&command);
+        locator.force_lossy_os_string = true;
+        let lossy = args.iter().map(|arg| arg.to_string_lossy().into_owned());
+        let content = locator.get_location(lossy, "port").unwrap().content().unwrap().clone();
+        let span = SourceSpan::new(content.offset.into(), content.length);
+        assert_eq!(source.read_span(&span, 0, 0).unwrap().data(), b"1");
+    }
+
+    #[test]
+    fn test_source_providers() {
+        // `--port=80` in `app --port=80`.
+        let diagnostic = MietteDiagnostic::new("invalid port").with_label(LabeledSpan::new_with_span(None, SourceSpan::new(4.into(), 9)));
+
+        let report = ArgvSource::new("app --port=80").attach_remapped(diagnostic.clone());
+        assert_eq!(labels(&report), [(4, 9)]);
+
+        let script = "app  '--port=80'";
+        let report = ScriptSource {
+            name: "run.sh".to_owned(),
+            content: script.to_owned(),
+            tokens: split_command_line(script, ShellDialect::Zsh),
+        }
+        .attach_remapped(diagnostic.clone());
+        // The quotes are labelled with the token.
+        assert_eq!(labels(&report), [(5, 11)]);
+
+        let env = EnvSource { var: "PORT".to_owned(), value: "80".to_owned() };
+        assert_eq!(env.content(), "PORT=80");
+        assert_eq!(env.value_part(), ArgPart { offset: 5, length: 2 });
+        assert_eq!(labels(&env.attach_remapped(diagnostic.clone())), []);
+
+        let mut sources = SourceRegistry::default();
+        sources.register("pod", YamlArgs { args: vec!["app".to_owned(), "--port=80".to_owned()] });
+        let report = sources.attach_remapped("pod", diagnostic.clone());
+        assert_eq!(labels(&report), [(8, 9)]);
+        assert_eq!(report.source_code().unwrap().read_span(&SourceSpan::new(8.into(), 9), 0, 0).unwrap().data(), b"--port=80");
+        assert_eq!(labels(&sources.attach_remapped("compose", diagnostic)), [(4, 9)]);
+    }
+}
@@ -0,0 +1,476 @@
+//! Suggestions for what users might have meant to type, labelled on
+//! the token that they actually typed.
+
+use std::{ffi::OsString, fmt, ops::ControlFlow};
+
+use clap::{
+    error::{ContextKind, ContextValue, ErrorKind},
+    parser::ValueSource,
+    ArgMatches, Command,
+};
+use miette::{Diagnostic, LabeledSpan, MietteDiagnostic, Report, Severity, SourceCode, SourceSpan};
+
+use crate::{
+    code,
+    parse::{all_aliases, display_name, ArgAlias, ArgLocation, ArgLocator},
+    scope::locate_scoped,
+    source::{ArgvSource, SourceProvider},
+};
+
+/// A machine-applicable fix: replace the text at `span` of the source
+/// code with `replacement`. An empty span inserts and an empty
+/// replacement removes.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct Suggestion {
+    pub span: SourceSpan,
+    pub replacement: String,
+}
+
+/// A diagnostic that carries `Suggestion`s as data rather than only in
+/// the help, so the JSON and SARIF formats of `ReportContext` can emit
+/// them as fixes. Every suggestion is also shown as a label, e.g.
+/// "replace with `add`".
+///
+/// The source code is kept here instead of being attached with
+/// `Report::with_source_code()`, which would hide the suggestions from
+/// `Report::downcast_ref()`.
+#[derive(Clone, Debug)]
+pub struct Suggested {
+    pub diagnostic: MietteDiagnostic,
+    pub suggestions: Vec<Suggestion>,
+    pub source_code: Option<String>,
+}
+
+impl Suggested {
+    pub fn new(diagnostic: MietteDiagnostic) -> Self {
+        Self {
+            diagnostic,
+            suggestions: vec![],
+            source_code: None,
+        }
+    }
+
+    pub fn with_suggestion(mut self, span: SourceSpan, replacement: impl Into<String>) -> Self {
+        self.suggestions.push(Suggestion {
+            span,
+            replacement: replacement.into(),
+        });
+        self
+    }
+
+    pub fn with_source_code(mut self, source_code: impl Into<String>) -> Self {
+        self.source_code = Some(source_code.into());
+        self
+    }
+}
+
+impl fmt::Display for Suggested {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.diagnostic, f)
+    }
+}
+
+impl std::error::Error for Suggested {}
+
+impl Diagnostic for Suggested {
+    fn code<'a>(&'a self) -> Option<Box<dyn fmt::Display + 'a>> {
+        self.diagnostic.code()
+    }
+
+    fn severity(&self) -> Option<Severity> {
+        self.diagnostic.severity()
+    }
+
+    fn help<'a>(&'a self) -> Option<Box<dyn fmt::Display + 'a>> {
+        self.diagnostic.help()
+    }
+
+    fn url<'a>(&'a self) -> Option<Box<dyn fmt::Display + 'a>> {
+        self.diagnostic.url()
+    }
+
+    fn source_code(&self) -> Option<&dyn SourceCode> {
+        self.source_code.as_ref().map(|source_code| source_code as &dyn SourceCode)
+    }
+
+    fn labels(&self) -> Option<Box<dyn Iterator<Item = LabeledSpan> + '_>> {
+        let suggestions = self.suggestions.iter().map(|suggestion| {
+            let label = match (suggestion.span.is_empty(), suggestion.replacement.is_empty()) {
+                (true, _) => format!("insert `{}`", suggestion.replacement),
+                (false, true) => "remove this".to_owned(),
+                (false, false) => format!("replace with `{}`", suggestion.replacement),
+            };
+            LabeledSpan::new_with_span(Some(label), suggestion.span)
+        });
+        let labels = self.diagnostic.labels.iter().flatten().cloned().chain(suggestions).collect::<Vec<_>>();
+        if labels.is_empty() {
+            return None;
+        }
+
+        Some(Box::new(labels.into_iter()))
+    }
+}
+
+/// Minimum `strsim::jaro()` similarity for a suggestion, same as clap.
+const SIMILARITY_THRESHOLD: f64 = 0.7;
+
+/// Returns the candidates similar to `typed`, the most similar first.
+pub(crate) fn similar<'a>(typed: &str, candidates: impl IntoIterator<Item = &'a str>) -> Vec<&'a str> {
+    let mut scored = candidates
+        .into_iter()
+        .map(|candidate| (strsim::jaro(typed, candidate), candidate))
+        .filter(|(score, _)| *score > SIMILARITY_THRESHOLD)
+        .collect::<Vec<_>>();
+    scored.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+    scored.dedup_by_key(|(_, candidate)| *candidate);
+
+    scored.into_iter().map(|(_, candidate)| candidate).collect()
+}
+
+/// Returns a report labelling the first argument in `args` that is
+/// neither a flag, a value nor a subcommand of the command it follows,
+/// with the closest names and visible aliases of the subcommands as
+/// help. Use this in place of the plain "unrecognized subcommand" error
+/// of clap. Returns `None` if every subcommand is recognized, or if
+/// the command at that level takes positional arguments, in which case
+/// the argument is not necessarily meant to be a subcommand.
+pub fn suggest_subcommand<R>(command: &Command, args: R) -> Option<Report>
+where
+    R: IntoIterator<Item: Into<OsString>>,
+{
+    let args = args.into_iter().map(|arg| arg.into().to_string_lossy().to_string()).collect::<Vec<_>>();
+    let mut offsets = vec![];
+    let mut offset = 0;
+    for arg in &args {
+        offsets.push(offset);
+        offset += arg.len() + 1;
+    }
+    let mut command = command.clone();
+    command.build();
+
+    let mut current = &command;
+    let mut start = 0;
+    let (index, typed) = loop {
+        let level = &args[start..];
+        let mut covered = vec![];
+        ArgLocator::from_command(current).visit_locations(level, |_, location| {
+            let whole = location.whole();
+            covered.push(offsets[start] + whole.offset..offsets[start] + whole.offset + whole.length);
+            ControlFlow::<()>::Continue(())
+        });
+        let (index, typed) = level.iter().enumerate().skip(1).find(|(index, arg)| {
+            let offset = offsets[start + index];
+            arg.as_str() == "--" || !(arg.len() > 1 && arg.starts_with('-') || covered.iter().any(|range| range.contains(&offset)))
+        })?;
+        if typed == "--" {
+            return None;
+        }
+        match current.find_subcommand(typed) {
+            Some(subcommand) => {
+                current = subcommand;
+                start += index;
+            }
+            None if current.has_subcommands() && current.get_positionals().next().is_none() => break (start + index, typed),
+            None => return None,
+        }
+    };
+
+    let names = current
+        .get_subcommands()
+        .filter(|subcommand| !subcommand.is_hide_set())
+        .flat_map(|subcommand| std::iter::once(subcommand.get_name()).chain(subcommand.get_visible_aliases()))
+        .collect::<Vec<_>>();
+    let closest = similar(typed, names.iter().copied());
+    let quote = |names: &[&str]| names.iter().map(|name| format!("`{name}`")).collect::<Vec<_>>().join(", ");
+    let help = match closest.as_slice() {
+        [] => format!("available subcommands: {}", quote(&names)),
+        [closest] => format!("did you mean `{closest}`?"),
+        closest => format!("similar subcommands: {}", quote(closest)),
+    };
+
+    let span = SourceSpan::new(offsets[index].into(), typed.len());
+    let diagnostic = MietteDiagnostic::new(format!("unrecognized subcommand `{typed}`"))
+        .with_code(code::UNRECOGNIZED_SUBCOMMAND)
+        .with_label(LabeledSpan::new_primary_with_span(Some("not a subcommand".to_owned()), span))
+        .with_help(help);
+    let mut suggested = Suggested::new(diagnostic).with_source_code(args.join(" "));
+    if let [closest] = closest.as_slice() {
+        suggested = suggested.with_suggestion(span, *closest);
+    }
+
+    Some(Report::from(suggested))
+}
+
+/// Returns a report for a `MissingRequiredArgument` error of clap that
+/// labels a zero-width span at the end of the command line and shows
+/// the missing arguments rendered from their value names, e.g.
+/// `--output <FILE>`, so users can see what to append and where.
+/// Returns `None` for the other kinds of errors.
+pub fn missing_required_hint<R>(command: &Command, args: R, error: &clap::Error) -> Option<Report>
+where
+    R: IntoIterator<Item: Into<OsString>>,
+{
+    if error.kind() != ErrorKind::MissingRequiredArgument {
+        return None;
+    }
+    let Some(ContextValue::Strings(missing)) = error.get(ContextKind::InvalidArg) else {
+        return None;
+    };
+    let mut command = command.clone();
+    command.build();
+    // clap renders the missing arguments the same way, but the
+    // templates are taken from the arguments when they can be found.
+    let templates = missing
+        .iter()
+        .map(|rendered| find_template(&command, rendered).unwrap_or_else(|| rendered.clone()))
+        .collect::<Vec<_>>();
+
+    let args = args.into_iter().map(|arg| arg.into().to_string_lossy().to_string()).collect::<Vec<_>>();
+    let full_command = args.join(" ");
+    let end = full_command.len();
+    let message = match templates.len() {
+        1 => "missing a required argument",
+        _ => "missing required arguments",
+    };
+    let diagnostic = MietteDiagnostic::new(message)
+        .with_code(code::MISSING_REQUIRED)
+        .with_label(LabeledSpan::new_primary_with_span(
+            Some(format!("append `{}` here", templates.join(" "))),
+            SourceSpan::new(end.into(), 0),
+        ))
+        .with_help(format!("the command needs {}", templates.iter().map(|template| format!("`{template}`")).collect::<Vec<_>>().join(", ")));
+
+    // miette does not render labels at the very end of the source.
+    Some(ArgvSource::new(full_command + " ").attach(Report::from(diagnostic)))
+}
+
+/// Returns an advice for every short in a cluster like `-npspn` that
+/// took the rest of the cluster as its value, while the rest starts
+/// with a short of `command` that users might have meant as a flag.
+/// The advice labels exactly which characters became the value.
+pub fn stuck_value_advisories<R>(command: &Command, args: R) -> Vec<Report>
+where
+    R: IntoIterator<Item: Into<OsString>>,
+{
+    let args = args.into_iter().map(|arg| arg.into().to_string_lossy().to_string()).collect::<Vec<_>>();
+    let full_command = args.join(" ");
+    let mut command = command.clone();
+    command.build();
+    let shorts = command
+        .get_arguments()
+        .flat_map(all_aliases)
+        .filter_map(|alias| match alias {
+            ArgAlias::Short(short) => Some(short),
+            ArgAlias::Long(_) => None,
+        })
+        .collect::<Vec<_>>();
+
+    let mut advisories = vec![];
+    ArgLocator::from_command(&command).visit_locations(&args, |_, location| {
+        let ArgLocation::Stuck { name, content, .. } = location else {
+            return ControlFlow::<()>::Continue(());
+        };
+        let short = &full_command[name.offset..name.offset + name.length];
+        let value = &full_command[content.offset..content.offset + content.length];
+        if !value.chars().next().is_some_and(|first| shorts.contains(&first)) {
+            return ControlFlow::Continue(());
+        }
+        let diagnostic = MietteDiagnostic::new(format!("`{value}` was taken as the value of `-{short}`"))
+            .with_severity(Severity::Advice)
+            .with_code(code::STUCK_VALUE)
+            .with_labels([
+                LabeledSpan::new_with_span(Some("takes a value".to_owned()), SourceSpan::new(name.offset.into(), name.length)),
+                LabeledSpan::new_primary_with_span(
+                    Some(format!("became the value of `-{short}`")),
+                    SourceSpan::new(content.offset.into(), content.length),
+                ),
+            ])
+            .with_help(format!("write `-{short} {value}` to make it explicit, or put `-{short}` last if these are flags"));
+        advisories.push(ArgvSource::new(full_command.clone()).attach(Report::from(diagnostic)));
+        ControlFlow::Continue(())
+    });
+
+    advisories
+}
+
+/// Returns an advice for every argument that was given on the command
+/// line while its env variable is set to a different value, which clap
+/// silently ignores. The advice labels the argument and names the env
+/// variable, with its value unless it is hidden from the help.
+pub fn env_override_advisories<R>(command: &Command, args: R, matches: &ArgMatches) -> Vec<Report>
+where
+    R: IntoIterator<Item: Into<OsString>>,
+{
+    let args = args.into_iter().map(|arg| arg.into().to_string_lossy().to_string()).collect::<Vec<_>>();
+    let full_command = args.join(" ");
+    let mut command = command.clone();
+    command.build();
+
+    let mut advisories = vec![];
+    let mut reported = vec![];
+    for located in locate_scoped(&command, &args) {
+        let (Some(current), Some(current_matches)) = (find_scope(&command, &located.scope), find_scope_matches(matches, &located.scope)) else {
+            continue;
+        };
+        let Some(found) = current.get_arguments().find(|arg| arg.get_id() == located.id.as_str()) else {
+            continue;
+        };
+        let (Some(env), Some(ValueSource::CommandLine)) = (found.get_env(), current_matches.value_source(&located.id)) else {
+            continue;
+        };
+        let Some(env_value) = std::env::var_os(env) else {
+            continue;
+        };
+        let matched = current_matches.get_raw(&located.id).into_iter().flatten().any(|value| value == env_value);
+        if matched || reported.contains(&(located.scope.clone(), located.id.clone())) {
+            continue;
+        }
+        reported.push((located.scope.clone(), located.id.clone()));
+
+        let env = env.to_string_lossy();
+        let help = match found.is_hide_env_values_set() {
+            true => format!("`{env}` is set to a different value, which is ignored"),
+            false => format!("`{env}` is set to `{}`, which is ignored", env_value.to_string_lossy()),
+        };
+        let whole = located.location.whole();
+        let diagnostic = MietteDiagnostic::new(format!("`{}` overrides the env variable `{env}`", display_name(found)))
+            .with_severity(Severity::Advice)
+            .with_code(code::ENV_OVERRIDE)
+            .with_label(LabeledSpan::new_primary_with_span(
+                Some(format!("takes precedence over `{env}`")),
+                SourceSpan::new(whole.offset.into(), whole.length),
+            ))
+            .with_help(help);
+        advisories.push(ArgvSource::new(full_command.clone()).attach(Report::from(diagnostic)));
+    }
+
+    advisories
+}
+
+fn find_scope<'a>(command: &'a Command, scope: &[String]) -> Option<&'a Command> {
+    scope.iter().try_fold(command, |current, name| current.find_subcommand(name))
+}
+
+fn find_scope_matches<'a>(matches: &'a ArgMatches, scope: &[String]) -> Option<&'a ArgMatches> {
+    scope.iter().try_fold(matches, |current, name| current.subcommand_matches(name))
+}
+
+fn find_template(command: &Command, rendered: &str) -> Option<String> {
+    command
+        .get_arguments()
+        .map(ToString::to_string)
+        .find(|template| template == rendered)
+        .or_else(|| command.get_subcommands().find_map(|subcommand| find_template(subcommand, rendered)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Arg;
+
+    fn command() -> Command {
+        Command::new("app")
+            .arg(Arg::new("config").long("config"))
+            .subcommand(
+                Command::new("remote")
+                    .visible_alias("rmt")
+                    .subcommand(Command::new("add"))
+                    .subcommand(Command::new("remove"))
+                    .subcommand(Command::new("rename")),
+            )
+            .subcommand(Command::new("build"))
+    }
+
+    #[test]
+    fn test_similar() {
+        assert_eq!(similar("remot", ["build", "remote", "rmt", "remote"]), ["remote"]);
+        assert!(similar("xyz", ["build"]).is_empty());
+    }
+
+    #[test]
+    fn test_suggest_subcommand() {
+        let report = suggest_subcommand(&command(), ["app", "--config", "x", "remote", "ad"]).unwrap();
+        assert_eq!(report.to_string(), "unrecognized subcommand `ad`");
+        assert_eq!(report.code().unwrap().to_string(), code::UNRECOGNIZED_SUBCOMMAND);
+        assert_eq!(report.help().unwrap().to_string(), "did you mean `add`?");
+        let label = report.labels().unwrap().next().unwrap();
+        assert_eq!((label.offset(), label.len()), (22, 2));
+        let suggested = report.downcast_ref::<Suggested>().unwrap();
+        assert_eq!(suggested.suggestions, [Suggestion {
+            span: SourceSpan::new(22.into(), 2),
+            replacement: "add".to_owned(),
+        }]);
+        assert_eq!(report.labels().unwrap().nth(1).unwrap().label(), Some("replace with `add`"));
+
+        let report = suggest_subcommand(&command(), ["app", "rmt", "remn"]).unwrap();
+        assert_eq!(report.help().unwrap().to_string(), "similar subcommands: `rename`, `remove`");
+        assert!(report.downcast_ref::<Suggested>().unwrap().suggestions.is_empty());
+        let report = suggest_subcommand(&command(), ["app", "xyz"]).unwrap();
+        assert_eq!(report.help().unwrap().to_string(), "available subcommands: `remote`, `rmt`, `build`, `help`");
+        let label = report.labels().unwrap().next().unwrap();
+        assert_eq!((label.offset(), label.len()), (4, 3));
+
+        assert!(suggest_subcommand(&command(), ["app", "remote", "add"]).is_none());
+        assert!(suggest_subcommand(&command(), ["app", "--", "remot"]).is_none());
+    }
+
+    #[test]
+    fn test_missing_required_hint() {
+        let command = Command::new("app")
+            .arg(Arg::new("output").long("output").value_name("FILE").required(true))
+            .arg(Arg::new("name").required(true))
+            .arg(Arg::new("verbose").short('v').action(clap::ArgAction::SetTrue));
+        let args = ["app", "-v"];
+        let error = command.clone().try_get_matches_from(args).unwrap_err();
+        let report = missing_required_hint(&command, args, &error).unwrap();
+        assert_eq!(report.to_string(), "missing required arguments");
+        assert_eq!(report.help().unwrap().to_string(), "the command needs `--output <FILE>`, `<name>`");
+        let label = report.labels().unwrap().next().unwrap();
+        assert_eq!(label.label(), Some("append `--output <FILE> <name>` here"));
+        assert_eq!((label.offset(), label.len()), (6, 0));
+
+        let error = command.clone().try_get_matches_from(["app", "--nope"]).unwrap_err();
+        assert!(missing_required_hint(&command, ["app", "--nope"], &error).is_none());
+    }
+
+    #[test]
+    fn test_stuck_value_advisories() {
+        let command = Command::new("app")
+            .arg(Arg::new("numbers").short('n').action(clap::ArgAction::SetTrue))
+            .arg(Arg::new("pattern").short('p'))
+            .arg(Arg::new("silent").short('s').action(clap::ArgAction::SetTrue));
+        let advisories = stuck_value_advisories(&command, ["app", "-npspn"]);
+        assert_eq!(advisories.len(), 1);
+        assert_eq!(advisories[0].to_string(), "`spn` was taken as the value of `-p`");
+        assert_eq!(advisories[0].severity(), Some(Severity::Advice));
+        let labels = advisories[0].labels().unwrap().map(|label| (label.offset(), label.len())).collect::<Vec<_>>();
+        assert_eq!(labels, [(6, 1), (7, 3)]);
+
+        assert!(stuck_value_advisories(&command, ["app", "-pfoo"]).is_empty());
+        assert!(stuck_value_advisories(&command, ["app", "-np", "spn"]).is_empty());
+    }
+
+    #[test]
+    fn test_env_override_advisories() {
+        std::env::set_var("FANCY_CLAP_TEST_OVERRIDE_PORT", "8080");
+        std::env::set_var("FANCY_CLAP_TEST_OVERRIDE_TOKEN", "secret");
+        let command = Command::new("app").subcommand(
+            Command::new("serve")
+                .arg(Arg::new("port").long("port").env("FANCY_CLAP_TEST_OVERRIDE_PORT"))
+                .arg(Arg::new("token").long("token").env("FANCY_CLAP_TEST_OVERRIDE_TOKEN").hide_env_values(true)),
+        );
+        let args = ["app", "serve", "--port=80", "--token", "other"];
+        let matches = command.clone().get_matches_from(args);
+        let advisories = env_override_advisories(&command, args, &matches);
+        assert_eq!(advisories.len(), 2);
+        assert_eq!(advisories[0].to_string(), "`--port` overrides the env variable `FANCY_CLAP_TEST_OVERRIDE_PORT`");
+        assert_eq!(advisories[0].help().unwrap().to_string(), "`FANCY_CLAP_TEST_OVERRIDE_PORT` is set to `8080`, which is ignored");
+        let label = advisories[0].labels().unwrap().next().unwrap();
+        assert_eq!((label.offset(), label.len()), (10, 9));
+        assert_eq!(advisories[1].help().unwrap().to_string(), "`FANCY_CLAP_TEST_OVERRIDE_TOKEN` is set to a different value, which is ignored");
+
+        let args = ["app", "serve", "--port=8080"];
+        let matches = command.clone().get_matches_from(args);
+        assert!(env_override_advisories(&command, args, &matches).is_empty());
+    }
+}
@@ -0,0 +1,162 @@
+//! Cross-validating the Argv passed to this crate against the
+//! `ArgMatches` it is supposed to have produced, so that a wrong or
+//! pre-processed Argv is reported instead of highlighted with spans
+//! that silently point at the wrong characters.
+
+use std::{ffi::OsString, fmt, ops::ControlFlow};
+
+use clap::{parser::ValueSource, ArgMatches, Command};
+#[cfg(feature = "miette")]
+use miette::Diagnostic;
+
+use crate::parse::{ArgLocation, ArgLocator, ArgPart};
+
+/// How the Argv and the `ArgMatches` checked by `check_sync()` disagree.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Desync {
+    /// The `ArgMatches` has the argument from the command line, but it
+    /// cannot be found in the Argv.
+    Missing { arg: String },
+    /// The argument is in the Argv, but the `ArgMatches` did not take it
+    /// from the command line.
+    Unexpected { arg: String, location: ArgLocation },
+    /// The last value of the argument in the Argv is not the last value
+    /// in the `ArgMatches`. `content` is the part of the located value.
+    Value {
+        arg: String,
+        content: ArgPart,
+        located: String,
+        matched: String,
+    },
+    /// The argument comes after `after` in the Argv, but before it
+    /// according to `ArgMatches::indices_of()`.
+    Order { arg: String, location: ArgLocation, after: String },
+}
+
+/// Returns an error if the occurrences of the flags and options of
+/// `command` located in `args` do not correspond to `matches`, which
+/// should be parsed from the same `args` by the same `command`.
+/// Positional arguments and subcommands are not checked.
+pub fn check_sync<R>(command: &Command, args: R, matches: &ArgMatches) -> Result<(), Desync>
+where
+    R: IntoIterator<Item: Into<OsString>>,
+{
+    let args = args.into_iter().map(|arg| arg.into().to_string_lossy().to_string()).collect::<Vec<_>>();
+    let full_command = args.join(" ");
+    let mut command = command.clone();
+    command.build();
+
+    // The last location of every located argument, in the order they
+    // appear.
+    let mut located: Vec<(String, ArgLocation)> = vec![];
+    ArgLocator::from_command(&command).visit_locations(&args, |id, location| {
+        located.retain(|(other, _)| other != id.as_str());
+        located.push((id.to_string(), location));
+        ControlFlow::<()>::Continue(())
+    });
+
+    for (arg, location) in &located {
+        let unexpected = || Desync::Unexpected {
+            arg: arg.clone(),
+            location: location.clone(),
+        };
+        let Ok(occurrences) = matches.try_get_raw_occurrences(arg) else {
+            return Err(unexpected());
+        };
+        if matches.value_source(arg) != Some(ValueSource::CommandLine) {
+            return Err(unexpected());
+        }
+        let (Some(content), Some(mut last)) = (location.content(), occurrences.and_then(Iterator::last)) else {
+            continue;
+        };
+        let located = full_command.get(content.offset..content.offset + content.length).unwrap_or_default();
+        let matched = last.next().map(|value| value.to_string_lossy().to_string()).unwrap_or_default();
+        if located != matched {
+            return Err(Desync::Value {
+                arg: arg.clone(),
+                content: content.clone(),
+                located: located.to_owned(),
+                matched,
+            });
+        }
+    }
+
+    for arg in command.get_arguments().filter(|arg| !arg.is_positional()) {
+        let id = arg.get_id().as_str();
+        let from_command_line = matches!(matches.try_get_raw_occurrences(id), Ok(Some(_))) && matches.value_source(id) == Some(ValueSource::CommandLine);
+        if from_command_line && !located.iter().any(|(other, _)| other == id) {
+            return Err(Desync::Missing { arg: id.to_owned() });
+        }
+    }
+
+    // clap only keeps the index of the last occurrence for flags, so
+    // the last occurrences are compared.
+    let last_index = |arg: &str| matches.indices_of(arg).and_then(Iterator::max);
+    for pair in located.windows(2) {
+        let [(before, _), (arg, location)] = pair else {
+            unreachable!("Windows of 2")
+        };
+        if last_index(arg) < last_index(before) {
+            return Err(Desync::Order {
+                arg: arg.clone(),
+                location: location.clone(),
+                after: before.clone(),
+            });
+        }
+    }
+
+    Ok(())
+}
+
+impl fmt::Display for Desync {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Missing { arg } => write!(f, "`{arg}` is matched but cannot be found in the Argv"),
+            Self::Unexpected { arg, .. } => write!(f, "`{arg}` is in the Argv but not matched from the command line"),
+            Self::Value { arg, located, matched, .. } => write!(f, "`{arg}` is `{located}` in the Argv but `{matched}` in the matches"),
+            Self::Order { arg, after, .. } => write!(f, "`{arg}` comes after `{after}` in the Argv but not in the matches"),
+        }
+    }
+}
+
+impl std::error::Error for Desync {}
+
+#[cfg(feature = "miette")]
+impl Diagnostic for Desync {
+    fn code<'a>(&'a self) -> Option<Box<dyn fmt::Display + 'a>> {
+        Some(Box::new(crate::code::DESYNC))
+    }
+
+    fn help<'a>(&'a self) -> Option<Box<dyn fmt::Display + 'a>> {
+        Some(Box::new("pass the same Argv that the `ArgMatches` was parsed from"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::{Arg, ArgAction};
+
+    fn command() -> Command {
+        Command::new("app")
+            .arg(Arg::new("verbose").short('v').action(ArgAction::Count))
+            .arg(Arg::new("output").short('o').long("output").action(ArgAction::Append))
+            .arg(Arg::new("name"))
+    }
+
+    #[test]
+    fn test_check_sync() {
+        let args = ["app", "-vv", "-o", "a", "name", "--output=b"];
+        let matches = command().get_matches_from(args);
+        assert_eq!(check_sync(&command(), args, &matches), Ok(()));
+
+        let error = check_sync(&command(), ["app", "-vv", "-o", "a", "--output=c"], &matches).unwrap_err();
+        assert!(matches!(&error, Desync::Value { arg, located, matched, .. } if arg == "output" && located == "c" && matched == "b"));
+        assert_eq!(check_sync(&command(), ["app", "-o", "a", "--output=b"], &matches), Err(Desync::Missing { arg: "verbose".to_owned() }));
+        assert!(matches!(check_sync(&command(), ["app", "--output=b", "-vv"], &matches), Err(Desync::Order { arg, after, .. }) if arg == "verbose" && after == "output"));
+
+        let matches = command().get_matches_from(["app", "-v"]);
+        assert!(matches!(check_sync(&command(), ["app", "-v", "-o", "a"], &matches), Err(Desync::Unexpected { arg, .. }) if arg == "output"));
+    }
+}
@@ -0,0 +1,158 @@
+//! Helpers for testing the diagnostics made with this crate without
+//! counting offsets by hand. See `ArgvBuilder` and `assert_underlined()`.
+
+use std::ops::Range;
+
+/// Composes an Argv and answers where its parts are in the Argv string,
+/// i.e. the arguments joined by spaces.
+///
+/// # Examples
+/// ```
+/// use fancy_clap::testing::ArgvBuilder;
+///
+/// let argv = ArgvBuilder::new("program_name").args(["-v", "--port", "80"]);
+/// assert_eq!(argv.token_span(2), 16..22);
+/// assert_eq!(argv.value_span("--port"), Some(23..25));
+/// ```
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct ArgvBuilder {
+    args: Vec<String>,
+}
+
+impl ArgvBuilder {
+    pub fn new(program_name: impl Into<String>) -> Self {
+        Self {
+            args: vec![program_name.into()],
+        }
+    }
+
+    pub fn arg(mut self, arg: impl Into<String>) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    pub fn args<S: Into<String>>(mut self, args: impl IntoIterator<Item = S>) -> Self {
+        self.args.extend(args.into_iter().map(Into::into));
+        self
+    }
+
+    /// Returns the arguments, including the program name.
+    pub fn to_vec(&self) -> Vec<String> {
+        self.args.clone()
+    }
+
+    /// Returns the Argv string.
+    pub fn argv(&self) -> String {
+        self.args.join(" ")
+    }
+
+    /// Returns the range of the argument at `index` in the Argv string,
+    /// where the program name is at `0`.
+    ///
+    /// # Panics
+    /// Panics if `index` is out of bounds.
+    pub fn token_span(&self, index: usize) -> Range<usize> {
+        let start = self.args[..index].iter().map(|arg| arg.len() + 1).sum::<usize>();
+
+        start..start + self.args[index].len()
+    }
+
+    /// Returns the range of the value of the first occurrence of `arg`,
+    /// which is a long or short with the hyphens, in the Argv string.
+    /// The value can be in the next argument, after an equal sign, or
+    /// stuck to a short.
+    pub fn value_span(&self, arg: &str) -> Option<Range<usize>> {
+        let is_short = !arg.starts_with("--") && arg.chars().count() == 2;
+        for (index, token) in self.args.iter().enumerate().skip(1) {
+            let span = self.token_span(index);
+            if token == arg {
+                return (index + 1 < self.args.len()).then(|| self.token_span(index + 1));
+            }
+            let Some(rest) = token.strip_prefix(arg) else {
+                continue;
+            };
+            if let Some(value) = rest.strip_prefix('=') {
+                return Some(span.end - value.len()..span.end);
+            }
+            if is_short && !rest.is_empty() {
+                return Some(span.end - rest.len()..span.end);
+            }
+        }
+
+        None
+    }
+}
+
+/// Returns `true` if `rendered`, a report rendered by the graphical
+/// handler of `miette`, has a line that contains `substring` followed
+/// by a line with a non-blank character under every character of it.
+/// Characters wider than one column, e.g. CJK and emoji, are only
+/// supported with the `unicode-width` feature.
+pub fn is_underlined(rendered: &str, substring: &str) -> bool {
+    let lines = rendered.lines().collect::<Vec<_>>();
+    lines.windows(2).any(|pair| {
+        let [line, under] = pair else {
+            return false;
+        };
+        let under = under.chars().collect::<Vec<_>>();
+        line.match_indices(substring).any(|(index, _)| {
+            let column = crate::width::columns(&line[..index]);
+            let columns = column..column + crate::width::columns(substring);
+            columns.into_iter().all(|column| under.get(column).is_some_and(|c| !c.is_whitespace()))
+        })
+    })
+}
+
+/// Panics with `rendered` unless `substring` is underlined in it.
+/// See `is_underlined()`.
+#[track_caller]
+pub fn assert_underlined(rendered: &str, substring: &str) {
+    assert!(is_underlined(rendered, substring), "`{substring}` is not underlined in:\n{rendered}");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ArgLocator;
+
+    #[test]
+    fn test_argv_builder() {
+        #[derive(clap::Parser)]
+        struct Args {
+            #[clap(short, long)]
+            port: u16,
+        }
+
+        let locator = ArgLocator::from_command_factory::<Args>();
+        for (argv, flag) in [
+            (ArgvBuilder::new("program_name").args(["--port", "80"]), "--port"),
+            (ArgvBuilder::new("program_name").arg("--port=80"), "--port"),
+            (ArgvBuilder::new("program_name").arg("-p80"), "-p"),
+        ] {
+            let content = locator.get_location(argv.to_vec(), "port").unwrap().content().cloned().unwrap();
+            assert_eq!(argv.value_span(flag), Some(content.offset..content.offset + content.length));
+            assert_eq!(&argv.argv()[content.offset..content.offset + content.length], "80");
+        }
+        assert_eq!(ArgvBuilder::new("program_name").arg("--port").value_span("--port"), None);
+    }
+
+    #[test]
+    fn test_is_underlined() {
+        let rendered = " 1 │ program_name --port 80\n   ·                     ─┬\n   ·                      ╰── here\n";
+        assert!(is_underlined(rendered, "80"));
+        assert!(!is_underlined(rendered, "--port"));
+    }
+
+    #[test]
+    #[cfg(feature = "fancy")]
+    fn test_assert_underlined_graphical() {
+        use miette::{GraphicalReportHandler, GraphicalTheme, LabeledSpan, MietteDiagnostic, Report};
+
+        let argv = ArgvBuilder::new("program_name").args(["--port", "80"]);
+        let report = Report::from(MietteDiagnostic::new("privileged port").with_label(LabeledSpan::at(argv.value_span("--port").unwrap(), "here")))
+            .with_source_code(argv.argv());
+        let mut rendered = String::new();
+        GraphicalReportHandler::new_themed(GraphicalTheme::unicode_nocolor()).render_report(&mut rendered, report.as_ref()).unwrap();
+        assert_underlined(&rendered, "80");
+    }
+}
@@ -0,0 +1,137 @@
+//! Configurable glyphs for drawing labels under the Argv string, for
+//! environments with limited font support.
+
+use crate::{parse::ArgPart, width::columns};
+
+/// Characters used for drawing the labels of a report. Applied to the
+/// `miette` graphical theme with the `fancy` feature.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct Markers {
+    /// Underlines the spans of primary labels.
+    pub primary_underline: char,
+    /// Underlines the spans of secondary labels.
+    pub secondary_underline: char,
+    /// Marks where the connector leaves an underline.
+    pub underbar: char,
+    /// Vertical line from an underline down to its label text.
+    pub connector: char,
+    /// Turns the connector towards the label text.
+    pub corner: char,
+    /// Horizontal line in between the corner and the label text.
+    pub horizontal: char,
+}
+
+impl Markers {
+    /// Box-drawing characters, which is the default.
+    pub fn unicode() -> Self {
+        Self {
+            primary_underline: '━',
+            secondary_underline: '─',
+            underbar: '┬',
+            connector: '│',
+            corner: '╰',
+            horizontal: '─',
+        }
+    }
+
+    /// Pure-ASCII characters.
+    pub fn ascii() -> Self {
+        Self {
+            primary_underline: '^',
+            secondary_underline: '-',
+            underbar: '|',
+            connector: '|',
+            corner: '`',
+            horizontal: '-',
+        }
+    }
+
+    /// Returns `theme` with its drawing characters replaced by `self`.
+    /// `miette` underlines all labels alike, so `secondary_underline`
+    /// is not used.
+    #[cfg(feature = "fancy")]
+    pub fn graphical_theme(&self, mut theme: miette::GraphicalTheme) -> miette::GraphicalTheme {
+        let characters = &mut theme.characters;
+        characters.underline = self.primary_underline;
+        characters.underbar = self.underbar;
+        characters.vbar = self.connector;
+        characters.lbot = self.corner;
+        characters.hbar = self.horizontal;
+
+        theme
+    }
+
+    /// Returns the line to be printed under `line` that underlines the
+    /// `primary` and `secondary` spans, which are byte offsets in it,
+    /// for plain-text output without the graphical handler. Primary
+    /// underlines are drawn over secondary ones, and an empty span gets
+    /// a single marker. Columns are counted by the `unicode-width`
+    /// feature if it is enabled, so the underlines stay under wide
+    /// characters, e.g. CJK and emoji.
+    pub fn underline(&self, line: &str, primary: &[ArgPart], secondary: &[ArgPart]) -> String {
+        let mut under = Vec::<char>::new();
+        let mut draw = |part: &ArgPart, marker: char| {
+            let end = (part.offset + part.length).min(line.len());
+            let (Some(before), Some(text)) = (line.get(..part.offset.min(end)), line.get(part.offset.min(end)..end)) else {
+                return;
+            };
+            let start = columns(before);
+            let stop = start + columns(text).max(1);
+            if under.len() < stop {
+                under.resize(stop, ' ');
+            }
+            under[start..stop].fill(marker);
+        };
+        for part in secondary {
+            draw(part, self.secondary_underline);
+        }
+        for part in primary {
+            draw(part, self.primary_underline);
+        }
+
+        under.into_iter().collect()
+    }
+}
+
+impl Default for Markers {
+    fn default() -> Self {
+        Self::unicode()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_underline() {
+        let markers = Markers::ascii();
+        let part = |offset, length| ArgPart { offset, length };
+        let line = "app --name=x --port";
+        assert_eq!(markers.underline(line, &[part(11, 1)], &[part(4, 6)]), "    ------ ^");
+        assert_eq!(markers.underline(line, &[part(19, 0)], &[]), format!("{}^", " ".repeat(19)));
+
+        // `--name=名前 --port`
+        let line = "app --name=\u{540d}\u{524d} --port";
+        let underlined = markers.underline(line, &[part(18, 6)], &[part(11, 6)]);
+        #[cfg(feature = "unicode-width")]
+        assert_eq!(underlined, "           ---- ^^^^^^");
+        #[cfg(not(feature = "unicode-width"))]
+        assert_eq!(underlined, "           -- ^^^^^^");
+    }
+
+    #[test]
+    #[cfg(feature = "fancy")]
+    fn test_graphical_theme_ascii() {
+        use miette::{GraphicalReportHandler, GraphicalTheme, LabeledSpan, MietteDiagnostic, Report};
+
+        let theme = Markers::ascii().graphical_theme(GraphicalTheme::unicode_nocolor());
+        let report = Report::from(MietteDiagnostic::new("bad port").with_label(LabeledSpan::at(13..19, "here")))
+            .with_source_code("program_name --port");
+
+        let mut rendered = String::new();
+        GraphicalReportHandler::new_themed(theme).render_report(&mut rendered, report.as_ref()).unwrap();
+        assert!(rendered.contains("^^^|^^"));
+        assert!(rendered.contains("`-- here"));
+    }
+}
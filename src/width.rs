@@ -0,0 +1,38 @@
+//! Columns that text takes in a terminal, for drawing underlines under
+//! the right glyphs. With the `unicode-width` feature, CJK characters
+//! and emoji take two columns and combining characters none, otherwise
+//! every character takes one.
+
+/// Returns the number of columns that `text` takes.
+pub(crate) fn columns(text: &str) -> usize {
+    text.chars().map(char_columns).sum()
+}
+
+/// Returns the number of columns that `c` takes. Control characters
+/// take none, as the renderers escape them before drawing.
+#[cfg(feature = "unicode-width")]
+pub(crate) fn char_columns(c: char) -> usize {
+    unicode_width::UnicodeWidthChar::width(c).unwrap_or_default()
+}
+
+/// Returns the number of columns that `c` takes. Control characters
+/// take none, as the renderers escape them before drawing.
+#[cfg(not(feature = "unicode-width"))]
+pub(crate) fn char_columns(c: char) -> usize {
+    usize::from(!c.is_control())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_columns() {
+        assert_eq!(columns("--name=x"), 8);
+        assert_eq!(columns("\t"), 0);
+        #[cfg(feature = "unicode-width")]
+        assert_eq!(columns("名前🦀e\u{301}"), 7);
+        #[cfg(not(feature = "unicode-width"))]
+        assert_eq!(columns("名前🦀e\u{301}"), 5);
+    }
+}
@@ -0,0 +1,145 @@
+//! Wrapping a long Argv at the terminal width into virtual lines, and
+//! translating offsets in it to positions in the wrapped view. See
+//! `WrappedArgv`.
+
+use crate::{
+    parse::ArgPart,
+    width::{char_columns, columns},
+};
+
+/// Position of an offset in `WrappedArgv`.
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub struct LinePosition {
+    /// Index of the virtual line.
+    pub line: usize,
+    /// Number of columns before the offset in the line.
+    pub column: usize,
+}
+
+/// The Argv joined by spaces and wrapped into virtual lines that are at
+/// most `width` columns wide. Lines break at the spaces in between the
+/// arguments if possible, which are not in any line, and within an
+/// argument that is wider than a line otherwise.
+#[derive(Clone, Debug)]
+pub struct WrappedArgv {
+    pub argv: String,
+    /// Byte range of every line in `Self::argv`.
+    lines: Vec<ArgPart>,
+}
+
+impl WrappedArgv {
+    /// Wraps `argv` at `width` columns. Columns are counted by the
+    /// `unicode-width` feature if it is enabled.
+    ///
+    /// # Examples
+    /// ```
+    /// use fancy_clap::{LinePosition, WrappedArgv};
+    ///
+    /// let wrapped = WrappedArgv::new("app --name=alice --port 80", 16);
+    /// assert_eq!(wrapped.lines().collect::<Vec<_>>(), ["app --name=alice", "--port 80"]);
+    /// assert_eq!(wrapped.position(24), LinePosition { line: 1, column: 7 });
+    /// ```
+    pub fn new(argv: impl Into<String>, width: usize) -> Self {
+        let argv = argv.into();
+        let width = width.max(1);
+        let mut lines = vec![];
+        let mut start = 0;
+        let mut column = 0;
+        let mut space = None;
+        for (index, c) in argv.char_indices() {
+            let c_columns = char_columns(c);
+            if column + c_columns <= width || column == 0 {
+                if c == ' ' {
+                    space = Some(index);
+                }
+                column += c_columns;
+                continue;
+            }
+            // Breaks at the overflowing space, the last space of the
+            // line, or right before the overflowing character.
+            let (end, next) = match (c, space) {
+                (' ', _) => (index, index + 1),
+                (_, Some(space)) => (space, space + 1),
+                (_, None) => (index, index),
+            };
+            lines.push(ArgPart { offset: start, length: end - start });
+            start = next;
+            space = None;
+            // Nothing is left of the line after an overflowing space.
+            column = argv.get(start..index).map_or(0, columns);
+            if c != ' ' {
+                column += c_columns;
+            }
+        }
+        lines.push(ArgPart {
+            offset: start,
+            length: argv.len().saturating_sub(start),
+        });
+
+        Self { argv, lines }
+    }
+
+    /// Returns the text of every virtual line.
+    pub fn lines(&self) -> impl Iterator<Item = &str> + '_ {
+        self.lines.iter().map(|line| &self.argv[line.offset..line.offset + line.length])
+    }
+
+    /// Returns the position of the byte `offset` of `Self::argv`. The
+    /// space that a line breaks at is at the end of that line.
+    pub fn position(&self, offset: usize) -> LinePosition {
+        let offset = offset.min(self.argv.len());
+        let line = self.lines.partition_point(|line| line.offset <= offset).saturating_sub(1);
+        let part = &self.lines[line];
+        let end = offset.clamp(part.offset, part.offset + part.length);
+
+        LinePosition {
+            line,
+            column: columns(&self.argv[part.offset..end]),
+        }
+    }
+
+    /// Returns the pieces of `part` of `Self::argv` on every line it
+    /// covers, as the index of the line and the columns of the piece in
+    /// it, e.g. for underlining a value that is wrapped.
+    pub fn line_parts(&self, part: &ArgPart) -> Vec<(usize, ArgPart)> {
+        let start = self.position(part.offset);
+        let end = self.position(part.offset + part.length);
+        (start.line..=end.line)
+            .map(|line| {
+                let from = if line == start.line { start.column } else { 0 };
+                let to = match line == end.line {
+                    true => end.column,
+                    false => columns(&self.argv[self.lines[line].offset..self.lines[line].offset + self.lines[line].length]),
+                };
+                (line, ArgPart {
+                    offset: from,
+                    length: to.saturating_sub(from),
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wrapped_argv() {
+        let wrapped = WrappedArgv::new("app --name=alice --include=a,b,c,d,e,f,g --port 80", 16);
+        assert_eq!(wrapped.lines().collect::<Vec<_>>(), ["app --name=alice", "--include=a,b,c,", "d,e,f,g --port", "80"]);
+        assert!(wrapped.lines().all(|line| columns(line) <= 16));
+        assert_eq!(wrapped.position(0), LinePosition { line: 0, column: 0 });
+        // The space that the first line breaks at.
+        assert_eq!(wrapped.position(16), LinePosition { line: 0, column: 16 });
+        assert_eq!(wrapped.position(17), LinePosition { line: 1, column: 0 });
+        assert_eq!(wrapped.position(wrapped.argv.len()), LinePosition { line: 3, column: 2 });
+
+        // `a,b,c,d,e,f,g`
+        let value = ArgPart { offset: 27, length: 13 };
+        assert_eq!(wrapped.line_parts(&value), [(1, ArgPart { offset: 10, length: 6 }), (2, ArgPart { offset: 0, length: 7 })]);
+
+        assert_eq!(WrappedArgv::new("app --name", 80).lines().collect::<Vec<_>>(), ["app --name"]);
+        assert_eq!(WrappedArgv::new("", 80).lines().collect::<Vec<_>>(), [""]);
+    }
+}